@@ -103,6 +103,51 @@ class DatabaseLoginsStorage(private val dbPath: String) : AutoCloseable, LoginsS
         }
     }
 
+    /**
+     * Unlocks a database that uses field-level encryption, instead of
+     * encrypting the whole database with SQLCipher.
+     */
+    @Synchronized
+    @Throws(LoginsStorageException::class)
+    fun unlockWithFieldEncryption(fieldEncryptionKey: String) {
+        return unlockCounters.measure {
+            rustCall {
+                if (!isLocked()) {
+                    throw MismatchedLockException("Unlock called when we are already unlocked")
+                }
+                LoginsStoreMetrics.unlockTime.measure {
+                    raw.set(PasswordSyncAdapter.INSTANCE.sync15_passwords_state_new_with_field_encryption(
+                            dbPath,
+                            fieldEncryptionKey,
+                            it))
+                }
+            }
+        }
+    }
+
+    /**
+     * Opens a database that uses field-level encryption without its key. Only
+     * [listMetadata] works until the database is locked and unlocked again
+     * with [unlockWithFieldEncryption]; everything else throws
+     * [InvalidKeyException].
+     */
+    @Synchronized
+    @Throws(LoginsStorageException::class)
+    fun unlockWithoutFieldEncryptionKey() {
+        return unlockCounters.measure {
+            rustCall {
+                if (!isLocked()) {
+                    throw MismatchedLockException("Unlock called when we are already unlocked")
+                }
+                LoginsStoreMetrics.unlockTime.measure {
+                    raw.set(PasswordSyncAdapter.INSTANCE.sync15_passwords_state_new_without_field_encryption_key(
+                            dbPath,
+                            it))
+                }
+            }
+        }
+    }
+
     @Synchronized
     @Throws(LoginsStorageException::class)
     override fun ensureUnlocked(encryptionKey: String) {
@@ -141,6 +186,27 @@ class DatabaseLoginsStorage(private val dbPath: String) : AutoCloseable, LoginsS
         return SyncTelemetryPing.fromJSONString(json)
     }
 
+    /**
+     * Like [sync], but also validates the collection, reporting any problems
+     * in the returned ping. If [repair] is true, the problems are also fixed
+     * where possible.
+     */
+    @Throws(LoginsStorageException::class)
+    fun validateAndSync(syncInfo: SyncUnlockInfo, repair: Boolean): SyncTelemetryPing {
+        val json = rustCallWithLock { raw, error ->
+            PasswordSyncAdapter.INSTANCE.sync15_passwords_validate_and_sync(
+                    raw,
+                    syncInfo.kid,
+                    syncInfo.fxaAccessToken,
+                    syncInfo.syncKey,
+                    syncInfo.tokenserverURL,
+                    (if (repair) 1 else 0).toByte(),
+                    error
+            )?.getAndConsumeRustString()
+        }
+        return SyncTelemetryPing.fromJSONString(json)
+    }
+
     @Throws(LoginsStorageException::class)
     override fun reset() {
         rustCallWithLock { raw, error ->
@@ -209,6 +275,53 @@ class DatabaseLoginsStorage(private val dbPath: String) : AutoCloseable, LoginsS
         }
     }
 
+    /**
+     * Returns every login, with empty usernames and passwords. This works even
+     * if the database was unlocked with [unlockWithoutFieldEncryptionKey].
+     */
+    @Throws(LoginsStorageException::class)
+    fun listMetadata(): List<ServerPassword> {
+        return readQueryCounters.measure {
+            val json = rustCallWithLock { raw, error ->
+                LoginsStoreMetrics.readQueryTime.measure {
+                    PasswordSyncAdapter.INSTANCE.sync15_passwords_get_all_metadata(raw, error)
+                }
+            }.getAndConsumeRustString()
+            ServerPassword.fromJSONArray(json)
+        }
+    }
+
+    /**
+     * Returns a page of logins, sorted and filtered according to [query].
+     */
+    @Throws(LoginsStorageException::class)
+    fun query(query: LoginsQuery): List<ServerPassword> {
+        return readQueryCounters.measure {
+            val s = query.toJSON().toString()
+            val json = rustCallWithLock { raw, error ->
+                LoginsStoreMetrics.readQueryTime.measure {
+                    PasswordSyncAdapter.INSTANCE.sync15_passwords_query(raw, s, error)
+                }
+            }.getAndConsumeRustString()
+            ServerPassword.fromJSONArray(json)
+        }
+    }
+
+    /**
+     * Returns the login's current TOTP code, or null if there's no such login
+     * or it has no TOTP secret.
+     */
+    @Throws(LoginsStorageException::class)
+    fun totpCode(id: String): String? {
+        return readQueryCounters.measure {
+            nullableRustCallWithLock { raw, error ->
+                LoginsStoreMetrics.readQueryTime.measure {
+                    PasswordSyncAdapter.INSTANCE.sync15_passwords_totp_code(raw, id, error)
+                }
+            }?.getAndConsumeRustString()
+        }
+    }
+
     @Throws(LoginsStorageException::class)
     override fun getByBaseDomain(baseDomain: String): List<ServerPassword> {
         return readQueryCounters.measure {
@@ -288,6 +401,20 @@ class DatabaseLoginsStorage(private val dbPath: String) : AutoCloseable, LoginsS
         }
     }
 
+    /**
+     * Re-encrypts the usernames and passwords in a database that uses
+     * field-level encryption with a new key.
+     */
+    @Throws(LoginsStorageException::class)
+    fun rotateFieldEncryptionKey(newFieldEncryptionKey: String) {
+        return rustCallWithLock { raw, error ->
+            PasswordSyncAdapter.INSTANCE.sync15_passwords_rotate_field_encryption_key(
+                    raw,
+                    newFieldEncryptionKey,
+                    error)
+        }
+    }
+
     @Synchronized
     @Throws(LoginsStorageException::class)
     override fun close() {
@@ -299,6 +426,72 @@ class DatabaseLoginsStorage(private val dbPath: String) : AutoCloseable, LoginsS
         }
     }
 
+    companion object {
+        /**
+         * Decrypts the SQLCipher database at [dbPath] into a new database at
+         * [newDbPath], which uses field-level encryption instead. The [salt]
+         * may be null if the database stores its salt in the header.
+         */
+        @Throws(LoginsStorageException::class)
+        fun migrateToFieldEncryption(
+            dbPath: String,
+            encryptionKey: String,
+            salt: String?,
+            newDbPath: String,
+            fieldEncryptionKey: String
+        ) {
+            staticRustCall { error ->
+                PasswordSyncAdapter.INSTANCE.sync15_passwords_migrate_to_field_encryption(
+                        dbPath,
+                        encryptionKey,
+                        salt,
+                        newDbPath,
+                        fieldEncryptionKey,
+                        error)
+            }
+        }
+
+        /**
+         * Generates a password following the site's `passwordrules` attribute,
+         * which may be empty (in which case the default rules are used).
+         */
+        @Throws(LoginsStorageException::class)
+        fun generatePassword(passwordRules: String): String {
+            return staticRustCall { error ->
+                PasswordSyncAdapter.INSTANCE.sync15_passwords_generate_password(passwordRules, error)
+            }!!.getAndConsumeRustString()
+        }
+
+        /**
+         * Returns an unsaved login with a generated password for [hostname].
+         * Pass it to [add] once the form is submitted.
+         */
+        @Throws(LoginsStorageException::class)
+        fun newGeneratedLogin(hostname: String, formSubmitURL: String?, passwordRules: String): ServerPassword {
+            val json = staticRustCall { error ->
+                PasswordSyncAdapter.INSTANCE.sync15_passwords_new_generated_login(
+                        hostname,
+                        formSubmitURL,
+                        passwordRules,
+                        error)
+            }!!.getAndConsumeRustString()
+            return ServerPassword.fromJSON(json)
+        }
+
+        private inline fun <U> staticRustCall(callback: (RustError.ByReference) -> U?): U? {
+            val e = RustError.ByReference()
+            try {
+                val ret = callback(e)
+                if (e.isFailure()) {
+                    throw e.intoException()
+                }
+                return ret
+            } finally {
+                e.ensureConsumed()
+            }
+        }
+    }
+
     // In practice we usually need to be synchronized to call this safely, so it doesn't
     // synchronize itself
     private inline fun <U> nullableRustCall(callback: (RustError.ByReference) -> U?): U? {
@@ -0,0 +1,57 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+package mozilla.appservices.logins
+
+import org.json.JSONObject
+
+/**
+ * How [DatabaseLoginsStorage.query] should order its results.
+ */
+enum class LoginsSortOrder(internal val jsonValue: String) {
+    /** Most recently used first. */
+    LAST_USED("lastUsed"),
+    /** Highest `timesUsed` first. */
+    MOST_USED("mostUsed"),
+    /** Most recently created first. */
+    RECENTLY_CREATED("recentlyCreated"),
+    /** Least recently changed password first. */
+    OLDEST_PASSWORD("oldestPassword")
+}
+
+/**
+ * A page of logins for [DatabaseLoginsStorage.query].
+ */
+data class LoginsQuery(
+    val sort: LoginsSortOrder = LoginsSortOrder.LAST_USED,
+
+    /**
+     * Only include "stale" logins, whose password hasn't been changed in at
+     * least this many days.
+     */
+    val staleAfterDays: Int? = null,
+
+    /**
+     * Only include logins whose hostname or username contains this string
+     * (ignoring ASCII case).
+     */
+    val filter: String? = null,
+
+    /**
+     * The maximum number of logins to return. `null` returns them all.
+     */
+    val limit: Int? = null,
+
+    val offset: Int = 0
+) {
+    fun toJSON(): JSONObject {
+        val o = JSONObject()
+        o.put("sort", sort.jsonValue)
+        staleAfterDays?.let { o.put("staleAfterDays", it) }
+        filter?.let { o.put("filter", it) }
+        limit?.let { o.put("limit", it) }
+        o.put("offset", offset)
+        return o
+    }
+}
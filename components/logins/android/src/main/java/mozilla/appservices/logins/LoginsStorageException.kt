@@ -54,6 +54,18 @@ class RequestFailedException(msg: String) : LoginsStorageException(msg)
  */
 class InterruptedException(msg: String) : LoginsStorageException(msg)
 
+/**
+ * This error is emitted if the password rules passed to `generatePassword()`
+ * or `newGeneratedLogin()` are malformed, or can't be satisfied.
+ */
+class InvalidPasswordRulesException(msg: String) : LoginsStorageException(msg)
+
+/**
+ * This error is emitted by `totpCode()` if the login's TOTP secret is
+ * malformed, or uses an unsupported algorithm.
+ */
+class InvalidTotpSecretException(msg: String) : LoginsStorageException(msg)
+
 /**
  * A reason a login may be invalid
  */
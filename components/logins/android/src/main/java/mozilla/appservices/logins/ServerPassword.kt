@@ -68,7 +68,14 @@ data class ServerPassword(
     val timePasswordChanged: Long = 0L,
 
     val usernameField: String,
-    val passwordField: String
+    val passwordField: String,
+
+    /**
+     * Additional data about this login, like notes or a TOTP secret. See the
+     * logins component's documentation for the well-known keys. Keys you don't
+     * understand must be kept when updating the login.
+     */
+    val metadata: Map<String, String> = emptyMap()
 ) {
 
     fun toJSON(): JSONObject {
@@ -89,6 +96,9 @@ data class ServerPassword(
         }
         o.put("usernameField", usernameField)
         o.put("passwordField", passwordField)
+        if (metadata.isNotEmpty()) {
+            o.put("metadata", JSONObject(metadata))
+        }
         return o
     }
 
@@ -103,6 +113,13 @@ data class ServerPassword(
                 }
             }
 
+            val metadata = mutableMapOf<String, String>()
+            jsonObject.optJSONObject("metadata")?.let { m ->
+                for (key in m.keys()) {
+                    metadata[key] = m.getString(key)
+                }
+            }
+
             return ServerPassword(
                     id = jsonObject.getString("id"),
 
@@ -120,7 +137,9 @@ data class ServerPassword(
 
                     timeCreated = jsonObject.getLong("timeCreated"),
                     timeLastUsed = jsonObject.getLong("timeLastUsed"),
-                    timePasswordChanged = jsonObject.getLong("timePasswordChanged")
+                    timePasswordChanged = jsonObject.getLong("timePasswordChanged"),
+
+                    metadata = metadata
             )
         }
 
@@ -31,6 +31,26 @@ internal interface PasswordSyncAdapter : Library {
         error: RustError.ByReference
     ): LoginsDbHandle
 
+    fun sync15_passwords_state_new_with_field_encryption(
+        db_path: String,
+        field_encryption_key: String,
+        error: RustError.ByReference
+    ): LoginsDbHandle
+
+    fun sync15_passwords_state_new_without_field_encryption_key(
+        db_path: String,
+        error: RustError.ByReference
+    ): LoginsDbHandle
+
+    fun sync15_passwords_migrate_to_field_encryption(
+        db_path: String,
+        encryption_key: String,
+        salt: String?,
+        new_db_path: String,
+        field_encryption_key: String,
+        error: RustError.ByReference
+    )
+
     fun sync15_passwords_state_destroy(handle: LoginsDbHandle, error: RustError.ByReference)
 
     // Important: strings returned from rust as *char must be Pointers on this end, returning a
@@ -43,6 +63,15 @@ internal interface PasswordSyncAdapter : Library {
     // return json array
     fun sync15_passwords_get_all(handle: LoginsDbHandle, error: RustError.ByReference): Pointer?
 
+    // return json array, with empty usernames, passwords and metadata values
+    fun sync15_passwords_get_all_metadata(handle: LoginsDbHandle, error: RustError.ByReference): Pointer?
+
+    // return json array
+    fun sync15_passwords_query(handle: LoginsDbHandle, query_json: String, error: RustError.ByReference): Pointer?
+
+    // Returns null if the id does not exist or has no TOTP secret
+    fun sync15_passwords_totp_code(handle: LoginsDbHandle, id: String, error: RustError.ByReference): Pointer?
+
     // return json array
     fun sync15_passwords_get_by_base_domain(handle: LoginsDbHandle, basedomain: String, error: RustError.ByReference): Pointer?
 
@@ -56,6 +85,18 @@ internal interface PasswordSyncAdapter : Library {
         error: RustError.ByReference
     ): Pointer?
 
+    // Returns a JSON string containing a sync ping, including any validation
+    // problems. `repair` is 1 for true and 0 for false.
+    fun sync15_passwords_validate_and_sync(
+        handle: LoginsDbHandle,
+        key_id: String,
+        access_token: String,
+        sync_key: String,
+        token_server_url: String,
+        repair: Byte,
+        error: RustError.ByReference
+    ): Pointer?
+
     fun sync15_passwords_wipe(handle: LoginsDbHandle, error: RustError.ByReference)
     fun sync15_passwords_wipe_local(handle: LoginsDbHandle, error: RustError.ByReference)
     fun sync15_passwords_reset(handle: LoginsDbHandle, error: RustError.ByReference)
@@ -74,6 +115,15 @@ internal interface PasswordSyncAdapter : Library {
     // Returns a JSON string containing import metrics
     fun sync15_passwords_import(handle: LoginsDbHandle, logins_json: String, error: RustError.ByReference): Pointer?
 
+    fun sync15_passwords_generate_password(password_rules: String, error: RustError.ByReference): Pointer?
+    // Returns an unsaved login as json
+    fun sync15_passwords_new_generated_login(
+        hostname: String,
+        form_submit_url: String?,
+        password_rules: String,
+        error: RustError.ByReference
+    ): Pointer?
+
     fun sync15_passwords_destroy_string(p: Pointer)
 
     fun sync15_passwords_new_interrupt_handle(handle: LoginsDbHandle, error: RustError.ByReference): RawLoginsInterruptHandle?
@@ -91,6 +141,11 @@ internal interface PasswordSyncAdapter : Library {
         new_encryption_key_len: Int,
         error: RustError.ByReference
     )
+    fun sync15_passwords_rotate_field_encryption_key(
+        handle: LoginsDbHandle,
+        new_field_encryption_key: String,
+        error: RustError.ByReference
+    )
 }
 
 internal typealias LoginsDbHandle = Long
@@ -51,6 +51,8 @@ open class RustError : Structure() {
             4 -> return InvalidKeyException(message)
             5 -> return RequestFailedException(message)
             6 -> return InterruptedException(message)
+            8 -> return InvalidPasswordRulesException(message)
+            9 -> return InvalidTotpSecretException(message)
 
             64 -> return InvalidRecordException(message, InvalidLoginReason.EMPTY_ORIGIN)
             65 -> return InvalidRecordException(message, InvalidLoginReason.EMPTY_PASSWORD)
@@ -347,6 +347,24 @@ abstract class LoginsStorageTest {
         finishAndClose(test)
     }
 
+    @Test
+    fun testUpdateKeepsMetadata() {
+        val test = getTestStore()
+        test.unlock(encryptionKey)
+
+        val metadata = mapOf("notes" to "Some notes", "field:pin" to "1234")
+        test.update(test.get("aaaaaaaaaaaa")!!.copy(metadata = metadata))
+        assertEquals(metadata, test.get("aaaaaaaaaaaa")!!.metadata)
+
+        // Updating another field keeps the metadata we read.
+        test.update(test.get("aaaaaaaaaaaa")!!.copy(password = "myNewPassword"))
+        val record = test.get("aaaaaaaaaaaa")!!
+        assertEquals("myNewPassword", record.password)
+        assertEquals(metadata, record.metadata)
+
+        finishAndClose(test)
+    }
+
     @Test
     @Suppress("DEPRECATION")
     fun testUnlockAfterError() {
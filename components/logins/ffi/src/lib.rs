@@ -12,7 +12,8 @@ use ffi_support::ConcurrentHandleMap;
 use ffi_support::{
     define_box_destructor, define_handle_map_deleter, define_string_destructor, ExternError, FfiStr,
 };
-use logins::{Login, LoginDb, PasswordEngine, Result};
+use logins::password_gen::{self, PasswordRules};
+use logins::{Login, LoginDb, LoginsQuery, PasswordEngine, Result, ValidationMode};
 use std::os::raw::c_char;
 use std::sync::{Arc, Mutex};
 
@@ -55,6 +56,38 @@ pub extern "C" fn sync15_passwords_state_new_with_salt(
     })
 }
 
+#[no_mangle]
+pub extern "C" fn sync15_passwords_state_new_with_field_encryption(
+    db_path: FfiStr<'_>,
+    field_encryption_key: FfiStr<'_>,
+    error: &mut ExternError,
+) -> u64 {
+    log::debug!("sync15_passwords_state_new_with_field_encryption");
+    ENGINES.insert_with_result(error, || -> logins::Result<_> {
+        let path = db_path.as_str();
+        let key = field_encryption_key.as_str();
+        Ok(Arc::new(Mutex::new(
+            PasswordEngine::new_with_field_encryption(path, key)?,
+        )))
+    })
+}
+
+/// Opens a database that uses field encryption without its key. Only
+/// `sync15_passwords_get_all_metadata` works until it's opened with the key.
+#[no_mangle]
+pub extern "C" fn sync15_passwords_state_new_without_field_encryption_key(
+    db_path: FfiStr<'_>,
+    error: &mut ExternError,
+) -> u64 {
+    log::debug!("sync15_passwords_state_new_without_field_encryption_key");
+    ENGINES.insert_with_result(error, || -> logins::Result<_> {
+        let path = db_path.as_str();
+        Ok(Arc::new(Mutex::new(
+            PasswordEngine::new_without_field_encryption_key(path)?,
+        )))
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn sync15_passwords_num_open_connections(error: &mut ExternError) -> u64 {
     ffi_support::call_with_output(error, || ENGINES.len() as u64)
@@ -142,6 +175,30 @@ pub unsafe extern "C" fn sync15_passwords_migrate_plaintext_header(
     })
 }
 
+/// Decrypts an existing SQLCipher database into a new database at `new_db_path`,
+/// which uses field-level encryption instead. The `salt` may be null if the
+/// database stores its salt in the header.
+#[no_mangle]
+pub extern "C" fn sync15_passwords_migrate_to_field_encryption(
+    db_path: FfiStr<'_>,
+    encryption_key: FfiStr<'_>,
+    salt: FfiStr<'_>,
+    new_db_path: FfiStr<'_>,
+    field_encryption_key: FfiStr<'_>,
+    error: &mut ExternError,
+) {
+    log::debug!("sync15_passwords_migrate_to_field_encryption");
+    ffi_support::call_with_result(error, || {
+        LoginDb::migrate_sqlcipher_to_field_encryption(
+            db_path.as_str(),
+            encryption_key.as_str(),
+            salt.as_opt_str(),
+            new_db_path.as_str(),
+            field_encryption_key.as_str(),
+        )
+    })
+}
+
 // indirection to help `?` figure out the target error type
 fn parse_url(url: &str) -> sync15::Result<url::Url> {
     Ok(url::Url::parse(url)?)
@@ -192,6 +249,19 @@ pub unsafe extern "C" fn sync15_passwords_rekey_database_with_hex_key(
     })
 }
 
+#[no_mangle]
+pub extern "C" fn sync15_passwords_rotate_field_encryption_key(
+    handle: u64,
+    new_field_encryption_key: FfiStr<'_>,
+    error: &mut ExternError,
+) {
+    log::debug!("sync15_passwords_rotate_field_encryption_key");
+    let new_key = new_field_encryption_key.as_str();
+    ENGINES.call_with_result(error, handle, |state| -> Result<()> {
+        state.lock().unwrap().rotate_field_encryption_key(new_key)
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn sync15_passwords_sync(
     handle: u64,
@@ -215,6 +285,39 @@ pub extern "C" fn sync15_passwords_sync(
     })
 }
 
+/// Like `sync15_passwords_sync`, but also validates the collection, reporting
+/// any problems in the returned telemetry ping. If `repair` is nonzero, the
+/// problems are also fixed where possible.
+#[no_mangle]
+pub extern "C" fn sync15_passwords_validate_and_sync(
+    handle: u64,
+    key_id: FfiStr<'_>,
+    access_token: FfiStr<'_>,
+    sync_key: FfiStr<'_>,
+    tokenserver_url: FfiStr<'_>,
+    repair: u8,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("sync15_passwords_validate_and_sync");
+    ENGINES.call_with_result(error, handle, |state| -> Result<_> {
+        let validation_mode = if repair != 0 {
+            ValidationMode::Repair
+        } else {
+            ValidationMode::Validate
+        };
+        let ping = state.lock().unwrap().sync_with_validation(
+            &sync15::Sync15StorageClientInit {
+                key_id: key_id.into_string(),
+                access_token: access_token.into_string(),
+                tokenserver_url: parse_url(tokenserver_url.as_str())?,
+            },
+            &sync15::KeyBundle::from_ksync_base64(sync_key.as_str())?,
+            validation_mode,
+        )?;
+        Ok(ping)
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn sync15_passwords_touch(handle: u64, id: FfiStr<'_>, error: &mut ExternError) {
     log::debug!("sync15_passwords_touch");
@@ -296,6 +399,21 @@ pub extern "C" fn sync15_passwords_get_all(handle: u64, error: &mut ExternError)
     })
 }
 
+/// Returns every login as JSON, with empty usernames, passwords and metadata
+/// values.
+#[no_mangle]
+pub extern "C" fn sync15_passwords_get_all_metadata(
+    handle: u64,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("sync15_passwords_get_all_metadata");
+    ENGINES.call_with_result(error, handle, |state| -> Result<String> {
+        let all_logins = state.lock().unwrap().list_metadata()?;
+        let result = serde_json::to_string(&all_logins)?;
+        Ok(result)
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn sync15_passwords_get_by_base_domain(
     handle: u64,
@@ -313,6 +431,23 @@ pub extern "C" fn sync15_passwords_get_by_base_domain(
     })
 }
 
+/// Returns a page of logins, sorted and filtered according to `query_json`,
+/// which is a JSON-serialized `LoginsQuery`.
+#[no_mangle]
+pub extern "C" fn sync15_passwords_query(
+    handle: u64,
+    query_json: FfiStr<'_>,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("sync15_passwords_query");
+    ENGINES.call_with_result(error, handle, |state| -> Result<String> {
+        let query: LoginsQuery = serde_json::from_str(query_json.as_str())?;
+        let passwords = state.lock().unwrap().query(&query)?;
+        let result = serde_json::to_string(&passwords)?;
+        Ok(result)
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn sync15_passwords_get_by_id(
     handle: u64,
@@ -325,6 +460,20 @@ pub extern "C" fn sync15_passwords_get_by_id(
     })
 }
 
+/// Returns the login's current TOTP code, or null if there's no such login or
+/// it has no TOTP secret.
+#[no_mangle]
+pub extern "C" fn sync15_passwords_totp_code(
+    handle: u64,
+    id: FfiStr<'_>,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("sync15_passwords_totp_code");
+    ENGINES.call_with_result(error, handle, |state| {
+        state.lock().unwrap().totp_code(id.as_str())
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn sync15_passwords_add(
     handle: u64,
@@ -371,6 +520,37 @@ pub extern "C" fn sync15_passwords_update(
     });
 }
 
+/// Generates a password following the site's `passwordrules` attribute, which
+/// may be empty (in which case our default rules are used).
+#[no_mangle]
+pub extern "C" fn sync15_passwords_generate_password(
+    password_rules: FfiStr<'_>,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("sync15_passwords_generate_password");
+    ffi_support::call_with_result(error, || -> Result<String> {
+        let rules = PasswordRules::parse(password_rules.as_str())?;
+        password_gen::generate_password(&rules)
+    })
+}
+
+/// Returns (as JSON) an unsaved login with a generated password for the
+/// given origin. The application should pass it to `sync15_passwords_add`
+/// once the form is submitted.
+#[no_mangle]
+pub extern "C" fn sync15_passwords_new_generated_login(
+    hostname: FfiStr<'_>,
+    form_submit_url: FfiStr<'_>,
+    password_rules: FfiStr<'_>,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("sync15_passwords_new_generated_login");
+    ffi_support::call_with_result(error, || -> Result<Login> {
+        let rules = PasswordRules::parse(password_rules.as_str())?;
+        password_gen::generated_login(hostname.as_str(), form_submit_url.as_opt_str(), &rules)
+    })
+}
+
 define_string_destructor!(sync15_passwords_destroy_string);
 define_handle_map_deleter!(ENGINES, sync15_passwords_state_destroy);
 define_box_destructor!(
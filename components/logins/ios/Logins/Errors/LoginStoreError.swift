@@ -45,6 +45,14 @@ public enum LoginsStoreError: LocalizedError {
     /// database was invalid.
     case invalidSalt(message: String)
 
+    /// This error is emitted if the password rules passed to the password
+    /// generator are malformed, or can't be satisfied.
+    case invalidPasswordRules(message: String)
+
+    /// This error is emitted if a login's TOTP secret is malformed, or uses
+    /// an unsupported algorithm.
+    case invalidTotpSecret(message: String)
+
     /// Our implementation of the localizedError protocol -- (This shows up in Sentry)
     public var errorDescription: String? {
         switch self {
@@ -68,6 +76,10 @@ public enum LoginsStoreError: LocalizedError {
             return "LoginsStoreError.interrupted: \(message)"
         case let .invalidSalt(message):
             return "LoginsStoreError.invalidSalt: \(message)"
+        case let .invalidPasswordRules(message):
+            return "LoginsStoreError.invalidPasswordRules: \(message)"
+        case let .invalidTotpSecret(message):
+            return "LoginsStoreError.invalidTotpSecret: \(message)"
         }
     }
 
@@ -126,6 +138,12 @@ public enum LoginsStoreError: LocalizedError {
         case Sync15Passwords_InvalidSaltError:
             return .invalidSalt(message: String(freeingRustString: message!))
 
+        case Sync15Passwords_InvalidPasswordRulesError:
+            return .invalidPasswordRules(message: String(freeingRustString: message!))
+
+        case Sync15Passwords_InvalidTotpSecretError:
+            return .invalidTotpSecret(message: String(freeingRustString: message!))
+
         default:
             return .unspecified(message: String(freeingRustString: message!))
         }
@@ -72,6 +72,10 @@ open class LoginRecord {
     /// HTML field name of the password, if known.
     public var passwordField: String
 
+    /// Additional data about this login, like notes or a TOTP secret. Keys
+    /// you don't understand must be kept when updating the login.
+    public var metadata: [String: String]
+
     open func toJSONDict() -> [String: Any] {
         var dict: [String: Any] = [
             "id": id,
@@ -96,6 +100,10 @@ open class LoginRecord {
             dict["formSubmitURL"] = formSubmitURL
         }
 
+        if !metadata.isEmpty {
+            dict["metadata"] = metadata
+        }
+
         return dict
     }
 
@@ -125,7 +133,9 @@ open class LoginRecord {
             timePasswordChanged: (dict["timePasswordChanged"] as? Int64) ?? 0,
 
             usernameField: dict["usernameField"] as? String ?? "",
-            passwordField: dict["passwordField"] as? String ?? ""
+            passwordField: dict["passwordField"] as? String ?? "",
+
+            metadata: dict["metadata"] as? [String: String] ?? [:]
         )
     }
 
@@ -140,7 +150,8 @@ open class LoginRecord {
          timeCreated: Int64?,
          timePasswordChanged: Int64?,
          usernameField: String,
-         passwordField: String) {
+         passwordField: String,
+         metadata: [String: String] = [:]) {
         self.id = id
         self.password = password
         self.hostname = hostname
@@ -153,6 +164,7 @@ open class LoginRecord {
         self.timePasswordChanged = timePasswordChanged ?? 0
         self.usernameField = usernameField
         self.passwordField = passwordField
+        self.metadata = metadata
     }
 
     public convenience init(fromJSONString json: String) throws {
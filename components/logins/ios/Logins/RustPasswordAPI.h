@@ -16,6 +16,8 @@ typedef enum Sync15PasswordsErrorCode {
     Sync15Passwords_NetworkError     = 5,
     Sync15Passwords_InterruptedError = 6,
     Sync15Passwords_InvalidSaltError = 7,
+    Sync15Passwords_InvalidPasswordRulesError = 8,
+    Sync15Passwords_InvalidTotpSecretError = 9,
 
     Sync15Passwords_InvalidLogin_EmptyOrigin = 64 + 0,
     Sync15Passwords_InvalidLogin_EmptyPassword = 64 + 1,
@@ -46,6 +48,13 @@ Sync15PasswordEngineHandle sync15_passwords_state_new_with_salt(char const *_Non
                                                                 char const *_Nonnull salt,
                                                                 Sync15PasswordsError *_Nonnull error_out);
 
+Sync15PasswordEngineHandle sync15_passwords_state_new_with_field_encryption(char const *_Nonnull db_path,
+                                                                            char const *_Nonnull field_encryption_key,
+                                                                            Sync15PasswordsError *_Nonnull error_out);
+
+Sync15PasswordEngineHandle sync15_passwords_state_new_without_field_encryption_key(char const *_Nonnull db_path,
+                                                                                   Sync15PasswordsError *_Nonnull error_out);
+
 Sync15PasswordEngineHandle sync15_passwords_state_new_with_hex_key(char const *_Nonnull db_path,
                                                                    uint8_t const *_Nullable encryption_key_bytes,
                                                                    uint32_t encryption_key_len,
@@ -63,17 +72,35 @@ void sync15_passwords_migrate_plaintext_header(char const *_Nonnull db_path,
                                                char const *_Nonnull salt,
                                                Sync15PasswordsError *_Nonnull error_out);
 
+void sync15_passwords_migrate_to_field_encryption(char const *_Nonnull db_path,
+                                                  char const *_Nonnull encryption_key,
+                                                  char const *_Nullable salt,
+                                                  char const *_Nonnull new_db_path,
+                                                  char const *_Nonnull field_encryption_key,
+                                                  Sync15PasswordsError *_Nonnull error_out);
+
 char *_Nullable sync15_passwords_get_by_id(Sync15PasswordEngineHandle handle,
                                           char const *_Nonnull id,
                                           Sync15PasswordsError *_Nonnull error_out);
 
+char *_Nullable sync15_passwords_totp_code(Sync15PasswordEngineHandle handle,
+                                           char const *_Nonnull id,
+                                           Sync15PasswordsError *_Nonnull error_out);
+
 char *_Nullable sync15_passwords_get_by_base_domain(Sync15PasswordEngineHandle handle,
                                           char const *_Nonnull baseDomain,
                                           Sync15PasswordsError *_Nonnull error_out);
 
+char *_Nullable sync15_passwords_query(Sync15PasswordEngineHandle handle,
+                                       char const *_Nonnull query_json,
+                                       Sync15PasswordsError *_Nonnull error_out);
+
 char *_Nullable sync15_passwords_get_all(Sync15PasswordEngineHandle handle,
                                          Sync15PasswordsError *_Nonnull error_out);
 
+char *_Nullable sync15_passwords_get_all_metadata(Sync15PasswordEngineHandle handle,
+                                                  Sync15PasswordsError *_Nonnull error_out);
+
 char *_Nullable sync15_passwords_sync(Sync15PasswordEngineHandle handle,
                                       char const *_Nonnull key_id,
                                       char const *_Nonnull access_token,
@@ -81,6 +108,14 @@ char *_Nullable sync15_passwords_sync(Sync15PasswordEngineHandle handle,
                                       char const *_Nonnull token_server_url,
                                       Sync15PasswordsError *_Nonnull error);
 
+char *_Nullable sync15_passwords_validate_and_sync(Sync15PasswordEngineHandle handle,
+                                                   char const *_Nonnull key_id,
+                                                   char const *_Nonnull access_token,
+                                                   char const *_Nonnull sync_key,
+                                                   char const *_Nonnull token_server_url,
+                                                   uint8_t repair,
+                                                   Sync15PasswordsError *_Nonnull error);
+
 void sync15_passwords_wipe(Sync15PasswordEngineHandle handle,
                            Sync15PasswordsError *_Nonnull error);
 
@@ -99,6 +134,10 @@ void sync15_passwords_rekey_database_with_hex_key(Sync15PasswordEngineHandle han
                                                   uint32_t new_encryption_key_len,
                                                   Sync15PasswordsError *_Nonnull error);
 
+void sync15_passwords_rotate_field_encryption_key(Sync15PasswordEngineHandle handle,
+                                                  char const *_Nonnull new_field_encryption_key,
+                                                  Sync15PasswordsError *_Nonnull error);
+
 void sync15_passwords_reset(Sync15PasswordEngineHandle handle,
                             Sync15PasswordsError *_Nonnull error);
 
@@ -122,6 +161,14 @@ void sync15_passwords_update(Sync15PasswordEngineHandle handle,
                              char const *_Nonnull json,
                              Sync15PasswordsError *_Nonnull error);
 
+char *_Nullable sync15_passwords_generate_password(char const *_Nonnull password_rules,
+                                                  Sync15PasswordsError *_Nonnull error);
+
+char *_Nullable sync15_passwords_new_generated_login(char const *_Nonnull hostname,
+                                                    char const *_Nullable form_submit_url,
+                                                    char const *_Nonnull password_rules,
+                                                    Sync15PasswordsError *_Nonnull error);
+
 void sync15_passwords_destroy_string(char const *_Nonnull str);
 
 Sync15PasswordsInterruptHandle *_Nullable
@@ -2,11 +2,13 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use crate::encryption::{Field, FieldEncryptor};
 use crate::error::*;
 use crate::login::{LocalLogin, Login, MirrorLogin, SyncLoginData, SyncStatus};
 use crate::schema;
 use crate::update_plan::UpdatePlan;
 use crate::util;
+use crate::validation::{self, ValidationMode};
 use lazy_static::lazy_static;
 use rusqlite::{
     named_params,
@@ -16,6 +18,7 @@ use rusqlite::{
 use serde_derive::*;
 use sql_support::{self, ConnExt};
 use sql_support::{SqlInterruptHandle, SqlInterruptScope};
+use std::borrow::Cow;
 use std::collections::HashSet;
 use std::ops::Deref;
 use std::path::Path;
@@ -49,9 +52,63 @@ pub struct MigrationMetrics {
     errors: Vec<String>,
 }
 
+/// How `LoginDb::query` should order its results.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum LoginsSortOrder {
+    /// Most recently used first.
+    LastUsed,
+    /// Highest `timesUsed` first.
+    MostUsed,
+    /// Most recently created first.
+    RecentlyCreated,
+    /// Least recently changed password first.
+    OldestPassword,
+}
+
+impl Default for LoginsSortOrder {
+    fn default() -> Self {
+        LoginsSortOrder::LastUsed
+    }
+}
+
+impl LoginsSortOrder {
+    fn order_by(self) -> &'static str {
+        match self {
+            LoginsSortOrder::LastUsed => "timeLastUsed DESC",
+            LoginsSortOrder::MostUsed => "timesUsed DESC, timeLastUsed DESC",
+            LoginsSortOrder::RecentlyCreated => "timeCreated DESC",
+            LoginsSortOrder::OldestPassword => "timePasswordChanged ASC",
+        }
+    }
+}
+
+/// A page of logins for `LoginDb::query`. All fields are optional when
+/// deserializing from JSON.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LoginsQuery {
+    pub sort: LoginsSortOrder,
+    /// Only include "stale" logins, whose password hasn't been changed in at
+    /// least this many days.
+    pub stale_after_days: Option<u32>,
+    /// Only include logins whose hostname or username contains this string
+    /// (ignoring ASCII case).
+    pub filter: Option<String>,
+    /// The maximum number of logins to return. `None` returns them all.
+    pub limit: Option<u32>,
+    pub offset: u32,
+}
+
 pub struct LoginDb {
     pub db: Connection,
     interrupt_counter: Arc<AtomicUsize>,
+    // Set if the `username` and `password` columns are encrypted by us,
+    // rather than the whole database being encrypted by SQLCipher.
+    field_encryptor: Option<FieldEncryptor>,
+    // Set if the database uses field encryption, but was opened without the
+    // key. Logins can only be read with `get_all_metadata`.
+    field_encryption_locked: bool,
 }
 
 impl LoginDb {
@@ -60,11 +117,6 @@ impl LoginDb {
         encryption_key: Option<&str>,
         salt: Option<&str>,
     ) -> Result<Self> {
-        #[cfg(test)]
-        {
-            util::init_test_logging();
-        }
-
         if let Some(key) = encryption_key {
             db.set_pragma("key", key)?
                 .set_pragma("secure_delete", true)?;
@@ -78,6 +130,27 @@ impl LoginDb {
                 db.set_pragma("cipher_salt", format!("x'{}'", s))?;
             }
         }
+        Self::init(db, None)
+    }
+
+    /// Use field-level encryption instead of SQLCipher: the database itself is
+    /// plain SQLite, but the `username` and `password` columns are encrypted
+    /// with a key derived from `field_encryption_key`.
+    ///
+    /// If the database has existing logins that aren't field-encrypted (for
+    /// example, it was produced by `migrate_sqlcipher_to_field_encryption`),
+    /// they're encrypted as part of opening it.
+    pub fn with_field_encryption(db: Connection, field_encryption_key: &str) -> Result<Self> {
+        // So that plaintext doesn't linger in free pages once it's encrypted.
+        db.set_pragma("secure_delete", true)?;
+        Self::init(db, Some(FieldEncryptor::new(field_encryption_key)?))
+    }
+
+    fn init(db: Connection, field_encryptor: Option<FieldEncryptor>) -> Result<Self> {
+        #[cfg(test)]
+        {
+            util::init_test_logging();
+        }
 
         // `temp_store = 2` is required on Android to force the DB to keep temp
         // files in memory, since on Android there's no tmp partition. See
@@ -88,10 +161,13 @@ impl LoginDb {
         let mut logins = Self {
             db,
             interrupt_counter: Arc::new(AtomicUsize::new(0)),
+            field_encryptor,
+            field_encryption_locked: false,
         };
         let tx = logins.db.transaction()?;
         schema::init(&tx)?;
         tx.commit()?;
+        logins.field_encryption_locked = logins.check_field_encryption()?;
         Ok(logins)
     }
 
@@ -124,6 +200,90 @@ impl LoginDb {
         )?)
     }
 
+    pub fn open_with_field_encryption(
+        path: impl AsRef<Path>,
+        field_encryption_key: &str,
+    ) -> Result<Self> {
+        Self::with_field_encryption(Connection::open(path)?, field_encryption_key)
+    }
+
+    pub fn open_in_memory_with_field_encryption(field_encryption_key: &str) -> Result<Self> {
+        Self::with_field_encryption(Connection::open_in_memory()?, field_encryption_key)
+    }
+
+    /// Decrypts the SQLCipher database at `sqlcipher_path` into a new database
+    /// at `new_path` (which must not already exist), which uses field-level
+    /// encryption with `field_encryption_key` instead. The original database
+    /// is left untouched; it's up to the caller to delete it once they've
+    /// switched over.
+    ///
+    /// `salt` must be provided if the database stores its salt outside of
+    /// the header (see `open_with_salt`).
+    pub fn migrate_sqlcipher_to_field_encryption(
+        sqlcipher_path: impl AsRef<Path>,
+        encryption_key: &str,
+        salt: Option<&str>,
+        new_path: impl AsRef<Path>,
+        field_encryption_key: &str,
+    ) -> Result<()> {
+        if let Some(s) = salt {
+            ensure_valid_salt(s)?;
+        }
+        // Open the connection defensively without attempting to create a db if it doesn't exist.
+        let db = Connection::open_with_flags(sqlcipher_path, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+        db.set_pragma("key", encryption_key)?;
+        sqlcipher_3_compat(&db)?;
+        if let Some(s) = salt {
+            db.set_pragma("cipher_plaintext_header_size", 32)?;
+            db.set_pragma("cipher_salt", format!("x'{}'", s))?;
+        }
+        // `sqlcipher_export` doesn't copy the schema version.
+        let user_version = db.query_one::<i64>("PRAGMA user_version")?;
+        // The attached database inherits our flags, so it can't create the file.
+        let new_path = new_path.as_ref();
+        Connection::open(new_path)?
+            .close()
+            .map_err(|(_conn, err)| err)?;
+        db.execute(
+            "ATTACH DATABASE ? AS plaintext KEY ''",
+            &[new_path.to_string_lossy().as_ref() as &str],
+        )?;
+        db.query_row(
+            "SELECT sqlcipher_export('plaintext')",
+            NO_PARAMS,
+            |_| Ok(()),
+        )?;
+        db.execute_batch(&format!("PRAGMA plaintext.user_version = {}", user_version))?;
+        db.execute_batch("DETACH DATABASE plaintext")?;
+        db.close().map_err(|(_conn, err)| err)?;
+
+        // Opening it encrypts the fields.
+        Self::open_with_field_encryption(new_path, field_encryption_key)?;
+        Ok(())
+    }
+
+    /// Re-encrypts the `username` and `password` fields of every login with a
+    /// new field encryption key. This is the field-encryption equivalent of
+    /// `rekey_database`.
+    pub fn rotate_field_encryption_key(&mut self, new_field_encryption_key: &str) -> Result<()> {
+        let new_encryptor = FieldEncryptor::new(new_field_encryption_key)?;
+        {
+            let old_encryptor = match self.field_encryptor()? {
+                Some(e) => e,
+                None => throw!(ErrorKind::FieldEncryptionNotEnabled),
+            };
+            let tx = self.unchecked_transaction_imm()?;
+            self.reencrypt_fields(Some(old_encryptor), &new_encryptor)?;
+            self.put_meta(
+                schema::FIELD_ENCRYPTION_CANARY_META_KEY,
+                &new_encryptor.canary()?,
+            )?;
+            tx.commit()?;
+        }
+        self.field_encryptor = Some(new_encryptor);
+        Ok(())
+    }
+
     /// Opens an existing database and fetches the salt.
     /// This method is used by iOS consumers as part as the migration plan to store
     /// the salt outside of the sqlite db headers.
@@ -185,6 +345,123 @@ impl LoginDb {
     pub fn begin_interrupt_scope(&self) -> SqlInterruptScope {
         SqlInterruptScope::new(self.interrupt_counter.clone())
     }
+
+    // Makes sure we've been given the right field encryption key, and
+    // encrypts any existing plaintext logins the first time one is used.
+    // Returns true if the database uses field encryption, but we weren't
+    // given a key.
+    fn check_field_encryption(&self) -> Result<bool> {
+        let tx = self.unchecked_transaction_imm()?;
+        let canary = self.get_meta::<String>(schema::FIELD_ENCRYPTION_CANARY_META_KEY)?;
+        let mut locked = false;
+        match (&self.field_encryptor, &canary) {
+            (None, None) => {}
+            (None, Some(_)) => {
+                log::info!("The database uses field encryption, but no key was provided");
+                locked = true;
+            }
+            (Some(encryptor), Some(canary)) => {
+                if !encryptor.check_canary(canary) {
+                    throw!(ErrorKind::IncorrectFieldEncryptionKey);
+                }
+            }
+            (Some(encryptor), None) => {
+                log::info!("Enabling field encryption");
+                self.reencrypt_fields(None, encryptor)?;
+                self.write_field_encryption_canary()?;
+            }
+        }
+        tx.commit()?;
+        Ok(locked)
+    }
+
+    // Returns the field encryptor, or an error if we need one but weren't
+    // given the key.
+    fn field_encryptor(&self) -> Result<Option<&FieldEncryptor>> {
+        if self.field_encryption_locked {
+            throw!(ErrorKind::IncorrectFieldEncryptionKey);
+        }
+        Ok(self.field_encryptor.as_ref())
+    }
+
+    fn write_field_encryption_canary(&self) -> Result<()> {
+        if let Some(encryptor) = &self.field_encryptor {
+            self.put_meta(
+                schema::FIELD_ENCRYPTION_CANARY_META_KEY,
+                &encryptor.canary()?,
+            )?;
+        }
+        Ok(())
+    }
+
+    // Must be called in a transaction. `from` is `None` if the fields are
+    // currently plaintext.
+    fn reencrypt_fields(&self, from: Option<&FieldEncryptor>, to: &FieldEncryptor) -> Result<()> {
+        for table in &["loginsL", "loginsM"] {
+            let mut rows = Vec::new();
+            {
+                let mut stmt = self.prepare(&format!(
+                    "SELECT guid, username, password, metadata FROM {}",
+                    table
+                ))?;
+                let mut query = stmt.query(NO_PARAMS)?;
+                while let Some(row) = query.next()? {
+                    let guid: String = row.get("guid")?;
+                    let mut login = Login {
+                        username: row
+                            .get::<_, Option<String>>("username")?
+                            .unwrap_or_default(),
+                        password: row.get("password")?,
+                        metadata: match row.get::<_, Option<String>>("metadata")? {
+                            Some(json) => serde_json::from_str(&json)?,
+                            None => Default::default(),
+                        },
+                        ..Login::default()
+                    };
+                    if let Some(from) = from {
+                        from.decrypt_login(&mut login)?;
+                    }
+                    to.encrypt_login(&mut login)?;
+                    rows.push((guid, login));
+                }
+            }
+            let mut stmt = self.prepare(&format!(
+                "UPDATE {}
+                 SET username = :username, password = :password, metadata = :metadata
+                 WHERE guid = :guid",
+                table
+            ))?;
+            for (guid, login) in rows {
+                stmt.execute_named(named_params! {
+                    ":username": login.username,
+                    ":password": login.password,
+                    ":metadata": login.metadata_json()?,
+                    ":guid": guid,
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the login as it should be written to (or compared against) the
+    // `username`, `password` and `metadata` columns.
+    fn encrypt_login<'a>(&self, login: &'a Login) -> Result<Cow<'a, Login>> {
+        Ok(match self.field_encryptor()? {
+            Some(encryptor) => {
+                let mut encrypted = login.clone();
+                encryptor.encrypt_login(&mut encrypted)?;
+                Cow::Owned(encrypted)
+            }
+            None => Cow::Borrowed(login),
+        })
+    }
+
+    pub(crate) fn decrypt_login(&self, mut login: Login) -> Result<Login> {
+        if let Some(encryptor) = self.field_encryptor()? {
+            encryptor.decrypt_login(&mut login)?;
+        }
+        Ok(login)
+    }
 }
 
 // Checks if the provided string is a 32 len hex string.
@@ -304,7 +581,7 @@ impl LoginDb {
                         log::error!("Failed to deserialize record {:?}: {}", incoming.0.id, e);
                         // Ideally we'd track new_failed, but it's unclear how
                         // much value it has.
-                        telem.failed(1);
+                        telem.failed_with_reason(1, "deserialize");
                     }
                 }
             }
@@ -363,9 +640,13 @@ impl LoginDb {
                     let guid_idx = guid_idx_i as usize;
                     let is_mirror: bool = row.get("is_mirror")?;
                     if is_mirror {
-                        sync_data[guid_idx].set_mirror(MirrorLogin::from_row(row)?)?;
+                        let mut mirror = MirrorLogin::from_row(row)?;
+                        mirror.login = self.decrypt_login(mirror.login)?;
+                        sync_data[guid_idx].set_mirror(mirror)?;
                     } else {
-                        sync_data[guid_idx].set_local(LocalLogin::from_row(row)?)?;
+                        let mut local = LocalLogin::from_row(row)?;
+                        local.login = self.decrypt_login(local.login)?;
+                        sync_data[guid_idx].set_local(local)?;
                     }
                     scope.err_if_interrupted()?;
                     Ok(())
@@ -381,6 +662,7 @@ impl LoginDb {
     // It would be nice if this were a batch-ish api (e.g. takes a slice of records and finds dupes
     // for each one if they exist)... I can't think of how to write that query, though.
     fn find_dupe(&self, l: &Login) -> Result<Option<Login>> {
+        let l = self.encrypt_login(l)?;
         let form_submit_host_port = l
             .form_submit_url
             .as_ref()
@@ -405,13 +687,32 @@ impl LoginDb {
         } else {
             query += " AND formSubmitURL IS :form_submit"
         }
-        Ok(self.try_query_row(&query, args, |row| Login::from_row(row), false)?)
+        let dupe = self.try_query_row(&query, args, |row| Login::from_row(row), false)?;
+        dupe.map(|login| self.decrypt_login(login)).transpose()
     }
 
     pub fn get_all(&self) -> Result<Vec<Login>> {
         let mut stmt = self.db.prepare_cached(&GET_ALL_SQL)?;
         let rows = stmt.query_and_then(NO_PARAMS, Login::from_row)?;
-        rows.collect::<Result<_>>()
+        rows.map(|login| self.decrypt_login(login?)).collect()
+    }
+
+    /// Returns every login without its encrypted fields: the username,
+    /// password and metadata values are left empty. Unlike the other
+    /// queries, this works if the database uses field encryption but was
+    /// opened without the key.
+    pub fn get_all_metadata(&self) -> Result<Vec<Login>> {
+        let mut stmt = self.db.prepare_cached(&GET_ALL_SQL)?;
+        let rows = stmt.query_and_then(NO_PARAMS, |row| -> Result<Login> {
+            let mut login = Login::from_row(row)?;
+            login.username.clear();
+            login.password.clear();
+            for value in login.metadata.values_mut() {
+                value.clear();
+            }
+            Ok(login)
+        })?;
+        rows.collect()
     }
 
     pub fn get_by_base_domain(&self, base_domain: &str) -> Result<Vec<Login>> {
@@ -462,16 +763,90 @@ impl LoginDb {
                     _ => false,
                 }
             });
-        rows.collect::<Result<_>>()
+        rows.map(|login| self.decrypt_login(login?)).collect()
     }
 
     pub fn get_by_id(&self, id: &str) -> Result<Option<Login>> {
-        self.try_query_row(
+        let login = self.try_query_row(
             &GET_BY_GUID_SQL,
             &[(":guid", &id as &dyn ToSql)],
             Login::from_row,
             true,
-        )
+        )?;
+        login.map(|login| self.decrypt_login(login)).transpose()
+    }
+
+    /// Fetches logins sorted and filtered by their usage metadata, a page at
+    /// a time, so that the whole list doesn't need to be loaded to show the
+    /// first few.
+    pub fn query(&self, query: &LoginsQuery) -> Result<Vec<Login>> {
+        let now_ms = util::system_time_ms_i64(SystemTime::now());
+        let changed_before = query
+            .stale_after_days
+            .map(|days| now_ms - i64::from(days) * 24 * 60 * 60 * 1000);
+        let filter = query
+            .filter
+            .as_ref()
+            .filter(|f| !f.is_empty())
+            .map(|f| f.to_ascii_lowercase());
+        // The username is ciphertext if we're using field encryption, so we
+        // have to filter (and so also paginate) after decrypting.
+        let filter_after_decrypting = filter.is_some() && self.field_encryptor.is_some();
+        let (sql_filter, limit, offset) = if filter_after_decrypting {
+            (None, -1, 0)
+        } else {
+            (
+                filter.as_ref(),
+                query.limit.map_or(-1, i64::from),
+                i64::from(query.offset),
+            )
+        };
+
+        let sql = format!(
+            "SELECT * FROM ({all})
+             WHERE (:changed_before IS NULL OR timePasswordChanged < :changed_before)
+               AND (:filter IS NULL
+                    OR instr(lower(hostname), :filter) > 0
+                    OR instr(lower(username), :filter) > 0)
+             -- Use the guid as a tiebreaker, so that pages are stable.
+             ORDER BY {order_by}, guid
+             LIMIT :limit OFFSET :offset",
+            all = &*GET_ALL_SQL,
+            order_by = query.sort.order_by(),
+        );
+        let mut stmt = self.db.prepare(&sql)?;
+        let rows = stmt
+            .query_and_then_named(
+                named_params! {
+                    ":changed_before": changed_before,
+                    ":filter": sql_filter,
+                    ":limit": limit,
+                    ":offset": offset,
+                },
+                Login::from_row,
+            )?
+            .map(|login| self.decrypt_login(login?));
+        match filter {
+            Some(filter) if filter_after_decrypting => {
+                let matches = |login: &Login| {
+                    login.hostname.to_ascii_lowercase().contains(&filter)
+                        || login.username.to_ascii_lowercase().contains(&filter)
+                };
+                let mut results = Vec::new();
+                for login in rows {
+                    let login = login?;
+                    if matches(&login) {
+                        results.push(login);
+                    }
+                }
+                Ok(results
+                    .into_iter()
+                    .skip(query.offset as usize)
+                    .take(query.limit.map_or(usize::max_value(), |l| l as usize))
+                    .collect())
+            }
+            _ => rows.collect(),
+        }
     }
 
     pub fn touch(&self, id: &str) -> Result<()> {
@@ -538,6 +913,7 @@ impl LoginDb {
                 timeCreated,
                 timeLastUsed,
                 timePasswordChanged,
+                metadata,
                 local_modified,
                 is_deleted,
                 sync_status
@@ -554,6 +930,7 @@ impl LoginDb {
                 :time_created,
                 :time_last_used,
                 :time_password_changed,
+                :metadata,
                 :local_modified,
                 0, -- is_deleted
                 {new} -- sync_status
@@ -561,6 +938,7 @@ impl LoginDb {
             new = SyncStatus::New as u8
         );
 
+        let stored = self.encrypt_login(&login)?;
         let rows_changed = self.execute_named(
             &sql,
             named_params! {
@@ -569,13 +947,14 @@ impl LoginDb {
                 ":form_submit_url": login.form_submit_url,
                 ":username_field": login.username_field,
                 ":password_field": login.password_field,
-                ":username": login.username,
-                ":password": login.password,
+                ":username": stored.username,
+                ":password": stored.password,
                 ":guid": login.guid,
                 ":time_created": login.time_created,
                 ":times_used": login.times_used,
                 ":time_last_used": login.time_last_used,
                 ":time_password_changed": login.time_password_changed,
+                ":metadata": stored.metadata_json()?,
                 ":local_modified": now_ms,
             },
         )?;
@@ -616,6 +995,7 @@ impl LoginDb {
                 timeCreated,
                 timeLastUsed,
                 timePasswordChanged,
+                metadata,
                 local_modified,
                 is_deleted,
                 sync_status
@@ -632,6 +1012,7 @@ impl LoginDb {
                 :time_created,
                 :time_last_used,
                 :time_password_changed,
+                :metadata,
                 :local_modified,
                 0, -- is_deleted
                 {new} -- sync_status
@@ -677,6 +1058,7 @@ impl LoginDb {
                 Guid::random()
             };
             fixup_phase_duration = import_start.elapsed();
+            let stored = self.encrypt_login(login)?;
             match self.execute_named_cached(
                 &sql,
                 named_params! {
@@ -685,13 +1067,14 @@ impl LoginDb {
                     ":form_submit_url": login.form_submit_url,
                     ":username_field": login.username_field,
                     ":password_field": login.password_field,
-                    ":username": login.username,
-                    ":password": login.password,
+                    ":username": stored.username,
+                    ":password": stored.password,
                     ":guid": guid,
                     ":time_created": login.time_created,
                     ":times_used": login.times_used,
                     ":time_last_used": login.time_last_used,
                     ":time_password_changed": login.time_password_changed,
+                    ":metadata": stored.metadata_json()?,
                     ":local_modified": now_ms,
                 },
             ) {
@@ -755,6 +1138,17 @@ impl LoginDb {
         self.mark_mirror_overridden(login.guid_str())?;
 
         let now_ms = util::system_time_ms_i64(SystemTime::now());
+        // Passwords are encrypted with random nonces, so we can't compare
+        // them in SQL.
+        let old_password = self.db.query_row_named(
+            "SELECT password FROM loginsL WHERE guid = :guid",
+            named_params! { ":guid": login.guid },
+            |row| row.get::<_, String>(0),
+        )?;
+        let password_changed = match self.field_encryptor()? {
+            Some(encryptor) => encryptor.decrypt(Field::Password, &old_password)? != login.password,
+            None => old_password != login.password,
+        };
 
         let sql = format!(
             "UPDATE loginsL
@@ -762,14 +1156,15 @@ impl LoginDb {
                  timeLastUsed        = :now_millis,
                  -- Only update timePasswordChanged if, well, the password changed.
                  timePasswordChanged = (CASE
-                     WHEN password = :password
-                     THEN timePasswordChanged
-                     ELSE :now_millis
+                     WHEN :password_changed
+                     THEN :now_millis
+                     ELSE timePasswordChanged
                  END),
                  httpRealm           = :http_realm,
                  formSubmitURL       = :form_submit_url,
                  usernameField       = :username_field,
                  passwordField       = :password_field,
+                 metadata            = :metadata,
                  timesUsed           = timesUsed + 1,
                  username            = :username,
                  password            = :password,
@@ -780,18 +1175,21 @@ impl LoginDb {
             changed = SyncStatus::Changed as u8
         );
 
+        let stored = self.encrypt_login(&login)?;
         self.db.execute_named(
             &sql,
             named_params! {
                 ":hostname": login.hostname,
-                ":username": login.username,
-                ":password": login.password,
+                ":username": stored.username,
+                ":password": stored.password,
                 ":http_realm": login.http_realm,
                 ":form_submit_url": login.form_submit_url,
                 ":username_field": login.username_field,
                 ":password_field": login.password_field,
+                ":metadata": stored.metadata_json()?,
                 ":guid": login.guid,
                 ":now_millis": now_ms,
+                ":password_changed": password_changed,
             },
         )?;
         tx.commit()?;
@@ -819,6 +1217,7 @@ impl LoginDb {
     pub fn dupe_exists(&self, login: &Login) -> Result<bool> {
         // Note: the query below compares the guids of the given login with existing logins
         //  to prevent a login from being considered a duplicate of itself (e.g. during updates).
+        let login = self.encrypt_login(login)?;
         Ok(self.db.query_row_named(
             "SELECT EXISTS(
                 SELECT 1 FROM loginsL
@@ -886,7 +1285,8 @@ impl LoginDb {
                      is_deleted = 1,
                      password = '',
                      hostname = '',
-                     username = ''
+                     username = '',
+                     metadata = NULL
                  WHERE guid = :guid",
                 status_changed = SyncStatus::Changed as u8
             ),
@@ -984,7 +1384,8 @@ impl LoginDb {
                     is_deleted = 1,
                     password = '',
                     hostname = '',
-                    username = ''
+                    username = '',
+                    metadata = NULL
                 WHERE is_deleted = 0",
                 changed = SyncStatus::Changed as u8
             ),
@@ -1011,11 +1412,17 @@ impl LoginDb {
     pub fn wipe_local(&self) -> Result<()> {
         log::info!("Executing wipe_local on password store!");
         let tx = self.unchecked_transaction()?;
+        // Keep the canary, even if we don't have the key, so that the
+        // database still needs it.
+        let canary = self.get_meta::<String>(schema::FIELD_ENCRYPTION_CANARY_META_KEY)?;
         self.execute_all(&[
             "DELETE FROM loginsL",
             "DELETE FROM loginsM",
             "DELETE FROM loginsSyncMeta",
         ])?;
+        if let Some(canary) = canary {
+            self.put_meta(schema::FIELD_ENCRYPTION_CANARY_META_KEY, &canary)?;
+        }
         tx.commit()?;
         Ok(())
     }
@@ -1032,13 +1439,25 @@ impl LoginDb {
         for mut record in records {
             scope.err_if_interrupted()?;
             log::debug!("Processing remote change {}", record.guid());
-            let upstream = if let Some(inbound) = record.inbound.0.take() {
+            let mut upstream = if let Some(inbound) = record.inbound.0.take() {
                 inbound
             } else {
                 log::debug!("Processing inbound deletion (always prefer)");
                 plan.plan_delete(record.guid.clone());
                 continue;
             };
+            if !record.inbound_has_metadata {
+                // The record was uploaded by a client that doesn't know about
+                // `metadata`, so keep ours instead of removing it.
+                let ours = record
+                    .mirror
+                    .as_ref()
+                    .map(|m| &m.login)
+                    .or_else(|| record.local.as_ref().map(|l| &l.login));
+                if let Some(ours) = ours {
+                    upstream.metadata = ours.metadata.clone();
+                }
+            }
             let upstream_time = record.inbound.1;
             match (record.mirror.take(), record.local.take()) {
                 (Some(mirror), Some(local)) => {
@@ -1075,7 +1494,10 @@ impl LoginDb {
         Ok(plan)
     }
 
-    fn execute_plan(&self, plan: UpdatePlan, scope: &SqlInterruptScope) -> Result<()> {
+    fn execute_plan(&self, mut plan: UpdatePlan, scope: &SqlInterruptScope) -> Result<()> {
+        if let Some(encryptor) = self.field_encryptor()? {
+            plan.encrypt_logins(encryptor)?;
+        }
         // Because rusqlite want a mutable reference to create a transaction
         // (as a way to save us from ourselves), we side-step that by creating
         // it manually.
@@ -1105,8 +1527,15 @@ impl LoginDb {
                 Payload::new_tombstone(row.get::<_, String>("guid")?)
                     .with_sortindex(TOMBSTONE_SORTINDEX)
             } else {
-                let login = Login::from_row(row)?;
-                Payload::from_record(login)?.with_sortindex(DEFAULT_SORTINDEX)
+                let login = self.decrypt_login(Login::from_row(row)?)?;
+                let mut payload = Payload::from_record(login)?;
+                // Always send `metadata`, even if it's empty, so that other
+                // clients can tell we removed it.
+                payload
+                    .data
+                    .entry("metadata")
+                    .or_insert_with(|| serde_json::json!({}));
+                payload.with_sortindex(DEFAULT_SORTINDEX)
             })
         })?;
         outgoing.changes = rows.collect::<Result<_>>()?;
@@ -1114,12 +1543,54 @@ impl LoginDb {
         Ok(outgoing)
     }
 
+    // Forces the given records to be uploaded on the next sync, even though
+    // we think the server already has them.
+    fn mark_for_upload(&self, guids: &[Guid]) -> Result<()> {
+        let tx = self.unchecked_transaction()?;
+        for guid in guids {
+            self.execute_named_cached(&*CLONE_SINGLE_MIRROR_SQL, named_params! { ":guid": guid })?;
+            self.mark_mirror_overridden(guid)?;
+            self.execute_named_cached(
+                &format!(
+                    "UPDATE loginsL
+                     SET sync_status = max(sync_status, {changed})
+                     WHERE guid = :guid AND is_deleted = 0",
+                    changed = SyncStatus::Changed as u8
+                ),
+                named_params! { ":guid": guid },
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
     fn do_apply_incoming(
         &self,
-        inbound: IncomingChangeset,
+        mut inbound: IncomingChangeset,
         telem: &mut telemetry::Engine,
+        validation_mode: ValidationMode,
         scope: &SqlInterruptScope,
     ) -> Result<OutgoingChangeset> {
+        if validation_mode != ValidationMode::Skip {
+            // We asked for the whole collection, so validate it, then only
+            // apply what's actually changed (and what we want to repair).
+            let report = validation::validate(self, &inbound.changes, scope)?;
+            telem.validation(report.to_telemetry());
+            let last_sync = self.get_last_sync()?.unwrap_or_default();
+            let mut to_download = HashSet::new();
+            if validation_mode == ValidationMode::Repair {
+                log::info!(
+                    "Repairing: uploading {} and re-applying {} records",
+                    report.to_upload.len(),
+                    report.to_download.len()
+                );
+                self.mark_for_upload(&report.to_upload)?;
+                to_download.extend(report.to_download);
+            }
+            inbound
+                .changes
+                .retain(|(payload, ts)| *ts > last_sync || to_download.contains(&payload.id));
+        }
         let mut incoming_telemetry = telemetry::EngineIncoming::new();
         let data = self.fetch_login_data(&inbound.changes, &mut incoming_telemetry, scope)?;
         let plan = {
@@ -1204,13 +1675,19 @@ impl LoginDb {
 pub struct LoginStore<'a> {
     pub db: &'a LoginDb,
     pub scope: sql_support::SqlInterruptScope,
+    pub validation_mode: ValidationMode,
 }
 
 impl<'a> LoginStore<'a> {
     pub fn new(db: &'a LoginDb) -> Self {
+        Self::new_with_validation(db, ValidationMode::Skip)
+    }
+
+    pub fn new_with_validation(db: &'a LoginDb, validation_mode: ValidationMode) -> Self {
         Self {
             db,
             scope: db.begin_interrupt_scope(),
+            validation_mode,
         }
     }
 }
@@ -1227,7 +1704,9 @@ impl<'a> Store for LoginStore<'a> {
     ) -> result::Result<OutgoingChangeset, failure::Error> {
         assert_eq!(inbound.len(), 1, "logins only requests one item");
         let inbound = inbound.into_iter().next().unwrap();
-        Ok(self.db.do_apply_incoming(inbound, telem, &self.scope)?)
+        Ok(self
+            .db
+            .do_apply_incoming(inbound, telem, self.validation_mode, &self.scope)?)
     }
 
     fn sync_finished(
@@ -1243,8 +1722,21 @@ impl<'a> Store for LoginStore<'a> {
         Ok(())
     }
 
+    fn sync_finished_readonly(
+        &self,
+        new_timestamp: ServerTimestamp,
+    ) -> result::Result<(), failure::Error> {
+        self.db.set_last_sync(new_timestamp)?;
+        Ok(())
+    }
+
     fn get_collection_requests(&self) -> result::Result<Vec<CollectionRequest>, failure::Error> {
-        let since = self.db.get_last_sync()?.unwrap_or_default();
+        // Validation needs to see every record on the server.
+        let since = if self.validation_mode == ValidationMode::Skip {
+            self.db.get_last_sync()?.unwrap_or_default()
+        } else {
+            ServerTimestamp(0)
+        };
         Ok(vec![CollectionRequest::new("passwords")
             .full()
             .newer_than(since)])
@@ -1308,6 +1800,9 @@ lazy_static! {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::login::metadata_keys;
+    use std::collections::BTreeMap;
+
     #[test]
     fn test_bad_record() {
         let db = LoginDb::open_in_memory(Some("testing")).unwrap();
@@ -1546,6 +2041,228 @@ mod tests {
         );
     }
 
+    fn check_query(db: &LoginDb) {
+        let now_ms = util::system_time_ms_i64(SystemTime::now());
+        let day_ms = 24 * 60 * 60 * 1000;
+        for (i, (hostname, username)) in [
+            ("https://www.example.com", "alice"),
+            ("https://mail.example.com", "bob"),
+            ("https://www.mozilla.org", "Carol"),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let i = i as i64;
+            db.add(Login {
+                hostname: (*hostname).into(),
+                http_realm: Some((*hostname).into()),
+                username: (*username).into(),
+                password: "password".into(),
+                // Created and last used in order, used most in reverse order,
+                // and the password was changed 100 days ago for the first.
+                time_created: now_ms - (10 - i) * day_ms,
+                time_last_used: now_ms - (5 - i) * day_ms,
+                times_used: 10 - i,
+                time_password_changed: now_ms - (100 - i * 50) * day_ms,
+                ..Login::default()
+            })
+            .unwrap();
+        }
+        let usernames = |query: LoginsQuery| -> Vec<String> {
+            db.query(&query)
+                .unwrap()
+                .into_iter()
+                .map(|l| l.username)
+                .collect()
+        };
+
+        assert_eq!(
+            usernames(LoginsQuery::default()),
+            vec!["Carol", "bob", "alice"]
+        );
+        assert_eq!(
+            usernames(LoginsQuery {
+                sort: LoginsSortOrder::MostUsed,
+                ..LoginsQuery::default()
+            }),
+            vec!["alice", "bob", "Carol"]
+        );
+        assert_eq!(
+            usernames(LoginsQuery {
+                sort: LoginsSortOrder::RecentlyCreated,
+                limit: Some(2),
+                ..LoginsQuery::default()
+            }),
+            vec!["Carol", "bob"]
+        );
+        assert_eq!(
+            usernames(LoginsQuery {
+                sort: LoginsSortOrder::RecentlyCreated,
+                limit: Some(2),
+                offset: 2,
+                ..LoginsQuery::default()
+            }),
+            vec!["alice"]
+        );
+        assert_eq!(
+            usernames(LoginsQuery {
+                sort: LoginsSortOrder::OldestPassword,
+                stale_after_days: Some(30),
+                ..LoginsQuery::default()
+            }),
+            vec!["alice", "bob"]
+        );
+        assert_eq!(
+            usernames(LoginsQuery {
+                filter: Some("EXAMPLE".into()),
+                ..LoginsQuery::default()
+            }),
+            vec!["bob", "alice"]
+        );
+        assert_eq!(
+            usernames(LoginsQuery {
+                filter: Some("carol".into()),
+                ..LoginsQuery::default()
+            }),
+            vec!["Carol"]
+        );
+        assert_eq!(
+            usernames(LoginsQuery {
+                filter: Some("example".into()),
+                limit: Some(1),
+                offset: 1,
+                ..LoginsQuery::default()
+            }),
+            vec!["alice"]
+        );
+
+        let query: LoginsQuery =
+            serde_json::from_str(r#"{"sort": "mostUsed", "filter": "mozilla"}"#).unwrap();
+        assert_eq!(query.sort, LoginsSortOrder::MostUsed);
+        assert_eq!(usernames(query), vec!["Carol"]);
+    }
+
+    #[test]
+    fn test_query() {
+        check_query(&LoginDb::open_in_memory(Some("testing")).unwrap());
+        check_query(&LoginDb::open_in_memory_with_field_encryption("testing").unwrap());
+    }
+
+    #[test]
+    fn test_validation_repair() {
+        let db = LoginDb::open_in_memory(Some("testing")).unwrap();
+        let login = |id: &str| Login {
+            guid: id.into(),
+            hostname: format!("https://{}.example.com", id),
+            http_realm: Some("https://www.example.com".into()),
+            username: "user".into(),
+            password: "password".into(),
+            time_created: 1,
+            time_password_changed: 1,
+            times_used: 1,
+            ..Login::default()
+        };
+        let mut plan = UpdatePlan::default();
+        plan.plan_mirror_insert(login("onlymirror00"), ServerTimestamp(1000), false);
+        plan.plan_mirror_insert(login("synced000000"), ServerTimestamp(1000), false);
+        db.execute_plan(plan, &db.begin_interrupt_scope()).unwrap();
+        db.set_last_sync(ServerTimestamp(2000)).unwrap();
+
+        let mut inbound = IncomingChangeset::new("passwords", ServerTimestamp(3000));
+        for id in &["synced000000", "onlyserver00"] {
+            inbound.changes.push((
+                Payload::from_record(login(id)).unwrap(),
+                ServerTimestamp(1000),
+            ));
+        }
+
+        // Just validating doesn't change anything.
+        let store = LoginStore::new_with_validation(&db, ValidationMode::Validate);
+        assert_eq!(
+            store.get_collection_requests().unwrap()[0].newer,
+            Some(ServerTimestamp(0))
+        );
+        let mut telem = telemetry::Engine::new("passwords");
+        let outgoing = store
+            .apply_incoming(vec![inbound.clone()], &mut telem)
+            .unwrap();
+        assert!(outgoing.changes.is_empty());
+        assert!(!db.exists("onlyserver00").unwrap());
+        let problems = telem
+            .get_validation()
+            .expect("should have validated")
+            .get_problems()
+            .iter()
+            .map(|p| (p.get_name(), p.get_count()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            vec![("missingLocally", 1), ("missingOnServer", 1)]
+        );
+
+        // Repairing downloads the missing record and re-uploads ours.
+        let store = LoginStore::new_with_validation(&db, ValidationMode::Repair);
+        let mut telem = telemetry::Engine::new("passwords");
+        let outgoing = store.apply_incoming(vec![inbound], &mut telem).unwrap();
+        assert_eq!(outgoing.changes.len(), 1);
+        assert_eq!(outgoing.changes[0].id, "onlymirror00");
+        assert!(db.exists("onlyserver00").unwrap());
+    }
+
+    #[test]
+    fn test_incoming_without_metadata() {
+        let db = LoginDb::open_in_memory(Some("testing")).unwrap();
+        let mut login = Login {
+            guid: "metadata0000".into(),
+            hostname: "https://www.example.com".into(),
+            http_realm: Some("https://www.example.com".into()),
+            username: "user".into(),
+            password: "password".into(),
+            time_created: 1,
+            time_password_changed: 1,
+            times_used: 1,
+            ..Login::default()
+        };
+        login
+            .metadata
+            .insert(metadata_keys::NOTES.into(), "some notes".into());
+        let mut plan = UpdatePlan::default();
+        plan.plan_mirror_insert(login.clone(), ServerTimestamp(1000), false);
+        db.execute_plan(plan, &db.begin_interrupt_scope()).unwrap();
+
+        // An older client changes the password, and drops the metadata.
+        let older = Login {
+            password: "changed".into(),
+            time_password_changed: 2,
+            metadata: BTreeMap::new(),
+            ..login.clone()
+        };
+        let payload = Payload::from_record(older).unwrap();
+        assert!(!payload.data.contains_key("metadata"));
+        let mut inbound = IncomingChangeset::new("passwords", ServerTimestamp(3000));
+        inbound.changes.push((payload, ServerTimestamp(2000)));
+        let store = LoginStore::new(&db);
+        let mut telem = telemetry::Engine::new("passwords");
+        store.apply_incoming(vec![inbound], &mut telem).unwrap();
+
+        let stored = db.get_by_id("metadata0000").unwrap().unwrap();
+        assert_eq!(stored.password, "changed");
+        assert_eq!(stored.metadata, login.metadata);
+
+        // Removing the metadata locally uploads an empty map, so that it's
+        // removed on other clients too.
+        db.update(Login {
+            metadata: BTreeMap::new(),
+            ..stored
+        })
+        .unwrap();
+        let outgoing = db
+            .fetch_outgoing(ServerTimestamp(3000), &db.begin_interrupt_scope())
+            .unwrap();
+        assert_eq!(outgoing.changes.len(), 1);
+        assert_eq!(outgoing.changes[0].data["metadata"], serde_json::json!({}));
+    }
+
     #[test]
     fn test_get_by_base_domain_ipv4() {
         check_good_bad(
@@ -1921,6 +2638,295 @@ mod tests {
         );
     }
 
+    fn raw_password(db: &LoginDb, guid: &str) -> String {
+        db.query_row_named(
+            "SELECT password FROM loginsL WHERE guid = :guid",
+            named_params! { ":guid": guid },
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_field_encryption() {
+        let db = LoginDb::open_in_memory_with_field_encryption("testing").unwrap();
+        let login = db
+            .add(Login {
+                hostname: "https://www.example.com".into(),
+                http_realm: Some("https://www.example.com".into()),
+                username: "test_user".into(),
+                password: "test_password".into(),
+                ..Login::default()
+            })
+            .unwrap();
+        let guid = login.guid_str();
+
+        let raw = raw_password(&db, guid);
+        assert!(!raw.is_empty());
+        assert_ne!(raw, "test_password");
+        let fetched = db.get_by_id(guid).unwrap().unwrap();
+        assert_eq!(fetched.username, "test_user");
+        assert_eq!(fetched.password, "test_password");
+        assert_eq!(db.get_all().unwrap(), vec![fetched.clone()]);
+
+        // Dupe checks compare the encrypted values.
+        assert!(db
+            .dupe_exists(&Login {
+                guid: Guid::empty(),
+                ..fetched.clone()
+            })
+            .unwrap());
+
+        db.update(Login {
+            password: "new_password".into(),
+            ..fetched
+        })
+        .unwrap();
+        let fetched = db.get_by_id(guid).unwrap().unwrap();
+        assert_eq!(fetched.password, "new_password");
+
+        let outgoing = db
+            .fetch_outgoing(ServerTimestamp(0), &db.begin_interrupt_scope())
+            .unwrap();
+        let record: Login = outgoing.changes[0].clone().into_record().unwrap();
+        assert_eq!(record.password, "new_password");
+
+        // The canary survives a local wipe.
+        db.wipe_local().unwrap();
+        assert!(db
+            .get_meta::<String>(schema::FIELD_ENCRYPTION_CANARY_META_KEY)
+            .unwrap()
+            .is_some());
+    }
+
+    fn check_metadata(db: LoginDb) {
+        let raw_metadata = |guid: &str| -> Option<String> {
+            db.query_row_named(
+                "SELECT metadata FROM loginsL WHERE guid = :guid",
+                named_params! { ":guid": guid },
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        let mut metadata = BTreeMap::new();
+        metadata.insert(metadata_keys::NOTES.to_string(), "my notes".to_string());
+        metadata.insert("field:pin".to_string(), "1234".to_string());
+        let login = db
+            .add(Login {
+                hostname: "https://www.example.com".into(),
+                http_realm: Some("https://www.example.com".into()),
+                username: "test_user".into(),
+                password: "test_password".into(),
+                metadata: metadata.clone(),
+                ..Login::default()
+            })
+            .unwrap();
+        let guid = login.guid_str();
+        let fetched = db.get_by_id(guid).unwrap().unwrap();
+        assert_eq!(fetched.metadata, metadata);
+        let raw = raw_metadata(guid).unwrap();
+        assert_eq!(
+            raw.contains("my notes"),
+            db.field_encryptor.is_none(),
+            "{}",
+            raw
+        );
+
+        let outgoing = db
+            .fetch_outgoing(ServerTimestamp(0), &db.begin_interrupt_scope())
+            .unwrap();
+        assert_eq!(
+            outgoing.changes[0].data["metadata"],
+            serde_json::json!({ "notes": "my notes", "field:pin": "1234" })
+        );
+
+        // Incoming records keep their metadata too.
+        let mut inbound = IncomingChangeset::new("passwords", ServerTimestamp(3000));
+        inbound.changes.push((
+            serde_json::from_value(serde_json::json!({
+                "id": "incoming0000",
+                "hostname": "https://www.example.org",
+                "httpRealm": "https://www.example.org",
+                "username": "someone",
+                "password": "hunter2",
+                "metadata": { "totp": "GEZDGNBVGY3TQOJQ" },
+            }))
+            .unwrap(),
+            ServerTimestamp(1000),
+        ));
+        let mut telem = telemetry::Engine::new("passwords");
+        LoginStore::new(&db)
+            .apply_incoming(vec![inbound], &mut telem)
+            .unwrap();
+        let incoming = db.get_by_id("incoming0000").unwrap().unwrap();
+        assert_eq!(
+            incoming.metadata.get(metadata_keys::TOTP).unwrap(),
+            "GEZDGNBVGY3TQOJQ"
+        );
+
+        // Updating replaces the metadata, and deleting clears it.
+        db.update(Login {
+            metadata: BTreeMap::new(),
+            ..fetched
+        })
+        .unwrap();
+        assert!(db.get_by_id(guid).unwrap().unwrap().metadata.is_empty());
+        assert_eq!(raw_metadata(guid), None);
+        db.update(Login {
+            metadata,
+            ..db.get_by_id(guid).unwrap().unwrap()
+        })
+        .unwrap();
+        db.delete(guid).unwrap();
+        assert_eq!(raw_metadata(guid), None);
+    }
+
+    #[test]
+    fn test_metadata() {
+        check_metadata(LoginDb::open_in_memory(Some("testing")).unwrap());
+        check_metadata(LoginDb::open_in_memory_with_field_encryption("testing").unwrap());
+    }
+
+    #[test]
+    fn test_field_encryption_wrong_key() {
+        let dir = tempdir::TempDir::new("field_encryption_wrong_key").unwrap();
+        let dbpath = dir.path().join("logins.sqlite");
+        let db = LoginDb::open_with_field_encryption(&dbpath, "testing").unwrap();
+        drop(db);
+
+        let err = LoginDb::open_with_field_encryption(&dbpath, "wrong")
+            .err()
+            .unwrap();
+        assert_eq!(err.label(), "IncorrectFieldEncryptionKey");
+        LoginDb::open_with_field_encryption(&dbpath, "testing").unwrap();
+    }
+
+    #[test]
+    fn test_field_encryption_without_key() {
+        let dir = tempdir::TempDir::new("field_encryption_without_key").unwrap();
+        let dbpath = dir.path().join("logins.sqlite");
+        let db = LoginDb::open_with_field_encryption(&dbpath, "testing").unwrap();
+        let login = db
+            .add(Login {
+                hostname: "https://www.example.com".into(),
+                http_realm: Some("https://www.example.com".into()),
+                username: "test_user".into(),
+                password: "test_password".into(),
+                ..Login::default()
+            })
+            .unwrap();
+        drop(db);
+
+        // Without the key, we can read everything but the encrypted fields...
+        let db = LoginDb::open(&dbpath, None).unwrap();
+        let metadata = db.get_all_metadata().unwrap();
+        assert_eq!(metadata.len(), 1);
+        assert_eq!(metadata[0].guid, login.guid);
+        assert_eq!(metadata[0].hostname, "https://www.example.com");
+        assert_eq!(metadata[0].username, "");
+        assert_eq!(metadata[0].password, "");
+        // ...and anything that needs them fails.
+        let err = db.get_all().unwrap_err();
+        assert_eq!(err.label(), "IncorrectFieldEncryptionKey");
+        let err = db
+            .add(Login {
+                hostname: "https://www.example.org".into(),
+                http_realm: Some("https://www.example.org".into()),
+                username: "other_user".into(),
+                password: "other_password".into(),
+                ..Login::default()
+            })
+            .unwrap_err();
+        assert_eq!(err.label(), "IncorrectFieldEncryptionKey");
+
+        // Wiping keeps the canary, so the database still needs the key.
+        db.wipe_local().unwrap();
+        drop(db);
+        assert!(LoginDb::open_with_field_encryption(&dbpath, "wrong").is_err());
+        LoginDb::open_with_field_encryption(&dbpath, "testing").unwrap();
+    }
+
+    #[test]
+    fn test_rotate_field_encryption_key() {
+        let dir = tempdir::TempDir::new("rotate_field_encryption_key").unwrap();
+        let dbpath = dir.path().join("logins.sqlite");
+        let mut db = LoginDb::open_with_field_encryption(&dbpath, "old key").unwrap();
+        let login = db
+            .add(Login {
+                hostname: "https://www.example.com".into(),
+                http_realm: Some("https://www.example.com".into()),
+                username: "test_user".into(),
+                password: "test_password".into(),
+                ..Login::default()
+            })
+            .unwrap();
+        let old_raw = raw_password(&db, login.guid_str());
+
+        db.rotate_field_encryption_key("new key").unwrap();
+        assert_ne!(raw_password(&db, login.guid_str()), old_raw);
+        let fetched = db.get_by_id(login.guid_str()).unwrap().unwrap();
+        assert_eq!(fetched.password, "test_password");
+        drop(db);
+
+        assert!(LoginDb::open_with_field_encryption(&dbpath, "old key").is_err());
+        let db = LoginDb::open_with_field_encryption(&dbpath, "new key").unwrap();
+        let fetched = db.get_by_id(login.guid_str()).unwrap().unwrap();
+        assert_eq!(fetched.username, "test_user");
+
+        let mut db = LoginDb::open_in_memory(None).unwrap();
+        let err = db.rotate_field_encryption_key("new key").unwrap_err();
+        assert_eq!(err.label(), "FieldEncryptionNotEnabled");
+    }
+
+    #[test]
+    fn test_migrate_sqlcipher_to_field_encryption() {
+        let dir = tempdir::TempDir::new("migrate_to_field_encryption").unwrap();
+        let old_path = dir.path().join("logins.sqlite");
+        let new_path = dir.path().join("logins-field-encrypted.sqlite");
+        let db = LoginDb::open(&old_path, Some("testing")).unwrap();
+        let login = db
+            .add(Login {
+                hostname: "https://www.example.com".into(),
+                http_realm: Some("https://www.example.com".into()),
+                username: "test_user".into(),
+                password: "test_password".into(),
+                ..Login::default()
+            })
+            .unwrap();
+        drop(db);
+
+        LoginDb::migrate_sqlcipher_to_field_encryption(
+            &old_path,
+            "testing",
+            None,
+            &new_path,
+            "field key",
+        )
+        .unwrap();
+
+        // The new database is readable without a key...
+        let conn = Connection::open(&new_path).unwrap();
+        let hostname: String = conn
+            .query_row("SELECT hostname FROM loginsL", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(hostname, "https://www.example.com");
+        let password: String = conn
+            .query_row("SELECT password FROM loginsL", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_ne!(password, "test_password");
+        drop(conn);
+
+        // ...but the sensitive fields need the field key.
+        let db = LoginDb::open_with_field_encryption(&new_path, "field key").unwrap();
+        let fetched = db.get_by_id(login.guid_str()).unwrap().unwrap();
+        assert_eq!(fetched.username, "test_user");
+        assert_eq!(fetched.password, "test_password");
+
+        // And the original is untouched.
+        let db = LoginDb::open(&old_path, Some("testing")).unwrap();
+        assert_eq!(db.get_all().unwrap().len(), 1);
+    }
+
     #[test]
     fn test_ensure_valid_salt() {
         assert!(ensure_valid_salt("bobo").is_err());
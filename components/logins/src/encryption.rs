@@ -0,0 +1,207 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Field-level encryption for logins.
+//!
+//! This is an alternative to encrypting the whole database with SQLCipher:
+//! only the sensitive columns (`username`, `password` and the values in
+//! `metadata`) are encrypted,
+//! using AES-256-GCM under keys derived from a caller-supplied secret, and
+//! everything else is stored as plain SQLite.
+//!
+//! Passwords and metadata values are encrypted with random nonces. Usernames
+//! are encrypted deterministically: the nonce is an HMAC of the plaintext
+//! (much like AES-GCM-SIV), so the same username always encrypts to the same
+//! ciphertext under a given key. That leaks which logins share a username,
+//! but the dupe checks need to compare usernames in SQL. The field name is
+//! used as the AAD, so a username can't be swapped into the password column.
+//!
+//! Empty strings are stored as-is, since deleted records and tombstones use
+//! `''` for both fields. The keys of the `metadata` map aren't encrypted.
+
+use crate::error::*;
+use crate::login::Login;
+use rc_crypto::{aead, digest, hkdf, hmac, rand};
+
+const NONCE_KEY_INFO: &[u8] = b"logins field encryption: nonce";
+const AES_KEY_INFO: &[u8] = b"logins field encryption: aes-256-gcm";
+const NONCE_KEY_LEN: usize = 32;
+const CANARY_PLAINTEXT: &str = "logins";
+
+/// The encrypted columns. Each gets its own AAD.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Field {
+    Username,
+    Password,
+    Metadata,
+    Canary,
+}
+
+impl Field {
+    // Only usernames are compared in SQL.
+    fn is_deterministic(self) -> bool {
+        match self {
+            Field::Username => true,
+            Field::Password | Field::Metadata | Field::Canary => false,
+        }
+    }
+
+    fn aad(self) -> &'static [u8] {
+        match self {
+            Field::Username => b"username",
+            Field::Password => b"password",
+            Field::Metadata => b"metadata",
+            Field::Canary => b"canary",
+        }
+    }
+}
+
+pub struct FieldEncryptor {
+    sealing_key: aead::SealingKey,
+    opening_key: aead::OpeningKey,
+    nonce_key: hmac::SigningKey,
+}
+
+impl FieldEncryptor {
+    /// Derives the field keys from `key`, which should be a high-entropy
+    /// secret (for example, a hex-encoded random key kept in the platform's
+    /// keystore).
+    pub fn new(key: &str) -> Result<Self> {
+        let salt = hmac::SigningKey::new(&digest::SHA256, &[]);
+        let prk = hkdf::extract(&salt, key.as_bytes())?;
+        let mut aes_key = vec![0u8; aead::AES_256_GCM.key_len()];
+        hkdf::expand(&prk, AES_KEY_INFO, &mut aes_key)?;
+        let mut nonce_key = vec![0u8; NONCE_KEY_LEN];
+        hkdf::expand(&prk, NONCE_KEY_INFO, &mut nonce_key)?;
+        Ok(Self {
+            sealing_key: aead::SealingKey::new(&aead::AES_256_GCM, &aes_key)?,
+            opening_key: aead::OpeningKey::new(&aead::AES_256_GCM, &aes_key)?,
+            nonce_key: hmac::SigningKey::new(&digest::SHA256, &nonce_key),
+        })
+    }
+
+    /// Encrypts `plaintext`, returning the base64-encoded nonce and ciphertext.
+    pub(crate) fn encrypt(&self, field: Field, plaintext: &str) -> Result<String> {
+        if plaintext.is_empty() {
+            return Ok(String::new());
+        }
+        let mut nonce_bytes = vec![0u8; aead::AES_256_GCM.nonce_len()];
+        if field.is_deterministic() {
+            let mut mac_input = field.aad().to_vec();
+            mac_input.push(0);
+            mac_input.extend_from_slice(plaintext.as_bytes());
+            let mac = hmac::sign(&self.nonce_key, &mac_input)?;
+            nonce_bytes.copy_from_slice(&mac.as_ref()[..nonce_bytes.len()]);
+        } else {
+            rand::fill(&mut nonce_bytes)?;
+        }
+        let nonce = aead::Nonce::try_assume_unique_for_key(&aead::AES_256_GCM, &nonce_bytes)?;
+        let ciphertext = aead::seal(
+            &self.sealing_key,
+            nonce,
+            aead::Aad::from(field.aad()),
+            plaintext.as_bytes(),
+        )?;
+        let mut out = nonce_bytes;
+        out.extend_from_slice(&ciphertext);
+        Ok(base64::encode_config(&out, base64::URL_SAFE_NO_PAD))
+    }
+
+    pub(crate) fn decrypt(&self, field: Field, ciphertext: &str) -> Result<String> {
+        if ciphertext.is_empty() {
+            return Ok(String::new());
+        }
+        let bytes = base64::decode_config(ciphertext, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| ErrorKind::IncorrectFieldEncryptionKey)?;
+        let nonce_len = aead::AES_256_GCM.nonce_len();
+        if bytes.len() < nonce_len {
+            throw!(ErrorKind::IncorrectFieldEncryptionKey);
+        }
+        let (nonce_bytes, ciphertext) = bytes.split_at(nonce_len);
+        let nonce = aead::Nonce::try_assume_unique_for_key(&aead::AES_256_GCM, nonce_bytes)?;
+        let plaintext = aead::open(
+            &self.opening_key,
+            nonce,
+            aead::Aad::from(field.aad()),
+            ciphertext,
+        )
+        .map_err(|_| ErrorKind::IncorrectFieldEncryptionKey)?;
+        Ok(String::from_utf8(plaintext).map_err(|_| ErrorKind::IncorrectFieldEncryptionKey)?)
+    }
+
+    pub(crate) fn encrypt_login(&self, login: &mut Login) -> Result<()> {
+        login.username = self.encrypt(Field::Username, &login.username)?;
+        login.password = self.encrypt(Field::Password, &login.password)?;
+        for value in login.metadata.values_mut() {
+            *value = self.encrypt(Field::Metadata, value)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn decrypt_login(&self, login: &mut Login) -> Result<()> {
+        login.username = self.decrypt(Field::Username, &login.username)?;
+        login.password = self.decrypt(Field::Password, &login.password)?;
+        for value in login.metadata.values_mut() {
+            *value = self.decrypt(Field::Metadata, value)?;
+        }
+        Ok(())
+    }
+
+    /// A known value, stored alongside the data so we can tell whether we've
+    /// been given the right key before reading (or worse, writing) anything.
+    pub(crate) fn canary(&self) -> Result<String> {
+        self.encrypt(Field::Canary, CANARY_PLAINTEXT)
+    }
+
+    pub(crate) fn check_canary(&self, canary: &str) -> bool {
+        match self.decrypt(Field::Canary, canary) {
+            Ok(plaintext) => plaintext == CANARY_PLAINTEXT,
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let encryptor = FieldEncryptor::new("my secret key").unwrap();
+        let ciphertext = encryptor.encrypt(Field::Password, "hunter2").unwrap();
+        assert_ne!(ciphertext, "hunter2");
+        assert_eq!(
+            encryptor.decrypt(Field::Password, &ciphertext).unwrap(),
+            "hunter2"
+        );
+        // Passwords use random nonces...
+        assert_ne!(
+            encryptor.encrypt(Field::Password, "hunter2").unwrap(),
+            ciphertext
+        );
+        // ...but usernames are deterministic, so that the SQL can compare
+        // them, though not across fields.
+        let username = encryptor.encrypt(Field::Username, "hunter2").unwrap();
+        assert_eq!(
+            encryptor.encrypt(Field::Username, "hunter2").unwrap(),
+            username
+        );
+        assert_ne!(username, ciphertext);
+        assert!(encryptor.decrypt(Field::Username, &ciphertext).is_err());
+        // Empty values are left alone.
+        assert_eq!(encryptor.encrypt(Field::Username, "").unwrap(), "");
+        assert_eq!(encryptor.decrypt(Field::Username, "").unwrap(), "");
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let encryptor = FieldEncryptor::new("my secret key").unwrap();
+        let other = FieldEncryptor::new("some other key").unwrap();
+        let ciphertext = encryptor.encrypt(Field::Password, "hunter2").unwrap();
+        assert!(other.decrypt(Field::Password, &ciphertext).is_err());
+        assert!(encryptor.check_canary(&encryptor.canary().unwrap()));
+        assert!(!other.check_canary(&encryptor.canary().unwrap()));
+        assert!(!other.check_canary("not base64!"));
+    }
+}
@@ -1,9 +1,11 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
-use crate::db::{LoginDb, LoginStore, MigrationMetrics};
+use crate::db::{LoginDb, LoginStore, LoginsQuery, MigrationMetrics};
 use crate::error::*;
 use crate::login::Login;
+use crate::totp;
+use crate::validation::ValidationMode;
 use std::cell::Cell;
 use std::path::Path;
 use sync15::{
@@ -44,18 +46,52 @@ impl PasswordEngine {
         })
     }
 
+    pub fn new_with_field_encryption(
+        path: impl AsRef<Path>,
+        field_encryption_key: &str,
+    ) -> Result<Self> {
+        let db = LoginDb::open_with_field_encryption(path, field_encryption_key)?;
+        Ok(Self {
+            db,
+            mem_cached_state: Cell::default(),
+        })
+    }
+
+    /// Opens a database that uses field encryption without its key, so that
+    /// logins can be listed with `list_metadata`.
+    pub fn new_without_field_encryption_key(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(path, None)
+    }
+
     pub fn list(&self) -> Result<Vec<Login>> {
         self.db.get_all()
     }
 
+    pub fn list_metadata(&self) -> Result<Vec<Login>> {
+        self.db.get_all_metadata()
+    }
+
     pub fn get(&self, id: &str) -> Result<Option<Login>> {
         self.db.get_by_id(id)
     }
 
+    /// Returns the current TOTP code for the login, or `None` if there's no
+    /// such login or it doesn't have a TOTP secret.
+    pub fn totp_code(&self, id: &str) -> Result<Option<String>> {
+        match self.db.get_by_id(id)? {
+            Some(login) => totp::totp_code(&login),
+            None => Ok(None),
+        }
+    }
+
     pub fn get_by_base_domain(&self, base_domain: &str) -> Result<Vec<Login>> {
         self.db.get_by_base_domain(base_domain)
     }
 
+    pub fn query(&self, query: &LoginsQuery) -> Result<Vec<Login>> {
+        self.db.query(query)
+    }
+
     pub fn touch(&self, id: &str) -> Result<()> {
         self.db.touch(id)
     }
@@ -101,6 +137,11 @@ impl PasswordEngine {
         self.db.rekey_database(new_encryption_key)
     }
 
+    pub fn rotate_field_encryption_key(&mut self, new_field_encryption_key: &str) -> Result<()> {
+        self.db
+            .rotate_field_encryption_key(new_field_encryption_key)
+    }
+
     // This is basically exposed just for sync_pass_sql, but it doesn't seem
     // unreasonable.
     pub fn conn(&self) -> &rusqlite::Connection {
@@ -116,13 +157,24 @@ impl PasswordEngine {
         &self,
         storage_init: &Sync15StorageClientInit,
         root_sync_key: &KeyBundle,
+    ) -> Result<telemetry::SyncTelemetryPing> {
+        self.sync_with_validation(storage_init, root_sync_key, ValidationMode::Skip)
+    }
+
+    /// Like `sync`, but can also validate (and optionally repair) the
+    /// collection. The problems found are reported in the returned ping.
+    pub fn sync_with_validation(
+        &self,
+        storage_init: &Sync15StorageClientInit,
+        root_sync_key: &KeyBundle,
+        validation_mode: ValidationMode,
     ) -> Result<telemetry::SyncTelemetryPing> {
         // migrate our V1 state - this needn't live for long.
         self.db.migrate_global_state()?;
 
         let mut disk_cached_state = self.db.get_global_state()?;
         let mut mem_cached_state = self.mem_cached_state.take();
-        let store = LoginStore::new(&self.db);
+        let store = LoginStore::new_with_validation(&self.db, validation_mode);
 
         let mut result = sync_multiple(
             &[&store],
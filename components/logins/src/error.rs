@@ -39,6 +39,18 @@ pub enum ErrorKind {
     #[fail(display = "The provided salt is invalid")]
     InvalidSalt,
 
+    #[fail(display = "The field encryption key is incorrect, or the data is corrupt")]
+    IncorrectFieldEncryptionKey,
+
+    #[fail(display = "The database doesn't use field encryption")]
+    FieldEncryptionNotEnabled,
+
+    #[fail(display = "Invalid password rules: {}", _0)]
+    InvalidPasswordRules(String),
+
+    #[fail(display = "Invalid TOTP secret: {}", _0)]
+    InvalidTotpSecret(String),
+
     #[fail(display = "Error synchronizing: {}", _0)]
     SyncAdapterError(#[fail(cause)] sync15::Error),
 
@@ -53,6 +65,9 @@ pub enum ErrorKind {
 
     #[fail(display = "{}", _0)]
     Interrupted(#[fail(cause)] interrupt::Interrupted),
+
+    #[fail(display = "Crypto error: {}", _0)]
+    CryptoError(#[fail(cause)] rc_crypto::Error),
 }
 
 error_support::define_error! {
@@ -63,6 +78,7 @@ error_support::define_error! {
         (SqlError, rusqlite::Error),
         (InvalidLogin, InvalidLogin),
         (Interrupted, interrupt::Interrupted),
+        (CryptoError, rc_crypto::Error),
     }
 }
 
@@ -93,11 +109,16 @@ impl Error {
             ErrorKind::NoSuchRecord(_) => "NoSuchRecord",
             ErrorKind::NonEmptyTable => "NonEmptyTable",
             ErrorKind::InvalidSalt => "InvalidSalt",
+            ErrorKind::IncorrectFieldEncryptionKey => "IncorrectFieldEncryptionKey",
+            ErrorKind::FieldEncryptionNotEnabled => "FieldEncryptionNotEnabled",
+            ErrorKind::InvalidPasswordRules(_) => "InvalidPasswordRules",
+            ErrorKind::InvalidTotpSecret(_) => "InvalidTotpSecret",
             ErrorKind::SyncAdapterError(_) => "SyncAdapterError",
             ErrorKind::JsonError(_) => "JsonError",
             ErrorKind::UrlParseError(_) => "UrlParseError",
             ErrorKind::SqlError(_) => "SqlError",
             ErrorKind::Interrupted(_) => "Interrupted",
+            ErrorKind::CryptoError(_) => "CryptoError",
             ErrorKind::InvalidLogin(desc) => match desc {
                 InvalidLogin::EmptyOrigin => "InvalidLogin::EmptyOrigin",
                 InvalidLogin::EmptyPassword => "InvalidLogin::EmptyPassword",
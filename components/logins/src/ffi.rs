@@ -26,7 +26,7 @@ pub mod error_codes {
     pub const DUPLICATE_GUID: i32 = 3;
 
     /// Either the file is not a database, or it is not encrypted with the
-    /// provided encryption key.
+    /// provided encryption key (or field encryption key).
     pub const INVALID_KEY: i32 = 4;
 
     /// A request to the sync server failed.
@@ -38,6 +38,12 @@ pub mod error_codes {
     /// An invalid salt was provided.
     pub const INVALID_SALT: i32 = 7;
 
+    /// The password rules were malformed, or can't be satisfied.
+    pub const INVALID_PASSWORD_RULES: i32 = 8;
+
+    /// The login's TOTP secret is malformed, or uses an unsupported algorithm.
+    pub const INVALID_TOTP_SECRET: i32 = 9;
+
     // Skip a bunch of spaces to make it clear these are part of a group,
     // even as more and more errors get added. We're only exposing the
     // InvalidLogin items that can actually be triggered, the others
@@ -105,11 +111,26 @@ fn get_code(err: &Error) -> ErrorCode {
             ErrorCode::new(error_codes::INTERRUPTED)
         }
 
+        ErrorKind::IncorrectFieldEncryptionKey => {
+            log::error!("Incorrect field encryption key");
+            ErrorCode::new(error_codes::INVALID_KEY)
+        }
+
         ErrorKind::InvalidSalt => {
             log::error!("Invalid salt provided");
             ErrorCode::new(error_codes::INVALID_SALT)
         }
 
+        ErrorKind::InvalidPasswordRules(reason) => {
+            log::error!("Invalid password rules: {}", reason);
+            ErrorCode::new(error_codes::INVALID_PASSWORD_RULES)
+        }
+
+        ErrorKind::InvalidTotpSecret(reason) => {
+            log::error!("Invalid TOTP secret: {}", reason);
+            ErrorCode::new(error_codes::INVALID_TOTP_SECRET)
+        }
+
         err => {
             log::error!("Unexpected error: {:?}", err);
             ErrorCode::new(error_codes::UNEXPECTED)
@@ -10,16 +10,23 @@ mod error;
 mod login;
 
 mod db;
+mod encryption;
 mod engine;
+pub mod password_gen;
 pub mod schema;
+pub mod totp;
 mod update_plan;
 mod util;
+mod validation;
 
 mod ffi;
 
 // Mostly exposed for the sync manager.
 pub use crate::db::LoginDb;
 pub use crate::db::LoginStore;
+pub use crate::db::{LoginsQuery, LoginsSortOrder};
+pub use crate::encryption::FieldEncryptor;
 pub use crate::engine::*;
 pub use crate::error::*;
 pub use crate::login::*;
+pub use crate::validation::ValidationMode;
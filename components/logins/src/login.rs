@@ -216,6 +216,24 @@
 //!   - test that we don't set this for changes to other fields.
 //!   - test that we correctly merge dupes
 //!
+//! - `metadata`: Additional data about the login, as a map of string keys to string values.
+//!
+//!   This holds the things that don't fit in the fields above, such as the values of other
+//!   fields in the login form, free-form notes, or the secret for generating TOTP codes. The
+//!   well-known keys are listed in `metadata_keys`, but applications may store other keys too,
+//!   and must preserve any keys they don't understand when updating the record. Keys must not
+//!   be empty.
+//!
+//!   The metadata is stored encrypted, just like the `username` and `password`, and is synced
+//!   as a `metadata` object in the record. It's omitted from the login's JSON when it's empty,
+//!   but the records we upload always include it. Older clients don't know about it, and drop
+//!   it when they upload the login, so an incoming record without `metadata` keeps the
+//!   metadata we already have, instead of removing it.
+//!
+//!   If invalid data is received in this field (either from the application, or via sync)
+//!   then the logins store will attempt to coerce it into valid data by:
+//!   - removing the entry with an empty key
+//!
 //! In order to deal with data from legacy clients in a robust way, it is necessary to be able to build
 //! and manipulate `Login` structs that contain invalid data.  The following methods can be used by callers
 //! to ensure that they're only working with valid records:
@@ -230,6 +248,7 @@ use crate::error::*;
 use crate::util;
 use rusqlite::Row;
 use serde_derive::*;
+use std::collections::BTreeMap;
 use std::time::{self, SystemTime};
 use sync15::ServerTimestamp;
 use sync_guid::Guid;
@@ -277,6 +296,22 @@ pub struct Login {
 
     #[serde(default)]
     pub times_used: i64,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// The well-known keys in `Login::metadata`.
+pub mod metadata_keys {
+    /// Free-form notes about the login.
+    pub const NOTES: &str = "notes";
+    /// A TOTP secret, as either an `otpauth://totp/...` URI or a bare base32
+    /// secret. See the `totp` module.
+    pub const TOTP: &str = "totp";
+    /// The prefix for the values of additional form fields, keyed by the
+    /// field's name (so `field:pin` for a field named `pin`).
+    pub const FIELD_PREFIX: &str = "field:";
 }
 
 fn deserialize_timestamp<'de, D>(deserializer: D) -> std::result::Result<i64, D::Error>
@@ -295,6 +330,13 @@ fn string_or_default(row: &Row<'_>, col: &str) -> Result<String> {
     Ok(row.get::<_, Option<String>>(col)?.unwrap_or_default())
 }
 
+fn metadata_from_row(row: &Row<'_>) -> Result<BTreeMap<String, String>> {
+    Ok(match row.get::<_, Option<String>>("metadata")? {
+        Some(json) => serde_json::from_str(&json)?,
+        None => BTreeMap::new(),
+    })
+}
+
 impl Login {
     #[inline]
     pub fn guid(&self) -> &Guid {
@@ -306,6 +348,14 @@ impl Login {
         self.guid.as_str()
     }
 
+    /// The `metadata` column value for this login.
+    pub(crate) fn metadata_json(&self) -> Result<Option<String>> {
+        if self.metadata.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::to_string(&self.metadata)?))
+    }
+
     /// Checks whether the Login is valid, without attempting to fix any fields.
     /// Returns an error if invalid data is found, even if it could have been fixed.
     pub fn check_valid(&self) -> Result<()> {
@@ -465,6 +515,14 @@ impl Login {
             });
         }
 
+        if self.metadata.contains_key("") {
+            get_fixed_or_throw!(InvalidLogin::IllegalFieldValue {
+                field_info: "`metadata` has an empty key".into()
+            })?
+            .metadata
+            .remove("");
+        }
+
         // Check we can parse the origin, then use the normalized version of it.
         if let Some(fixed) = Login::validate_and_fixup_origin(&self.hostname)? {
             get_fixed_or_throw!(InvalidLogin::IllegalFieldValue {
@@ -536,6 +594,8 @@ impl Login {
 
             time_password_changed: row.get("timePasswordChanged")?,
             times_used: row.get("timesUsed")?,
+
+            metadata: metadata_from_row(row)?,
         };
         // For now, we want to apply fixups but still return the record if
         // there is unfixably invalid data in the db.
@@ -660,6 +720,9 @@ pub(crate) struct SyncLoginData {
     pub mirror: Option<MirrorLogin>,
     // None means it's a deletion
     pub inbound: (Option<Login>, ServerTimestamp),
+    // False if the inbound record was uploaded by a client that doesn't know
+    // about `metadata`.
+    pub inbound_has_metadata: bool,
 }
 
 impl SyncLoginData {
@@ -680,6 +743,7 @@ impl SyncLoginData {
         ts: ServerTimestamp,
     ) -> std::result::Result<Self, serde_json::Error> {
         let guid = payload.id.clone();
+        let inbound_has_metadata = payload.data.contains_key("metadata");
         let login: Option<Login> = if payload.is_tombstone() {
             None
         } else {
@@ -693,6 +757,7 @@ impl SyncLoginData {
             local: None,
             mirror: None,
             inbound: (login, ts),
+            inbound_has_metadata,
         })
     }
 }
@@ -749,6 +814,9 @@ pub(crate) struct LoginDelta {
     pub password_field: Option<String>,
     pub username_field: Option<String>,
 
+    // Merged as a whole, like the fields above.
+    pub metadata: Option<BTreeMap<String, String>>,
+
     // Commutative field
     pub times_used: i64,
 }
@@ -785,6 +853,8 @@ impl LoginDelta {
         merge_field!(merged, b, b_is_newer, password_field);
         merge_field!(merged, b, b_is_newer, username_field);
 
+        merge_field!(merged, b, b_is_newer, metadata);
+
         // commutative fields
         merged.times_used += b.times_used;
 
@@ -814,6 +884,8 @@ impl Login {
         apply_field!(self, delta, password_field);
         apply_field!(self, delta, username_field);
 
+        apply_field!(self, delta, metadata);
+
         // Use Some("") to indicate that it should be changed to be None (hacky...)
         if let Some(realm) = delta.http_realm.take() {
             self.http_realm = if realm.is_empty() { None } else { Some(realm) };
@@ -852,6 +924,9 @@ impl Login {
         if self.username_field != older.username_field {
             delta.username_field = Some(self.username_field.clone());
         }
+        if self.metadata != older.metadata {
+            delta.metadata = Some(self.metadata.clone());
+        }
 
         // We discard zero (and negative numbers) for timestamps so that a
         // record that doesn't contain this information (these are
@@ -1390,4 +1465,32 @@ mod tests {
         assert!(login.check_valid().is_err());
         assert_eq!(login.fixup().unwrap().password_field, "");
     }
+
+    #[test]
+    fn test_metadata() {
+        let payload: sync15::Payload = serde_json::from_value(serde_json::json!({
+            "id": "123412341234",
+            "httpRealm": "test",
+            "hostname": "https://www.example.com",
+            "username": "test",
+            "password": "test",
+            "metadata": { "notes": "some notes", "": "invalid" },
+        }))
+        .unwrap();
+
+        let login: Login = payload.into_record().unwrap();
+        assert_eq!(login.metadata.len(), 2);
+        assert!(login.check_valid().is_err());
+        let fixed = login.fixup().unwrap();
+        assert_eq!(fixed.metadata.len(), 1);
+        assert_eq!(fixed.metadata[metadata_keys::NOTES], "some notes");
+
+        // Empty metadata isn't serialized at all.
+        let json = serde_json::to_value(Login {
+            metadata: BTreeMap::new(),
+            ..fixed
+        })
+        .unwrap();
+        assert!(json.get("metadata").is_none());
+    }
 }
@@ -0,0 +1,504 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Password Generation
+//! ===================
+//!
+//! Generates strong passwords using the secure random number generator from
+//! `rc_crypto`, so that every platform generates passwords the same way.
+//!
+//! Generation is driven by a [PasswordRules] struct, which describes the
+//! allowed and required character classes, the length, and the maximum number
+//! of identical consecutive characters. Sites can describe their own rules
+//! using the [`passwordrules` attribute](https://github.com/whatwg/html/issues/3518),
+//! which can be parsed with [PasswordRules::parse].
+//!
+//! When no characters are required or allowed (which is the case for the
+//! default rules), we generate a password containing at least one lowercase
+//! letter, uppercase letter and digit, which matches what Desktop does.
+
+use crate::error::*;
+use crate::login::Login;
+use crate::util;
+use serde_derive::*;
+use std::collections::BTreeSet;
+use std::time::SystemTime;
+
+/// The length used when the rules don't force a different one.
+pub const DEFAULT_PASSWORD_LENGTH: usize = 15;
+
+/// We give up after this many attempts at satisfying `max-consecutive`.
+const MAX_GENERATION_ATTEMPTS: usize = 100;
+
+const LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGIT: &str = "0123456789";
+// The spec includes the space character in `special`, but we never generate
+// it, since it's too easy to lose when copying the password around.
+const SPECIAL: &str = "-~!@#$%^&*_+=`|(){}[:;\"'<>,.?]/\\";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CharacterClass {
+    Upper,
+    Lower,
+    Digit,
+    Special,
+    AsciiPrintable,
+    /// Any unicode character. We only ever generate ascii-printable
+    /// characters for this class.
+    Unicode,
+    /// The characters listed inside `[...]` in a `passwordrules` attribute.
+    Custom(String),
+}
+
+impl CharacterClass {
+    fn add_chars_to(&self, set: &mut BTreeSet<char>) {
+        match self {
+            CharacterClass::Upper => set.extend(UPPER.chars()),
+            CharacterClass::Lower => set.extend(LOWER.chars()),
+            CharacterClass::Digit => set.extend(DIGIT.chars()),
+            CharacterClass::Special => set.extend(SPECIAL.chars()),
+            CharacterClass::AsciiPrintable | CharacterClass::Unicode => {
+                set.extend(LOWER.chars().chain(UPPER.chars()));
+                set.extend(DIGIT.chars().chain(SPECIAL.chars()));
+            }
+            // Control characters and spaces are never generated, even if a
+            // site asks for them.
+            CharacterClass::Custom(chars) => set.extend(
+                chars
+                    .chars()
+                    .filter(|c| c.is_ascii_graphic() && !c.is_whitespace()),
+            ),
+        }
+    }
+}
+
+fn chars_in(classes: &[CharacterClass]) -> Vec<char> {
+    let mut set = BTreeSet::new();
+    for class in classes {
+        class.add_chars_to(&mut set);
+    }
+    set.into_iter().collect()
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordRules {
+    /// Each entry is a set of classes, and the password must contain at
+    /// least one character from each of them.
+    #[serde(default)]
+    pub required: Vec<Vec<CharacterClass>>,
+
+    /// Classes the password may contain, in addition to the required ones.
+    #[serde(default)]
+    pub allowed: Vec<CharacterClass>,
+
+    #[serde(default)]
+    pub min_length: Option<usize>,
+
+    #[serde(default)]
+    pub max_length: Option<usize>,
+
+    /// The maximum number of times the same character may appear in a row.
+    #[serde(default)]
+    pub max_consecutive: Option<usize>,
+}
+
+impl PasswordRules {
+    /// Parses a `passwordrules` attribute value, such as
+    /// `"minlength: 8; required: lower; required: upper; allowed: digit, [-_]"`.
+    ///
+    /// Unknown rule names are ignored, as the spec requires, but malformed
+    /// values for the rules we know about are an error.
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut rules = PasswordRules::default();
+        for rule in split_rules(input) {
+            let rule = rule.trim();
+            if rule.is_empty() {
+                continue;
+            }
+            let colon = match rule.find(':') {
+                Some(i) => i,
+                None => throw!(invalid_rules(format!("Missing `:` in rule `{}`", rule))),
+            };
+            let name = rule[..colon].trim().to_ascii_lowercase();
+            let value = rule[colon + 1..].trim();
+            match name.as_str() {
+                "required" => {
+                    let classes = parse_classes(value)?;
+                    if !classes.is_empty() {
+                        rules.required.push(classes);
+                    }
+                }
+                "allowed" => rules.allowed.extend(parse_classes(value)?),
+                "minlength" => rules.min_length = Some(parse_number(&name, value)?),
+                "maxlength" => rules.max_length = Some(parse_number(&name, value)?),
+                "max-consecutive" => {
+                    let max = parse_number(&name, value)?;
+                    // Multiple rules narrow the limit, they don't replace it.
+                    rules.max_consecutive = Some(rules.max_consecutive.map_or(max, |m| m.min(max)));
+                }
+                _ => log::debug!("Ignoring unknown password rule `{}`", name),
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Picks the length of the generated password: the default, clamped to
+    /// whatever the rules demand.
+    fn password_length(&self) -> Result<usize> {
+        let min = self.min_length.unwrap_or(0).max(self.required.len()).max(1);
+        let max = self.max_length.unwrap_or(std::usize::MAX);
+        if min > max {
+            throw!(invalid_rules(format!(
+                "Required length {} is longer than the maximum length {}",
+                min, max
+            )));
+        }
+        Ok(DEFAULT_PASSWORD_LENGTH.max(min).min(max))
+    }
+}
+
+fn invalid_rules(reason: String) -> ErrorKind {
+    ErrorKind::InvalidPasswordRules(reason)
+}
+
+/// Splits `input` into rules at each `;` that isn't in a custom class, since
+/// custom classes may contain `;` and `,`.
+fn split_rules(input: &str) -> Vec<&str> {
+    let mut rules = vec![];
+    let mut start = 0;
+    // The index of the `[` that opened the custom class we're in, if any.
+    let mut class_start = None;
+    for (i, c) in input.char_indices() {
+        match (c, class_start) {
+            ('[', None) => class_start = Some(i),
+            // A `]` right after the `[` is a literal, not the end.
+            (']', Some(open)) if i > open + 1 => class_start = None,
+            (';', None) => {
+                rules.push(&input[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    rules.push(&input[start..]);
+    rules
+}
+
+fn parse_number(name: &str, value: &str) -> Result<usize> {
+    match value.parse() {
+        Ok(n) => Ok(n),
+        Err(_) => throw!(invalid_rules(format!(
+            "`{}` expects a number, got `{}`",
+            name, value
+        ))),
+    }
+}
+
+/// Parses the comma-separated list of classes in a `required` or `allowed`
+/// rule. Custom classes are written as `[...]`; per the spec, `-` may only
+/// appear at the end and `]` only at the start of a custom class.
+fn parse_classes(value: &str) -> Result<Vec<CharacterClass>> {
+    let mut classes = vec![];
+    let mut rest = value.trim();
+    while !rest.is_empty() {
+        if rest.starts_with('[') {
+            // A leading `]` is a literal, not the end of the class.
+            let body_start = if rest[1..].starts_with(']') { 2 } else { 1 };
+            let end = match rest[body_start..].find(']') {
+                Some(i) => body_start + i,
+                None => throw!(invalid_rules(format!("Unterminated class in `{}`", value))),
+            };
+            classes.push(CharacterClass::Custom(rest[1..end].to_string()));
+            rest = &rest[end + 1..];
+        } else {
+            let end = rest.find(',').unwrap_or_else(|| rest.len());
+            let name = rest[..end].trim().to_ascii_lowercase();
+            classes.push(match name.as_str() {
+                "upper" => CharacterClass::Upper,
+                "lower" => CharacterClass::Lower,
+                "digit" => CharacterClass::Digit,
+                "special" => CharacterClass::Special,
+                "ascii-printable" => CharacterClass::AsciiPrintable,
+                "unicode" => CharacterClass::Unicode,
+                _ => throw!(invalid_rules(format!("Unknown character class `{}`", name))),
+            });
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+        if rest.starts_with(',') {
+            rest = rest[1..].trim_start();
+        } else if !rest.is_empty() {
+            throw!(invalid_rules(format!("Expected `,` in `{}`", value)));
+        }
+    }
+    Ok(classes)
+}
+
+/// Returns a uniformly distributed random number in `0..n`.
+fn random_index(n: usize) -> Result<usize> {
+    assert!(n > 0 && n <= std::u32::MAX as usize);
+    let n = n as u32;
+    // Reject values from the final partial range to avoid modulo bias.
+    let limit = std::u32::MAX - (std::u32::MAX % n);
+    loop {
+        let mut buf = [0u8; 4];
+        rc_crypto::rand::fill(&mut buf)?;
+        let value = u32::from_le_bytes(buf);
+        if value < limit {
+            return Ok((value % n) as usize);
+        }
+    }
+}
+
+fn random_char(chars: &[char]) -> Result<char> {
+    Ok(chars[random_index(chars.len())?])
+}
+
+fn exceeds_max_consecutive(password: &[char], max: usize) -> bool {
+    let mut run = 0;
+    let mut prev = None;
+    for &c in password {
+        run = if prev == Some(c) { run + 1 } else { 1 };
+        if run > max {
+            return true;
+        }
+        prev = Some(c);
+    }
+    false
+}
+
+/// Generates a password satisfying `rules`.
+pub fn generate_password(rules: &PasswordRules) -> Result<String> {
+    let default_required;
+    let required = if rules.required.is_empty() && rules.allowed.is_empty() {
+        default_required = vec![
+            vec![CharacterClass::Lower],
+            vec![CharacterClass::Upper],
+            vec![CharacterClass::Digit],
+        ];
+        &default_required
+    } else {
+        &rules.required
+    };
+    let required_chars = required
+        .iter()
+        .map(|classes| chars_in(classes))
+        .collect::<Vec<_>>();
+    if required_chars.iter().any(Vec::is_empty) {
+        throw!(invalid_rules(
+            "A required class has no usable characters".into()
+        ));
+    }
+    let mut all_classes = rules.allowed.clone();
+    all_classes.extend(required.iter().flatten().cloned());
+    let allowed_chars = chars_in(&all_classes);
+
+    let length = rules.password_length()?;
+    if length < required_chars.len() {
+        throw!(invalid_rules(format!(
+            "{} classes are required, but the password can only be {} long",
+            required_chars.len(),
+            length
+        )));
+    }
+    if rules.max_consecutive == Some(0) {
+        throw!(invalid_rules("`max-consecutive` must be positive".into()));
+    }
+
+    for _ in 0..MAX_GENERATION_ATTEMPTS {
+        let mut password = Vec::with_capacity(length);
+        for chars in &required_chars {
+            password.push(random_char(chars)?);
+        }
+        while password.len() < length {
+            password.push(random_char(&allowed_chars)?);
+        }
+        // Fisher-Yates, so the required characters aren't always up front.
+        for i in (1..password.len()).rev() {
+            password.swap(i, random_index(i + 1)?);
+        }
+        match rules.max_consecutive {
+            Some(max) if exceeds_max_consecutive(&password, max) => continue,
+            _ => return Ok(password.into_iter().collect()),
+        }
+    }
+    throw!(invalid_rules(
+        "Couldn't generate a password that satisfies `max-consecutive`".into()
+    ))
+}
+
+/// Creates a login for a freshly generated password, which hasn't been saved
+/// yet. The application should hold on to it until the form is submitted, and
+/// then pass it to `add()` (possibly after filling in the username).
+pub fn generated_login(
+    hostname: &str,
+    form_submit_url: Option<&str>,
+    rules: &PasswordRules,
+) -> Result<Login> {
+    let now_ms = util::system_time_ms_i64(SystemTime::now());
+    let login = Login {
+        hostname: hostname.into(),
+        // Generated passwords are always for forms, so we default the
+        // form's target to the page's origin.
+        form_submit_url: Some(form_submit_url.unwrap_or(hostname).into()),
+        password: generate_password(rules)?,
+        time_created: now_ms,
+        time_password_changed: now_ms,
+        ..Login::default()
+    };
+    login.fixup()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_in(password: &str, chars: &str) -> usize {
+        password.chars().filter(|c| chars.contains(*c)).count()
+    }
+
+    #[test]
+    fn test_default_rules() {
+        let password = generate_password(&PasswordRules::default()).unwrap();
+        assert_eq!(password.chars().count(), DEFAULT_PASSWORD_LENGTH);
+        assert!(count_in(&password, LOWER) > 0);
+        assert!(count_in(&password, UPPER) > 0);
+        assert!(count_in(&password, DIGIT) > 0);
+        assert_eq!(count_in(&password, SPECIAL), 0);
+        // Astronomically unlikely to collide.
+        assert_ne!(
+            password,
+            generate_password(&PasswordRules::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let rules = PasswordRules::parse(
+            "minlength: 20; MaxLength: 30 ;required: lower; required: upper, digit; \
+             allowed: [-]; max-consecutive: 3; max-consecutive: 2; unknown-rule: 1;",
+        )
+        .unwrap();
+        assert_eq!(
+            rules,
+            PasswordRules {
+                required: vec![
+                    vec![CharacterClass::Lower],
+                    vec![CharacterClass::Upper, CharacterClass::Digit],
+                ],
+                allowed: vec![CharacterClass::Custom("-".into())],
+                min_length: Some(20),
+                max_length: Some(30),
+                max_consecutive: Some(2),
+            }
+        );
+        assert_eq!(PasswordRules::parse("").unwrap(), PasswordRules::default());
+    }
+
+    #[test]
+    fn test_parse_custom_classes() {
+        let rules = PasswordRules::parse("allowed: []abc-], special ,[.]").unwrap();
+        assert_eq!(
+            rules.allowed,
+            vec![
+                CharacterClass::Custom("]abc-".into()),
+                CharacterClass::Special,
+                CharacterClass::Custom(".".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_separators_in_custom_classes() {
+        let rules = PasswordRules::parse("required: [;,]; allowed: [],;], lower").unwrap();
+        assert_eq!(
+            rules.required,
+            vec![vec![CharacterClass::Custom(";,".into())]]
+        );
+        assert_eq!(
+            rules.allowed,
+            vec![CharacterClass::Custom("],;".into()), CharacterClass::Lower,]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for bad in &[
+            "required",
+            "required: bogus",
+            "allowed: [abc",
+            "allowed: upper lower",
+            "minlength: ten",
+        ] {
+            assert!(PasswordRules::parse(bad).is_err(), "{} should fail", bad);
+        }
+    }
+
+    #[test]
+    fn test_site_rules() {
+        let rules =
+            PasswordRules::parse("required: digit; required: [!#]; allowed: lower; maxlength: 8")
+                .unwrap();
+        for _ in 0..50 {
+            let password = generate_password(&rules).unwrap();
+            assert_eq!(password.len(), 8);
+            assert!(count_in(&password, DIGIT) > 0);
+            assert!(count_in(&password, "!#") > 0);
+            assert_eq!(
+                count_in(&password, DIGIT) + count_in(&password, "!#") + count_in(&password, LOWER),
+                8
+            );
+        }
+    }
+
+    #[test]
+    fn test_max_consecutive() {
+        let rules =
+            PasswordRules::parse("required: [abc]; maxlength: 12; max-consecutive: 2").unwrap();
+        for _ in 0..20 {
+            let password = generate_password(&rules).unwrap();
+            assert_eq!(password.len(), 12);
+            assert!(!password.contains("aaa"));
+            assert!(!password.contains("bbb"));
+            assert!(!password.contains("ccc"));
+        }
+    }
+
+    #[test]
+    fn test_impossible_rules() {
+        for bad in &[
+            "minlength: 10; maxlength: 5",
+            "required: lower; required: upper; maxlength: 1",
+            "required: [ ]",
+            "max-consecutive: 0",
+            "required: [a]; minlength: 3; max-consecutive: 1",
+        ] {
+            let rules = PasswordRules::parse(bad).unwrap();
+            assert!(generate_password(&rules).is_err(), "{} should fail", bad);
+        }
+    }
+
+    #[test]
+    fn test_generated_login() {
+        let login = generated_login(
+            "https://www.example.com/login",
+            Some("https://www.example.com"),
+            &PasswordRules::default(),
+        )
+        .unwrap();
+        assert_eq!(login.hostname, "https://www.example.com");
+        assert_eq!(
+            login.form_submit_url,
+            Some("https://www.example.com".to_string())
+        );
+        assert!(login.guid_str().is_empty());
+        assert!(login.username.is_empty());
+        assert_eq!(login.password.len(), DEFAULT_PASSWORD_LENGTH);
+        assert!(login.time_created > 0);
+        login.check_valid().unwrap();
+    }
+}
@@ -2,7 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-//! Logins Schema v4
+//! Logins Schema v5
 //! ================
 //!
 //! The schema we use is a evolution of the firefox-ios logins database format.
@@ -12,6 +12,9 @@
 //! - `loginsM`: The mirror table.
 //! - `loginsSyncMeta`: The table used to to store various sync metadata.
 //!
+//! Version 5 adds a `metadata` column to both tables, holding the login's
+//! `metadata` map as a JSON object (or NULL if it's empty).
+//!
 //! ## `loginsL`
 //!
 //! This stores local login information, also known as the "overlay".
@@ -78,7 +81,7 @@
 //! This table was added (by this rust crate) in version 4, and so is not
 //! present in firefox-ios.
 //!
-//! Currently it is used to store two items:
+//! Currently it is used to store three items:
 //!
 //! 1. The last sync timestamp is stored under [LAST_SYNC_META_KEY], a
 //!    `sync15::ServerTimestamp` stored in integer milliseconds.
@@ -87,15 +90,23 @@
 //!    [GLOBAL_STATE_META_KEY]. This is a `sync15::GlobalState` stored as
 //!    JSON.
 //!
+//! 3. For databases using field-level encryption (instead of SQLCipher), a
+//!    known value encrypted with the field key is stored under
+//!    [FIELD_ENCRYPTION_CANARY_META_KEY], so that we can detect an incorrect
+//!    key. The `username` and `password` columns of both tables (and the
+//!    values in the `metadata` column) then hold ciphertext, see the
+//!    `encryption` module for details.
+//!
 
 use crate::error::*;
 use lazy_static::lazy_static;
 use rusqlite::Connection;
 use sql_support::ConnExt;
 
-/// Note that firefox-ios is currently on version 3. Version 4 is this version,
-/// which adds a metadata table and changes timestamps to be in milliseconds
-pub const VERSION: i64 = 4;
+/// Note that firefox-ios is currently on version 3. Version 4 adds a metadata
+/// table and changes timestamps to be in milliseconds, and version 5 (this
+/// version) adds the `metadata` column.
+pub const VERSION: i64 = 5;
 
 /// Every column shared by both tables except for `id`
 ///
@@ -126,7 +137,8 @@ pub const COMMON_COLS: &str = "
     timeCreated,
     timeLastUsed,
     timePasswordChanged,
-    timesUsed
+    timesUsed,
+    metadata
 ";
 
 const COMMON_SQL: &str = "
@@ -143,7 +155,9 @@ const COMMON_SQL: &str = "
     timePasswordChanged INTEGER NOT NULL,
     username            TEXT,
     password            TEXT NOT NULL,
-    guid                TEXT NOT NULL UNIQUE
+    guid                TEXT NOT NULL UNIQUE,
+    -- A JSON object, or NULL if there's no metadata.
+    metadata            TEXT
 ";
 
 lazy_static! {
@@ -209,6 +223,7 @@ pub(crate) static LAST_SYNC_META_KEY: &str = "last_sync_time";
 pub(crate) static GLOBAL_STATE_META_KEY: &str = "global_state_v2";
 pub(crate) static GLOBAL_SYNCID_META_KEY: &str = "global_sync_id";
 pub(crate) static COLLECTION_SYNCID_META_KEY: &str = "passwords_sync_id";
+pub(crate) static FIELD_ENCRYPTION_CANARY_META_KEY: &str = "field_encryption_canary";
 
 pub(crate) fn init(db: &Connection) -> Result<()> {
     let user_version = db.query_one::<i64>("PRAGMA user_version")?;
@@ -277,9 +292,15 @@ fn upgrade(db: &Connection, from: i64) -> Result<()> {
             CREATE_META_TABLE_SQL,
             UPDATE_LOCAL_TIMESTAMPS_TO_MILLIS_SQL,
             UPDATE_MIRROR_TIMESTAMPS_TO_MILLIS_SQL,
-            &*SET_VERSION_SQL,
         ])?;
     }
+    if from < 5 {
+        db.execute_all(&[
+            "ALTER TABLE loginsL ADD COLUMN metadata TEXT",
+            "ALTER TABLE loginsM ADD COLUMN metadata TEXT",
+        ])?;
+    }
+    db.execute_all(&[&*SET_VERSION_SQL])?;
     Ok(())
 }
 
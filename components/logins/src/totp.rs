@@ -0,0 +1,246 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! TOTP (RFC 6238) codes, for logins that keep their two-factor secret in the
+//! `totp` metadata entry.
+//!
+//! The secret may be either an `otpauth://totp/...` URI, as encoded in the QR
+//! codes sites show when setting up two-factor authentication, or just the
+//! base32-encoded secret, in which case the usual defaults (HMAC-SHA1, six
+//! digits and a 30 second period) are used.
+
+use crate::error::*;
+use crate::login::{metadata_keys, Login};
+use rc_crypto::{digest, hmac};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+const DEFAULT_DIGITS: u32 = 6;
+const DEFAULT_PERIOD: u64 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TotpAlgorithm {
+    Sha1,
+    Sha256,
+}
+
+impl TotpAlgorithm {
+    fn digest_algorithm(self) -> &'static digest::Algorithm {
+        match self {
+            TotpAlgorithm::Sha1 => &digest::SHA1,
+            TotpAlgorithm::Sha256 => &digest::SHA256,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TotpParams {
+    pub secret: Vec<u8>,
+    pub algorithm: TotpAlgorithm,
+    pub digits: u32,
+    /// In seconds.
+    pub period: u64,
+}
+
+fn invalid(reason: &str) -> Error {
+    ErrorKind::InvalidTotpSecret(reason.into()).into()
+}
+
+impl TotpParams {
+    /// Parses an `otpauth://totp/...` URI, or a bare base32 secret.
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if !s.starts_with("otpauth:") {
+            return Ok(Self {
+                secret: decode_base32(s)?,
+                algorithm: TotpAlgorithm::Sha1,
+                digits: DEFAULT_DIGITS,
+                period: DEFAULT_PERIOD,
+            });
+        }
+        let url = Url::parse(s).map_err(|_| invalid("Malformed otpauth URI"))?;
+        if url.host_str() != Some("totp") {
+            return Err(invalid("Only TOTP is supported"));
+        }
+        let mut secret = None;
+        let mut params = Self {
+            secret: vec![],
+            algorithm: TotpAlgorithm::Sha1,
+            digits: DEFAULT_DIGITS,
+            period: DEFAULT_PERIOD,
+        };
+        for (key, value) in url.query_pairs() {
+            match &*key {
+                "secret" => secret = Some(decode_base32(&value)?),
+                "algorithm" => {
+                    params.algorithm = match value.to_ascii_uppercase().as_str() {
+                        "SHA1" => TotpAlgorithm::Sha1,
+                        "SHA256" => TotpAlgorithm::Sha256,
+                        _ => return Err(invalid("Unsupported algorithm")),
+                    }
+                }
+                "digits" => {
+                    params.digits = match value.parse() {
+                        Ok(digits @ 6..=8) => digits,
+                        _ => return Err(invalid("`digits` must be 6, 7 or 8")),
+                    }
+                }
+                "period" => {
+                    params.period = match value.parse() {
+                        Ok(period) if period > 0 => period,
+                        _ => return Err(invalid("`period` must be a positive integer")),
+                    }
+                }
+                // `issuer`, and anything else we don't need.
+                _ => {}
+            }
+        }
+        params.secret = secret.ok_or_else(|| invalid("Missing secret"))?;
+        Ok(params)
+    }
+
+    /// Returns the code for the given time, in seconds since the epoch.
+    pub fn code_at(&self, unix_time: u64) -> Result<String> {
+        let counter = unix_time / self.period;
+        let key = hmac::SigningKey::new(self.algorithm.digest_algorithm(), &self.secret);
+        let mac = hmac::sign(&key, &counter.to_be_bytes())?;
+        let mac = mac.as_ref();
+        // "Dynamic truncation", from RFC 4226 section 5.3.
+        let offset = (mac[mac.len() - 1] & 0xf) as usize;
+        let mut truncated = [0u8; 4];
+        truncated.copy_from_slice(&mac[offset..offset + 4]);
+        let binary = u32::from_be_bytes(truncated) & 0x7fff_ffff;
+        let code = binary % 10u32.pow(self.digits);
+        Ok(format!("{:0width$}", code, width = self.digits as usize))
+    }
+
+    /// Returns the current code.
+    pub fn code(&self) -> Result<String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.code_at(now.as_secs())
+    }
+}
+
+/// Returns the current TOTP code for the login, or `None` if it doesn't have
+/// a TOTP secret.
+pub fn totp_code(login: &Login) -> Result<Option<String>> {
+    login
+        .metadata
+        .get(metadata_keys::TOTP)
+        .map(|secret| TotpParams::parse(secret)?.code())
+        .transpose()
+}
+
+// RFC 4648 base32, ignoring case, whitespace and padding, since that's how
+// sites tend to present secrets for manual entry.
+fn decode_base32(s: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let mut buffer = 0u64;
+    let mut bits = 0;
+    for c in s.chars() {
+        let value = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u64 - 'A' as u64,
+            c @ '2'..='7' => c as u64 - '2' as u64 + 26,
+            ' ' | '-' | '=' => continue,
+            _ => return Err(invalid("Secret isn't valid base32")),
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    if out.is_empty() {
+        return Err(invalid("Secret is empty"));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The test vectors from RFC 6238, appendix B.
+    #[test]
+    fn test_rfc_vectors() {
+        let sha1 = TotpParams {
+            secret: b"12345678901234567890".to_vec(),
+            algorithm: TotpAlgorithm::Sha1,
+            digits: 8,
+            period: 30,
+        };
+        let sha256 = TotpParams {
+            secret: b"12345678901234567890123456789012".to_vec(),
+            algorithm: TotpAlgorithm::Sha256,
+            ..sha1.clone()
+        };
+        for &(time, sha1_code, sha256_code) in &[
+            (59, "94287082", "46119246"),
+            (1_111_111_109, "07081804", "68084774"),
+            (1_234_567_890, "89005924", "91819424"),
+            (2_000_000_000, "69279037", "90698825"),
+            (20_000_000_000, "65353130", "77737706"),
+        ] {
+            assert_eq!(sha1.code_at(time).unwrap(), sha1_code);
+            assert_eq!(sha256.code_at(time).unwrap(), sha256_code);
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let bare = TotpParams::parse("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap();
+        assert_eq!(bare.secret, b"12345678901234567890");
+        assert_eq!(bare.algorithm, TotpAlgorithm::Sha1);
+        assert_eq!(bare.digits, 6);
+        assert_eq!(bare.period, 30);
+        assert_eq!(bare.code_at(59).unwrap(), "287082");
+
+        let uri = TotpParams::parse(
+            "otpauth://totp/Example:alice@example.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+             &issuer=Example&algorithm=SHA256&digits=8&period=60",
+        )
+        .unwrap();
+        assert_eq!(uri.secret, b"12345678901234567890");
+        assert_eq!(uri.algorithm, TotpAlgorithm::Sha256);
+        assert_eq!(uri.digits, 8);
+        assert_eq!(uri.period, 60);
+
+        for bad in &[
+            "",
+            "not base32!",
+            "otpauth://hotp/Example?secret=GEZDGNBV&counter=1",
+            "otpauth://totp/Example?issuer=Example",
+            "otpauth://totp/Example?secret=GEZDGNBV&algorithm=SHA512",
+            "otpauth://totp/Example?secret=GEZDGNBV&digits=4",
+            "otpauth://totp/Example?secret=GEZDGNBV&period=0",
+        ] {
+            assert!(
+                TotpParams::parse(bad).is_err(),
+                "{:?} should be invalid",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_totp_code() {
+        let mut login = Login::default();
+        assert_eq!(totp_code(&login).unwrap(), None);
+        login.metadata.insert(
+            metadata_keys::TOTP.into(),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ".into(),
+        );
+        let code = totp_code(&login).unwrap().unwrap();
+        assert_eq!(code.len(), 6);
+        assert!(code.chars().all(|c| c.is_ascii_digit()));
+        login
+            .metadata
+            .insert(metadata_keys::TOTP.into(), "nope!".into());
+        assert!(totp_code(&login).is_err());
+    }
+}
@@ -2,6 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use crate::encryption::FieldEncryptor;
 use crate::error::*;
 use crate::login::{LocalLogin, Login, MirrorLogin, SyncStatus};
 use crate::util;
@@ -73,6 +74,19 @@ impl UpdatePlan {
             .push((login, time.as_millis() as i64, is_override));
     }
 
+    pub fn encrypt_logins(&mut self, encryptor: &FieldEncryptor) -> Result<()> {
+        for l in &mut self.local_updates {
+            encryptor.encrypt_login(&mut l.login)?;
+        }
+        for (login, _, _) in &mut self.mirror_inserts {
+            encryptor.encrypt_login(login)?;
+        }
+        for (login, _) in &mut self.mirror_updates {
+            encryptor.encrypt_login(login)?;
+        }
+        Ok(())
+    }
+
     fn perform_deletes(&self, conn: &Connection, scope: &SqlInterruptScope) -> Result<()> {
         sql_support::each_chunk(&self.delete_local, |chunk, _| -> Result<()> {
             conn.execute(
@@ -110,6 +124,7 @@ impl UpdatePlan {
                 password        = :password,
                 hostname        = :hostname,
                 username        = :username,
+                metadata        = :metadata,
                 -- Avoid zeroes if the remote has been overwritten by an older client.
                 timesUsed           = coalesce(nullif(:times_used,            0), timesUsed),
                 timeLastUsed        = coalesce(nullif(:time_last_used,        0), timeLastUsed),
@@ -129,6 +144,7 @@ impl UpdatePlan {
                 ":password": login.password,
                 ":hostname": login.hostname,
                 ":username": login.username,
+                ":metadata": login.metadata_json()?,
                 ":times_used": login.times_used,
                 ":time_last_used": login.time_last_used,
                 ":time_password_changed": login.time_password_changed,
@@ -153,6 +169,7 @@ impl UpdatePlan {
                 password,
                 hostname,
                 username,
+                metadata,
 
                 timesUsed,
                 timeLastUsed,
@@ -171,6 +188,7 @@ impl UpdatePlan {
                 :password,
                 :hostname,
                 :username,
+                :metadata,
 
                 :times_used,
                 :time_last_used,
@@ -193,6 +211,7 @@ impl UpdatePlan {
                 ":password": login.password,
                 ":hostname": login.hostname,
                 ":username": login.username,
+                ":metadata": login.metadata_json()?,
                 ":times_used": login.times_used,
                 ":time_last_used": login.time_last_used,
                 ":time_password_changed": login.time_password_changed,
@@ -218,6 +237,7 @@ impl UpdatePlan {
                  password            = :password,
                  hostname            = :hostname,
                  username            = :username,
+                 metadata            = :metadata,
                  sync_status         = {changed}
              WHERE guid = :guid",
             changed = SyncStatus::Changed as u8
@@ -236,6 +256,7 @@ impl UpdatePlan {
                 ":password": l.login.password,
                 ":hostname": l.login.hostname,
                 ":username": l.login.username,
+                ":metadata": l.login.metadata_json()?,
                 ":time_last_used": l.login.time_last_used,
                 ":time_password_changed": l.login.time_password_changed,
                 ":times_used": l.login.times_used,
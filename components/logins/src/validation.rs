@@ -0,0 +1,348 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Validation of the logins collection.
+//!
+//! A validating sync downloads the entire collection (rather than just the
+//! records changed since the last sync) and compares it with our local and
+//! mirror records. The problems found are reported in the engine's telemetry,
+//! and can optionally be repaired in the same sync, by re-uploading our copy
+//! of the affected records or re-applying the server's copy.
+
+use crate::db::LoginDb;
+use crate::error::*;
+use crate::login::{LocalLogin, Login, MirrorLogin};
+use rusqlite::NO_PARAMS;
+use sql_support::SqlInterruptScope;
+use std::collections::{HashMap, HashSet};
+use sync15::{telemetry, Payload, ServerTimestamp};
+use sync_guid::Guid;
+
+/// Bump this when the set of problems we report changes.
+const VALIDATION_VERSION: u32 = 2;
+
+/// Whether a sync should also validate the collection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationMode {
+    /// A normal sync.
+    Skip,
+    /// Validate, and report the problems in telemetry.
+    Validate,
+    /// Validate, then fix what we can by re-uploading or re-downloading the
+    /// affected records.
+    Repair,
+}
+
+impl Default for ValidationMode {
+    fn default() -> Self {
+        ValidationMode::Skip
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ValidationReport {
+    /// On the server, but we have neither a mirror nor a local record.
+    pub missing_locally: Vec<Guid>,
+    /// In our mirror, but not on the server.
+    pub missing_on_server: Vec<Guid>,
+    /// In our mirror, but deleted on the server.
+    pub server_deleted: Vec<Guid>,
+    /// The server's record doesn't match our mirror.
+    pub differences: Vec<Guid>,
+    /// The server's record can't be parsed, or `check_valid` rejects it.
+    pub server_invalid: Vec<Guid>,
+    /// Our local record is rejected by `check_valid`.
+    pub local_invalid: Vec<Guid>,
+    /// Records we should upload to repair the problems above.
+    pub to_upload: Vec<Guid>,
+    /// Records we should re-apply from the server to repair the problems above.
+    pub to_download: Vec<Guid>,
+}
+
+impl ValidationReport {
+    pub fn to_telemetry(&self) -> telemetry::Validation {
+        let mut validation = telemetry::Validation::with_version(VALIDATION_VERSION);
+        validation
+            .problem("missingLocally", self.missing_locally.len())
+            .problem("missingOnServer", self.missing_on_server.len())
+            .problem("serverDeleted", self.server_deleted.len())
+            .problem("differences", self.differences.len())
+            .problem("serverInvalid", self.server_invalid.len())
+            .problem("localInvalid", self.local_invalid.len());
+        validation
+    }
+}
+
+// The fields that should round-trip through the server exactly, including
+// the decrypted `metadata` map. The timestamps and use counts aren't
+// compared, since we deliberately avoid clobbering them with zeroes from
+// older clients.
+fn same_contents(a: &Login, b: &Login) -> bool {
+    a.hostname == b.hostname
+        && a.http_realm == b.http_realm
+        && a.form_submit_url == b.form_submit_url
+        && a.username_field == b.username_field
+        && a.password_field == b.password_field
+        && a.username == b.username
+        && a.password == b.password
+        && a.metadata == b.metadata
+}
+
+fn is_valid(login: &Login) -> bool {
+    login.check_valid().is_ok()
+}
+
+pub(crate) fn validate(
+    db: &LoginDb,
+    server_records: &[(Payload, ServerTimestamp)],
+    scope: &SqlInterruptScope,
+) -> Result<ValidationReport> {
+    let mut report = ValidationReport::default();
+
+    let mut mirror: HashMap<Guid, MirrorLogin> = HashMap::new();
+    {
+        let mut stmt = db.prepare("SELECT * FROM loginsM")?;
+        let rows = stmt.query_and_then(NO_PARAMS, MirrorLogin::from_row)?;
+        for row in rows {
+            let mut record = row?;
+            record.login = db.decrypt_login(record.login)?;
+            mirror.insert(record.login.guid.clone(), record);
+        }
+    }
+    let mut local: HashMap<Guid, LocalLogin> = HashMap::new();
+    {
+        let mut stmt = db.prepare("SELECT * FROM loginsL")?;
+        let rows = stmt.query_and_then(NO_PARAMS, LocalLogin::from_row)?;
+        for row in rows {
+            let mut record = row?;
+            record.login = db.decrypt_login(record.login)?;
+            local.insert(record.login.guid.clone(), record);
+        }
+    }
+    scope.err_if_interrupted()?;
+
+    // `None` for tombstones.
+    let mut server: HashMap<Guid, Option<Login>> = HashMap::new();
+    let mut server_invalid: HashSet<Guid> = HashSet::new();
+    for (payload, _) in server_records {
+        let guid = payload.id.clone();
+        if payload.is_tombstone() {
+            server.insert(guid, None);
+            continue;
+        }
+        let mut login = match payload.clone().into_record::<Login>() {
+            Ok(login) if is_valid(&login) => login,
+            _ => {
+                // Our copy is only worth uploading if it's valid.
+                let ours = local
+                    .get(&guid)
+                    .filter(|l| !l.is_deleted)
+                    .map(|l| &l.login)
+                    .or_else(|| mirror.get(&guid).map(|m| &m.login));
+                if ours.map_or(false, is_valid) {
+                    report.to_upload.push(guid.clone());
+                }
+                server_invalid.insert(guid.clone());
+                report.server_invalid.push(guid);
+                continue;
+            }
+        };
+        match mirror.get(&guid) {
+            Some(m) => {
+                // Older clients drop `metadata`, and syncing keeps ours when
+                // they do, so that's not a difference.
+                if !payload.data.contains_key("metadata") {
+                    login.metadata = m.login.metadata.clone();
+                }
+                if !same_contents(&m.login, &login) {
+                    report.differences.push(guid.clone());
+                    report.to_download.push(guid.clone());
+                }
+            }
+            None => {
+                if !local.contains_key(&guid) {
+                    report.missing_locally.push(guid.clone());
+                    report.to_download.push(guid.clone());
+                }
+            }
+        }
+        server.insert(guid, Some(login));
+    }
+    scope.err_if_interrupted()?;
+
+    for (guid, m) in &mirror {
+        // Local deletions haven't been uploaded yet, so that's expected.
+        if local.get(guid).map_or(false, |l| l.is_deleted) {
+            continue;
+        }
+        match server.get(guid) {
+            Some(Some(_)) => {}
+            // Another client deleted it, so we should apply the tombstone,
+            // rather than upload the login again.
+            Some(None) => {
+                report.server_deleted.push(guid.clone());
+                report.to_download.push(guid.clone());
+            }
+            // Server records that are invalid are dealt with above.
+            None if server_invalid.contains(guid) => {}
+            None => {
+                report.missing_on_server.push(guid.clone());
+                if is_valid(&m.login) {
+                    report.to_upload.push(guid.clone());
+                }
+            }
+        }
+    }
+
+    for (guid, l) in &local {
+        if !l.is_deleted && !is_valid(&l.login) {
+            report.local_invalid.push(guid.clone());
+        }
+    }
+
+    log::info!(
+        "Validated {} server records against {} mirror and {} local records",
+        server_records.len(),
+        mirror.len(),
+        local.len()
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn login(id: &str, password: &str) -> Login {
+        Login {
+            guid: id.into(),
+            hostname: "https://www.example.com".into(),
+            http_realm: Some("https://www.example.com".into()),
+            username: "user".into(),
+            password: password.into(),
+            ..Login::default()
+        }
+    }
+
+    fn insert_mirror(db: &LoginDb, login: &Login) {
+        db.execute_named(
+            "INSERT INTO loginsM (
+                guid, hostname, httpRealm, username, password,
+                timeCreated, timePasswordChanged, timesUsed,
+                is_overridden, server_modified
+             ) VALUES (
+                :guid, :hostname, :http_realm, :username, :password,
+                1, 1, 1, 0, 1000
+             )",
+            rusqlite::named_params! {
+                ":guid": login.guid,
+                ":hostname": login.hostname,
+                ":http_realm": login.http_realm,
+                ":username": login.username,
+                ":password": login.password,
+            },
+        )
+        .unwrap();
+    }
+
+    fn payload(login: &Login) -> (Payload, ServerTimestamp) {
+        (
+            Payload::from_record(login.clone()).unwrap(),
+            ServerTimestamp(1000),
+        )
+    }
+
+    #[test]
+    fn test_validate() {
+        let db = LoginDb::open_in_memory(Some("testing")).unwrap();
+        let same = login("same00000000", "password");
+        let different = login("different000", "password");
+        let only_mirror = login("onlymirror00", "password");
+        let only_server = login("onlyserver00", "password");
+        let invalid = login("invalid00000", "");
+        for l in &[&same, &different, &only_mirror, &invalid] {
+            insert_mirror(&db, l);
+        }
+        db.add(Login {
+            username: "someone else".into(),
+            ..login("newlocal0000", "password")
+        })
+        .unwrap();
+
+        let server = vec![
+            payload(&same),
+            payload(&login("different000", "changed")),
+            payload(&only_server),
+            payload(&invalid),
+        ];
+        let report = validate(&db, &server, &db.begin_interrupt_scope()).unwrap();
+        assert_eq!(report.missing_locally, vec![only_server.guid.clone()]);
+        assert_eq!(report.missing_on_server, vec![only_mirror.guid.clone()]);
+        assert_eq!(report.differences, vec![different.guid.clone()]);
+        assert_eq!(report.server_invalid, vec![invalid.guid]);
+        assert!(report.local_invalid.is_empty());
+        // Our copy of the invalid record is just as invalid, so there's
+        // nothing to upload for that.
+        assert_eq!(report.to_upload, vec![only_mirror.guid]);
+        let mut to_download = report.to_download.clone();
+        to_download.sort();
+        assert_eq!(to_download, vec![different.guid, only_server.guid]);
+
+        let telem = serde_json::to_value(report.to_telemetry()).unwrap();
+        assert_eq!(telem["version"], 2);
+        assert_eq!(telem["problems"].as_array().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_validate_server_tombstone() {
+        let db = LoginDb::open_in_memory(Some("testing")).unwrap();
+        let deleted = login("deleted00000", "password");
+        insert_mirror(&db, &deleted);
+
+        let server = vec![(
+            Payload::new_tombstone(deleted.guid.clone()),
+            ServerTimestamp(2000),
+        )];
+        let report = validate(&db, &server, &db.begin_interrupt_scope()).unwrap();
+        assert!(report.missing_on_server.is_empty());
+        assert_eq!(report.server_deleted, vec![deleted.guid.clone()]);
+        // We apply the tombstone, instead of uploading the login again.
+        assert!(report.to_upload.is_empty());
+        assert_eq!(report.to_download, vec![deleted.guid]);
+    }
+
+    #[test]
+    fn test_validate_metadata() {
+        let db = LoginDb::open_in_memory(Some("testing")).unwrap();
+        let mut ours = login("metadata0000", "password");
+        ours.metadata.insert("notes".into(), "ours".into());
+        insert_mirror(&db, &ours);
+        db.execute_named(
+            "UPDATE loginsM SET metadata = :metadata WHERE guid = :guid",
+            rusqlite::named_params! {
+                ":guid": ours.guid,
+                ":metadata": ours.metadata_json().unwrap(),
+            },
+        )
+        .unwrap();
+
+        let report = validate(&db, &[payload(&ours)], &db.begin_interrupt_scope()).unwrap();
+        assert!(report.differences.is_empty());
+
+        let mut theirs = ours.clone();
+        theirs.metadata.insert("notes".into(), "theirs".into());
+        let report = validate(&db, &[payload(&theirs)], &db.begin_interrupt_scope()).unwrap();
+        assert_eq!(report.differences, vec![ours.guid.clone()]);
+        assert_eq!(report.to_download, vec![ours.guid.clone()]);
+
+        // A record from an older client, without metadata, keeps ours.
+        let older = Login {
+            metadata: BTreeMap::new(),
+            ..ours
+        };
+        let report = validate(&db, &[payload(&older)], &db.begin_interrupt_scope()).unwrap();
+        assert!(report.differences.is_empty());
+    }
+}
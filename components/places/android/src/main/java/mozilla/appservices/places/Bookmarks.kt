@@ -291,6 +291,13 @@ interface WritableBookmarksConnection : ReadableBookmarksConnection {
      */
     fun resetBookmarkSyncMetadata()
 
+    /**
+     * Asks the next bookmark sync to repair the problems it finds in the
+     * server's bookmark tree. It fixes what it can, and asks other devices
+     * to upload the bookmarks that it doesn't have.
+     */
+    fun requestBookmarkRepair()
+
     /**
      * Create a bookmark folder, returning its guid.
      *
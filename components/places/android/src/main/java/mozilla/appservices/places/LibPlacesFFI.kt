@@ -71,6 +71,16 @@ internal interface LibPlacesFFI : Library {
         out_err: RustError.ByReference
     ): RustBuffer.ByValue
 
+    /** Like `places_query_autocomplete`, but also matches the `SearchableRemoteTabs` passed in. */
+    fun places_query_autocomplete_with_remote_tabs(
+        handle: PlacesConnectionHandle,
+        search: String,
+        limit: Int,
+        remote_tabs_data: Pointer,
+        remote_tabs_len: Int,
+        out_err: RustError.ByReference
+    ): RustBuffer.ByValue
+
     /** Returns a URL, or null if no match was found. */
     fun places_match_url(
         handle: PlacesConnectionHandle,
@@ -189,6 +199,13 @@ internal interface LibPlacesFFI : Library {
         error: RustError.ByReference
     )
 
+    fun places_set_history_sync_policy(
+        handle: PlacesConnectionHandle,
+        policy_data: Pointer,
+        policy_len: Int,
+        error: RustError.ByReference
+    )
+
     // Returns a JSON string containing a sync ping.
     fun sync15_history_sync(
         handle: PlacesApiHandle,
@@ -279,6 +296,11 @@ internal interface LibPlacesFFI : Library {
         error: RustError.ByReference
     )
 
+    fun bookmarks_request_repair(
+        handle: PlacesConnectionHandle,
+        error: RustError.ByReference
+    )
+
     /** Destroy strings returned from libplaces_ffi calls. */
     fun places_destroy_string(s: Pointer)
 
@@ -263,6 +263,28 @@ open class PlacesReaderConnection internal constructor(connHandle: Long) :
         }
     }
 
+    override fun queryAutocomplete(
+        query: String,
+        limit: Int,
+        remoteTabs: List<SearchableRemoteTab>
+    ): List<SearchResult> {
+        val tabs = MsgTypes.SearchableRemoteTabs.newBuilder()
+                .addAllTabs(remoteTabs.map { it.toProtobuf() })
+                .build()
+        val (nioBuf, len) = tabs.toNioDirectBuffer()
+        val resultBuffer = rustCall { error ->
+            val ptr = Native.getDirectBufferPointer(nioBuf)
+            LibPlacesFFI.INSTANCE.places_query_autocomplete_with_remote_tabs(
+                    this.handle.get(), query, limit, ptr, len, error)
+        }
+        try {
+            val results = MsgTypes.SearchResultList.parseFrom(resultBuffer.asCodedInputStream()!!)
+            return SearchResult.fromCollectionMessage(results)
+        } finally {
+            LibPlacesFFI.INSTANCE.places_destroy_bytebuffer(resultBuffer)
+        }
+    }
+
     override fun matchUrl(query: String): String? {
         return rustCallForOptString { error ->
             LibPlacesFFI.INSTANCE.places_match_url(this.handle.get(), query, error)
@@ -573,6 +595,14 @@ class PlacesWriterConnection internal constructor(connHandle: Long, api: PlacesA
         }
     }
 
+    override fun setHistorySyncPolicy(policy: HistorySyncPolicy) {
+        val (nioBuf, len) = policy.toProtobuf().toNioDirectBuffer()
+        rustCall { error ->
+            val ptr = Native.getDirectBufferPointer(nioBuf)
+            LibPlacesFFI.INSTANCE.places_set_history_sync_policy(this.handle.get(), ptr, len, error)
+        }
+    }
+
     override fun deleteAllBookmarks() {
         return writeQueryCounters.measure {
             rustCall { error ->
@@ -589,6 +619,12 @@ class PlacesWriterConnection internal constructor(connHandle: Long, api: PlacesA
         }
     }
 
+    override fun requestBookmarkRepair() {
+        rustCall { error ->
+            LibPlacesFFI.INSTANCE.bookmarks_request_repair(this.handle.get(), error)
+        }
+    }
+
     override fun deleteBookmarkNode(guid: String): Boolean {
         return writeQueryCounters.measure {
             rustCall { error ->
@@ -788,6 +824,19 @@ interface ReadableHistoryConnection : InterruptibleConnection {
      */
     fun queryAutocomplete(query: String, limit: Int): List<SearchResult>
 
+    /**
+     * Like [queryAutocomplete], but also matches tabs open on other devices.
+     * Results for those tabs have the [SearchResultReason.REMOTE_TAB] reason,
+     * and the names of the devices in [SearchResult.deviceNames].
+     *
+     * @param query a string to match results against.
+     * @param limit a maximum number of results to retrieve. At most half of
+     *        these are remote tabs.
+     * @param remoteTabs the tabs open on other devices, from the tabs component.
+     * @return a list of [SearchResult] matching the [query], in arbitrary order.
+     */
+    fun queryAutocomplete(query: String, limit: Int, remoteTabs: List<SearchableRemoteTab>): List<SearchResult>
+
     /**
      * See if a url that's sufficiently close to `search` exists in
      * the database.
@@ -945,6 +994,12 @@ interface WritableHistoryConnection : ReadableHistoryConnection {
      */
     fun resetHistorySyncMetadata()
 
+    /**
+     * Sets how much history future syncs download and upload. The policy
+     * is kept when history sync metadata is reset.
+     */
+    fun setHistorySyncPolicy(policy: HistorySyncPolicy)
+
     /**
      * Deletes all information about the given URL. If the place has previously
      * been synced, a tombstone will be written to the sync server, meaning
@@ -1105,7 +1160,8 @@ enum class SearchResultReason {
     URL,
     PREVIOUS_USE,
     BOOKMARK,
-    TAG;
+    TAG,
+    REMOTE_TAB;
 
     companion object {
         fun fromMessage(reason: MsgTypes.SearchResultReason): SearchResultReason {
@@ -1116,6 +1172,7 @@ enum class SearchResultReason {
                 MsgTypes.SearchResultReason.PREVIOUS_USE -> PREVIOUS_USE
                 MsgTypes.SearchResultReason.BOOKMARK -> BOOKMARK
                 MsgTypes.SearchResultReason.TAG -> TAG
+                MsgTypes.SearchResultReason.REMOTE_TAB -> REMOTE_TAB
             }
         }
     }
@@ -1125,7 +1182,9 @@ data class SearchResult(
     val url: String,
     val title: String,
     val frecency: Long,
-    val reasons: List<SearchResultReason>
+    val reasons: List<SearchResultReason>,
+    // The devices that have the page open, for `REMOTE_TAB` results.
+    val deviceNames: List<String> = listOf()
 ) {
     companion object {
         internal fun fromMessage(msg: MsgTypes.SearchResultMessage): SearchResult {
@@ -1135,7 +1194,8 @@ data class SearchResult(
                 frecency = msg.frecency,
                 reasons = msg.reasonsList.map {
                     SearchResultReason.fromMessage(it)
-                }
+                },
+                deviceNames = msg.deviceNamesList
             )
         }
         internal fun fromCollectionMessage(msg: MsgTypes.SearchResultList): List<SearchResult> {
@@ -1146,6 +1206,60 @@ data class SearchResult(
     }
 }
 
+/**
+ * A tab open on another device, for [ReadableHistoryConnection.queryAutocomplete]
+ * to match.
+ */
+data class SearchableRemoteTab(
+    val deviceName: String,
+    val title: String,
+    val url: String,
+    val iconUrl: String?,
+    // When the tab was last used, in milliseconds since the epoch.
+    val lastUsed: Long
+) {
+    internal fun toProtobuf(): MsgTypes.SearchableRemoteTab {
+        val builder = MsgTypes.SearchableRemoteTab.newBuilder()
+                .setDeviceName(deviceName)
+                .setTitle(title)
+                .setUrl(url)
+                .setLastUsed(lastUsed)
+        iconUrl?.let { builder.setIconUrl(it) }
+        return builder.build()
+    }
+}
+
+/**
+ * How much history to sync, for [WritableHistoryConnection.setHistorySyncPolicy].
+ * The defaults download at most 5000 records in each sync, and upload the 5000
+ * most recently changed pages with their 20 most recent visits.
+ */
+data class HistorySyncPolicy(
+    // If set, the first sync only downloads records modified on the server in the last this
+    // many days. That's when a client last uploaded the record, not when it was visited.
+    val firstSyncModifiedDays: Int? = null,
+    // The most records to download in one sync, or 0 for no limit. The next sync downloads
+    // the rest. If null, the limit is 5000.
+    val maxIncomingRecords: Long? = null,
+    // Whether later syncs download the older history the first sync skipped.
+    val backfill: Boolean = true,
+    // The most visits to upload, and keep from incoming records, for each page.
+    // 0 is treated as 1.
+    val maxVisits: Int = 20,
+    // The most pages to upload in one sync. 0 is treated as 1.
+    val maxOutgoingRecords: Int = 5000
+) {
+    internal fun toProtobuf(): MsgTypes.HistorySyncPolicy {
+        val builder = MsgTypes.HistorySyncPolicy.newBuilder()
+                .setBackfill(backfill)
+                .setMaxVisits(maxVisits)
+                .setMaxOutgoingRecords(maxOutgoingRecords)
+        firstSyncModifiedDays?.let { builder.setFirstSyncModifiedDays(it) }
+        maxIncomingRecords?.let { builder.setMaxIncomingRecords(it) }
+        return builder.build()
+    }
+}
+
 /**
  * Information about a history visit. Returned by `PlacesAPI.getVisitInfos`.
  */
@@ -22,7 +22,9 @@ use std::os::raw::c_char;
 use std::sync::Arc;
 use sync_guid::Guid as SyncGuid;
 
-use places::api::matcher::{self, match_url, search_frecent, SearchParams};
+use places::api::matcher::{
+    self, match_url, search_frecent, search_frecent_with_remote_tabs, SearchParams,
+};
 
 // indirection to help `?` figure out the target error type
 fn parse_url(url: &str) -> places::Result<url::Url> {
@@ -209,6 +211,41 @@ pub extern "C" fn places_query_autocomplete(
     })
 }
 
+/// Like `places_query_autocomplete`, but also matches the tabs open on other
+/// devices, passed as a `SearchableRemoteTabs` protobuf message.
+///
+/// # Safety
+/// Deref pointer, thus unsafe
+#[no_mangle]
+pub unsafe extern "C" fn places_query_autocomplete_with_remote_tabs(
+    handle: u64,
+    search: FfiStr<'_>,
+    limit: u32,
+    remote_tabs_data: *const u8,
+    remote_tabs_len: i32,
+    error: &mut ExternError,
+) -> ByteBuffer {
+    log::debug!("places_query_autocomplete_with_remote_tabs");
+    use places::msg_types::SearchableRemoteTabs;
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        let buffer = get_buffer(remote_tabs_data, remote_tabs_len);
+        let remote_tabs: SearchableRemoteTabs = prost::Message::decode(buffer)?;
+        let remote_tabs: Vec<matcher::SearchableRemoteTab> = remote_tabs.into();
+        let results = search_frecent_with_remote_tabs(
+            conn,
+            SearchParams {
+                search_string: search.into_string(),
+                limit,
+            },
+            &remote_tabs,
+        )?
+        .into_iter()
+        .map(|r| r.into())
+        .collect();
+        Ok(SearchResultList { results })
+    })
+}
+
 /// Execute a query, returning a URL string or null. Returned string must be freed
 /// using `places_destroy_string`. Returns null if no match is found.
 #[no_mangle]
@@ -495,6 +532,38 @@ pub extern "C" fn bookmarks_reset(handle: u64, error: &mut ExternError) {
     })
 }
 
+/// Asks the next bookmark sync to repair the problems it finds in the
+/// server's tree.
+#[no_mangle]
+pub extern "C" fn bookmarks_request_repair(handle: u64, error: &mut ExternError) {
+    log::debug!("bookmarks_request_repair");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        places::bookmark_sync::repair::request_repair(conn)?;
+        Ok(())
+    })
+}
+
+/// Sets how much history future history syncs download and upload, from a
+/// `HistorySyncPolicy` message.
+///
+/// # Safety
+/// Deref pointer, thus unsafe
+#[no_mangle]
+pub unsafe extern "C" fn places_set_history_sync_policy(
+    handle: u64,
+    policy_data: *const u8,
+    policy_len: i32,
+    error: &mut ExternError,
+) {
+    log::debug!("places_set_history_sync_policy");
+    CONNECTIONS.call_with_result(error, handle, |conn| -> places::Result<_> {
+        let buffer = get_buffer(policy_data, policy_len);
+        let policy: places::msg_types::HistorySyncPolicy = prost::Message::decode(buffer)?;
+        places::history_sync::set_sync_policy(conn, &policy.into())?;
+        Ok(())
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn sync15_history_sync(
     handle: u64,
@@ -567,6 +567,86 @@ public class PlacesWriteConnection: PlacesReadConnection {
         }
     }
 
+    /**
+     * Sets how much history future syncs download and upload. The policy is
+     * kept when history sync metadata is reset.
+     *
+     * - Parameter firstSyncModifiedDays: If set, the first sync only downloads
+     *                                    records modified on the server in the
+     *                                    last this many days. That's when a
+     *                                    client last uploaded the record, not
+     *                                    when it was visited.
+     * - Parameter maxIncomingRecords: The most records to download in one sync,
+     *                                 or 0 for no limit. The next sync downloads
+     *                                 the rest. If nil, the limit is 5000.
+     * - Parameter backfill: Whether later syncs download the older history that
+     *                       the first sync skipped.
+     * - Parameter maxVisits: The most visits to upload, and keep from incoming
+     *                        records, for each page. 0 is treated as 1.
+     * - Parameter maxOutgoingRecords: The most pages to upload in one sync. 0 is
+     *                                 treated as 1.
+     *
+     * - Throws:
+     *     - `PlacesError.connUseAfterAPIClosed`: if the PlacesAPI that returned this connection
+     *                                            object has been closed. This indicates API
+     *                                            misuse.
+     *     - `PlacesError.unexpected`: When an error that has not specifically been exposed
+     *                                 to Swift is encountered (for example IO errors from
+     *                                 the database code, etc).
+     *     - `PlacesError.panic`: If the rust code panics while completing this
+     *                            operation. (If this occurs, please let us know).
+     */
+    open func setHistorySyncPolicy(firstSyncModifiedDays: UInt32? = nil,
+                                   maxIncomingRecords: UInt64? = nil,
+                                   backfill: Bool = true,
+                                   maxVisits: UInt32 = 20,
+                                   maxOutgoingRecords: UInt32 = 5000) throws {
+        return try queue.sync {
+            try self.checkApi()
+            var msg = MsgTypes_HistorySyncPolicy()
+            if let days = firstSyncModifiedDays {
+                msg.firstSyncModifiedDays = days
+            }
+            if let max = maxIncomingRecords {
+                msg.maxIncomingRecords = max
+            }
+            msg.backfill = backfill
+            msg.maxVisits = maxVisits
+            msg.maxOutgoingRecords = maxOutgoingRecords
+            let data = try! msg.serializedData()
+            let size = Int32(data.count)
+            try data.withUnsafeBytes { bytes in
+                try PlacesError.unwrap { error in
+                    places_set_history_sync_policy(self.handle, bytes.bindMemory(to: UInt8.self).baseAddress!, size, error)
+                }
+            }
+        }
+    }
+
+    /**
+     * Asks the next bookmark sync to repair the problems it finds in the
+     * server's bookmark tree. It fixes what it can, and asks other devices
+     * to upload the bookmarks that it doesn't have.
+     *
+     * - Throws:
+     *     - `PlacesError.connUseAfterAPIClosed`: if the PlacesAPI that returned this connection
+     *                                            object has been closed. This indicates API
+     *                                            misuse.
+     *     - `PlacesError.unexpected`: When an error that has not specifically been exposed
+     *                                 to Swift is encountered (for example IO errors from
+     *                                 the database code, etc).
+     *     - `PlacesError.panic`: If the rust code panics while completing this
+     *                            operation. (If this occurs, please let us know).
+     */
+    open func requestBookmarkRepair() throws {
+        return try queue.sync {
+            try self.checkApi()
+            try PlacesError.unwrap { error in
+                bookmarks_request_repair(self.handle, error)
+            }
+        }
+    }
+
     /**
      * Delete the bookmark with the provided GUID.
      *
@@ -65,6 +65,13 @@ char *_Nullable places_query_autocomplete(PlacesConnectionHandle handle,
                                           int32_t limit,
                                           PlacesRustError *_Nonnull out_err);
 
+PlacesRustBuffer places_query_autocomplete_with_remote_tabs(PlacesConnectionHandle handle,
+                                                            const char *_Nonnull search,
+                                                            int32_t limit,
+                                                            uint8_t const *_Nonnull remote_tabs_data,
+                                                            int32_t remote_tabs_len,
+                                                            PlacesRustError *_Nonnull out_err);
+
 char *_Nullable places_match_url(PlacesConnectionHandle handle,
                                  const char *_Nonnull search,
                                  PlacesRustError *_Nonnull out_err);
@@ -128,6 +135,11 @@ PlacesRustBuffer places_get_visit_infos(PlacesConnectionHandle handle,
 void places_reset(PlacesAPIHandle handle,
                   PlacesRustError *_Nonnull out_err);
 
+void places_set_history_sync_policy(PlacesConnectionHandle handle,
+                                    uint8_t const *_Nonnull policy_data,
+                                    int32_t policy_len,
+                                    PlacesRustError *_Nonnull out_err);
+
 char *_Nonnull sync15_history_sync(PlacesAPIHandle handle,
                                    char const *_Nonnull key_id,
                                    char const *_Nonnull access_token,
@@ -191,6 +203,9 @@ uint8_t bookmarks_delete(PlacesConnectionHandle handle,
 void bookmarks_reset(PlacesAPIHandle handle,
                      PlacesRustError *_Nonnull out_err);
 
+void bookmarks_request_repair(PlacesConnectionHandle handle,
+                              PlacesRustError *_Nonnull out_err);
+
 // MARK: memory/lifecycle management
 
 void places_api_return_write_conn(PlacesAPIHandle api,
@@ -4,8 +4,9 @@
 
 use crate::db::PlacesDb;
 use crate::error::Result;
+use crate::match_impl::AutocompleteMatch;
 pub use crate::match_impl::{MatchBehavior, SearchBehavior};
-use crate::msg_types::{SearchResultMessage, SearchResultReason};
+use crate::msg_types::{self, SearchResultMessage, SearchResultReason, SearchableRemoteTabs};
 use rusqlite::{types::ToSql, Row};
 use serde_derive::*;
 use sql_support::{maybe_log_plan, ConnExt};
@@ -51,6 +52,33 @@ pub struct SearchParams {
 /// A provider can be anything that returns URL suggestions: Places history
 /// and bookmarks, synced tabs, search engine suggestions, and search keywords.
 pub fn search_frecent(conn: &PlacesDb, params: SearchParams) -> Result<Vec<SearchResult>> {
+    search_frecent_with_remote_tabs(conn, params, &[])
+}
+
+/// A tab that's open on another device, for `search_frecent_with_remote_tabs`
+/// to match. Places doesn't store synced tabs, so the application passes
+/// them in from the tabs component.
+#[derive(Debug, Clone, Default)]
+pub struct SearchableRemoteTab {
+    /// The name of the device the tab is open on.
+    pub device_name: String,
+    pub title: String,
+    /// The tab's current URL.
+    pub url: String,
+    pub icon_url: Option<String>,
+    /// When the tab was last used, in milliseconds since the epoch.
+    pub last_used: i64,
+}
+
+/// Like `search_frecent`, but also matches tabs open on other devices,
+/// using the same rules as history and bookmarks. Synced tabs count as open
+/// pages for the `SearchBehavior`, and matches are tagged with
+/// `MatchReason::RemoteTab`.
+pub fn search_frecent_with_remote_tabs(
+    conn: &PlacesDb,
+    params: SearchParams,
+    remote_tabs: &[SearchableRemoteTab],
+) -> Result<Vec<SearchResult>> {
     // TODO: Tokenize the query.
 
     // Try to find the first heuristic result. Desktop tries extensions,
@@ -70,6 +98,17 @@ pub fn search_frecent(conn: &PlacesDb, params: SearchParams) -> Result<Vec<Searc
                 MatchBehavior::Anywhere,
                 SearchBehavior::default(),
             ),
+            // Tabs open on other devices are more likely to be what the
+            // user wants than other pages in their history, but we leave
+            // at least half the results for those, so that a lot of
+            // matching tabs don't crowd out everything else.
+            &RemoteTabs::with_behavior(
+                &params.search_string,
+                remote_tabs,
+                MatchBehavior::Anywhere,
+                SearchBehavior::default(),
+            )
+            .with_max_results((params.limit + 1) / 2),
             &Suggestions::with_behavior(
                 &params.search_string,
                 MatchBehavior::Anywhere,
@@ -79,8 +118,21 @@ pub fn search_frecent(conn: &PlacesDb, params: SearchParams) -> Result<Vec<Searc
         params.limit,
     )?;
 
-    matches.sort_unstable_by(|a, b| a.url.cmp(&b.url));
-    matches.dedup_by(|a, b| a.url == b.url);
+    // The sort is stable, so we keep the match from the first matcher that
+    // found the page, along with the reasons the others matched it, like
+    // the devices that have the page open.
+    matches.sort_by(|a, b| a.url.cmp(&b.url));
+    matches.dedup_by(|a, b| {
+        if a.url != b.url {
+            return false;
+        }
+        for reason in a.reasons.drain(..) {
+            if !b.reasons.contains(&reason) {
+                b.reasons.push(reason);
+            }
+        }
+        true
+    });
 
     Ok(matches)
 }
@@ -115,7 +167,7 @@ fn match_with_limit(
         scope.err_if_interrupted()?;
         let matches = m.search(conn, rem_results)?;
         results.extend(matches);
-        rem_results = rem_results.saturating_sub(results.len() as u32);
+        rem_results = max_results.saturating_sub(results.len() as u32);
     }
     Ok(results)
 }
@@ -184,6 +236,8 @@ pub enum MatchReason {
     Bookmark,
     // Hrm... This will probably make this all serialize weird...
     Tags(String),
+    /// The page is open in a tab on another device, with this name.
+    RemoteTab(String),
 }
 
 #[derive(Debug, Clone, Serialize, Eq, PartialEq)]
@@ -291,6 +345,48 @@ impl SearchResult {
         })
     }
 
+    /// Returns `None` if the tab's URL is invalid.
+    pub fn from_remote_tab(
+        conn: &PlacesDb,
+        search_string: &str,
+        tab: &SearchableRemoteTab,
+    ) -> Result<Option<Self>> {
+        let url = match Url::parse(&tab.url) {
+            Ok(url) => url,
+            Err(e) => {
+                log::warn!("Ignoring remote tab with invalid URL: {}", e);
+                return Ok(None);
+            }
+        };
+        // Remote tabs don't have a frecency, but their pages might be in
+        // our history.
+        let frecency = conn
+            .try_query_row(
+                "SELECT frecency FROM moz_places
+                 WHERE url_hash = hash(:url) AND url = :url",
+                &[(":url", &url.as_str())],
+                |row| row.get::<_, i64>(0),
+                true,
+            )?
+            .unwrap_or_default();
+        let title = if tab.title.is_empty() {
+            url.as_str().to_owned()
+        } else {
+            tab.title.clone()
+        };
+        Ok(Some(Self {
+            search_string: search_string.to_owned(),
+            title,
+            icon_url: tab
+                .icon_url
+                .as_ref()
+                .and_then(|icon_url| Url::parse(icon_url).ok()),
+            url,
+            frecency,
+            reasons: vec![MatchReason::RemoteTab(tab.device_name.clone())],
+        }))
+    }
+
     pub fn from_url_row(row: &rusqlite::Row<'_>) -> Result<Self> {
         let search_string = row.get::<_, String>("searchString")?;
         let href = row.get::<_, String>("url")?;
@@ -339,6 +435,14 @@ impl From<SearchResult> for SearchResultMessage {
             url: res.url.into_string(),
             title: res.title,
             frecency: res.frecency,
+            device_names: res
+                .reasons
+                .iter()
+                .filter_map(|r| match r {
+                    MatchReason::RemoteTab(device_name) => Some(device_name.clone()),
+                    _ => None,
+                })
+                .collect(),
             reasons: res
                 .reasons
                 .into_iter()
@@ -348,6 +452,21 @@ impl From<SearchResult> for SearchResultMessage {
     }
 }
 
+impl From<SearchableRemoteTabs> for Vec<SearchableRemoteTab> {
+    fn from(msg: SearchableRemoteTabs) -> Self {
+        msg.tabs
+            .into_iter()
+            .map(|tab: msg_types::SearchableRemoteTab| SearchableRemoteTab {
+                device_name: tab.device_name,
+                title: tab.title,
+                url: tab.url,
+                icon_url: tab.icon_url,
+                last_used: tab.last_used,
+            })
+            .collect()
+    }
+}
+
 impl From<MatchReason> for SearchResultReason {
     fn from(mr: MatchReason) -> Self {
         match mr {
@@ -357,6 +476,7 @@ impl From<MatchReason> for SearchResultReason {
             MatchReason::PreviousUse => SearchResultReason::PreviousUse,
             MatchReason::Bookmark => SearchResultReason::Bookmark,
             MatchReason::Tags(_) => SearchResultReason::Tag,
+            MatchReason::RemoteTab(_) => SearchResultReason::RemoteTab,
         }
     }
 }
@@ -544,6 +664,76 @@ impl<'query> Matcher for Adaptive<'query> {
     }
 }
 
+struct RemoteTabs<'query, 'tabs> {
+    query: &'query str,
+    tabs: &'tabs [SearchableRemoteTab],
+    match_behavior: MatchBehavior,
+    search_behavior: SearchBehavior,
+    max_results: u32,
+}
+
+impl<'query, 'tabs> RemoteTabs<'query, 'tabs> {
+    pub fn with_behavior(
+        query: &'query str,
+        tabs: &'tabs [SearchableRemoteTab],
+        match_behavior: MatchBehavior,
+        search_behavior: SearchBehavior,
+    ) -> RemoteTabs<'query, 'tabs> {
+        RemoteTabs {
+            query,
+            tabs,
+            match_behavior,
+            search_behavior,
+            max_results: u32::max_value(),
+        }
+    }
+
+    /// Returns at most `max_results` tabs, even if the search has room for
+    /// more.
+    pub fn with_max_results(self, max_results: u32) -> RemoteTabs<'query, 'tabs> {
+        RemoteTabs {
+            max_results,
+            ..self
+        }
+    }
+}
+
+impl<'query, 'tabs> Matcher for RemoteTabs<'query, 'tabs> {
+    fn search(&self, conn: &PlacesDb, max_results: u32) -> Result<Vec<SearchResult>> {
+        let mut tabs = self
+            .tabs
+            .iter()
+            .filter(|tab| {
+                AutocompleteMatch {
+                    search_str: self.query,
+                    url_str: &tab.url,
+                    title_str: &tab.title,
+                    tags: "",
+                    visit_count: 0,
+                    typed: false,
+                    bookmarked: false,
+                    open_page_count: 1,
+                    match_behavior: self.match_behavior,
+                    search_behavior: self.search_behavior,
+                }
+                .invoke()
+            })
+            .collect::<Vec<_>>();
+        tabs.sort_by(|a, b| b.last_used.cmp(&a.last_used));
+        let max_results = max_results.min(self.max_results);
+        let mut results = Vec::new();
+        for tab in tabs {
+            if results.len() >= max_results as usize {
+                break;
+            }
+            if let Some(result) = SearchResult::from_remote_tab(conn, self.query, tab)? {
+                results.push(result);
+            }
+        }
+        Ok(results)
+    }
+}
+
 struct Suggestions<'query> {
     query: &'query str,
     match_behavior: MatchBehavior,
@@ -729,6 +919,135 @@ mod tests {
             }]
         );
     }
+    #[test]
+    fn search_remote_tabs() {
+        let conn = new_mem_connection();
+
+        let url = Url::parse("http://example.com/123").unwrap();
+        let visit = VisitObservation::new(url.clone())
+            .with_title("Example page 123".to_string())
+            .with_visit_type(VisitTransition::Typed)
+            .with_at(Timestamp::now());
+        apply_observation(&conn, visit).expect("Should apply visit");
+
+        let remote_tabs = vec![
+            SearchableRemoteTab {
+                device_name: "Laptop".into(),
+                title: "Example page 123".into(),
+                url: "http://example.com/123".into(),
+                last_used: 1,
+                ..SearchableRemoteTab::default()
+            },
+            SearchableRemoteTab {
+                device_name: "Phone".into(),
+                title: "Some other page".into(),
+                url: "https://mozilla.org/tabs".into(),
+                icon_url: Some("https://mozilla.org/favicon.ico".into()),
+                last_used: 2,
+            },
+            SearchableRemoteTab {
+                device_name: "Tablet".into(),
+                title: "Other".into(),
+                url: "not a url".into(),
+                last_used: 3,
+                ..SearchableRemoteTab::default()
+            },
+        ];
+
+        let by_title = search_frecent_with_remote_tabs(
+            &conn,
+            SearchParams {
+                search_string: "other".into(),
+                limit: 10,
+            },
+            &remote_tabs,
+        )
+        .expect("Should search remote tabs by title");
+        assert_eq!(
+            by_title,
+            vec![SearchResult {
+                search_string: "other".into(),
+                url: Url::parse("https://mozilla.org/tabs").unwrap(),
+                title: "Some other page".into(),
+                icon_url: Some(Url::parse("https://mozilla.org/favicon.ico").unwrap()),
+                frecency: 0,
+                reasons: vec![MatchReason::RemoteTab("Phone".into())],
+            }]
+        );
+
+        // Pages in history and open on another device keep both reasons.
+        let by_url = search_frecent_with_remote_tabs(
+            &conn,
+            SearchParams {
+                search_string: "example.com/1".into(),
+                limit: 10,
+            },
+            &remote_tabs,
+        )
+        .expect("Should search remote tabs by URL");
+        let result = by_url
+            .iter()
+            .find(|result| result.url == url)
+            .expect("Should match history and remote tab");
+        assert!(result
+            .reasons
+            .contains(&MatchReason::RemoteTab("Laptop".into())));
+        assert!(result.reasons.len() > 1);
+        assert!(result.frecency > 0);
+
+        // Synced tabs aren't bookmarks.
+        let bookmarks_only = RemoteTabs::with_behavior(
+            "other",
+            &remote_tabs,
+            MatchBehavior::Anywhere,
+            SearchBehavior::RESTRICT | SearchBehavior::BOOKMARK,
+        )
+        .search(&conn, 10)
+        .expect("Should search remote tabs");
+        assert!(bookmarks_only.is_empty());
+    }
+
+    #[test]
+    fn search_remote_tabs_leave_room_for_history() {
+        let conn = new_mem_connection();
+        for i in 0..4 {
+            let visit = VisitObservation::new(
+                Url::parse(&format!("http://example.com/history/{}", i)).unwrap(),
+            )
+            .with_title(format!("Example history {}", i))
+            .with_visit_type(VisitTransition::Typed)
+            .with_at(Timestamp::now());
+            apply_observation(&conn, visit).expect("Should apply visit");
+        }
+        let remote_tabs = (0..4)
+            .map(|i| SearchableRemoteTab {
+                device_name: "Laptop".into(),
+                title: format!("Example tab {}", i),
+                url: format!("https://example.org/tabs/{}", i),
+                last_used: i,
+                ..SearchableRemoteTab::default()
+            })
+            .collect::<Vec<_>>();
+
+        let results = search_frecent_with_remote_tabs(
+            &conn,
+            SearchParams {
+                search_string: "example".into(),
+                limit: 4,
+            },
+            &remote_tabs,
+        )
+        .expect("Should search");
+        let num_tabs = results
+            .iter()
+            .filter(|result| result.url.host_str() == Some("example.org"))
+            .count();
+        assert_eq!(num_tabs, 2);
+        assert!(results
+            .iter()
+            .any(|result| result.url.host_str() == Some("example.com")));
+    }
+
     #[test]
     fn search_unicode() {
         let conn = new_mem_connection();
@@ -33,11 +33,13 @@ impl<'a> IncomingApplicator<'a> {
         Self { db }
     }
 
+    /// Stages an incoming record. Returns `false` if the record has an
+    /// unknown type, and was skipped.
     pub fn apply_payload(
         &self,
         payload: sync15::Payload,
         timestamp: ServerTimestamp,
-    ) -> Result<()> {
+    ) -> Result<bool> {
         if payload.is_tombstone() {
             self.store_incoming_tombstone(
                 timestamp,
@@ -51,10 +53,13 @@ impl<'a> IncomingApplicator<'a> {
                 Some("folder") => self.store_incoming_folder(timestamp, &value)?,
                 Some("livemark") => self.store_incoming_livemark(timestamp, &value)?,
                 Some("separator") => self.store_incoming_sep(timestamp, &value)?,
-                t => log::warn!("Incoming payload has invalid type: {:?}", t),
+                t => {
+                    log::warn!("Incoming payload has invalid type: {:?}", t);
+                    return Ok(false);
+                }
             };
         }
-        Ok(())
+        Ok(true)
     }
 
     fn store_incoming_bookmark(&self, modified: ServerTimestamp, b: &JsonValue) -> Result<()> {
@@ -4,6 +4,7 @@
 
 mod incoming;
 pub mod record;
+pub mod repair;
 pub mod store;
 
 #[cfg(test)]
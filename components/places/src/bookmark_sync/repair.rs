@@ -0,0 +1,211 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Repairing the server's bookmark tree.
+//!
+//! Every bookmark sync validates the tree in `moz_bookmarks_synced`, and
+//! reports the problems it finds in the engine's telemetry. Once a repair is
+//! requested with `request_repair`, the next sync also fixes what it can in
+//! the mirror before merging:
+//!
+//! - Cycles are broken by detaching one of their items, which dogear then
+//!   moves to unfiled.
+//! - Folders that list missing or deleted children are flagged for reupload,
+//!   so that the merge uploads them with the children they actually have.
+//!
+//! Items that we don't have, like missing children and parents, are
+//! requested from other clients with a `repairRequest` command, which the
+//! sync manager sends on the next sync.
+
+use super::SyncedBookmarkValidity;
+use crate::db::PlacesDb;
+use crate::error::*;
+use crate::storage::{delete_meta, get_meta, put_meta};
+use dogear::{DivergedParent, DivergedParentGuid, Problem, Problems};
+use serde_derive::*;
+use sql_support::ConnExt;
+use std::collections::BTreeSet;
+use sync15::clients::{RepairRequest, RepairResponse};
+use sync_guid::Guid as SyncGuid;
+
+pub const REPAIR_REQUESTED_META_KEY: &str = "bookmarks_repair_requested";
+pub const OUTGOING_REPAIR_REQUEST_META_KEY: &str = "bookmarks_repair_request";
+
+/// Asks the next bookmark sync to repair the problems it finds.
+pub fn request_repair(db: &PlacesDb) -> Result<()> {
+    put_meta(db, REPAIR_REQUESTED_META_KEY, &true)
+}
+
+pub(crate) fn is_repair_requested(db: &PlacesDb) -> Result<bool> {
+    Ok(get_meta::<bool>(db, REPAIR_REQUESTED_META_KEY)?.unwrap_or(false))
+}
+
+pub(crate) fn repair_finished(db: &PlacesDb) -> Result<()> {
+    delete_meta(db, REPAIR_REQUESTED_META_KEY)
+}
+
+// The items we'll ask other clients to upload, stored as JSON until the
+// request is sent.
+#[derive(Debug, Deserialize, Serialize)]
+struct OutgoingRepairRequest {
+    #[serde(rename = "flowID")]
+    flow_id: String,
+    ids: Vec<String>,
+}
+
+/// Returns the `repairRequest` to send to other clients, if a repair found
+/// items that we don't have.
+pub fn fetch_outgoing_repair_request(
+    db: &PlacesDb,
+    local_id: &str,
+) -> Result<Option<RepairRequest>> {
+    let json = match get_meta::<String>(db, OUTGOING_REPAIR_REQUEST_META_KEY)? {
+        Some(json) => json,
+        None => return Ok(None),
+    };
+    let request: OutgoingRepairRequest = serde_json::from_str(&json)?;
+    Ok(Some(RepairRequest {
+        collection: "bookmarks".into(),
+        request: "upload".into(),
+        requestor: local_id.into(),
+        ids: request.ids,
+        flow_id: request.flow_id,
+    }))
+}
+
+/// Forgets the outgoing `repairRequest`, once it's been sent.
+pub fn repair_request_sent(db: &PlacesDb) -> Result<()> {
+    delete_meta(db, OUTGOING_REPAIR_REQUEST_META_KEY)
+}
+
+/// Handles a `repairResponse` from another client, which uploaded some of the
+/// items we asked for. If we haven't sent the request for the same repair
+/// yet, we stop asking for those items, and forget the request if that was
+/// all of them. The next sync downloads the uploaded items either way.
+pub fn repair_response_received(db: &PlacesDb, response: &RepairResponse) -> Result<()> {
+    let json = match get_meta::<String>(db, OUTGOING_REPAIR_REQUEST_META_KEY)? {
+        Some(json) => json,
+        None => return Ok(()),
+    };
+    let mut request: OutgoingRepairRequest = serde_json::from_str(&json)?;
+    if request.flow_id != response.flow_id {
+        return Ok(());
+    }
+    request.ids.retain(|id| !response.ids.contains(id));
+    if request.ids.is_empty() {
+        delete_meta(db, OUTGOING_REPAIR_REQUEST_META_KEY)
+    } else {
+        put_meta(
+            db,
+            OUTGOING_REPAIR_REQUEST_META_KEY,
+            &serde_json::to_string(&request)?,
+        )
+    }
+}
+
+/// What a repair does about the problems in the remote tree.
+#[derive(Debug, Default)]
+pub(crate) struct RepairPlan {
+    /// `(parent, child)` pairs from `moz_bookmarks_synced_structure` for
+    /// children that are missing or deleted.
+    dangling_children: Vec<(SyncGuid, SyncGuid)>,
+    /// Items to ask other clients to upload.
+    requested_ids: BTreeSet<SyncGuid>,
+}
+
+impl RepairPlan {
+    pub fn new(problems: &Problems) -> Self {
+        let mut plan = RepairPlan::default();
+        for summary in problems.summarize() {
+            let guid = SyncGuid::from(summary.guid().as_str());
+            match summary.problem() {
+                Problem::Orphan => {
+                    plan.requested_ids.insert(guid);
+                }
+                Problem::MissingChild { child_guid } => {
+                    let child_guid = SyncGuid::from(child_guid.as_str());
+                    plan.requested_ids.insert(child_guid.clone());
+                    plan.dangling_children.push((guid, child_guid));
+                }
+                Problem::DeletedChild { child_guid } => {
+                    let child_guid = SyncGuid::from(child_guid.as_str());
+                    plan.dangling_children.push((guid, child_guid));
+                }
+                Problem::MisparentedRoot(parents) | Problem::DivergedParents(parents) => {
+                    // Ask for the item, and any parents we don't have.
+                    plan.requested_ids.insert(guid);
+                    for parent in parents {
+                        if let DivergedParent::ByParentGuid(DivergedParentGuid::Missing(p)) = parent
+                        {
+                            plan.requested_ids.insert(SyncGuid::from(p.as_str()));
+                        }
+                    }
+                }
+            }
+        }
+        plan
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dangling_children.is_empty() && self.requested_ids.is_empty()
+    }
+
+    /// Fixes the mirror, and stores the items to request from other
+    /// clients. This should be called from within an open transaction.
+    pub fn apply(&self, db: &PlacesDb) -> Result<()> {
+        for (parent_guid, child_guid) in &self.dangling_children {
+            db.execute_named_cached(
+                "DELETE FROM moz_bookmarks_synced_structure
+                 WHERE guid = :child_guid AND parentGuid = :parent_guid",
+                &[(":child_guid", child_guid), (":parent_guid", parent_guid)],
+            )?;
+            flag_for_reupload(db, parent_guid)?;
+        }
+        if !self.requested_ids.is_empty() {
+            let request = OutgoingRepairRequest {
+                flow_id: SyncGuid::random().into_string(),
+                ids: self
+                    .requested_ids
+                    .iter()
+                    .map(|guid| guid.as_str().to_owned())
+                    .collect(),
+            };
+            put_meta(
+                db,
+                OUTGOING_REPAIR_REQUEST_META_KEY,
+                &serde_json::to_string(&request)?,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Breaks a cycle in the remote tree by removing an item from its parents,
+/// so that dogear moves it to unfiled and reuploads it. This should be
+/// called from within an open transaction.
+pub(crate) fn detach_synced_item(db: &PlacesDb, guid: &SyncGuid) -> Result<()> {
+    db.execute_named_cached(
+        "DELETE FROM moz_bookmarks_synced_structure WHERE guid = :guid",
+        &[(":guid", guid)],
+    )?;
+    db.execute_named_cached(
+        "UPDATE moz_bookmarks_synced SET parentGuid = NULL WHERE guid = :guid",
+        &[(":guid", guid)],
+    )?;
+    flag_for_reupload(db, guid)
+}
+
+fn flag_for_reupload(db: &PlacesDb, guid: &SyncGuid) -> Result<()> {
+    db.execute_named_cached(
+        "UPDATE moz_bookmarks_synced SET
+           needsMerge = 1,
+           validity = :validity
+         WHERE guid = :guid",
+        &[
+            (":guid", guid),
+            (":validity", &SyncedBookmarkValidity::Reupload),
+        ],
+    )?;
+    Ok(())
+}
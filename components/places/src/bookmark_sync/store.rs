@@ -7,6 +7,7 @@ use super::record::{
     BookmarkItemRecord, BookmarkRecord, BookmarkRecordId, FolderRecord, QueryRecord,
     SeparatorRecord,
 };
+use super::repair::{self, RepairPlan};
 use super::{SyncedBookmarkKind, SyncedBookmarkValidity};
 use crate::api::places_api::ConnectionType;
 use crate::db::PlacesDb;
@@ -21,19 +22,19 @@ use crate::storage::{
 };
 use crate::types::{BookmarkType, SyncStatus, Timestamp};
 use dogear::{
-    self, AbortSignal, CompletionOps, Content, Item, MergedRoot, TelemetryEvent, Tree, UploadItem,
-    UploadTombstone,
+    self, AbortSignal, CompletionOps, Content, Item, MergedRoot, ProblemCounts, TelemetryEvent,
+    Tree, UploadItem, UploadTombstone,
 };
 use rusqlite::{Row, NO_PARAMS};
 use sql_support::{self, ConnExt, SqlInterruptScope};
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryFrom;
 use std::fmt;
 use std::result;
 use sync15::{
-    telemetry, CollSyncIds, CollectionRequest, IncomingChangeset, OutgoingChangeset, Payload,
-    ServerTimestamp, Store, StoreSyncAssociation,
+    telemetry, CollSyncIds, CollectionRequest, IncomingChangeset, OutgoingChangeset,
+    OutgoingRecords, Payload, ServerTimestamp, Store, StoreSyncAssociation,
 };
 use sync_guid::Guid as SyncGuid;
 pub const LAST_SYNC_META_KEY: &str = "bookmarks_last_sync_time";
@@ -48,6 +49,9 @@ pub const COLLECTION_SYNCID_META_KEY: &str = "bookmarks_sync_id";
 /// blocking writes from other connections.
 const MAX_FRECENCIES_TO_RECALCULATE_PER_CHUNK: usize = 400;
 
+/// The number of outgoing items to inflate into records at once.
+const OUTGOING_RECORDS_PAGE_SIZE: i64 = 500;
+
 /// Adapts an interruptee to a Dogear abort signal.
 struct MergeInterruptee<'a, I>(&'a I);
 
@@ -83,8 +87,11 @@ impl<'a> BookmarksStore<'a> {
         let applicator = IncomingApplicator::new(&self.db);
 
         for incoming in inbound.changes {
-            applicator.apply_payload(incoming.0, incoming.1)?;
-            incoming_telemetry.applied(1);
+            if applicator.apply_payload(incoming.0, incoming.1)? {
+                incoming_telemetry.applied(1);
+            } else {
+                incoming_telemetry.failed_with_reason(1, "invalid");
+            }
             tx.maybe_commit()?;
             self.interruptee.err_if_interrupted()?;
         }
@@ -652,9 +659,9 @@ impl<'a> BookmarksStore<'a> {
         Ok(())
     }
 
-    /// Inflates Sync records for all staged outgoing items.
-    fn fetch_outgoing_records(&self, timestamp: ServerTimestamp) -> Result<OutgoingChangeset> {
-        let mut outgoing = OutgoingChangeset::new(self.collection_name(), timestamp);
+    /// Returns an iterator that inflates Sync records for all staged
+    /// outgoing items.
+    fn outgoing_records(&self) -> Result<OutgoingBookmarks<'a>> {
         let mut child_record_ids_by_local_parent_id: HashMap<i64, Vec<BookmarkRecordId>> =
             HashMap::new();
         let mut tags_by_local_id: HashMap<i64, Vec<String>> = HashMap::new();
@@ -684,94 +691,15 @@ impl<'a> BookmarksStore<'a> {
             tags.push(tag);
         }
 
-        let mut stmt = self.db.prepare(
-            "SELECT id, syncChangeCounter, guid, isDeleted, kind, keyword,
-                    url, IFNULL(title, '') AS title, position, parentGuid,
-                    IFNULL(parentTitle, '') AS parentTitle, dateAdded
-             FROM itemsToUpload",
-        )?;
-        let mut results = stmt.query(NO_PARAMS)?;
-        while let Some(row) = results.next()? {
-            self.interruptee.err_if_interrupted()?;
-            let guid = row.get::<_, SyncGuid>("guid")?;
-            let is_deleted = row.get::<_, bool>("isDeleted")?;
-            if is_deleted {
-                outgoing.changes.push(Payload::new_tombstone(
-                    BookmarkRecordId::from(guid).into_payload_id(),
-                ));
-                continue;
-            }
-            let parent_guid = row.get::<_, SyncGuid>("parentGuid")?;
-            let parent_title = row.get::<_, String>("parentTitle")?;
-            let date_added = row.get::<_, i64>("dateAdded")?;
-            let record: BookmarkItemRecord = match SyncedBookmarkKind::from_u8(row.get("kind")?)? {
-                SyncedBookmarkKind::Bookmark => {
-                    let local_id = row.get::<_, i64>("id")?;
-                    let title = row.get::<_, String>("title")?;
-                    let url = row.get::<_, String>("url")?;
-                    BookmarkRecord {
-                        record_id: guid.into(),
-                        parent_record_id: Some(parent_guid.into()),
-                        parent_title: Some(parent_title),
-                        date_added: Some(date_added),
-                        has_dupe: true,
-                        title: Some(title),
-                        url: Some(url),
-                        keyword: row.get::<_, Option<String>>("keyword")?,
-                        tags: tags_by_local_id.remove(&local_id).unwrap_or_default(),
-                    }
-                    .into()
-                }
-                SyncedBookmarkKind::Query => {
-                    let title = row.get::<_, String>("title")?;
-                    let url = row.get::<_, String>("url")?;
-                    QueryRecord {
-                        record_id: guid.into(),
-                        parent_record_id: Some(parent_guid.into()),
-                        parent_title: Some(parent_title),
-                        date_added: Some(date_added),
-                        has_dupe: true,
-                        title: Some(title),
-                        url: Some(url),
-                        tag_folder_name: None,
-                    }
-                    .into()
-                }
-                SyncedBookmarkKind::Folder => {
-                    let title = row.get::<_, String>("title")?;
-                    let local_id = row.get::<_, i64>("id")?;
-                    let children = child_record_ids_by_local_parent_id
-                        .remove(&local_id)
-                        .unwrap_or_default();
-                    FolderRecord {
-                        record_id: guid.into(),
-                        parent_record_id: Some(parent_guid.into()),
-                        parent_title: Some(parent_title),
-                        date_added: Some(date_added),
-                        has_dupe: true,
-                        title: Some(title),
-                        children,
-                    }
-                    .into()
-                }
-                SyncedBookmarkKind::Livemark => continue,
-                SyncedBookmarkKind::Separator => {
-                    let position = row.get::<_, i64>("position")?;
-                    SeparatorRecord {
-                        record_id: guid.into(),
-                        parent_record_id: Some(parent_guid.into()),
-                        parent_title: Some(parent_title),
-                        date_added: Some(date_added),
-                        has_dupe: true,
-                        position: Some(position),
-                    }
-                    .into()
-                }
-            };
-            outgoing.changes.push(Payload::from_record(record)?);
-        }
-
-        Ok(outgoing)
+        Ok(OutgoingBookmarks {
+            db: self.db,
+            interruptee: self.interruptee,
+            child_record_ids_by_local_parent_id,
+            tags_by_local_id,
+            last_id: None,
+            page: VecDeque::new(),
+            done: false,
+        })
     }
 
     /// Decrements the change counter, updates the sync status, and cleans up
@@ -939,13 +867,22 @@ impl<'a> Store for BookmarksStore<'a> {
         // records.
         put_meta(self.db, LAST_SYNC_META_KEY, &(timestamp.as_millis() as i64))?;
 
-        // Merge.
+        // Merge, repairing the remote tree first if that's been requested.
+        let repair = repair::is_repair_requested(self.db)?;
         let mut merger = Merger::with_telemetry(&self, timestamp, telem);
+        merger.set_repair(repair);
         merger.merge()?;
+        if repair {
+            repair::repair_finished(self.db)?;
+        }
 
-        // Finally, stage outgoing items.
-        let outgoing = self.fetch_outgoing_records(timestamp)?;
-        Ok(outgoing)
+        // The outgoing items are staged, and inflated into records during
+        // the upload, by `fetch_outgoing_records`.
+        Ok(OutgoingChangeset::new(self.collection_name(), timestamp))
+    }
+
+    fn fetch_outgoing_records(&self) -> result::Result<OutgoingRecords<'_>, failure::Error> {
+        Ok(Box::new(self.outgoing_records()?))
     }
 
     fn sync_finished(
@@ -959,6 +896,21 @@ impl<'a> Store for BookmarksStore<'a> {
         Ok(())
     }
 
+    fn sync_finished_readonly(
+        &self,
+        new_timestamp: ServerTimestamp,
+    ) -> result::Result<(), failure::Error> {
+        // Local changes stay flagged, and are staged again for the next
+        // sync, so we only fast-forward the last sync time.
+        put_meta(
+            self.db,
+            LAST_SYNC_META_KEY,
+            &(new_timestamp.as_millis() as i64),
+        )?;
+        self.update_frecencies()?;
+        Ok(())
+    }
+
     fn get_collection_requests(&self) -> result::Result<Vec<CollectionRequest>, failure::Error> {
         let since = get_meta::<i64>(self.db, LAST_SYNC_META_KEY)?.unwrap_or_default();
         Ok(vec![CollectionRequest::new(self.collection_name())
@@ -1018,9 +970,202 @@ impl<'a> Store for BookmarksStore<'a> {
     }
 }
 
-#[derive(Default)]
+/// Inflates Sync records for staged outgoing items a page at a time, so
+/// that a large upload doesn't hold every record in memory at once. The
+/// children and tags of the items are fetched up front, since they're small.
+struct OutgoingBookmarks<'a> {
+    db: &'a PlacesDb,
+    interruptee: &'a SqlInterruptScope,
+    child_record_ids_by_local_parent_id: HashMap<i64, Vec<BookmarkRecordId>>,
+    tags_by_local_id: HashMap<i64, Vec<String>>,
+    /// The ID of the last item we inflated, or `None` if we haven't
+    /// fetched any items yet.
+    last_id: Option<i64>,
+    page: VecDeque<Payload>,
+    done: bool,
+}
+
+impl<'a> OutgoingBookmarks<'a> {
+    fn fetch_page(&mut self) -> Result<()> {
+        let mut stmt = self.db.prepare(
+            "SELECT id, syncChangeCounter, guid, isDeleted, kind, keyword,
+                    url, IFNULL(title, '') AS title, position, parentGuid,
+                    IFNULL(parentTitle, '') AS parentTitle, dateAdded
+             FROM itemsToUpload
+             WHERE :lastId IS NULL OR id > :lastId
+             ORDER BY id
+             LIMIT :limit",
+        )?;
+        let mut results = stmt.query_named(&[
+            (":lastId", &self.last_id),
+            (":limit", &OUTGOING_RECORDS_PAGE_SIZE),
+        ])?;
+        let mut count = 0;
+        while let Some(row) = results.next()? {
+            self.interruptee.err_if_interrupted()?;
+            count += 1;
+            self.last_id = Some(row.get::<_, i64>("id")?);
+            let guid = row.get::<_, SyncGuid>("guid")?;
+            let is_deleted = row.get::<_, bool>("isDeleted")?;
+            if is_deleted {
+                self.page.push_back(Payload::new_tombstone(
+                    BookmarkRecordId::from(guid).into_payload_id(),
+                ));
+                continue;
+            }
+            let parent_guid = row.get::<_, SyncGuid>("parentGuid")?;
+            let parent_title = row.get::<_, String>("parentTitle")?;
+            let date_added = row.get::<_, i64>("dateAdded")?;
+            let record: BookmarkItemRecord = match SyncedBookmarkKind::from_u8(row.get("kind")?)? {
+                SyncedBookmarkKind::Bookmark => {
+                    let local_id = row.get::<_, i64>("id")?;
+                    let title = row.get::<_, String>("title")?;
+                    let url = row.get::<_, String>("url")?;
+                    BookmarkRecord {
+                        record_id: guid.into(),
+                        parent_record_id: Some(parent_guid.into()),
+                        parent_title: Some(parent_title),
+                        date_added: Some(date_added),
+                        has_dupe: true,
+                        title: Some(title),
+                        url: Some(url),
+                        keyword: row.get::<_, Option<String>>("keyword")?,
+                        tags: self.tags_by_local_id.remove(&local_id).unwrap_or_default(),
+                    }
+                    .into()
+                }
+                SyncedBookmarkKind::Query => {
+                    let title = row.get::<_, String>("title")?;
+                    let url = row.get::<_, String>("url")?;
+                    QueryRecord {
+                        record_id: guid.into(),
+                        parent_record_id: Some(parent_guid.into()),
+                        parent_title: Some(parent_title),
+                        date_added: Some(date_added),
+                        has_dupe: true,
+                        title: Some(title),
+                        url: Some(url),
+                        tag_folder_name: None,
+                    }
+                    .into()
+                }
+                SyncedBookmarkKind::Folder => {
+                    let title = row.get::<_, String>("title")?;
+                    let local_id = row.get::<_, i64>("id")?;
+                    let children = self
+                        .child_record_ids_by_local_parent_id
+                        .remove(&local_id)
+                        .unwrap_or_default();
+                    FolderRecord {
+                        record_id: guid.into(),
+                        parent_record_id: Some(parent_guid.into()),
+                        parent_title: Some(parent_title),
+                        date_added: Some(date_added),
+                        has_dupe: true,
+                        title: Some(title),
+                        children,
+                    }
+                    .into()
+                }
+                SyncedBookmarkKind::Livemark => continue,
+                SyncedBookmarkKind::Separator => {
+                    let position = row.get::<_, i64>("position")?;
+                    SeparatorRecord {
+                        record_id: guid.into(),
+                        parent_record_id: Some(parent_guid.into()),
+                        parent_title: Some(parent_title),
+                        date_added: Some(date_added),
+                        has_dupe: true,
+                        position: Some(position),
+                    }
+                    .into()
+                }
+            };
+            self.page.push_back(Payload::from_record(record)?);
+        }
+
+        if count < OUTGOING_RECORDS_PAGE_SIZE {
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for OutgoingBookmarks<'a> {
+    type Item = result::Result<Payload, failure::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(payload) = self.page.pop_front() {
+                return Some(Ok(payload));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.fetch_page() {
+                self.done = true;
+                return Some(Err(e.into()));
+            }
+        }
+    }
+}
+
+/// Bump this when the set of problems we report changes.
+const VALIDATION_VERSION: u32 = 1;
+
 struct Driver {
     validation: RefCell<telemetry::Validation>,
+    // Set once we've recorded the problems in the remote tree, so that a
+    // repair doesn't count them twice.
+    validated: Cell<bool>,
+}
+
+impl Default for Driver {
+    fn default() -> Self {
+        Self {
+            validation: RefCell::new(telemetry::Validation::with_version(VALIDATION_VERSION)),
+            validated: Cell::new(false),
+        }
+    }
+}
+
+impl Driver {
+    fn record_problems(&self, problems: &ProblemCounts) {
+        if self.validated.replace(true) {
+            return;
+        }
+        self.validation
+            .borrow_mut()
+            .problem("orphans", problems.orphans)
+            .problem("misparentedRoots", problems.misparented_roots)
+            .problem("multipleParents", problems.multiple_parents_by_children)
+            .problem("missingParents", problems.missing_parent_guids)
+            .problem("deletedParents", problems.deleted_parent_guids)
+            .problem("nonFolderParents", problems.non_folder_parent_guids)
+            .problem(
+                "parentChildDisagreements",
+                problems.parent_child_disagreements,
+            )
+            .problem("missingChildren", problems.missing_children)
+            .problem("deletedChildren", problems.deleted_children);
+    }
+
+    // Dogear can't build trees with cycles, so they're counted separately.
+    fn record_cycles(&self, cycles: usize) {
+        self.validation.borrow_mut().problem("cycles", cycles);
+        if cycles > 0 {
+            self.validated.set(true);
+        }
+    }
+
+    /// Returns the validation telemetry, if we validated the remote tree.
+    fn into_validation(self) -> Option<telemetry::Validation> {
+        if self.validated.get() {
+            Some(self.validation.into_inner())
+        } else {
+            None
+        }
+    }
 }
 
 impl dogear::Driver for Driver {
@@ -1031,25 +1176,22 @@ impl dogear::Driver for Driver {
     fn record_telemetry_event(&self, event: TelemetryEvent) {
         // Record validation telemetry for remote trees.
         if let TelemetryEvent::FetchRemoteTree(stats) = event {
-            self.validation
-                .borrow_mut()
-                .problem("orphans", stats.problems.orphans)
-                .problem("misparentedRoots", stats.problems.misparented_roots)
-                .problem(
-                    "multipleParents",
-                    stats.problems.multiple_parents_by_children,
-                )
-                .problem("missingParents", stats.problems.missing_parent_guids)
-                .problem("nonFolderParents", stats.problems.non_folder_parent_guids)
-                .problem(
-                    "parentChildDisagreements",
-                    stats.problems.parent_child_disagreements,
-                )
-                .problem("missingChildren", stats.problems.missing_children);
+            self.record_problems(&stats.problems);
         }
     }
 }
 
+/// Returns the item where dogear found a cycle, if that's why it failed.
+fn cycle_guid(err: &Error) -> Option<SyncGuid> {
+    match err.kind() {
+        ErrorKind::MergeError(err) => match err.kind() {
+            dogear::ErrorKind::Cycle(guid) => Some(guid.as_str().into()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 // The "merger", which is just a thin wrapper for dogear.
 pub(crate) struct Merger<'a> {
     store: &'a BookmarksStore<'a>,
@@ -1061,6 +1203,8 @@ pub(crate) struct Merger<'a> {
     // turns it on, to avoid accidentally enabling unintentionally.
     external_transaction: bool,
     telem: Option<&'a mut telemetry::Engine>,
+    // Whether to fix the remote tree's problems before merging.
+    repair: bool,
 }
 
 impl<'a> Merger<'a> {
@@ -1071,6 +1215,7 @@ impl<'a> Merger<'a> {
             local_time: Timestamp::now(),
             external_transaction: false,
             telem: None,
+            repair: false,
         }
     }
 
@@ -1085,6 +1230,7 @@ impl<'a> Merger<'a> {
             local_time: Timestamp::now(),
             external_transaction: false,
             telem: Some(telem),
+            repair: false,
         }
     }
 
@@ -1100,6 +1246,7 @@ impl<'a> Merger<'a> {
             local_time,
             external_transaction: false,
             telem: None,
+            repair: false,
         }
     }
 
@@ -1111,23 +1258,78 @@ impl<'a> Merger<'a> {
         self.external_transaction = v;
     }
 
+    /// Repairs the remote tree before merging. See the `repair` module.
+    pub(crate) fn set_repair(&mut self, v: bool) {
+        self.repair = v;
+    }
+
     pub(crate) fn merge(&mut self) -> Result<()> {
+        let driver = Driver::default();
+        let result = self.repair_and_merge(&driver);
+
+        // Record telemetry in all cases, even if the merge fails.
+        if let Some(ref mut telem) = self.telem {
+            if let Some(validation) = driver.into_validation() {
+                telem.validation(validation);
+            }
+        }
+        result
+    }
+
+    fn repair_and_merge(&mut self, driver: &Driver) -> Result<()> {
         use dogear::Store;
+        // A repair can flag items for reupload, so it happens before we
+        // check for changes.
+        if self.repair {
+            self.repair_remote_tree(driver)?;
+        }
         if !self.store.has_changes()? {
             return Ok(());
         }
         // Merge and stage outgoing items via dogear.
-        let driver = Driver::default();
-        let result = self.merge_with_driver(&driver, &MergeInterruptee(self.store.interruptee));
+        let result = self.merge_with_driver(driver, &MergeInterruptee(self.store.interruptee));
         log::debug!("merge completed");
-
-        // Record telemetry in all cases, even if the merge fails.
-        if let Some(ref mut telem) = self.telem {
-            telem.validation(driver.validation.into_inner());
+        if let Err(e) = &result {
+            if cycle_guid(e).is_some() {
+                driver.record_cycles(1);
+            }
         }
         result
     }
 
+    /// Validates the remote tree, and fixes what we can in the mirror, so
+    /// that the merge uploads the fixed tree.
+    fn repair_remote_tree(&self, driver: &Driver) -> Result<()> {
+        use dogear::Store;
+        let tx = self.store.db.begin_transaction()?;
+        let mut cycles = 0;
+        let tree = loop {
+            match self.fetch_remote_tree() {
+                Ok(tree) => break tree,
+                // A detached item doesn't have a parent, so it can't be in
+                // another cycle, and this loop always ends.
+                Err(e) => match cycle_guid(&e) {
+                    Some(guid) => {
+                        log::warn!("Breaking a cycle in the remote tree at {}", guid);
+                        repair::detach_synced_item(self.store.db, &guid)?;
+                        cycles += 1;
+                    }
+                    None => return Err(e),
+                },
+            }
+        };
+        driver.record_problems(&tree.problems().counts());
+        driver.record_cycles(cycles);
+
+        let plan = RepairPlan::new(tree.problems());
+        if !plan.is_empty() {
+            log::info!("Repairing the remote tree: {:?}", plan);
+            plan.apply(self.store.db)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
     /// Creates a local tree item from a row in the `localItems` CTE.
     fn local_row_to_item(&self, row: &Row<'_>) -> Result<(Item, Option<Content>)> {
         let guid = row.get::<_, SyncGuid>("guid")?;
@@ -1631,7 +1833,21 @@ mod tests {
     use sync_guid::Guid;
     use url::Url;
 
-    use sync15::{CollSyncIds, Payload};
+    use sync15::{clients::RepairResponse, CollSyncIds, Payload};
+
+    // Applies incoming records, and returns every outgoing record, including
+    // the ones inflated during the upload.
+    fn apply_and_fetch_outgoing(
+        store: &BookmarksStore<'_>,
+        incoming: IncomingChangeset,
+    ) -> result::Result<OutgoingChangeset, failure::Error> {
+        let mut outgoing =
+            store.apply_incoming(vec![incoming], &mut telemetry::Engine::new("bookmarks"))?;
+        for record in store.fetch_outgoing_records()? {
+            outgoing.changes.push(record?);
+        }
+        Ok(outgoing)
+    }
 
     fn apply_incoming(conn: &PlacesDb, remote_time: ServerTimestamp, records_json: Value) {
         // suck records into the store.
@@ -2154,9 +2370,11 @@ mod tests {
             json!({"children" : [{"guid": "bookmarkAAAA", "url": "http://example.com/a?b=c&d=%s"}]}),
         );
 
-        let outgoing = store.fetch_outgoing_records(ServerTimestamp(0))?;
+        let outgoing = store
+            .fetch_outgoing_records()
+            .and_then(|records| records.collect::<result::Result<Vec<_>, _>>())
+            .expect("Should fetch outgoing records");
         let record_for_a = outgoing
-            .changes
             .iter()
             .find(|payload| payload.id == "bookmarkAAAA")
             .expect("Should reupload A");
@@ -2269,8 +2487,7 @@ mod tests {
             incoming.changes.push((payload, ServerTimestamp(0)));
         }
 
-        let mut outgoing = store
-            .apply_incoming(vec![incoming], &mut telemetry::Engine::new("bookmarks"))
+        let mut outgoing = apply_and_fetch_outgoing(&store, incoming)
             .expect("Should apply incoming and stage outgoing records");
         outgoing.changes.sort_by(|a, b| a.id.cmp(&b.id));
         assert_eq!(
@@ -2419,9 +2636,8 @@ mod tests {
             incoming.changes.push((payload, ServerTimestamp(0)));
         }
 
-        let outgoing = store
-            .apply_incoming(vec![incoming], &mut telemetry::Engine::new("bookmarks"))
-            .expect("Should apply incoming records");
+        let outgoing =
+            apply_and_fetch_outgoing(&store, incoming).expect("Should apply incoming records");
         let mut outgoing_ids = outgoing
             .changes
             .iter()
@@ -2449,15 +2665,11 @@ mod tests {
             .into(),
         )?;
 
-        let outgoing = store
-            .apply_incoming(
-                vec![IncomingChangeset::new(
-                    store.collection_name(),
-                    ServerTimestamp(1000),
-                )],
-                &mut telemetry::Engine::new("bookmarks"),
-            )
-            .expect("Should fetch outgoing records after making local changes");
+        let outgoing = apply_and_fetch_outgoing(
+            &store,
+            IncomingChangeset::new(store.collection_name(), ServerTimestamp(1000)),
+        )
+        .expect("Should fetch outgoing records after making local changes");
         assert_eq!(outgoing.changes.len(), 1);
         assert_eq!(outgoing.changes[0].id, "bookmarkAAAA");
         assert_eq!(outgoing.changes[0].data["keyword"], "a");
@@ -2559,9 +2771,8 @@ mod tests {
             incoming.changes.push((payload, ServerTimestamp(0)));
         }
 
-        let outgoing = store
-            .apply_incoming(vec![incoming], &mut telemetry::Engine::new("bookmarks"))
-            .expect("Should apply incoming records");
+        let outgoing =
+            apply_and_fetch_outgoing(&store, incoming).expect("Should apply incoming records");
         let mut outgoing_ids = outgoing
             .changes
             .iter()
@@ -2621,8 +2832,7 @@ mod tests {
             ServerTimestamp(1000),
         ));
 
-        let outgoing = store
-            .apply_incoming(vec![incoming], &mut telemetry::Engine::new("bookmarks"))
+        let outgoing = apply_and_fetch_outgoing(&store, incoming)
             .expect("Should apply F and stage tombstones for A-E");
         let (outgoing_tombstones, outgoing_records): (Vec<_>, Vec<_>) =
             outgoing.changes.iter().partition(|record| record.deleted);
@@ -2688,6 +2898,216 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_fetch_outgoing_records_in_pages() -> result::Result<(), failure::Error> {
+        let api = new_mem_api();
+        let writer = api.open_connection(ConnectionType::ReadWrite)?;
+
+        // Enough bookmarks to need more than one page of records.
+        let count = OUTGOING_RECORDS_PAGE_SIZE as usize + 10;
+        let children = (0..count)
+            .map(|i| {
+                json!({
+                    "guid": format!("bookmark{:04}", i),
+                    "title": format!("{}", i),
+                    "url": format!("http://example.com/{}", i),
+                })
+            })
+            .collect::<Vec<_>>();
+        insert_local_json_tree(
+            &writer,
+            json!({
+                "guid": &BookmarkRootGuid::Unfiled.as_guid(),
+                "children": children,
+            }),
+        );
+
+        let syncer = api.open_sync_connection()?;
+        let interrupt_scope = syncer.begin_interrupt_scope();
+        let store = BookmarksStore::new(&syncer, &interrupt_scope);
+
+        let incoming = IncomingChangeset::new(store.collection_name(), ServerTimestamp(1_000));
+        let outgoing =
+            store.apply_incoming(vec![incoming], &mut telemetry::Engine::new("bookmarks"))?;
+        assert!(
+            outgoing.changes.is_empty(),
+            "Records should be fetched during the upload"
+        );
+
+        let records = store
+            .fetch_outgoing_records()?
+            .collect::<result::Result<Vec<_>, _>>()?;
+        assert_eq!(
+            records.len(),
+            count + 4,
+            "should be 4 roots + the bookmarks"
+        );
+        let mut ids = records.iter().map(|r| r.id.as_str()).collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), records.len(), "Records shouldn't be repeated");
+        let unfiled = records
+            .iter()
+            .find(|r| r.id == "unfiled")
+            .expect("Should upload the unfiled folder");
+        assert_eq!(unfiled.data["children"].as_array().unwrap().len(), count);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_and_repair() -> result::Result<(), failure::Error> {
+        let _ = env_logger::try_init();
+        let api = new_mem_api();
+        let syncer = api.open_sync_connection()?;
+        let interrupt_scope = syncer.begin_interrupt_scope();
+        let store = BookmarksStore::new(&syncer, &interrupt_scope);
+
+        // The menu lists a child that doesn't exist, and C's parent doesn't
+        // exist.
+        let records = vec![
+            json!({
+                "id": "menu",
+                "type": "folder",
+                "parentid": "places",
+                "parentName": "",
+                "dateAdded": 0,
+                "title": "menu",
+                "children": ["bookmarkAAAA", "missingBBBBB"],
+            }),
+            json!({
+                "id": "bookmarkAAAA",
+                "type": "bookmark",
+                "parentid": "menu",
+                "parentName": "menu",
+                "dateAdded": 0,
+                "title": "A",
+                "bmkUri": "http://example.com/a",
+            }),
+            json!({
+                "id": "bookmarkCCCC",
+                "type": "bookmark",
+                "parentid": "missingDDDDD",
+                "parentName": "",
+                "dateAdded": 0,
+                "title": "C",
+                "bmkUri": "http://example.com/c",
+            }),
+        ];
+        let mut incoming = IncomingChangeset::new(store.collection_name(), ServerTimestamp(0));
+        for record in records {
+            incoming
+                .changes
+                .push((Payload::from_json(record)?, ServerTimestamp(0)));
+        }
+
+        repair::request_repair(&syncer)?;
+        let mut telem = telemetry::Engine::new("bookmarks");
+        store.apply_incoming(vec![incoming], &mut telem)?;
+
+        let problems = serde_json::to_value(&telem)?["validation"]["problems"].clone();
+        assert_eq!(
+            problems,
+            json!([
+                { "name": "missingParents", "count": 1 },
+                { "name": "parentChildDisagreements", "count": 1 },
+                { "name": "missingChildren", "count": 1 },
+            ])
+        );
+
+        // The menu should be uploaded without the missing child, and we
+        // should ask other clients for the items we don't have.
+        let records = store
+            .fetch_outgoing_records()?
+            .collect::<result::Result<Vec<_>, _>>()?;
+        let menu = records
+            .iter()
+            .find(|r| r.id == "menu")
+            .expect("Should upload the menu");
+        assert_eq!(menu.data["children"], json!(["bookmarkAAAA"]));
+        let request = repair::fetch_outgoing_repair_request(&syncer, "localClient")?
+            .expect("Should ask other clients for missing items");
+        assert_eq!(request.collection, "bookmarks");
+        assert_eq!(request.requestor, "localClient");
+        assert_eq!(
+            request.ids,
+            vec!["bookmarkCCCC", "missingBBBBB", "missingDDDDD"]
+        );
+        assert!(!repair::is_repair_requested(&syncer)?);
+
+        let saved = get_meta::<String>(&syncer, repair::OUTGOING_REPAIR_REQUEST_META_KEY)?
+            .expect("Should store the request");
+
+        // A response for another repair doesn't change our request, but one
+        // for ours stops asking for the items that were uploaded.
+        let response = |flow_id: &str, ids: &[&str]| RepairResponse {
+            collection: "bookmarks".into(),
+            request: "upload".into(),
+            client_id: "remoteClient".into(),
+            ids: ids.iter().map(|&id| id.to_owned()).collect(),
+            flow_id: flow_id.into(),
+        };
+        repair::repair_response_received(&syncer, &response("otherFlowIDD", &["missingBBBBB"]))?;
+        repair::repair_response_received(
+            &syncer,
+            &response(request.flow_id.as_str(), &["missingBBBBB"]),
+        )?;
+        let request = repair::fetch_outgoing_repair_request(&syncer, "localClient")?
+            .expect("Should still ask for the other items");
+        assert_eq!(request.ids, vec!["bookmarkCCCC", "missingDDDDD"]);
+        repair::repair_response_received(
+            &syncer,
+            &response(request.flow_id.as_str(), &["bookmarkCCCC", "missingDDDDD"]),
+        )?;
+        assert!(repair::fetch_outgoing_repair_request(&syncer, "localClient")?.is_none());
+
+        put_meta(&syncer, repair::OUTGOING_REPAIR_REQUEST_META_KEY, &saved)?;
+        repair::repair_request_sent(&syncer)?;
+        assert!(repair::fetch_outgoing_repair_request(&syncer, "localClient")?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_invalid_type() -> result::Result<(), failure::Error> {
+        let api = new_mem_api();
+        let syncer = api.open_sync_connection()?;
+        let interrupt_scope = syncer.begin_interrupt_scope();
+        let store = BookmarksStore::new(&syncer, &interrupt_scope);
+
+        let mut incoming = IncomingChangeset::new(store.collection_name(), ServerTimestamp(0));
+        for record in vec![
+            json!({
+                "id": "bookmarkAAAA",
+                "type": "bookmark",
+                "parentid": "menu",
+                "parentName": "menu",
+                "dateAdded": 0,
+                "title": "A",
+                "bmkUri": "http://example.com/a",
+            }),
+            json!({
+                "id": "unknownBBBBB",
+                "type": "microsummary",
+                "parentid": "menu",
+            }),
+        ] {
+            incoming
+                .changes
+                .push((Payload::from_json(record)?, ServerTimestamp(0)));
+        }
+        let mut telem = telemetry::Engine::new("bookmarks");
+        store.apply_incoming(vec![incoming], &mut telem)?;
+
+        let incoming_telem = telem.get_incoming().expect("Should record incoming");
+        assert_eq!(incoming_telem.get_applied(), 1);
+        assert_eq!(
+            serde_json::to_value(incoming_telem)?["failedReasons"],
+            json!([{ "name": "invalid", "count": 1 }])
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_reset() -> result::Result<(), failure::Error> {
         let api = new_mem_api();
@@ -2716,8 +3136,7 @@ mod tests {
             assert_eq!(store.get_sync_assoc()?, StoreSyncAssociation::Disconnected);
 
             let incoming = IncomingChangeset::new(store.collection_name(), ServerTimestamp(1_000));
-            let outgoing =
-                store.apply_incoming(vec![incoming], &mut telemetry::Engine::new("bookmarks"))?;
+            let outgoing = apply_and_fetch_outgoing(&store, incoming)?;
             let synced_ids: Vec<Guid> = outgoing.changes.iter().map(|c| c.id.clone()).collect();
             assert_eq!(synced_ids.len(), 5, "should be 4 roots + 1 outgoing item");
             store.sync_finished(ServerTimestamp(2_000), synced_ids)?;
@@ -2735,8 +3154,7 @@ mod tests {
             let store = BookmarksStore::new(&syncer, &interrupt_scope);
 
             let incoming = IncomingChangeset::new(store.collection_name(), ServerTimestamp(1_000));
-            let outgoing =
-                store.apply_incoming(vec![incoming], &mut telemetry::Engine::new("bookmarks"))?;
+            let outgoing = apply_and_fetch_outgoing(&store, incoming)?;
             let synced_ids: Vec<Guid> = outgoing.changes.iter().map(|c| c.id.clone()).collect();
             assert_eq!(synced_ids.len(), 5, "should be 4 roots + 1 outgoing item");
             store.sync_finished(ServerTimestamp(2_000), synced_ids)?;
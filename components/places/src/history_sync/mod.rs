@@ -2,6 +2,9 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use crate::db::PlacesDb;
+use crate::error::*;
+use crate::storage::{get_meta, put_meta};
 use crate::types::Timestamp;
 use serde_derive::*;
 use std::fmt;
@@ -11,11 +14,108 @@ mod plan;
 pub mod record;
 pub mod store;
 
+// We download history this many records at a time.
+const INCOMING_PAGE_SIZE: usize = 5000;
+// By default, one sync downloads at most this many records. The next sync
+// downloads the rest.
 const MAX_INCOMING_PLACES: usize = 5000;
 const MAX_OUTGOING_PLACES: usize = 5000;
 const MAX_VISITS: usize = 20;
 pub const HISTORY_TTL: u32 = 5_184_000; // 60 days in milliseconds
 
+pub const SYNC_POLICY_META_KEY: &str = "history_sync_policy";
+
+/// How much history we download and upload. The defaults download at most
+/// 5000 records in each sync, and upload the 5000 most recently changed pages
+/// with their 20 most recent visits.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct HistorySyncPolicy {
+    /// If set, the first sync only downloads the records that were modified
+    /// on the server in the last this many days. This is when a client last
+    /// uploaded the record, not when the page was visited, so a recently
+    /// uploaded record can still have old visits. The rest is left for
+    /// backfilling.
+    pub first_sync_modified_days: Option<u32>,
+    /// If set, the most records to download in one sync. The rest are
+    /// downloaded by the next sync. When backfilling, this applies to the
+    /// backfill and the new records separately. `None` downloads everything
+    /// on the server in one sync.
+    pub max_incoming_records: Option<usize>,
+    /// Whether syncs after the first download the older history that it
+    /// skipped, a page at a time, until there's none left.
+    pub backfill: bool,
+    /// The most visits to upload, and keep from incoming records, for each
+    /// page.
+    pub max_visits: usize,
+    /// The most pages to upload in one sync.
+    pub max_outgoing_records: usize,
+}
+
+impl Default for HistorySyncPolicy {
+    fn default() -> Self {
+        HistorySyncPolicy {
+            first_sync_modified_days: None,
+            max_incoming_records: Some(MAX_INCOMING_PLACES),
+            backfill: true,
+            max_visits: MAX_VISITS,
+            max_outgoing_records: MAX_OUTGOING_PLACES,
+        }
+    }
+}
+
+impl From<crate::msg_types::HistorySyncPolicy> for HistorySyncPolicy {
+    fn from(msg: crate::msg_types::HistorySyncPolicy) -> Self {
+        let default = HistorySyncPolicy::default();
+        HistorySyncPolicy {
+            first_sync_modified_days: msg.first_sync_modified_days,
+            // 0 means there's no limit.
+            max_incoming_records: match msg.max_incoming_records {
+                Some(0) => None,
+                Some(n) => Some(n as usize),
+                None => default.max_incoming_records,
+            },
+            backfill: msg.backfill.unwrap_or(default.backfill),
+            max_visits: msg
+                .max_visits
+                .map(|n| n as usize)
+                .unwrap_or(default.max_visits),
+            max_outgoing_records: msg
+                .max_outgoing_records
+                .map(|n| n as usize)
+                .unwrap_or(default.max_outgoing_records),
+        }
+    }
+}
+
+/// Returns the policy set with `set_sync_policy`, or the default policy.
+pub fn get_sync_policy(db: &PlacesDb) -> Result<HistorySyncPolicy> {
+    let json = match get_meta::<String>(db, SYNC_POLICY_META_KEY)? {
+        Some(json) => json,
+        None => return Ok(HistorySyncPolicy::default()),
+    };
+    Ok(match serde_json::from_str(&json) {
+        Ok(policy) => policy,
+        Err(e) => {
+            log::warn!("Ignoring invalid history sync policy: {}", e);
+            HistorySyncPolicy::default()
+        }
+    })
+}
+
+/// Sets the policy for future history syncs. The policy isn't forgotten when
+/// history sync is reset. Limits of 0 for `max_visits` and
+/// `max_outgoing_records` would stop us from syncing any history, so they're
+/// raised to 1.
+pub fn set_sync_policy(db: &PlacesDb, policy: &HistorySyncPolicy) -> Result<()> {
+    let policy = HistorySyncPolicy {
+        max_visits: policy.max_visits.max(1),
+        max_outgoing_records: policy.max_outgoing_records.max(1),
+        ..policy.clone()
+    };
+    put_meta(db, SYNC_POLICY_META_KEY, &serde_json::to_string(&policy)?)
+}
+
 /// Visit timestamps on the server are *microseconds* since the epoch.
 #[derive(
     Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize, Serialize, Default,
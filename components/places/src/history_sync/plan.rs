@@ -3,13 +3,14 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use super::record::{HistoryRecord, HistoryRecordVisit, HistorySyncRecord};
-use super::{HISTORY_TTL, MAX_OUTGOING_PLACES, MAX_VISITS};
+use super::{HistorySyncPolicy, HISTORY_TTL};
 use crate::api::history::can_add_url;
 use crate::db::PlacesDb;
 use crate::error::*;
 use crate::storage::history::history_sync::{
     apply_synced_deletion, apply_synced_reconciliation, apply_synced_visits, fetch_outgoing,
-    fetch_visits, finish_incoming, finish_outgoing, FetchedVisit, FetchedVisitPage, OutgoingInfo,
+    fetch_visits, finish_incoming, finish_outgoing, forget_outgoing, FetchedVisit,
+    FetchedVisitPage, OutgoingInfo,
 };
 use crate::types::{Timestamp, VisitTransition};
 use interrupt::Interruptee;
@@ -170,12 +171,15 @@ fn plan_incoming_record(conn: &PlacesDb, record: HistoryRecord, max_visits: usiz
     }
 }
 
-pub fn apply_plan(
+/// Applies incoming records without preparing outgoing ones, for applying
+/// the pages of a paged download.
+pub fn apply_incoming_plan(
     db: &PlacesDb,
     inbound: IncomingChangeset,
+    policy: &HistorySyncPolicy,
     telem: &mut telemetry::EngineIncoming,
     interruptee: &impl Interruptee,
-) -> Result<OutgoingChangeset> {
+) -> Result<()> {
     // for a first-cut, let's do this in the most naive way possible...
     let mut plans: Vec<(SyncGuid, IncomingPlan)> = Vec::with_capacity(inbound.changes.len());
     for incoming in inbound.changes {
@@ -186,12 +190,12 @@ pub fn apply_plan(
                 // We can't push IncomingPlan::Invalid into plans as we don't
                 // know the guid - just skip it.
                 log::warn!("Error deserializing incoming record: {}", e);
-                telem.failed(1);
+                telem.failed_with_reason(1, "deserialize");
                 continue;
             }
         };
         let plan = match item.record {
-            Some(record) => plan_incoming_record(db, record, MAX_VISITS),
+            Some(record) => plan_incoming_record(db, record, policy.max_visits),
             None => IncomingPlan::Delete,
         };
         let guid = item.guid.clone();
@@ -200,7 +204,6 @@ pub fn apply_plan(
 
     let mut tx = db.begin_transaction()?;
 
-    let mut outgoing = OutgoingChangeset::new("history", inbound.timestamp);
     for (guid, plan) in plans {
         interruptee.err_if_interrupted()?;
         tx.maybe_commit()?;
@@ -215,11 +218,11 @@ pub fn apply_plan(
                     guid,
                     err
                 );
-                telem.failed(1);
+                telem.failed_with_reason(1, "invalid");
             }
             IncomingPlan::Failed(err) => {
                 log::error!("incoming: record {:?} failed to apply: {}", guid, err);
-                telem.failed(1);
+                telem.failed_with_reason(1, "apply");
             }
             IncomingPlan::Delete => {
                 log::trace!("incoming: deleting {:?}", guid);
@@ -250,12 +253,24 @@ pub fn apply_plan(
     }
     finish_incoming(&db)?;
     tx.commit()?;
+    Ok(())
+}
+
+pub fn apply_plan(
+    db: &PlacesDb,
+    inbound: IncomingChangeset,
+    policy: &HistorySyncPolicy,
+    telem: &mut telemetry::EngineIncoming,
+    interruptee: &impl Interruptee,
+) -> Result<OutgoingChangeset> {
+    let mut outgoing = OutgoingChangeset::new("history", inbound.timestamp);
+    apply_incoming_plan(db, inbound, policy, telem, interruptee)?;
     // It might make sense for fetch_outgoing to manage its own
     // begin_transaction - even though doesn't seem a large bottleneck
     // at this time, the fact we hold a single transaction for the entire call
     // really is used only for performance, so it's certainly a candidate.
     let tx = db.begin_transaction()?;
-    let mut out_infos = fetch_outgoing(db, MAX_OUTGOING_PLACES, MAX_VISITS)?;
+    let mut out_infos = fetch_outgoing(db, policy.max_outgoing_records, policy.max_visits)?;
 
     for (guid, out_record) in out_infos.drain() {
         let payload = match out_record {
@@ -281,6 +296,12 @@ pub fn finish_plan(db: &PlacesDb) -> Result<()> {
     Ok(())
 }
 
+/// Finishes a read-only sync, which didn't upload the outgoing records
+/// `apply_plan` returned, so they're left to upload on the next sync.
+pub fn finish_readonly_plan(db: &PlacesDb) -> Result<()> {
+    forget_outgoing(db)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -509,6 +530,7 @@ mod tests {
         let outgoing = apply_plan(
             &db,
             incoming,
+            &HistorySyncPolicy::default(),
             &mut telemetry::EngineIncoming::new(),
             &NeverInterrupts,
         )?;
@@ -575,6 +597,7 @@ mod tests {
         let outgoing = apply_plan(
             &db,
             incoming,
+            &HistorySyncPolicy::default(),
             &mut telemetry::EngineIncoming::new(),
             &NeverInterrupts,
         )?;
@@ -637,6 +660,7 @@ mod tests {
         let outgoing = apply_plan(
             &db,
             incoming,
+            &HistorySyncPolicy::default(),
             &mut telemetry::EngineIncoming::new(),
             &NeverInterrupts,
         )?;
@@ -673,6 +697,7 @@ mod tests {
         let outgoing = apply_plan(
             &db,
             result,
+            &HistorySyncPolicy::default(),
             &mut telemetry::EngineIncoming::new(),
             &NeverInterrupts,
         )?;
@@ -708,6 +733,7 @@ mod tests {
         let outgoing = apply_plan(
             &db,
             result,
+            &HistorySyncPolicy::default(),
             &mut telemetry::EngineIncoming::new(),
             &NeverInterrupts,
         )?;
@@ -760,6 +786,7 @@ mod tests {
         let outgoing = apply_plan(
             &db,
             result,
+            &HistorySyncPolicy::default(),
             &mut telemetry::EngineIncoming::new(),
             &NeverInterrupts,
         )?;
@@ -806,6 +833,7 @@ mod tests {
         let outgoing = apply_plan(
             &db,
             incoming,
+            &HistorySyncPolicy::default(),
             &mut telemetry::EngineIncoming::new(),
             &NeverInterrupts,
         )?;
@@ -848,6 +876,7 @@ mod tests {
         apply_plan(
             &db,
             incoming,
+            &HistorySyncPolicy::default(),
             &mut telemetry::EngineIncoming::new(),
             &NeverInterrupts,
         )?;
@@ -894,6 +923,7 @@ mod tests {
         let outgoing = apply_plan(
             &db,
             incoming,
+            &HistorySyncPolicy::default(),
             &mut telemetry::EngineIncoming::new(),
             &NeverInterrupts,
         )?;
@@ -947,6 +977,7 @@ mod tests {
         let outgoing = apply_plan(
             &db,
             incoming,
+            &HistorySyncPolicy::default(),
             &mut telemetry::EngineIncoming::new(),
             &NeverInterrupts,
         )?;
@@ -970,6 +1001,7 @@ mod tests {
         apply_plan(
             &db,
             IncomingChangeset::new("history", ServerTimestamp(0i64)),
+            &HistorySyncPolicy::default(),
             &mut telemetry::EngineIncoming::new(),
             &NeverInterrupts,
         )?;
@@ -989,6 +1021,7 @@ mod tests {
         let outgoing = apply_plan(
             &db,
             incoming,
+            &HistorySyncPolicy::default(),
             &mut telemetry::EngineIncoming::new(),
             &NeverInterrupts,
         )?;
@@ -1011,6 +1044,7 @@ mod tests {
         apply_plan(
             &db,
             IncomingChangeset::new("history", ServerTimestamp(0i64)),
+            &HistorySyncPolicy::default(),
             &mut telemetry::EngineIncoming::new(),
             &NeverInterrupts,
         )?;
@@ -1026,6 +1060,7 @@ mod tests {
         let outgoing = apply_plan(
             &db,
             IncomingChangeset::new("history", ServerTimestamp(0i64)),
+            &HistorySyncPolicy::default(),
             &mut telemetry::EngineIncoming::new(),
             &NeverInterrupts,
         )?;
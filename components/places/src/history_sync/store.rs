@@ -9,22 +9,31 @@ use crate::storage::history::{
     delete_everything,
     history_sync::{reset, reset_meta},
 };
+use crate::types::Timestamp;
 use rusqlite::types::{FromSql, ToSql};
 use rusqlite::Connection;
 use sql_support::SqlInterruptScope;
 use std::ops::Deref;
 use std::result;
+use std::time::Duration;
 use sync15::telemetry;
 use sync15::{
-    extract_v1_state, CollSyncIds, CollectionRequest, IncomingChangeset, OutgoingChangeset,
-    ServerTimestamp, Store, StoreSyncAssociation,
+    extract_v1_state, CollSyncIds, CollectionRequest, DownloadProgress, IncomingChangeset,
+    OutgoingChangeset, ServerTimestamp, Store, StoreSyncAssociation,
 };
 use sync_guid::Guid;
 
-use super::plan::{apply_plan, finish_plan};
-use super::MAX_INCOMING_PLACES;
+use super::plan::{apply_incoming_plan, apply_plan, finish_plan, finish_readonly_plan};
+use super::{get_sync_policy, HistorySyncPolicy, INCOMING_PAGE_SIZE};
 
 pub const LAST_SYNC_META_KEY: &str = "history_last_sync_time";
+// The progress of an interrupted download, as JSON.
+pub const DOWNLOAD_PROGRESS_META_KEY: &str = "history_download_progress";
+// The server timestamp, in milliseconds, before which we haven't downloaded
+// history yet, if the first sync was limited to recent history.
+pub const BACKFILL_BEFORE_META_KEY: &str = "history_backfill_before";
+// The progress of the backfill download, as JSON.
+pub const BACKFILL_PROGRESS_META_KEY: &str = "history_backfill_progress";
 // Note that all engines in this crate should use a *different* meta key
 // for the global sync ID, because engines are reset individually.
 pub const GLOBAL_SYNCID_META_KEY: &str = "history_global_sync_id";
@@ -51,24 +60,181 @@ impl<'a> HistoryStore<'a> {
         crate::storage::get_meta(self.db, key)
     }
 
+    fn delete_meta(&self, key: &str) -> Result<()> {
+        crate::storage::delete_meta(self.db, key)
+    }
+
+    /// Returns the `newer` of the request for new records. Until the first
+    /// download finishes, that's the start of the first sync's window, if it
+    /// has one.
+    fn incoming_since(&self) -> Result<i64> {
+        let last_sync = self
+            .get_meta::<i64>(LAST_SYNC_META_KEY)?
+            .unwrap_or_default();
+        if last_sync != 0 {
+            return Ok(last_sync);
+        }
+        Ok(self
+            .get_meta::<i64>(BACKFILL_BEFORE_META_KEY)?
+            .unwrap_or_default())
+    }
+
+    /// Starts the first sync's window, if the policy has one and it hasn't
+    /// started already. The start is persisted, so that an interrupted first
+    /// download resumes with the same request, and so we know where to
+    /// backfill from.
+    fn start_first_sync_window(&self, policy: &HistorySyncPolicy) -> Result<()> {
+        let days = match policy.first_sync_modified_days {
+            Some(days) => days,
+            None => return Ok(()),
+        };
+        if self.get_meta::<i64>(BACKFILL_BEFORE_META_KEY)?.is_some() {
+            return Ok(());
+        }
+        let window = Duration::from_secs(u64::from(days) * 24 * 60 * 60);
+        let start = Timestamp::now()
+            .checked_sub(window)
+            .unwrap_or(Timestamp::EARLIEST);
+        log::info!(
+            "First history sync is limited to records modified in the last {} days",
+            days
+        );
+        self.put_meta(BACKFILL_BEFORE_META_KEY, &(start.as_millis() as i64))
+    }
+
+    fn incoming_request(&self, policy: &HistorySyncPolicy) -> CollectionRequest {
+        let request = CollectionRequest::new("history")
+            .full()
+            .limit(INCOMING_PAGE_SIZE);
+        match policy.max_incoming_records {
+            Some(max_records) => request.max_records(max_records),
+            None => request,
+        }
+    }
+
+    fn do_get_collection_requests(&self) -> Result<Vec<CollectionRequest>> {
+        let policy = get_sync_policy(self.db)?;
+        let last_sync = self
+            .get_meta::<i64>(LAST_SYNC_META_KEY)?
+            .unwrap_or_default();
+        let mut requests = Vec::with_capacity(2);
+        if last_sync == 0 {
+            self.start_first_sync_window(&policy)?;
+        } else if policy.backfill {
+            // The backfill must come first, because sync15 wants the request
+            // for new records last.
+            if let Some(before) = self.get_meta::<i64>(BACKFILL_BEFORE_META_KEY)? {
+                requests.push(
+                    self.incoming_request(&policy)
+                        .older_than(ServerTimestamp(before)),
+                );
+            }
+        }
+        requests.push(
+            self.incoming_request(&policy)
+                .newer_than(ServerTimestamp(self.incoming_since()?)),
+        );
+        Ok(requests)
+    }
+
     fn do_apply_incoming(
         &self,
-        inbound: IncomingChangeset,
+        inbound: Vec<IncomingChangeset>,
         telem: &mut telemetry::Engine,
     ) -> Result<OutgoingChangeset> {
+        let policy = get_sync_policy(self.db)?;
+        let backfilled = inbound.len() > 1;
+        // We download in pages, so the changesets don't have any records,
+        // and the last is for the new records.
+        let inbound = inbound
+            .into_iter()
+            .last()
+            .expect("history requests new records");
         let timestamp = inbound.timestamp;
         let outgoing = {
             let mut incoming_telemetry = telemetry::EngineIncoming::new();
-            let result = apply_plan(&self.db, inbound, &mut incoming_telemetry, self.interruptee);
+            let result = apply_plan(
+                &self.db,
+                inbound,
+                &policy,
+                &mut incoming_telemetry,
+                self.interruptee,
+            );
             telem.incoming(incoming_telemetry);
             result
         }?;
-        // write the timestamp now, so if we are interrupted creating outgoing
-        // changesets we don't need to re-reconcile what we just did.
-        self.put_meta(LAST_SYNC_META_KEY, &(timestamp.as_millis() as i64))?;
+        if self.is_download_finished(DOWNLOAD_PROGRESS_META_KEY)? {
+            // write the timestamp now, so if we are interrupted creating outgoing
+            // changesets we don't need to re-reconcile what we just did.
+            self.put_meta(LAST_SYNC_META_KEY, &(timestamp.as_millis() as i64))?;
+            self.delete_meta(DOWNLOAD_PROGRESS_META_KEY)?;
+        } else {
+            log::info!("Downloaded some new history, the next sync downloads the rest");
+        }
+        if backfilled && self.is_download_finished(BACKFILL_PROGRESS_META_KEY)? {
+            log::info!("Finished backfilling history");
+            self.delete_meta(BACKFILL_BEFORE_META_KEY)?;
+            self.delete_meta(BACKFILL_PROGRESS_META_KEY)?;
+        }
         Ok(outgoing)
     }
 
+    fn do_apply_incoming_page(
+        &self,
+        page: IncomingChangeset,
+        progress: Option<&DownloadProgress>,
+        telem: &mut telemetry::Engine,
+    ) -> Result<()> {
+        let policy = get_sync_policy(self.db)?;
+        let mut incoming_telemetry = telemetry::EngineIncoming::new();
+        let result = apply_incoming_plan(
+            &self.db,
+            page,
+            &policy,
+            &mut incoming_telemetry,
+            self.interruptee,
+        );
+        telem.incoming(incoming_telemetry);
+        result?;
+        if let Some(progress) = progress {
+            // Applying a page again is harmless, so it's OK if we're
+            // interrupted before writing this.
+            self.put_meta(
+                download_progress_meta_key(progress.older),
+                &serde_json::to_string(progress)?,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn is_download_finished(&self, key: &str) -> Result<bool> {
+        Ok(match self.get_download_progress_for_key(key)? {
+            Some(progress) => progress.offset.is_none(),
+            None => true,
+        })
+    }
+
+    fn do_get_download_progress(
+        &self,
+        request: &CollectionRequest,
+    ) -> Result<Option<DownloadProgress>> {
+        self.get_download_progress_for_key(download_progress_meta_key(request.older))
+    }
+
+    fn get_download_progress_for_key(&self, key: &str) -> Result<Option<DownloadProgress>> {
+        let json = match self.get_meta::<String>(key)? {
+            Some(json) => json,
+            None => return Ok(None),
+        };
+        Ok(match serde_json::from_str(&json) {
+            Ok(progress) => Some(progress),
+            Err(e) => {
+                log::warn!("Ignoring invalid download progress: {}", e);
+                None
+            }
+        })
+    }
+
     fn do_sync_finished(
         &self,
         new_timestamp: ServerTimestamp,
@@ -80,14 +246,27 @@ impl<'a> HistoryStore<'a> {
         );
         finish_plan(&self.db)?;
 
-        // write timestamp to reflect what we just wrote.
-        self.put_meta(LAST_SYNC_META_KEY, &(new_timestamp.as_millis() as i64))?;
+        // write timestamp to reflect what we just wrote, unless we still have
+        // new records to download, in which case the next sync resumes the
+        // same download.
+        if self.is_download_finished(DOWNLOAD_PROGRESS_META_KEY)? {
+            self.put_meta(LAST_SYNC_META_KEY, &(new_timestamp.as_millis() as i64))?;
+        }
 
         self.db.pragma_update(None, "wal_checkpoint", &"PASSIVE")?;
 
         Ok(())
     }
 
+    fn do_sync_finished_readonly(&self, new_timestamp: ServerTimestamp) -> Result<()> {
+        log::info!("read-only sync completed, keeping local changes to upload later");
+        finish_readonly_plan(&self.db)?;
+        if self.is_download_finished(DOWNLOAD_PROGRESS_META_KEY)? {
+            self.put_meta(LAST_SYNC_META_KEY, &(new_timestamp.as_millis() as i64))?;
+        }
+        Ok(())
+    }
+
     pub(crate) fn do_reset(&self, assoc: &StoreSyncAssociation) -> Result<()> {
         match assoc {
             StoreSyncAssociation::Disconnected => {
@@ -134,6 +313,16 @@ impl<'a> HistoryStore<'a> {
     }
 }
 
+// Backfill requests are the only ones with an `older`. They have their own
+// progress, so that backfilling doesn't lose our place in the new records.
+fn download_progress_meta_key(older: Option<ServerTimestamp>) -> &'static str {
+    if older.is_some() {
+        BACKFILL_PROGRESS_META_KEY
+    } else {
+        DOWNLOAD_PROGRESS_META_KEY
+    }
+}
+
 impl<'a> Deref for HistoryStore<'a> {
     type Target = Connection;
     #[inline]
@@ -152,11 +341,29 @@ impl<'a> Store for HistoryStore<'a> {
         inbound: Vec<IncomingChangeset>,
         telem: &mut telemetry::Engine,
     ) -> result::Result<OutgoingChangeset, failure::Error> {
-        assert_eq!(inbound.len(), 1, "history only requests one item");
-        let inbound = inbound.into_iter().next().unwrap();
         Ok(self.do_apply_incoming(inbound, telem)?)
     }
 
+    fn downloads_in_pages(&self) -> bool {
+        true
+    }
+
+    fn apply_incoming_page(
+        &self,
+        page: IncomingChangeset,
+        progress: Option<&DownloadProgress>,
+        telem: &mut telemetry::Engine,
+    ) -> result::Result<(), failure::Error> {
+        Ok(self.do_apply_incoming_page(page, progress, telem)?)
+    }
+
+    fn get_download_progress(
+        &self,
+        request: &CollectionRequest,
+    ) -> result::Result<Option<DownloadProgress>, failure::Error> {
+        Ok(self.do_get_download_progress(request)?)
+    }
+
     fn sync_finished(
         &self,
         new_timestamp: ServerTimestamp,
@@ -166,14 +373,16 @@ impl<'a> Store for HistoryStore<'a> {
         Ok(())
     }
 
+    fn sync_finished_readonly(
+        &self,
+        new_timestamp: ServerTimestamp,
+    ) -> result::Result<(), failure::Error> {
+        self.do_sync_finished_readonly(new_timestamp)?;
+        Ok(())
+    }
+
     fn get_collection_requests(&self) -> result::Result<Vec<CollectionRequest>, failure::Error> {
-        let since = self
-            .get_meta::<i64>(LAST_SYNC_META_KEY)?
-            .unwrap_or_default();
-        Ok(vec![CollectionRequest::new("history")
-            .full()
-            .newer_than(ServerTimestamp(since))
-            .limit(MAX_INCOMING_PLACES)])
+        Ok(self.do_get_collection_requests()?)
     }
 
     fn get_sync_assoc(&self) -> result::Result<StoreSyncAssociation, failure::Error> {
@@ -196,3 +405,367 @@ impl<'a> Store for HistoryStore<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history_sync::{set_sync_policy, ServerVisitTimestamp, MAX_INCOMING_PLACES};
+    use crate::observation::VisitObservation;
+    use crate::storage::history::apply_observation;
+    use crate::storage::history::history_sync::fetch_visits;
+    use crate::types::VisitTransition;
+    use serde_json::json;
+    use sql_support::ConnExt;
+    use std::sync::{atomic::AtomicUsize, Arc};
+    use sync15::Payload;
+    use url::Url;
+
+    fn page_of(urls: &[(&str, &str)], timestamp: ServerTimestamp) -> IncomingChangeset {
+        let now: Timestamp = std::time::SystemTime::now().into();
+        let mut page = IncomingChangeset::new("history", timestamp);
+        for (guid, url) in urls {
+            let payload = Payload::from_json(json!({
+                "id": guid,
+                "title": "title",
+                "histUri": url,
+                "visits": [{"date": ServerVisitTimestamp::from(now), "type": 1}],
+            }))
+            .unwrap();
+            page.changes.push((payload, timestamp));
+        }
+        page
+    }
+
+    #[test]
+    fn test_paged_download() -> Result<()> {
+        let _ = env_logger::try_init();
+        let db = PlacesDb::open_in_memory(ConnectionType::Sync)?;
+        let interruptee = SqlInterruptScope::new(Arc::new(AtomicUsize::new(0)));
+        let store = HistoryStore::new(&db, &interruptee);
+        assert!(store.downloads_in_pages());
+
+        let request = &store.get_collection_requests().unwrap()[0];
+        assert_eq!(request.max_records, Some(MAX_INCOMING_PLACES));
+        assert_eq!(store.do_get_download_progress(request)?, None);
+        let mut progress = DownloadProgress {
+            collection: "history".into(),
+            newer: request.newer,
+            older: None,
+            offset: Some("1".into()),
+            last_modified: ServerTimestamp(1000),
+        };
+        let mut telem = telemetry::Engine::new("history");
+        store.do_apply_incoming_page(
+            page_of(
+                &[("aaaaaaaaaaaa", "http://example.com/a")],
+                progress.last_modified,
+            ),
+            Some(&progress),
+            &mut telem,
+        )?;
+        // The page is applied, and we know where to resume from.
+        assert!(fetch_visits(&db, &Url::parse("http://example.com/a")?, 1)?.is_some());
+        assert_eq!(
+            store.do_get_download_progress(request)?.as_ref(),
+            Some(&progress)
+        );
+        assert!(progress.is_for(request));
+
+        progress.offset = None;
+        progress.last_modified = ServerTimestamp(2000);
+        store.do_apply_incoming_page(
+            page_of(
+                &[("bbbbbbbbbbbb", "http://example.com/b")],
+                progress.last_modified,
+            ),
+            Some(&progress),
+            &mut telem,
+        )?;
+        assert!(fetch_visits(&db, &Url::parse("http://example.com/b")?, 1)?.is_some());
+        assert_eq!(
+            store.do_get_download_progress(request)?.as_ref(),
+            Some(&progress)
+        );
+
+        // Applying the empty changeset after the last page finishes the
+        // download.
+        let outgoing = store.do_apply_incoming(
+            vec![IncomingChangeset::new("history", progress.last_modified)],
+            &mut telem,
+        )?;
+        assert_eq!(outgoing.changes.len(), 0);
+        assert_eq!(store.do_get_download_progress(request)?, None);
+        assert_eq!(store.get_meta::<i64>(LAST_SYNC_META_KEY)?, Some(2000));
+        Ok(())
+    }
+
+    #[test]
+    fn test_readonly_sync_keeps_local_changes() -> Result<()> {
+        let db = PlacesDb::open_in_memory(ConnectionType::Sync)?;
+        let interruptee = SqlInterruptScope::new(Arc::new(AtomicUsize::new(0)));
+        let store = HistoryStore::new(&db, &interruptee);
+        let url = Url::parse("http://example.com/local")?;
+        apply_observation(
+            &db,
+            VisitObservation::new(url.clone())
+                .with_visit_type(VisitTransition::Link)
+                .with_at(Some(Timestamp::now())),
+        )?;
+        db.execute_batch("INSERT INTO moz_places_tombstones(guid) VALUES('deaddeaddead')")?;
+        let change_counter_sql = format!(
+            "SELECT sync_change_counter FROM moz_places WHERE url = '{}'",
+            url.as_str()
+        );
+        let mut telem = telemetry::Engine::new("history");
+
+        let outgoing = store.do_apply_incoming(
+            vec![IncomingChangeset::new("history", ServerTimestamp(1000))],
+            &mut telem,
+        )?;
+        assert_eq!(outgoing.changes.len(), 2);
+        store.do_sync_finished_readonly(ServerTimestamp(1000))?;
+
+        // We won't download what we applied again, but the local change and
+        // the tombstone are still waiting to be uploaded.
+        assert_eq!(store.get_meta::<i64>(LAST_SYNC_META_KEY)?, Some(1000));
+        assert_eq!(db.query_one::<i64>(&change_counter_sql)?, 1);
+        assert_eq!(
+            db.query_one::<i64>("SELECT COUNT(*) FROM moz_places_tombstones")?,
+            1
+        );
+
+        // And the next sync uploads them.
+        let outgoing = store.do_apply_incoming(
+            vec![IncomingChangeset::new("history", ServerTimestamp(2000))],
+            &mut telem,
+        )?;
+        assert_eq!(outgoing.changes.len(), 2);
+        store.do_sync_finished(
+            ServerTimestamp(2000),
+            outgoing.changes.iter().map(|p| p.id.clone()).collect(),
+        )?;
+        assert_eq!(db.query_one::<i64>(&change_counter_sql)?, 0);
+        assert_eq!(
+            db.query_one::<i64>("SELECT COUNT(*) FROM moz_places_tombstones")?,
+            0
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_forgets_download_progress() -> Result<()> {
+        let db = PlacesDb::open_in_memory(ConnectionType::Sync)?;
+        let interruptee = SqlInterruptScope::new(Arc::new(AtomicUsize::new(0)));
+        let store = HistoryStore::new(&db, &interruptee);
+        let request = &store.get_collection_requests().unwrap()[0];
+        let progress = DownloadProgress {
+            collection: "history".into(),
+            newer: Some(ServerTimestamp(0)),
+            older: None,
+            offset: Some("1".into()),
+            last_modified: ServerTimestamp(1000),
+        };
+        store.do_apply_incoming_page(
+            page_of(&[], progress.last_modified),
+            Some(&progress),
+            &mut telemetry::Engine::new("history"),
+        )?;
+        assert!(store.do_get_download_progress(request)?.is_some());
+        store.do_reset(&StoreSyncAssociation::Disconnected)?;
+        assert_eq!(store.do_get_download_progress(request)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_incoming_records() -> Result<()> {
+        let db = PlacesDb::open_in_memory(ConnectionType::Sync)?;
+        let interruptee = SqlInterruptScope::new(Arc::new(AtomicUsize::new(0)));
+        let store = HistoryStore::new(&db, &interruptee);
+        set_sync_policy(
+            &db,
+            &HistorySyncPolicy {
+                max_incoming_records: Some(1),
+                ..HistorySyncPolicy::default()
+            },
+        )?;
+        let requests = store.get_collection_requests().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].max_records, Some(1));
+
+        // sync15 stopped after the first page, so there's more to download.
+        let progress = DownloadProgress {
+            collection: "history".into(),
+            newer: requests[0].newer,
+            older: None,
+            offset: Some("1".into()),
+            last_modified: ServerTimestamp(1000),
+        };
+        let mut telem = telemetry::Engine::new("history");
+        store.do_apply_incoming_page(
+            page_of(
+                &[("aaaaaaaaaaaa", "http://example.com/a")],
+                progress.last_modified,
+            ),
+            Some(&progress),
+            &mut telem,
+        )?;
+        store.do_apply_incoming(
+            vec![IncomingChangeset::new("history", progress.last_modified)],
+            &mut telem,
+        )?;
+        // sync15 doesn't give us the progress for the download after an
+        // upload conflict, because it's not the one we'd resume.
+        store.do_apply_incoming_page(
+            page_of(
+                &[("bbbbbbbbbbbb", "http://example.com/b")],
+                ServerTimestamp(1500),
+            ),
+            None,
+            &mut telem,
+        )?;
+        assert!(fetch_visits(&db, &Url::parse("http://example.com/b")?, 1)?.is_some());
+        store.do_sync_finished(ServerTimestamp(2000), vec![])?;
+
+        // We don't move our last sync time until the download finishes, so
+        // the next sync resumes the same request.
+        assert_eq!(store.incoming_since()?, 0);
+        let requests = store.get_collection_requests().unwrap();
+        assert_eq!(
+            store.do_get_download_progress(&requests[0])?.as_ref(),
+            Some(&progress)
+        );
+        assert!(progress.is_for(&requests[0]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_policy_zero_limits() -> Result<()> {
+        let db = PlacesDb::open_in_memory(ConnectionType::Sync)?;
+        set_sync_policy(
+            &db,
+            &HistorySyncPolicy {
+                max_visits: 0,
+                max_outgoing_records: 0,
+                ..HistorySyncPolicy::default()
+            },
+        )?;
+        let policy = get_sync_policy(&db)?;
+        assert_eq!(policy.max_visits, 1);
+        assert_eq!(policy.max_outgoing_records, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_policy_from_msg() {
+        let policy = HistorySyncPolicy::from(crate::msg_types::HistorySyncPolicy::default());
+        assert_eq!(policy, HistorySyncPolicy::default());
+        assert_eq!(policy.max_incoming_records, Some(MAX_INCOMING_PLACES));
+
+        // Downloading everything in one sync is opt-in.
+        let policy = HistorySyncPolicy::from(crate::msg_types::HistorySyncPolicy {
+            max_incoming_records: Some(0),
+            ..crate::msg_types::HistorySyncPolicy::default()
+        });
+        assert_eq!(policy.max_incoming_records, None);
+    }
+
+    #[test]
+    fn test_first_sync_window_and_backfill() -> Result<()> {
+        let db = PlacesDb::open_in_memory(ConnectionType::Sync)?;
+        let interruptee = SqlInterruptScope::new(Arc::new(AtomicUsize::new(0)));
+        let store = HistoryStore::new(&db, &interruptee);
+        set_sync_policy(
+            &db,
+            &HistorySyncPolicy {
+                first_sync_modified_days: Some(30),
+                ..HistorySyncPolicy::default()
+            },
+        )?;
+
+        // The first sync only asks for the last 30 days, and asks for the
+        // same window again until it's downloaded.
+        let requests = store.get_collection_requests().unwrap();
+        assert_eq!(requests.len(), 1);
+        let window_start = requests[0].newer.expect("first sync has a window");
+        let thirty_days_ago = Timestamp::now()
+            .checked_sub(Duration::from_secs(30 * 24 * 60 * 60))
+            .unwrap()
+            .as_millis() as i64;
+        assert!((window_start.as_millis() - thirty_days_ago).abs() < 60_000);
+        assert_eq!(store.get_collection_requests().unwrap(), requests);
+
+        let mut telem = telemetry::Engine::new("history");
+        let progress = DownloadProgress {
+            collection: "history".into(),
+            newer: Some(window_start),
+            older: None,
+            offset: None,
+            last_modified: ServerTimestamp(window_start.as_millis() + 1000),
+        };
+        store.do_apply_incoming_page(
+            page_of(&[], progress.last_modified),
+            Some(&progress),
+            &mut telem,
+        )?;
+        store.do_apply_incoming(
+            vec![IncomingChangeset::new("history", progress.last_modified)],
+            &mut telem,
+        )?;
+        store.do_sync_finished(progress.last_modified, vec![])?;
+
+        // Later syncs backfill what's older than the window, before asking
+        // for new records.
+        let requests = store.get_collection_requests().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].older, Some(window_start));
+        assert_eq!(requests[0].newer, None);
+        assert_eq!(requests[1].older, None);
+        assert_eq!(requests[1].newer, Some(progress.last_modified));
+
+        // Backfilling doesn't disturb our place in the new records.
+        let backfill_progress = DownloadProgress {
+            collection: "history".into(),
+            newer: None,
+            older: Some(window_start),
+            offset: Some("1".into()),
+            last_modified: ServerTimestamp(window_start.as_millis() + 2000),
+        };
+        store.do_apply_incoming_page(
+            page_of(
+                &[("aaaaaaaaaaaa", "http://example.com/a")],
+                backfill_progress.last_modified,
+            ),
+            Some(&backfill_progress),
+            &mut telem,
+        )?;
+        assert!(fetch_visits(&db, &Url::parse("http://example.com/a")?, 1)?.is_some());
+        assert_eq!(
+            store.do_get_download_progress(&requests[0])?.as_ref(),
+            Some(&backfill_progress)
+        );
+        assert_eq!(store.do_get_download_progress(&requests[1])?, None);
+
+        // Once the backfill's done, we stop asking for it.
+        let backfill_progress = DownloadProgress {
+            offset: None,
+            ..backfill_progress
+        };
+        store.do_apply_incoming_page(
+            page_of(&[], backfill_progress.last_modified),
+            Some(&backfill_progress),
+            &mut telem,
+        )?;
+        store.do_apply_incoming(
+            vec![
+                IncomingChangeset::new("history", backfill_progress.last_modified),
+                IncomingChangeset::new("history", backfill_progress.last_modified),
+            ],
+            &mut telem,
+        )?;
+        let requests = store.get_collection_requests().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].older, None);
+        assert_eq!(store.get_meta::<i64>(BACKFILL_BEFORE_META_KEY)?, None);
+        Ok(())
+    }
+}
@@ -5,6 +5,7 @@
 use super::RowId;
 use super::{delete_meta, put_meta};
 use super::{fetch_page_info, new_page_info};
+use crate::bookmark_sync::repair::OUTGOING_REPAIR_REQUEST_META_KEY;
 use crate::bookmark_sync::store::{
     COLLECTION_SYNCID_META_KEY, GLOBAL_SYNCID_META_KEY, LAST_SYNC_META_KEY,
 };
@@ -1480,6 +1481,8 @@ pub mod bookmark_sync {
         ))?;
         create_synced_bookmark_roots(db)?;
         put_meta(db, LAST_SYNC_META_KEY, &0)?;
+        // Requests for items from the old server data are meaningless now.
+        delete_meta(db, OUTGOING_REPAIR_REQUEST_META_KEY)?;
         Ok(())
     }
 
@@ -8,7 +8,8 @@ use crate::error::Result;
 use crate::frecency;
 use crate::hash;
 use crate::history_sync::store::{
-    COLLECTION_SYNCID_META_KEY, GLOBAL_SYNCID_META_KEY, LAST_SYNC_META_KEY,
+    BACKFILL_BEFORE_META_KEY, BACKFILL_PROGRESS_META_KEY, COLLECTION_SYNCID_META_KEY,
+    DOWNLOAD_PROGRESS_META_KEY, GLOBAL_SYNCID_META_KEY, LAST_SYNC_META_KEY,
 };
 use crate::msg_types::{HistoryVisitInfo, HistoryVisitInfos, HistoryVisitInfosWithBound};
 use crate::observation::VisitObservation;
@@ -327,6 +328,9 @@ pub fn delete_everything(db: &PlacesDb) -> Result<()> {
 
     // Remove Sync metadata, too.
     put_meta(db, LAST_SYNC_META_KEY, &0)?;
+    delete_meta(db, DOWNLOAD_PROGRESS_META_KEY)?;
+    delete_meta(db, BACKFILL_BEFORE_META_KEY)?;
+    delete_meta(db, BACKFILL_PROGRESS_META_KEY)?;
     delete_meta(db, GLOBAL_SYNCID_META_KEY)?;
     delete_meta(db, COLLECTION_SYNCID_META_KEY)?;
 
@@ -851,6 +855,10 @@ pub mod history_sync {
                      change_delta INTEGER NOT NULL)",
             NO_PARAMS,
         )?;
+        // We're called again, without `finish_outgoing`, if our upload
+        // conflicts with another client's, so forget the records we fetched
+        // for that upload. We'll fetch them again below.
+        db.execute("DELETE FROM temp_sync_updated_meta", NO_PARAMS)?;
 
         let insert_meta_sql = "
             INSERT INTO temp_sync_updated_meta VALUES (:row_id, :change_delta)";
@@ -975,6 +983,14 @@ pub mod history_sync {
         Ok(())
     }
 
+    /// Forgets the outgoing records that `fetch_outgoing` fetched, without
+    /// marking them as synced. Their change counters, and the tombstones,
+    /// are kept, so the next sync fetches them again.
+    pub fn forget_outgoing(db: &PlacesDb) -> Result<()> {
+        db.execute_batch("DELETE FROM temp.temp_sync_updated_meta")?;
+        Ok(())
+    }
+
     /// Resets all sync metadata, including change counters, sync statuses,
     /// the last sync time, and sync ID. This should be called when the user
     /// signs out of Sync.
@@ -1000,6 +1016,9 @@ pub mod history_sync {
             NO_PARAMS,
         )?;
         put_meta(db, LAST_SYNC_META_KEY, &0)?;
+        delete_meta(db, DOWNLOAD_PROGRESS_META_KEY)?;
+        delete_meta(db, BACKFILL_BEFORE_META_KEY)?;
+        delete_meta(db, BACKFILL_PROGRESS_META_KEY)?;
         Ok(())
     }
 } // end of sync module.
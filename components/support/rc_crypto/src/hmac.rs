@@ -109,6 +109,16 @@ mod tests {
         assert!(verify_with_own_key(&key, MESSAGE, &expected_signature).is_ok());
     }
 
+    #[test]
+    fn hmac_sign_sha1() {
+        let key = SigningKey::new(&digest::SHA1, KEY);
+        let signature = sign(&key, MESSAGE).unwrap();
+        assert_eq!(
+            hex::encode(signature.as_ref()),
+            "de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9"
+        );
+    }
+
     #[test]
     fn hmac_sign_gives_different_signatures_for_different_keys() {
         let key = SigningKey::new(&digest::SHA256, b"another key");
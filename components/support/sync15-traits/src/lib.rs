@@ -13,9 +13,9 @@ pub mod telemetry;
 
 pub use changeset::{IncomingChangeset, OutgoingChangeset, RecordChangeset};
 pub use payload::Payload;
-pub use request::{CollectionRequest, RequestOrder};
+pub use request::{CollectionRequest, DownloadProgress, RequestOrder, StorageQuota};
 pub use server_timestamp::ServerTimestamp;
-pub use store::{CollSyncIds, Store, StoreSyncAssociation};
+pub use store::{CollSyncIds, OutgoingRecords, Store, StoreSyncAssociation};
 pub use sync_guid::Guid;
 
 // For skip_serializing_if
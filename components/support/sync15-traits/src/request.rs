@@ -2,6 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 use crate::{Guid, ServerTimestamp};
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use url::{form_urlencoded as form, Url, UrlQuery};
 #[derive(Debug, Clone, PartialEq)]
@@ -10,11 +11,15 @@ pub struct CollectionRequest {
     pub full: bool,
     pub ids: Option<Vec<Guid>>,
     pub limit: usize,
+    pub offset: Option<String>,
     pub older: Option<ServerTimestamp>,
     pub newer: Option<ServerTimestamp>,
     pub order: Option<RequestOrder>,
     pub commit: bool,
     pub batch: Option<String>,
+    /// For stores that download in pages, the most records to download for
+    /// this request in one sync. This isn't sent to the server.
+    pub max_records: Option<usize>,
 }
 
 impl CollectionRequest {
@@ -28,11 +33,13 @@ impl CollectionRequest {
             full: false,
             ids: None,
             limit: 0,
+            offset: None,
             older: None,
             newer: None,
             order: None,
             commit: false,
             batch: None,
+            max_records: None,
         }
     }
 
@@ -76,6 +83,23 @@ impl CollectionRequest {
         self
     }
 
+    /// Continues a request from the `X-Weave-Next-Offset` returned for its
+    /// previous page.
+    #[inline]
+    pub fn offset<S: Into<String>>(mut self, offset: S) -> CollectionRequest {
+        self.offset = Some(offset.into());
+        self
+    }
+
+    /// Stops a paged download after the page that reaches `num` records. The
+    /// rest is downloaded by the next sync, which resumes from the store's
+    /// `DownloadProgress`.
+    #[inline]
+    pub fn max_records(mut self, num: usize) -> CollectionRequest {
+        self.max_records = Some(num);
+        self
+    }
+
     #[inline]
     pub fn batch(mut self, batch: Option<String>) -> CollectionRequest {
         self.batch = batch;
@@ -95,6 +119,9 @@ impl CollectionRequest {
         if self.limit > 0 {
             pairs.append_pair("limit", &self.limit.to_string());
         }
+        if let Some(offset) = &self.offset {
+            pairs.append_pair("offset", offset);
+        }
         if let Some(ids) = &self.ids {
             // Most ids are 12 characters, and we comma separate them, so 13.
             let mut buf = String::with_capacity(ids.len() * 13);
@@ -140,6 +167,52 @@ impl CollectionRequest {
         Ok(base_url)
     }
 }
+/// How far a paged download has got, for resuming it if it's interrupted.
+/// Stores that download in pages persist this along with each page they
+/// apply; see `Store::apply_incoming_page`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DownloadProgress {
+    pub collection: String,
+    /// The `newer` of the request being paged. Progress for a request with a
+    /// different `newer` is ignored.
+    pub newer: Option<ServerTimestamp>,
+    /// The `older` of the request being paged. Like `newer`, progress for a
+    /// request with a different `older` is ignored.
+    #[serde(default)]
+    pub older: Option<ServerTimestamp>,
+    /// The offset of the next page, or `None` if all the pages have been
+    /// downloaded.
+    pub offset: Option<String>,
+    /// The collection's last modified time, as of the last page.
+    pub last_modified: ServerTimestamp,
+}
+
+impl DownloadProgress {
+    /// Returns true if this is progress for `request`.
+    pub fn is_for(&self, request: &CollectionRequest) -> bool {
+        self.collection == request.collection
+            && self.newer == request.newer
+            && self.older == request.older
+    }
+}
+
+/// The account's storage usage and quota on the server, in bytes, from
+/// `info/quota`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageQuota {
+    pub usage: u64,
+    /// `None` if the server doesn't enforce a quota.
+    pub quota: Option<u64>,
+}
+
+impl StorageQuota {
+    /// Returns how many more bytes the account can store, or `None` if there's
+    /// no quota.
+    pub fn remaining(&self) -> Option<u64> {
+        self.quota.map(|quota| quota.saturating_sub(self.usage))
+    }
+}
+
 #[derive(Debug)]
 pub struct UnacceptableBaseUrl(());
 
@@ -3,8 +3,8 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use crate::{
-    client::ClientData, telemetry, CollectionRequest, Guid, IncomingChangeset, OutgoingChangeset,
-    ServerTimestamp,
+    client::ClientData, telemetry, CollectionRequest, DownloadProgress, Guid, IncomingChangeset,
+    OutgoingChangeset, Payload, ServerTimestamp, StorageQuota,
 };
 use failure::Error;
 
@@ -23,6 +23,10 @@ pub enum StoreSyncAssociation {
     Connected(CollSyncIds),
 }
 
+/// Outgoing records that a store produces one at a time, from
+/// `Store::fetch_outgoing_records`.
+pub type OutgoingRecords<'a> = Box<dyn Iterator<Item = Result<Payload, Error>> + 'a>;
+
 /// Low-level store functionality. Stores that need custom reconciliation logic
 /// should use this.
 ///
@@ -48,6 +52,20 @@ pub trait Store {
         Ok(())
     }
 
+    /// Tells the store the maximum size, in bytes, of a record's payload that
+    /// the server accepts for this collection. This is called before
+    /// `apply_incoming`, so that stores that build large records, like tabs,
+    /// can trim them to fit. Records that are still too large fail to upload.
+    fn set_max_payload_size(&self, _max_payload_size: usize) {}
+
+    /// Returns the storage version to declare this collection with in
+    /// `meta/global`, if it isn't one of the engines every Sync client
+    /// declares by default. Only a full sync that includes this store adds
+    /// the collection to `meta/global`.
+    fn meta_global_version(&self) -> Option<usize> {
+        None
+    }
+
     /// `inbound` is a vector to support the case where
     /// `get_collection_requests` returned multiple requests. The changesets are
     /// in the same order as the requests were -- e.g. if `vec![req_a, req_b]`
@@ -60,12 +78,94 @@ pub trait Store {
         telem: &mut telemetry::Engine,
     ) -> Result<OutgoingChangeset, Error>;
 
+    /// Stores that return true here download their collection requests in
+    /// pages of `limit` records, sorted oldest first, and apply each page as
+    /// it arrives with `apply_incoming_page`, instead of holding every
+    /// incoming record in memory. `apply_incoming` is then called with
+    /// changesets that have the collections' timestamps, but no records.
+    fn downloads_in_pages(&self) -> bool {
+        false
+    }
+
+    /// Applies, or stages, a page of incoming records, and persists
+    /// `progress`, so that `get_download_progress` can return it if the
+    /// download is interrupted. The next sync then resumes after this page.
+    /// A page may be applied again if the store is interrupted before
+    /// persisting the progress, so doing so should be harmless.
+    ///
+    /// `progress` is `None` for downloads that shouldn't be resumed, like the
+    /// one for the records that conflicted with an upload. The store should
+    /// keep the progress it already persisted for these.
+    ///
+    /// The store should forget the progress once it's applied all the pages
+    /// in `apply_incoming`, and when it's reset or wiped.
+    ///
+    /// Stores that return true from `downloads_in_pages` must implement
+    /// this. The default fails the sync.
+    fn apply_incoming_page(
+        &self,
+        _page: IncomingChangeset,
+        _progress: Option<&DownloadProgress>,
+        _telem: &mut telemetry::Engine,
+    ) -> Result<(), Error> {
+        Err(failure::format_err!(
+            "The {} store downloads in pages, but doesn't implement apply_incoming_page",
+            self.collection_name()
+        ))
+    }
+
+    /// Returns the progress persisted by `apply_incoming_page` for `request`,
+    /// if any. Stores that return more than one request from
+    /// `get_collection_requests` should keep the progress for each of them.
+    fn get_download_progress(
+        &self,
+        _request: &CollectionRequest,
+    ) -> Result<Option<DownloadProgress>, Error> {
+        Ok(None)
+    }
+
+    /// Returns outgoing records that weren't in the changeset returned from
+    /// `apply_incoming`. They're fetched one at a time as the upload needs
+    /// them, and each one is encrypted and queued before the next is fetched,
+    /// so stores with many records to upload don't need to hold them all in
+    /// memory. This is called after `apply_incoming`, and only if the sync is
+    /// going to upload; the records in the changeset are uploaded first.
+    fn fetch_outgoing_records(&self) -> Result<OutgoingRecords<'_>, Error> {
+        Ok(Box::new(std::iter::empty()))
+    }
+
+    /// Called before uploading, with the changeset returned from
+    /// `apply_incoming`. Stores that might upload a lot, like bookmarks after
+    /// an import, can call `get_quota` to ask the server how much space the
+    /// account has left, and return an error to skip the upload instead of
+    /// failing part way through it. `get_quota` makes a request, so stores
+    /// should only call it when they have a lot to upload.
+    fn check_quota(
+        &self,
+        _outgoing: &OutgoingChangeset,
+        _get_quota: &dyn Fn() -> Result<StorageQuota, Error>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
     fn sync_finished(
         &self,
         new_timestamp: ServerTimestamp,
         records_synced: Vec<Guid>,
     ) -> Result<(), Error>;
 
+    /// Called instead of `sync_finished` after a read-only sync, which
+    /// applies incoming records, but doesn't upload. The store should only
+    /// persist `new_timestamp`, so that it doesn't download the records it
+    /// just applied again, and keep its local changes and tombstones for the
+    /// next sync to upload.
+    ///
+    /// Stores that persist a last sync time should override this. The default
+    /// does nothing, so the next sync downloads the same records again.
+    fn sync_finished_readonly(&self, _new_timestamp: ServerTimestamp) -> Result<(), Error> {
+        Ok(())
+    }
+
     /// The store is responsible for building the collection request. Engines
     /// typically will store a lastModified timestamp and use that to build a
     /// request saying "give me full records since that date" - however, other
@@ -71,6 +71,13 @@ impl Stopwatch {
             }
         }
     }
+
+    fn took_ms(&self) -> u64 {
+        match self {
+            Stopwatch::Started(_, si) => si.elapsed().as_millis() as u64,
+            Stopwatch::Finished(c) => c.took,
+        }
+    }
 }
 
 impl Serialize for Stopwatch {
@@ -293,6 +300,17 @@ pub struct EngineIncoming {
 
     #[serde(skip_serializing_if = "crate::skip_if_default")]
     reconciled: u32,
+
+    #[serde(rename = "failedReasons")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    failed_reasons: Vec<FailureReason>,
+}
+
+/// How many records failed for one reason.
+#[derive(Debug, Serialize)]
+struct FailureReason {
+    name: &'static str,
+    count: u32,
 }
 
 impl EngineIncoming {
@@ -310,6 +328,23 @@ impl EngineIncoming {
         }
     }
 
+    fn accumulate(&mut self, other: EngineIncoming) {
+        self.applied += other.applied;
+        self.new_failed += other.new_failed;
+        self.reconciled += other.reconciled;
+        self.failed += other.failed;
+        for reason in other.failed_reasons {
+            match self
+                .failed_reasons
+                .iter_mut()
+                .find(|r| r.name == reason.name)
+            {
+                Some(r) => r.count += reason.count,
+                None => self.failed_reasons.push(reason),
+            }
+        }
+    }
+
     /// Increment the value of `applied` by `n`.
     #[inline]
     pub fn applied(&mut self, n: u32) {
@@ -322,6 +357,20 @@ impl EngineIncoming {
         self.failed += n;
     }
 
+    /// Increment the value of `failed` by `n`, and record why those records
+    /// failed. The reason should be a short literal, like "deserialize", and
+    /// not an error message, which might include the record's contents.
+    pub fn failed_with_reason(&mut self, n: u32, reason: &'static str) {
+        self.failed += n;
+        match self.failed_reasons.iter_mut().find(|r| r.name == reason) {
+            Some(r) => r.count += n,
+            None => self.failed_reasons.push(FailureReason {
+                name: reason,
+                count: n,
+            }),
+        }
+    }
+
     /// Increment the value of `new_failed` by `n`.
     #[inline]
     pub fn new_failed(&mut self, n: u32) {
@@ -385,6 +434,16 @@ impl EngineOutgoing {
     pub fn failed(&mut self, n: usize) {
         self.failed += n;
     }
+
+    #[inline]
+    pub fn get_sent(&self) -> usize {
+        self.sent
+    }
+
+    #[inline]
+    pub fn get_failed(&self) -> usize {
+        self.failed
+    }
 }
 
 /// One engine's sync.
@@ -401,12 +460,29 @@ pub struct Engine {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     outgoing: Vec<EngineOutgoing>, // one for each batch posted.
 
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    steps: Vec<Step>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "failureReason")]
     failure: Option<SyncFailure>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     validation: Option<Validation>,
+
+    // Events belong to the ping, not the engine, so they're moved there when
+    // the sync finishes.
+    #[serde(skip)]
+    events: Vec<Event>,
+}
+
+/// How long one step of an engine's sync, like downloading or uploading
+/// records, took.
+#[derive(Debug, Serialize)]
+struct Step {
+    name: &'static str,
+    #[serde(skip_serializing_if = "crate::skip_if_default")]
+    took: u64,
 }
 
 impl Engine {
@@ -416,14 +492,33 @@ impl Engine {
             when_took: Stopwatch::new(),
             incoming: None,
             outgoing: Vec::new(),
+            steps: Vec::new(),
             failure: None,
             validation: None,
+            events: Vec::new(),
         }
     }
 
+    /// Record how long a step of the sync took.
+    pub fn step(&mut self, name: &'static str, took: time::Duration) {
+        self.steps.push(Step {
+            name,
+            took: took.as_millis() as u64,
+        });
+    }
+
+    /// Record an event, which is added to the ping's events.
+    pub fn event(&mut self, e: Event) {
+        self.events.push(e);
+    }
+
+    /// Records incoming records. Stores that apply incoming records a page
+    /// at a time can call this for each page, and the counts are added up.
     pub fn incoming(&mut self, inc: EngineIncoming) {
-        assert!(self.incoming.is_none());
-        self.incoming = Some(inc);
+        match &mut self.incoming {
+            Some(existing) => existing.accumulate(inc),
+            None => self.incoming = Some(inc),
+        }
     }
 
     pub fn outgoing(&mut self, out: EngineOutgoing) {
@@ -453,6 +548,38 @@ impl Engine {
     fn finished(&mut self) {
         self.when_took = self.when_took.finished();
     }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_incoming(&self) -> Option<&EngineIncoming> {
+        self.incoming.as_ref()
+    }
+
+    /// Returns the outgoing counts, added up across every batch.
+    pub fn get_outgoing(&self) -> EngineOutgoing {
+        let mut total = EngineOutgoing::new();
+        for out in &self.outgoing {
+            total.sent(out.sent);
+            total.failed(out.failed);
+        }
+        total
+    }
+
+    /// How long the engine's sync took, in milliseconds, or has taken so far
+    /// if it's still syncing.
+    pub fn get_took(&self) -> u64 {
+        self.when_took.took_ms()
+    }
+
+    pub fn get_failure(&self) -> Option<&SyncFailure> {
+        self.failure.as_ref()
+    }
+
+    pub fn get_validation(&self) -> Option<&Validation> {
+        self.validation.as_ref()
+    }
 }
 
 #[derive(Debug, Default, Serialize)]
@@ -481,6 +608,10 @@ impl Validation {
         }
         self
     }
+
+    pub fn get_problems(&self) -> &[Problem] {
+        &self.problems
+    }
 }
 
 #[derive(Debug, Default, Serialize)]
@@ -490,6 +621,16 @@ pub struct Problem {
     count: usize,
 }
 
+impl Problem {
+    pub fn get_name(&self) -> &str {
+        self.name
+    }
+
+    pub fn get_count(&self) -> usize {
+        self.count
+    }
+}
+
 #[cfg(test)]
 mod engine_tests {
     use super::*;
@@ -521,6 +662,82 @@ mod engine_tests {
         );
     }
 
+    #[test]
+    fn test_incoming_pages() {
+        let mut e = Engine::new("TestEngine");
+        let mut i = EngineIncoming::new();
+        i.applied(1);
+        i.failed_with_reason(1, "deserialize");
+        e.incoming(i);
+        let mut i = EngineIncoming::new();
+        i.applied(2);
+        i.reconciled(1);
+        i.failed_with_reason(2, "deserialize");
+        i.failed_with_reason(1, "apply");
+        e.incoming(i);
+        e.finished();
+        assert_json(
+            &e,
+            serde_json::json!({
+                "name": "TestEngine",
+                "when": 0.0,
+                "incoming": {
+                    "applied": 3,
+                    "failed": 4,
+                    "reconciled": 1,
+                    "failedReasons": [
+                        {"name": "deserialize", "count": 3},
+                        {"name": "apply", "count": 1},
+                    ],
+                },
+            }),
+        );
+    }
+
+    #[test]
+    fn test_failed_reasons() {
+        let mut i = EngineIncoming::new();
+        i.applied(1);
+        i.failed_with_reason(2, "deserialize");
+        i.failed_with_reason(1, "apply");
+        i.failed_with_reason(1, "deserialize");
+        assert_eq!(i.get_failed(), 4);
+        let mut e = Engine::new("TestEngine");
+        e.incoming(i);
+        e.finished();
+        assert_json(
+            &e,
+            serde_json::json!({
+                "name": "TestEngine",
+                "when": 0.0,
+                "incoming": {
+                    "applied": 1,
+                    "failed": 4,
+                    "failedReasons": [
+                        {"name": "deserialize", "count": 3},
+                        {"name": "apply", "count": 1},
+                    ]
+                }
+            }),
+        );
+    }
+
+    #[test]
+    fn test_steps() {
+        let mut e = Engine::new("TestEngine");
+        e.step("download", time::Duration::from_millis(1500));
+        e.step("apply", time::Duration::from_micros(10));
+        e.finished();
+        assert_json(
+            &e,
+            serde_json::json!({
+                "name": "TestEngine",
+                "when": 0.0,
+                "steps": [{"name": "download", "took": 1500}, {"name": "apply"}]
+            }),
+        );
+    }
+
     #[test]
     fn test_outgoing() {
         let mut o = EngineOutgoing::new();
@@ -581,6 +798,9 @@ pub struct SyncTelemetry {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "failureReason")]
     failure: Option<SyncFailure>,
+
+    #[serde(skip)]
+    events: Vec<Event>,
 }
 
 impl SyncTelemetry {
@@ -590,6 +810,7 @@ impl SyncTelemetry {
 
     pub fn engine(&mut self, mut e: Engine) {
         e.finished();
+        self.events.append(&mut e.events);
         self.engines.push(e);
     }
 
@@ -598,6 +819,10 @@ impl SyncTelemetry {
         self.failure = Some(failure);
     }
 
+    pub fn get_engines(&self) -> &[Engine] {
+        &self.engines
+    }
+
     // Note that unlike other 'finished' methods, this isn't private - someone
     // needs to explicitly call this before handling the json payload to
     // whatever ends up submitting it.
@@ -641,6 +866,31 @@ mod sync_tests {
         );
     }
 
+    #[test]
+    fn test_getters() {
+        let mut s = SyncTelemetry::new();
+        let mut e = Engine::new("test_engine");
+        let mut inc = EngineIncoming::new();
+        inc.applied(10);
+        e.incoming(inc);
+        for sent in &[3, 4] {
+            let mut out = EngineOutgoing::new();
+            out.sent(*sent);
+            out.failed(1);
+            e.outgoing(out);
+        }
+        s.engine(e);
+
+        let engines = s.get_engines();
+        assert_eq!(engines.len(), 1);
+        assert_eq!(engines[0].get_name(), "test_engine");
+        assert_eq!(engines[0].get_incoming().unwrap().get_applied(), 10);
+        assert_eq!(engines[0].get_outgoing().get_sent(), 7);
+        assert_eq!(engines[0].get_outgoing().get_failed(), 2);
+        assert_eq!(engines[0].get_took(), 0);
+        assert!(engines[0].get_failure().is_none());
+    }
+
     #[test]
     fn test_multi_engine() {
         let mut inc_e1 = EngineIncoming::new();
@@ -708,6 +958,16 @@ pub struct SyncTelemetryPing {
 
     uid: Option<String>,
 
+    #[serde(rename = "deviceID")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    os: Option<Os>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    why: Option<String>,
+
     #[serde(skip_serializing_if = "Vec::is_empty")]
     events: Vec<Event>,
 
@@ -715,6 +975,15 @@ pub struct SyncTelemetryPing {
     syncs: Vec<SyncTelemetry>,
 }
 
+/// The operating system the ping was recorded on. Only the consumer knows
+/// this, so it's optional.
+#[derive(Debug, Serialize)]
+pub struct Os {
+    pub name: String,
+    pub version: String,
+    pub locale: String,
+}
+
 impl SyncTelemetryPing {
     pub fn new() -> Self {
         Self {
@@ -732,14 +1001,61 @@ impl SyncTelemetryPing {
         self.uid = Some(uid);
     }
 
+    /// The *hashed* FxA device ID.
+    pub fn device_id(&mut self, device_id: String) {
+        self.device_id = Some(device_id);
+    }
+
+    pub fn os(&mut self, os: Os) {
+        self.os = Some(os);
+    }
+
+    /// Why the ping is being submitted, like "schedule" or "shutdown".
+    pub fn why(&mut self, why: String) {
+        self.why = Some(why);
+    }
+
     pub fn sync(&mut self, mut s: SyncTelemetry) {
         s.finished();
+        self.events.append(&mut s.events);
         self.syncs.push(s);
     }
 
     pub fn event(&mut self, e: Event) {
         self.events.push(e);
     }
+
+    /// Move the syncs and events from another ping into this one, so a
+    /// consumer can accumulate many syncs into a single ping. The other
+    /// ping's syncs must already be finished, which they are if they were
+    /// added with `sync()`.
+    pub fn accumulate(&mut self, mut other: SyncTelemetryPing) {
+        if let Some(uid) = other.uid.take() {
+            self.uid(uid);
+        }
+        self.syncs.append(&mut other.syncs);
+        self.events.append(&mut other.events);
+    }
+
+    pub fn get_syncs(&self) -> &[SyncTelemetry] {
+        &self.syncs
+    }
+
+    /// Drops the oldest syncs and events, so that the ping holds at most
+    /// `max_syncs` syncs and `max_events` events. Returns how many syncs
+    /// were dropped.
+    pub fn drop_oldest(&mut self, max_syncs: usize, max_events: usize) -> usize {
+        let dropped_syncs = self.syncs.len().saturating_sub(max_syncs);
+        self.syncs.drain(..dropped_syncs);
+        let dropped_events = self.events.len().saturating_sub(max_events);
+        self.events.drain(..dropped_events);
+        dropped_syncs
+    }
+
+    /// Returns true if there's nothing worth submitting.
+    pub fn is_empty(&self) -> bool {
+        self.syncs.is_empty() && self.events.is_empty()
+    }
 }
 
 ffi_support::implement_into_ffi_by_json!(SyncTelemetryPing);
@@ -774,4 +1090,100 @@ mod ping_tests {
             }),
         );
     }
+
+    #[test]
+    fn test_engine_events_move_to_ping() {
+        let mut engine = Engine::new("test");
+        engine.event(Event::new("foo", "bar"));
+        let mut s = SyncTelemetry::new();
+        s.engine(engine);
+        let mut p = SyncTelemetryPing::new();
+        p.sync(s);
+        assert_json(
+            &p,
+            serde_json::json!({
+                "events": [{
+                    "method": "bar", "object": "foo"
+                }],
+                "syncs": [{
+                    "engines": [{
+                        "name": "test", "when": 0.0
+                    }],
+                    "when": 0.0
+                }],
+                "uid": null,
+                "version": 1
+            }),
+        );
+    }
+
+    #[test]
+    fn test_accumulate() {
+        let mut first = SyncTelemetryPing::new();
+        first.sync(SyncTelemetry::new());
+        first.event(Event::new("first", "event"));
+
+        let mut second = SyncTelemetryPing::new();
+        second.uid("user-id".into());
+        second.sync(SyncTelemetry::new());
+
+        let mut p = SyncTelemetryPing::new();
+        assert!(p.is_empty());
+        p.device_id("device-id".into());
+        p.os(Os {
+            name: "Android".into(),
+            version: "29".into(),
+            locale: "en-US".into(),
+        });
+        p.why("schedule".into());
+        p.accumulate(first);
+        p.accumulate(second);
+        assert!(!p.is_empty());
+        assert_json(
+            &p,
+            serde_json::json!({
+                "deviceID": "device-id",
+                "events": [{
+                    "method": "event", "object": "first"
+                }],
+                "os": {
+                    "name": "Android", "version": "29", "locale": "en-US"
+                },
+                "syncs": [{"when": 0.0}, {"when": 0.0}],
+                "uid": "user-id",
+                "version": 1,
+                "why": "schedule"
+            }),
+        );
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let mut p = SyncTelemetryPing::new();
+        for (i, value) in ["0", "1", "2"].iter().enumerate() {
+            let mut engine = Engine::new("test");
+            engine.failure(SyncFailure::Unexpected {
+                error: format!("sync {}", i),
+            });
+            let mut s = SyncTelemetry::new();
+            s.engine(engine);
+            p.sync(s);
+            p.event(Event::new("event", "test").value(value));
+        }
+        assert_eq!(p.drop_oldest(5, 5), 0);
+        assert_eq!(p.get_syncs().len(), 3);
+
+        assert_eq!(p.drop_oldest(2, 1), 1);
+        let errors = p
+            .get_syncs()
+            .iter()
+            .map(|s| match s.get_engines()[0].get_failure() {
+                Some(SyncFailure::Unexpected { error }) => error.as_str(),
+                _ => panic!("should have failed"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(errors, vec!["sync 1", "sync 2"]);
+        assert_eq!(p.events.len(), 1);
+        assert_eq!(p.events[0].value, Some("2"));
+    }
 }
@@ -6,12 +6,15 @@ use crate::bso_record::{CleartextBso, EncryptedBso};
 use crate::client::{Sync15ClientResponse, Sync15StorageClient};
 use crate::error::{self, ErrorKind, ErrorResponse, Result};
 use crate::key_bundle::KeyBundle;
-use crate::request::{CollectionRequest, NormalResponseHandler, UploadInfo};
+use crate::request::{
+    CollectionRequest, DownloadProgress, NormalResponseHandler, RequestOrder, UploadInfo,
+};
 use crate::util::ServerTimestamp;
 use crate::CollState;
+use interrupt::Interruptee;
 use std::borrow::Cow;
 
-pub use sync15_traits::{IncomingChangeset, OutgoingChangeset, RecordChangeset};
+pub use sync15_traits::{IncomingChangeset, OutgoingChangeset, OutgoingRecords, RecordChangeset};
 
 pub fn encrypt_outgoing(o: OutgoingChangeset, key: &KeyBundle) -> Result<Vec<EncryptedBso>> {
     let RecordChangeset {
@@ -41,6 +44,118 @@ pub fn fetch_incoming(
     };
     // xxx - duplication below of `timestamp` smells wrong
     state.last_modified = timestamp;
+    decrypt_incoming(state, collection, timestamp, records)
+}
+
+/// Downloads the records for `collection_request` in pages of its `limit`,
+/// oldest first, and passes each page, with the progress after it, to
+/// `apply_page`. If `progress` is for the same request, the download resumes
+/// where it left off. Returns the changeset to pass to
+/// `Store::apply_incoming`, which has the collection's timestamp, but no
+/// records.
+pub fn fetch_incoming_in_pages(
+    client: &Sync15StorageClient,
+    state: &mut CollState,
+    collection_request: &CollectionRequest,
+    progress: Option<DownloadProgress>,
+    interruptee: &dyn Interruptee,
+    apply_page: &mut dyn FnMut(IncomingChangeset, &DownloadProgress) -> Result<()>,
+) -> Result<IncomingChangeset> {
+    let collection = collection_request.collection.clone();
+    // Records changed while we're paging move to the end, so sorting oldest
+    // first means we can't miss them.
+    let request = collection_request.clone().sort_by(RequestOrder::Oldest);
+    let mut offset = None;
+    if let Some(progress) = progress.filter(|p| p.is_for(&request)) {
+        match progress.offset {
+            Some(next_offset) => {
+                log::info!("Resuming the download of {}", collection);
+                offset = Some(next_offset);
+            }
+            None => {
+                // We applied every page last time, but were interrupted
+                // before the store finished.
+                log::info!("Already downloaded {}", collection);
+                state.last_modified = progress.last_modified;
+                return Ok(IncomingChangeset::new(collection, progress.last_modified));
+            }
+        }
+    }
+    let mut resuming = offset.is_some();
+    let mut downloaded = 0;
+    loop {
+        interruptee.err_if_interrupted()?;
+        let mut page_request = request.clone();
+        page_request.offset = offset.take();
+        let (records, timestamp, next_offset) =
+            match client.get_encrypted_records_page(&page_request)? {
+                (
+                    Sync15ClientResponse::Success {
+                        record,
+                        last_modified,
+                        ..
+                    },
+                    next_offset,
+                ) => (record, last_modified, next_offset),
+                (Sync15ClientResponse::Error(ErrorResponse::RequestFailed { status, .. }), _)
+                    if resuming && status == 400 =>
+                {
+                    // The server doesn't recognize the offset any more, so
+                    // start again. Pages we've already applied are applied
+                    // again, which is harmless.
+                    log::warn!("Couldn't resume the download of {}", collection);
+                    resuming = false;
+                    continue;
+                }
+                (other, _) => return Err(other.create_storage_error().into()),
+            };
+        resuming = false;
+        let page = decrypt_incoming(state, collection.clone(), timestamp, records)?;
+        log::info!(
+            "Downloaded a page of {} records for {}",
+            page.changes.len(),
+            collection
+        );
+        downloaded += page.changes.len();
+        let progress = DownloadProgress {
+            collection: collection.to_string(),
+            newer: request.newer,
+            older: request.older,
+            offset: next_offset.clone(),
+            last_modified: timestamp,
+        };
+        apply_page(page, &progress)?;
+        let reached_max = match request.max_records {
+            Some(max_records) => downloaded >= max_records,
+            None => false,
+        };
+        match next_offset {
+            Some(next_offset) if !reached_max => offset = Some(next_offset),
+            Some(_) => {
+                // The store knows from the progress it persisted that there's
+                // more to download, and the next sync resumes from there.
+                log::info!(
+                    "Downloaded {} records for {}, leaving the rest for the next sync",
+                    downloaded,
+                    collection
+                );
+                state.last_modified = timestamp;
+                return Ok(IncomingChangeset::new(collection, timestamp));
+            }
+            None => {
+                state.last_modified = timestamp;
+                return Ok(IncomingChangeset::new(collection, timestamp));
+            }
+        }
+    }
+}
+
+fn decrypt_incoming(
+    state: &CollState,
+    collection: Cow<'static, str>,
+    timestamp: ServerTimestamp,
+    records: Vec<EncryptedBso>,
+) -> Result<IncomingChangeset> {
     let mut result = IncomingChangeset::new(collection, timestamp);
     result.changes.reserve(records.len());
     for record in records {
@@ -90,17 +205,9 @@ impl<'a> CollectionUpdate<'a> {
         changeset: OutgoingChangeset,
         fully_atomic: bool,
     ) -> Result<CollectionUpdate<'a>> {
+        check_xius(state, &changeset)?;
         let collection = changeset.collection.clone();
         let xius = changeset.timestamp;
-        if xius < state.last_modified {
-            // We know we are going to fail the XIUS check...
-            return Err(
-                ErrorKind::StorageHttpError(ErrorResponse::PreconditionFailed {
-                    route: collection.into_owned(),
-                })
-                .into(),
-            );
-        }
         let to_update = crate::changeset::encrypt_outgoing(changeset, &state.key)?;
         Ok(CollectionUpdate::new(
             client,
@@ -115,31 +222,252 @@ impl<'a> CollectionUpdate<'a> {
     /// Returns a list of the IDs that failed if allowed_dropped_records is true, otherwise
     /// returns an empty vec.
     pub fn upload(self) -> error::Result<UploadInfo> {
-        let mut failed = vec![];
-        let mut q = self.client.new_post_queue(
+        upload_records(
+            self.client,
+            self.state,
             &self.collection,
-            &self.state.config,
             self.xius,
-            NormalResponseHandler::new(!self.fully_atomic),
-        )?;
+            self.to_update.into_iter().map(Ok),
+            self.fully_atomic,
+        )
+    }
+}
 
-        for record in self.to_update.into_iter() {
-            let enqueued = q.enqueue(&record)?;
-            if !enqueued && self.fully_atomic {
-                return Err(ErrorKind::RecordTooLargeError.into());
-            }
+/// Uploads the records in `changeset`, followed by `records`. Unlike
+/// `CollectionUpdate`, which encrypts every record before uploading any of
+/// them, each record is encrypted as it's queued, so only the records for the
+/// current POST are held in memory. The post queue commits batches as they
+/// reach the server's limits.
+pub fn upload_outgoing(
+    client: &Sync15StorageClient,
+    state: &CollState,
+    changeset: OutgoingChangeset,
+    records: OutgoingRecords<'_>,
+    fully_atomic: bool,
+) -> Result<UploadInfo> {
+    check_xius(state, &changeset)?;
+    let RecordChangeset {
+        changes,
+        timestamp,
+        collection,
+    } = changeset;
+    let encrypted = changes
+        .into_iter()
+        .map(Ok)
+        .chain(records.map(|record| record.map_err(error::Error::from)))
+        .map(|record| CleartextBso::from_payload(record?, collection.clone()).encrypt(&state.key));
+    upload_records(
+        client,
+        state,
+        &collection,
+        timestamp,
+        encrypted,
+        fully_atomic,
+    )
+}
+
+fn check_xius(state: &CollState, changeset: &OutgoingChangeset) -> Result<()> {
+    if changeset.timestamp < state.last_modified {
+        // We know we are going to fail the XIUS check...
+        return Err(
+            ErrorKind::StorageHttpError(ErrorResponse::PreconditionFailed {
+                route: changeset.collection.to_string(),
+            })
+            .into(),
+        );
+    }
+    Ok(())
+}
+
+fn upload_records(
+    client: &Sync15StorageClient,
+    state: &CollState,
+    collection: &str,
+    xius: ServerTimestamp,
+    records: impl Iterator<Item = Result<EncryptedBso>>,
+    fully_atomic: bool,
+) -> Result<UploadInfo> {
+    let mut q = client.new_post_queue(
+        collection,
+        &state.config,
+        xius,
+        NormalResponseHandler::new(!fully_atomic),
+    )?;
+
+    for record in records {
+        let enqueued = q.enqueue(&record?)?;
+        if !enqueued && fully_atomic {
+            return Err(ErrorKind::RecordTooLargeError.into());
+        }
+    }
+
+    q.flush(true)?;
+    let info = q.completed_upload_info();
+    if fully_atomic {
+        assert_eq!(
+            info.failed_ids.len(),
+            0,
+            "Bug: Should have failed by now if we aren't allowing dropped records"
+        );
+    }
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bso_record::Payload;
+    use crate::test_utils::{client_for, insert_record, new_coll_state};
+    use interrupt::NeverInterrupts;
+    use serde_json::json;
+    use sync_test_server::{Fault, TestServer};
+
+    // Starts a server with five history records, written one at a time, so
+    // that sorting oldest first returns them in order.
+    fn server_with_history(state: &CollState) -> TestServer {
+        let server = TestServer::start().expect("should start the server");
+        for i in 0..5 {
+            let payload = Payload::from_json(json!({ "id": format!("record{:06}", i) })).unwrap();
+            insert_record(&server, &state.key, "history", payload);
         }
+        server
+    }
+
+    // Downloads history in pages of two records, and returns the IDs in each
+    // page, and the progress after it.
+    fn download(
+        server: &TestServer,
+        state: &mut CollState,
+        progress: Option<DownloadProgress>,
+    ) -> Vec<(Vec<String>, DownloadProgress)> {
+        let request = CollectionRequest::new("history").full().limit(2);
+        let mut pages = vec![];
+        let incoming = fetch_incoming_in_pages(
+            &client_for(server),
+            state,
+            &request,
+            progress,
+            &NeverInterrupts,
+            &mut |page, progress| {
+                let ids = page
+                    .changes
+                    .iter()
+                    .map(|(payload, _)| payload.id.to_string())
+                    .collect();
+                pages.push((ids, progress.clone()));
+                Ok(())
+            },
+        )
+        .expect("should download");
+        assert!(incoming.changes.is_empty());
+        assert_eq!(
+            incoming.timestamp,
+            ServerTimestamp(server.collection_modified("history"))
+        );
+        assert_eq!(state.last_modified, incoming.timestamp);
+        pages
+    }
+
+    fn ids(pages: &[(Vec<String>, DownloadProgress)]) -> Vec<Vec<&str>> {
+        pages
+            .iter()
+            .map(|(ids, _)| ids.iter().map(String::as_str).collect())
+            .collect()
+    }
 
-        q.flush(true)?;
-        let mut info = q.completed_upload_info();
-        info.failed_ids.append(&mut failed);
-        if self.fully_atomic {
-            assert_eq!(
-                info.failed_ids.len(),
-                0,
-                "Bug: Should have failed by now if we aren't allowing dropped records"
-            );
+    #[test]
+    fn test_fetch_incoming_in_pages() {
+        let mut state = new_coll_state();
+        let server = server_with_history(&state);
+        let pages = download(&server, &mut state, None);
+        assert_eq!(
+            ids(&pages),
+            vec![
+                vec!["record000000", "record000001"],
+                vec!["record000002", "record000003"],
+                vec!["record000004"],
+            ]
+        );
+        // Only the last page has no offset to resume from.
+        assert_eq!(
+            pages
+                .iter()
+                .map(|(_, progress)| progress.offset.is_some())
+                .collect::<Vec<_>>(),
+            vec![true, true, false]
+        );
+        for (_, progress) in &pages {
+            assert_eq!(progress.collection, "history");
+            assert!(progress.is_for(&CollectionRequest::new("history")));
         }
-        Ok(info)
+    }
+
+    #[test]
+    fn test_fetch_incoming_in_pages_resumes() {
+        let mut state = new_coll_state();
+        let server = server_with_history(&state);
+        let first_page = download(&server, &mut state, None)
+            .into_iter()
+            .next()
+            .unwrap()
+            .1;
+        // We were interrupted after the first page, so we resume after it.
+        let pages = download(&server, &mut state, Some(first_page));
+        assert_eq!(
+            ids(&pages),
+            vec![vec!["record000002", "record000003"], vec!["record000004"],]
+        );
+
+        // Progress for a different request is ignored.
+        let other_progress = DownloadProgress {
+            collection: "history".into(),
+            newer: Some(ServerTimestamp(1)),
+            older: None,
+            offset: Some("4".into()),
+            last_modified: ServerTimestamp(1),
+        };
+        assert_eq!(download(&server, &mut state, Some(other_progress)).len(), 3);
+
+        // If we applied every page, but weren't told to finish, there's
+        // nothing left to download.
+        let last_page = pages.into_iter().last().unwrap().1;
+        let requests = server.requests().len();
+        assert!(download(&server, &mut state, Some(last_page)).is_empty());
+        assert_eq!(server.requests().len(), requests);
+    }
+
+    #[test]
+    fn test_fetch_incoming_in_pages_restarts() {
+        let mut state = new_coll_state();
+        let server = server_with_history(&state);
+        let first_page = download(&server, &mut state, None)
+            .into_iter()
+            .next()
+            .unwrap()
+            .1;
+        // The server doesn't recognize our offset any more, so we start
+        // again from the first page.
+        server.add_fault(Fault::status(400).path("/storage/history"));
+        let pages = download(&server, &mut state, Some(first_page));
+        assert_eq!(
+            ids(&pages),
+            vec![
+                vec!["record000000", "record000001"],
+                vec!["record000002", "record000003"],
+                vec!["record000004"],
+            ]
+        );
+
+        // But a 400 for a download we didn't resume fails it.
+        server.add_fault(Fault::status(400).path("/storage/history"));
+        let result = fetch_incoming_in_pages(
+            &client_for(&server),
+            &mut state,
+            &CollectionRequest::new("history").full().limit(2),
+            None,
+            &NeverInterrupts,
+            &mut |_, _| Ok(()),
+        );
+        assert!(result.is_err());
     }
 }
@@ -7,13 +7,14 @@ use crate::error::{self, ErrorKind, ErrorResponse};
 use crate::record_types::MetaGlobalRecord;
 use crate::request::{
     BatchPoster, CollectionRequest, InfoCollections, InfoConfiguration, PostQueue, PostResponse,
-    PostResponseHandler,
+    PostResponseHandler, StorageQuota,
 };
 use crate::token;
 use crate::util::ServerTimestamp;
 use serde_json::Value;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
 use url::Url;
 use viaduct::{
     header_names::{self, AUTHORIZATION},
@@ -44,6 +45,17 @@ fn parse_seconds(seconds_str: &str) -> Option<u32> {
     }
 }
 
+// The server reports the remaining quota in kilobytes, and it can be negative
+// if the account is already over its quota.
+fn parse_quota_remaining(kb_str: &str) -> Option<u64> {
+    let kb = kb_str.parse::<f64>().ok()?;
+    if !kb.is_finite() {
+        log::warn!("invalid quota value: {}", kb_str);
+        return None;
+    }
+    Some((kb.max(0.0) * 1024.0) as u64)
+}
+
 impl<T> Sync15ClientResponse<T> {
     pub fn from_response(resp: Response, backoff_listener: &BackoffListener) -> error::Result<Self>
     where
@@ -96,6 +108,12 @@ impl<T> Sync15ClientResponse<T> {
                 404 => Sync15ClientResponse::Error(ErrorResponse::NotFound { route }),
                 401 => Sync15ClientResponse::Error(ErrorResponse::Unauthorized { route }),
                 412 => Sync15ClientResponse::Error(ErrorResponse::PreconditionFailed { route }),
+                // Older servers return a 403 with the "over quota" error code
+                // instead of a 507.
+                403 if resp.text().trim() == "14" => {
+                    Sync15ClientResponse::Error(ErrorResponse::QuotaExceeded { route, status })
+                }
+                507 => Sync15ClientResponse::Error(ErrorResponse::QuotaExceeded { route, status }),
                 500..=600 => {
                     Sync15ClientResponse::Error(ErrorResponse::ServerError { route, status })
                 }
@@ -194,6 +212,9 @@ impl BackoffState {
 pub struct Sync15StorageClient {
     tsc: token::TokenProvider,
     pub(crate) backoff: BackoffListener,
+    // The remaining quota, in bytes, from the last response with an
+    // `X-Weave-Quota-Remaining` header.
+    quota_remaining: Mutex<Option<u64>>,
 }
 
 impl SetupStorageClient for Sync15StorageClient {
@@ -275,6 +296,7 @@ impl Sync15StorageClient {
         Ok(Sync15StorageClient {
             tsc,
             backoff: new_backoff_listener(),
+            quota_remaining: Mutex::new(None),
         })
     }
 
@@ -285,6 +307,50 @@ impl Sync15StorageClient {
         self.collection_request(Method::Get, collection_request)
     }
 
+    /// Fetches a page of the records for `collection_request`, which should
+    /// have a `limit`. Also returns the `X-Weave-Next-Offset` to pass as the
+    /// `offset` of the request for the next page, or `None` if this was the
+    /// last page.
+    pub fn get_encrypted_records_page(
+        &self,
+        collection_request: &CollectionRequest,
+    ) -> error::Result<(Sync15ClientResponse<Vec<EncryptedBso>>, Option<String>)> {
+        let url = collection_request.build_url(Url::parse(&self.tsc.api_endpoint()?)?)?;
+        let req = self.build_request(Method::Get, url)?;
+        log::trace!("request: GET {} ({:?})", req.url.path(), req.url.query());
+        let resp = req.send()?;
+        let next_offset = resp
+            .headers
+            .get(header_names::X_WEAVE_NEXT_OFFSET)
+            .map(str::to_owned);
+        let result = Sync15ClientResponse::from_response(resp, &self.backoff)?;
+        Ok((result, next_offset))
+    }
+
+    /// Fetches the account's storage usage, and its quota if it has one.
+    pub fn fetch_info_quota(&self) -> error::Result<StorageQuota> {
+        // info/quota returns `[usage, quota]`, in kilobytes. The quota is
+        // `null` if the server doesn't enforce one.
+        let resp: Sync15ClientResponse<(f64, Option<f64>)> =
+            self.relative_storage_request(Method::Get, "info/quota")?;
+        match resp {
+            Sync15ClientResponse::Success {
+                record: (usage, quota),
+                ..
+            } => Ok(StorageQuota {
+                usage: (usage.max(0.0) * 1024.0) as u64,
+                quota: quota.map(|quota| (quota.max(0.0) * 1024.0) as u64),
+            }),
+            other => Err(other.create_storage_error().into()),
+        }
+    }
+
+    /// Returns the remaining quota, in bytes, that the server reported since
+    /// the last call, if any.
+    pub(crate) fn take_quota_remaining(&self) -> Option<u64> {
+        self.quota_remaining.lock().unwrap().take()
+    }
+
     #[inline]
     fn authorized(&self, req: Request) -> error::Result<Request> {
         let hawk_header_value = self.tsc.authorization(&req)?;
@@ -294,7 +360,10 @@ impl Sync15StorageClient {
     // TODO: probably want a builder-like API to do collection requests (e.g. something
     // that occupies roughly the same conceptual role as the Collection class in desktop)
     fn build_request(&self, method: Method, url: Url) -> error::Result<Request> {
-        self.authorized(Request::new(method, url).header(header_names::ACCEPT, "application/json")?)
+        let req = Request::new(method, url).header(header_names::ACCEPT, "application/json")?;
+        #[cfg(feature = "gzip")]
+        let req = req.accept_gzip()?;
+        self.authorized(req)
     }
 
     fn relative_storage_request<P, T>(
@@ -326,6 +395,14 @@ impl Sync15StorageClient {
             req.url.query()
         );
         let resp = req.send()?;
+        if let Some(remaining) = resp
+            .headers
+            .get(header_names::X_WEAVE_QUOTA_REMAINING)
+            .and_then(parse_quota_remaining)
+        {
+            log::info!("Server reports {} bytes of quota remaining", remaining);
+            *self.quota_remaining.lock().unwrap() = Some(remaining);
+        }
 
         let result = Sync15ClientResponse::from_response(resp, &self.backoff)?;
         match result {
@@ -436,6 +513,8 @@ impl<'a> BatchPoster for PostWrapper<'a> {
             .header(header_names::CONTENT_TYPE, "application/json")?
             .header(header_names::X_IF_UNMODIFIED_SINCE, format!("{}", xius))?
             .body(bytes);
+        #[cfg(feature = "gzip")]
+        let req = req.gzip_body()?;
         self.client.exec_request(req, false)
     }
 }
@@ -449,4 +528,13 @@ mod test {
         // Compile will fail if not send.
         ensure_send::<Sync15StorageClient>();
     }
+
+    #[test]
+    fn test_parse_quota_remaining() {
+        assert_eq!(parse_quota_remaining("2"), Some(2048));
+        assert_eq!(parse_quota_remaining("0.5"), Some(512));
+        assert_eq!(parse_quota_remaining("-10.25"), Some(0));
+        assert_eq!(parse_quota_remaining("NaN"), None);
+        assert_eq!(parse_quota_remaining("lots"), None);
+    }
 }
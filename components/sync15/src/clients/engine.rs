@@ -13,6 +13,7 @@ use crate::{
     key_bundle::KeyBundle,
     request::{CollectionRequest, InfoConfiguration},
     state::GlobalState,
+    telemetry,
 };
 use interrupt::Interruptee;
 use sync15_traits::client::ClientData;
@@ -26,6 +27,34 @@ use crate::error::Result;
 
 const COLLECTION_NAME: &str = "clients";
 
+/// The names of the commands that we report in telemetry. Event methods must
+/// be static strings, so other commands are reported as "custom".
+const BUILT_IN_COMMANDS: &[&str] = &[
+    "wipeAll",
+    "wipeEngine",
+    "resetAll",
+    "resetEngine",
+    "displayURI",
+    "closeTab",
+    "repairRequest",
+    "repairResponse",
+];
+
+/// Returns a telemetry event for a command that we sent or processed.
+fn command_event(object: &'static str, command: &CommandRecord) -> telemetry::Event {
+    let method = BUILT_IN_COMMANDS
+        .iter()
+        .find(|&&name| name == command.name)
+        .copied()
+        .unwrap_or("custom");
+    let event = telemetry::Event::new(object, method);
+    match &command.flow_id {
+        // Event extras are limited to 85 characters.
+        Some(flow_id) if flow_id.len() <= 85 => event.extra("flowID", flow_id.clone()),
+        _ => event,
+    }
+}
+
 /// The driver for the clients engine. Internal; split out from the `Engine`
 /// struct to make testing easier.
 struct Driver<'a> {
@@ -33,6 +62,12 @@ struct Driver<'a> {
     interruptee: &'a dyn Interruptee,
     config: &'a InfoConfiguration,
     recent_clients: HashMap<String, RemoteClient>,
+    /// Client-specific commands that we wrote into other clients' records,
+    /// as `(record ID, client ID, commands)`, to report to the command
+    /// processor once they're uploaded.
+    sent_commands: Vec<(String, String, Vec<Command>)>,
+    /// Telemetry events for the commands that we sent and processed.
+    events: Vec<telemetry::Event>,
 }
 
 impl<'a> Driver<'a> {
@@ -46,6 +81,8 @@ impl<'a> Driver<'a> {
             interruptee,
             config,
             recent_clients: HashMap::new(),
+            sent_commands: Vec::new(),
+            events: Vec::new(),
         }
     }
 
@@ -56,6 +93,7 @@ impl<'a> Driver<'a> {
     fn sync(&mut self, inbound: IncomingChangeset) -> Result<OutgoingChangeset> {
         let mut outgoing = OutgoingChangeset::new(COLLECTION_NAME, inbound.timestamp);
         outgoing.timestamp = inbound.timestamp;
+        let now = inbound.timestamp;
 
         self.interruptee.err_if_interrupted()?;
         let outgoing_commands = self.command_processor.fetch_outgoing_commands()?;
@@ -80,14 +118,21 @@ impl<'a> Driver<'a> {
                 has_own_client_record = true;
                 let mut current_client_record = self.current_client_record();
                 for c in client.commands {
+                    if c.is_expired(now) {
+                        log::debug!("Dropping expired command {:?}", c);
+                        continue;
+                    }
                     let status = match c.as_command() {
                         Some(command) => self.command_processor.apply_incoming_command(command)?,
                         None => CommandStatus::Unsupported,
                     };
                     match status {
-                        CommandStatus::Applied => {}
+                        CommandStatus::Applied => {
+                            self.events.push(command_event("processcommand", &c));
+                        }
                         CommandStatus::Ignored => {
                             log::debug!("Ignored command {:?}", c);
+                            self.events.push(command_event("processcommand", &c));
                         }
                         CommandStatus::Unsupported => {
                             log::warn!("Don't know how to apply command {:?}", c);
@@ -119,21 +164,37 @@ impl<'a> Driver<'a> {
                 // Add the other client to our map of recently synced clients.
                 self.note_recent_client(&client);
 
+                // Drop the commands that the other client didn't process in
+                // time. This only takes effect if we have other commands to
+                // write into its record.
+                client.commands.retain(|c| !c.is_expired(now));
+
+                let current_commands: HashSet<Command> = client
+                    .commands
+                    .iter()
+                    .filter_map(|c| c.as_command())
+                    .collect();
+                let client_id = client
+                    .fxa_device_id
+                    .clone()
+                    .unwrap_or_else(|| client.id.clone());
+                let commands_for_client = self
+                    .command_processor
+                    .fetch_outgoing_commands_for_client(&client_id, &current_commands)?;
+
                 // Bail if we don't have any outgoing commands to write into
                 // the other client's record.
-                if outgoing_commands.is_empty() {
+                if outgoing_commands.is_empty() && commands_for_client.is_empty() {
                     continue;
                 }
 
                 // Determine if we have new commands, that aren't already in the
                 // client's command list.
                 let old_len = client.commands.len();
-                let current_commands: HashSet<Command> = client
-                    .commands
-                    .iter()
-                    .filter_map(|c| c.as_command())
-                    .collect();
                 let mut new_outgoing_commands = outgoing_commands
+                    .union(&commands_for_client)
+                    .cloned()
+                    .collect::<HashSet<_>>()
                     .difference(&current_commands)
                     .cloned()
                     .collect::<Vec<_>>();
@@ -141,7 +202,13 @@ impl<'a> Driver<'a> {
                 new_outgoing_commands.sort();
                 client
                     .commands
-                    .extend(new_outgoing_commands.into_iter().map(CommandRecord::from));
+                    .extend(new_outgoing_commands.into_iter().map(|command| {
+                        let expires_at = command.ttl().map(|ttl| now.as_millis() + ttl);
+                        CommandRecord {
+                            expires_at,
+                            ..CommandRecord::from(command)
+                        }
+                    }));
                 if client.commands.len() == old_len {
                     continue;
                 }
@@ -154,6 +221,23 @@ impl<'a> Driver<'a> {
                     self.memcache_max_record_payload_size(),
                 )?;
 
+                if client.commands.len() > old_len {
+                    for c in &client.commands[old_len..] {
+                        self.events.push(command_event("sendcommand", c));
+                    }
+                }
+
+                let sent = client
+                    .commands
+                    .iter()
+                    .filter_map(|c| c.as_command())
+                    .filter(|c| commands_for_client.contains(c) && !current_commands.contains(c))
+                    .collect::<Vec<_>>();
+                if !sent.is_empty() {
+                    self.sent_commands
+                        .push((client.id.clone(), client_id, sent));
+                }
+
                 outgoing.changes.push(Payload::from_record(client)?);
             }
         }
@@ -170,6 +254,24 @@ impl<'a> Driver<'a> {
         Ok(outgoing)
     }
 
+    /// Notes the clients in `inbound`, without applying or sending any
+    /// commands, or returning any records to upload. This is used for
+    /// read-only syncs, where we can't change our own or others' records.
+    fn sync_readonly(&mut self, inbound: IncomingChangeset) -> Result<()> {
+        for (payload, _) in inbound.changes {
+            self.interruptee.err_if_interrupted()?;
+            let client: ClientRecord = payload.into_record()?;
+            // Our own record is built from our settings below, instead of
+            // whatever's on the server.
+            if client.id != self.command_processor.settings().fxa_device_id {
+                self.note_recent_client(&client);
+            }
+        }
+        let current_client_record = self.current_client_record();
+        self.note_recent_client(&current_client_record);
+        Ok(())
+    }
+
     /// Builds a fresh client record for this device.
     fn current_client_record(&self) -> ClientRecord {
         let settings = self.command_processor.settings();
@@ -189,24 +291,8 @@ impl<'a> Driver<'a> {
         }
     }
 
-    fn max_record_payload_size(&self) -> usize {
-        let payload_max = self.config.max_record_payload_bytes;
-        if payload_max <= self.config.max_post_bytes {
-            self.config.max_post_bytes.saturating_sub(4096)
-        } else {
-            payload_max
-        }
-    }
-
-    /// Collections stored in memcached ("tabs", "clients" or "meta") have a
-    /// different max size than ones stored in the normal storage server db.
-    /// In practice, the real limit here is 1M (bug 1300451 comment 40), but
-    /// there's overhead involved that is hard to calculate on the client, so we
-    /// use 512k to be safe (at the recommendation of the server team). Note
-    /// that if the server reports a lower limit (via info/configuration), we
-    /// respect that limit instead. See also bug 1403052.
     fn memcache_max_record_payload_size(&self) -> usize {
-        self.max_record_payload_size().min(512 * 1024)
+        self.config.max_record_payload_size_for(COLLECTION_NAME)
     }
 }
 
@@ -214,6 +300,9 @@ pub struct Engine<'a> {
     pub command_processor: &'a dyn CommandProcessor,
     pub interruptee: &'a dyn Interruptee,
     pub recent_clients: HashMap<String, RemoteClient>,
+    /// Telemetry events for the commands sent and processed during the last
+    /// sync.
+    pub events: Vec<telemetry::Event>,
 }
 
 impl<'a> Engine<'a> {
@@ -227,6 +316,7 @@ impl<'a> Engine<'a> {
             command_processor,
             interruptee,
             recent_clients: HashMap::new(),
+            events: Vec::new(),
         }
     }
 
@@ -276,6 +366,8 @@ impl<'a> Engine<'a> {
 
         let outgoing = driver.sync(inbound)?;
         self.recent_clients = driver.recent_clients;
+        self.events = driver.events;
+        let sent_commands = driver.sent_commands;
 
         coll_state.last_modified = outgoing.timestamp;
 
@@ -290,6 +382,51 @@ impl<'a> Engine<'a> {
             upload_info.failed_ids.len()
         );
 
+        for (record_id, client_id, commands) in sent_commands {
+            if upload_info.successful_ids.iter().any(|id| *id == record_id) {
+                self.command_processor
+                    .commands_sent(&client_id, &commands)?;
+            }
+        }
+
+        log::info!("Finished syncing clients");
+        Ok(())
+    }
+
+    /// Fetches the clients collection for a read-only sync. Other clients
+    /// are available through `recent_clients`, as for a full sync, but we
+    /// don't process incoming commands, send outgoing ones, or upload our
+    /// own client record.
+    pub fn sync_readonly(
+        &mut self,
+        storage_client: &Sync15StorageClient,
+        global_state: &GlobalState,
+        root_sync_key: &KeyBundle,
+    ) -> Result<()> {
+        log::info!("Syncing collection clients (read-only)");
+
+        let coll_keys =
+            CollectionKeys::from_encrypted_bso(global_state.keys.clone(), &root_sync_key)?;
+        let mut coll_state = CollState {
+            config: global_state.config.clone(),
+            last_modified: global_state
+                .collections
+                .get(COLLECTION_NAME)
+                .cloned()
+                .unwrap_or_default(),
+            key: coll_keys.key_for_collection(COLLECTION_NAME).clone(),
+        };
+
+        let inbound = self.fetch_incoming(&storage_client, &mut coll_state)?;
+
+        let mut driver = Driver::new(
+            self.command_processor,
+            self.interruptee,
+            &global_state.config,
+        );
+        driver.sync_readonly(inbound)?;
+        self.recent_clients = driver.recent_clients;
+
         log::info!("Finished syncing clients");
         Ok(())
     }
@@ -332,7 +469,9 @@ mod tests {
     use interrupt::NeverInterrupts;
     use serde_json::{json, Value};
 
-    use crate::clients::{CommandStatus, DeviceType, Settings};
+    use crate::clients::{
+        CommandStatus, DeviceType, RepairRequest, RepairResponse, Settings, COMMAND_TTL_MS,
+    };
     use crate::util::ServerTimestamp;
 
     use super::*;
@@ -351,14 +490,16 @@ mod tests {
             &self,
             command: Command,
         ) -> result::Result<CommandStatus, failure::Error> {
-            Ok(if let Command::Reset(name) = command {
-                if name == "forms" {
-                    CommandStatus::Unsupported
-                } else {
-                    CommandStatus::Applied
+            Ok(match command {
+                Command::Reset(name) => {
+                    if name == "forms" {
+                        CommandStatus::Unsupported
+                    } else {
+                        CommandStatus::Applied
+                    }
                 }
-            } else {
-                CommandStatus::Ignored
+                Command::DisplayUri { .. } | Command::Custom { .. } => CommandStatus::Unsupported,
+                _ => CommandStatus::Ignored,
             })
         }
 
@@ -525,6 +666,343 @@ mod tests {
         }
     }
 
+    struct ClientCommandsProcessor {
+        settings: Settings,
+        commands_for_client: HashMap<String, HashSet<Command>>,
+        current_commands: std::cell::RefCell<HashMap<String, HashSet<Command>>>,
+    }
+
+    impl CommandProcessor for ClientCommandsProcessor {
+        fn settings(&self) -> &Settings {
+            &self.settings
+        }
+
+        fn apply_incoming_command(
+            &self,
+            _command: Command,
+        ) -> result::Result<CommandStatus, failure::Error> {
+            Ok(CommandStatus::Applied)
+        }
+
+        fn fetch_outgoing_commands(&self) -> result::Result<HashSet<Command>, failure::Error> {
+            Ok(HashSet::new())
+        }
+
+        fn fetch_outgoing_commands_for_client(
+            &self,
+            client_id: &str,
+            current_commands: &HashSet<Command>,
+        ) -> result::Result<HashSet<Command>, failure::Error> {
+            self.current_commands
+                .borrow_mut()
+                .insert(client_id.into(), current_commands.clone());
+            Ok(self
+                .commands_for_client
+                .get(client_id)
+                .cloned()
+                .unwrap_or_default())
+        }
+    }
+
+    #[test]
+    fn test_commands_for_client() {
+        let close_tabs = Command::CloseTabs(vec!["https://example.com".into()]);
+        let display_uri = Command::DisplayUri {
+            uri: "https://example.org".into(),
+            sender_id: "deviceAAAAAA".into(),
+            title: "Example".into(),
+        };
+        let processor = ClientCommandsProcessor {
+            settings: Settings {
+                fxa_device_id: "deviceAAAAAA".into(),
+                device_name: "Laptop".into(),
+                device_type: DeviceType::Desktop,
+            },
+            commands_for_client: vec![(
+                "iPhooooooone".to_string(),
+                [close_tabs.clone(), display_uri.clone()]
+                    .iter()
+                    .cloned()
+                    .collect(),
+            )]
+            .into_iter()
+            .collect(),
+            current_commands: Default::default(),
+        };
+
+        let config = InfoConfiguration::default();
+
+        let mut driver = Driver::new(&processor, &NeverInterrupts, &config);
+
+        let clients = json!([{
+            "id": "deviceBBBBBB",
+            "name": "iPhone",
+            "type": "mobile",
+            "commands": [{
+                "command": "closeTab",
+                "args": ["https://example.com"],
+            }],
+            "fxaDeviceId": "iPhooooooone",
+        }, {
+            "id": "deviceCCCCCC",
+            "name": "Fenix",
+            "type": "mobile",
+            "commands": [],
+            "fxaDeviceId": "deviceCCCCCC",
+        }]);
+        let inbound = if let Value::Array(clients) = clients {
+            let changes = clients
+                .into_iter()
+                .map(|c| (Payload::from_json(c).unwrap(), ServerTimestamp(0)))
+                .collect();
+            IncomingChangeset {
+                changes,
+                timestamp: ServerTimestamp(0),
+                collection: COLLECTION_NAME.into(),
+            }
+        } else {
+            unreachable!("`clients` must be an array of client records")
+        };
+
+        let mut outgoing = driver.sync(inbound).expect("Should sync clients");
+        outgoing.changes.sort_by(|a, b| a.id.cmp(&b.id));
+
+        // The processor is told which commands are still pending, keyed by
+        // the FxA device ID.
+        let current_commands = processor.current_commands.borrow();
+        assert_eq!(
+            current_commands["iPhooooooone"],
+            [close_tabs].iter().cloned().collect()
+        );
+        assert!(current_commands["deviceCCCCCC"].is_empty());
+
+        // Only the new command is added, and only to the iPhone's record.
+        assert_eq!(
+            driver.sent_commands,
+            vec![(
+                "deviceBBBBBB".to_string(),
+                "iPhooooooone".to_string(),
+                vec![display_uri]
+            )]
+        );
+        let ids = outgoing
+            .changes
+            .iter()
+            .map(|p| p.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["deviceAAAAAA", "deviceBBBBBB"]);
+        assert_eq!(
+            outgoing.changes[1],
+            Payload::from_json(json!({
+                "id": "deviceBBBBBB",
+                "name": "iPhone",
+                "type": "mobile",
+                "commands": [{
+                    "command": "closeTab",
+                    "args": ["https://example.com"],
+                }, {
+                    "command": "displayURI",
+                    "args": ["https://example.org", "deviceAAAAAA", "Example"],
+                    "expiresAt": COMMAND_TTL_MS,
+                }],
+                "fxaDeviceId": "iPhooooooone",
+            }))
+            .unwrap()
+        );
+    }
+
+    struct RepairProcessor {
+        settings: Settings,
+        applied: std::cell::RefCell<Vec<Command>>,
+        outgoing_commands: HashSet<Command>,
+    }
+
+    impl CommandProcessor for RepairProcessor {
+        fn settings(&self) -> &Settings {
+            &self.settings
+        }
+
+        fn apply_incoming_command(
+            &self,
+            command: Command,
+        ) -> result::Result<CommandStatus, failure::Error> {
+            let status = match &command {
+                Command::RepairRequest(_) => CommandStatus::Applied,
+                Command::Custom { name, .. } if name == "appCommand" => CommandStatus::Applied,
+                _ => CommandStatus::Unsupported,
+            };
+            self.applied.borrow_mut().push(command);
+            Ok(status)
+        }
+
+        fn fetch_outgoing_commands(&self) -> result::Result<HashSet<Command>, failure::Error> {
+            Ok(self.outgoing_commands.clone())
+        }
+    }
+
+    #[test]
+    fn test_extensible_commands() {
+        let repair_response = Command::RepairResponse(RepairResponse {
+            collection: "bookmarks".into(),
+            request: "upload".into(),
+            client_id: "deviceAAAAAA".into(),
+            ids: vec!["bookmarkAAAA".into()],
+            flow_id: "repairflow".into(),
+        });
+        let processor = RepairProcessor {
+            settings: Settings {
+                fxa_device_id: "deviceAAAAAA".into(),
+                device_name: "Laptop".into(),
+                device_type: DeviceType::Desktop,
+            },
+            applied: Default::default(),
+            outgoing_commands: [repair_response].iter().cloned().collect(),
+        };
+
+        let config = InfoConfiguration::default();
+
+        let mut driver = Driver::new(&processor, &NeverInterrupts, &config);
+
+        let clients = json!([{
+            "id": "deviceAAAAAA",
+            "name": "Laptop",
+            "type": "desktop",
+            "commands": [{
+                "command": "repairRequest",
+                "args": [{
+                    "collection": "bookmarks",
+                    "request": "upload",
+                    "requestor": "deviceBBBBBB",
+                    "ids": ["bookmarkAAAA"],
+                    "flowID": "repairflow",
+                }],
+                "flowID": "repairflow",
+            }, {
+                "command": "appCommand",
+                "args": ["hello"],
+                "flowID": "appflow",
+            }, {
+                "command": "logout",
+                "args": [],
+            }, {
+                "command": "objectArgs",
+                "args": [{ "some": "object" }],
+            }, {
+                "command": "displayURI",
+                "args": ["https://example.com", "deviceBBBBBB", "Expired"],
+                "expiresAt": 500,
+            }],
+            "fxaDeviceId": "deviceAAAAAA",
+        }, {
+            "id": "deviceBBBBBB",
+            "name": "Desktop",
+            "type": "desktop",
+            "commands": [{
+                "command": "closeTab",
+                "args": ["https://example.org"],
+                "expiresAt": 10,
+            }],
+            "fxaDeviceId": "deviceBBBBBB",
+        }]);
+        let inbound = if let Value::Array(clients) = clients {
+            let changes = clients
+                .into_iter()
+                .map(|c| (Payload::from_json(c).unwrap(), ServerTimestamp(1000)))
+                .collect();
+            IncomingChangeset {
+                changes,
+                timestamp: ServerTimestamp(1000),
+                collection: COLLECTION_NAME.into(),
+            }
+        } else {
+            unreachable!("`clients` must be an array of client records")
+        };
+
+        let mut outgoing = driver.sync(inbound).expect("Should sync clients");
+        outgoing.changes.sort_by(|a, b| a.id.cmp(&b.id));
+
+        // Expired commands, and commands with arguments we can't represent,
+        // aren't passed to the processor.
+        assert_eq!(
+            *processor.applied.borrow(),
+            vec![
+                Command::RepairRequest(RepairRequest {
+                    collection: "bookmarks".into(),
+                    request: "upload".into(),
+                    requestor: "deviceBBBBBB".into(),
+                    ids: vec!["bookmarkAAAA".into()],
+                    flow_id: "repairflow".into(),
+                }),
+                Command::Custom {
+                    name: "appCommand".into(),
+                    args: vec!["hello".into()],
+                    flow_id: Some("appflow".into()),
+                },
+                Command::Custom {
+                    name: "logout".into(),
+                    args: Vec::new(),
+                    flow_id: None,
+                },
+            ]
+        );
+
+        let expected = json!([{
+            "id": "deviceAAAAAA",
+            "name": "Laptop",
+            "type": "desktop",
+            "commands": [{
+                "command": "logout",
+                "args": [],
+            }, {
+                "command": "objectArgs",
+                "args": [{ "some": "object" }],
+            }],
+            "fxaDeviceId": "deviceAAAAAA",
+            "protocols": ["1.5"],
+        }, {
+            "id": "deviceBBBBBB",
+            "name": "Desktop",
+            "type": "desktop",
+            "commands": [{
+                "command": "repairResponse",
+                "args": [{
+                    "collection": "bookmarks",
+                    "request": "upload",
+                    "clientID": "deviceAAAAAA",
+                    "ids": ["bookmarkAAAA"],
+                    "flowID": "repairflow",
+                }],
+                "flowID": "repairflow",
+                "expiresAt": 1000 + COMMAND_TTL_MS,
+            }],
+            "fxaDeviceId": "deviceBBBBBB",
+        }]);
+        if let Value::Array(expected) = expected {
+            for (i, record) in expected.into_iter().enumerate() {
+                assert_eq!(outgoing.changes[i], Payload::from_json(record).unwrap());
+            }
+        } else {
+            unreachable!("`expected_clients` must be an array of client records")
+        }
+
+        assert_eq!(
+            serde_json::to_value(&driver.events).unwrap(),
+            json!([{
+                "object": "processcommand",
+                "method": "repairRequest",
+                "extra": { "flowID": "repairflow" },
+            }, {
+                "object": "processcommand",
+                "method": "custom",
+                "extra": { "flowID": "appflow" },
+            }, {
+                "object": "sendcommand",
+                "method": "repairResponse",
+                "extra": { "flowID": "repairflow" },
+            }])
+        );
+    }
+
     #[test]
     fn test_fresh_client_record() {
         let processor = TestProcessor {
@@ -610,4 +1088,96 @@ mod tests {
             unreachable!("`expected_clients` must be an array of client records")
         }
     }
+
+    #[test]
+    fn test_readonly_clients_sync() {
+        struct NoCommandsProcessor {
+            settings: Settings,
+        }
+
+        impl CommandProcessor for NoCommandsProcessor {
+            fn settings(&self) -> &Settings {
+                &self.settings
+            }
+
+            fn apply_incoming_command(
+                &self,
+                command: Command,
+            ) -> result::Result<CommandStatus, failure::Error> {
+                panic!("Shouldn't apply {:?} in a read-only sync", command);
+            }
+
+            fn fetch_outgoing_commands(&self) -> result::Result<HashSet<Command>, failure::Error> {
+                panic!("Shouldn't send commands in a read-only sync");
+            }
+        }
+
+        let processor = NoCommandsProcessor {
+            settings: Settings {
+                fxa_device_id: "deviceAAAAAA".into(),
+                device_name: "Laptop".into(),
+                device_type: DeviceType::Desktop,
+            },
+        };
+
+        let config = InfoConfiguration::default();
+
+        let mut driver = Driver::new(&processor, &NeverInterrupts, &config);
+
+        let clients = json!([{
+            "id": "deviceAAAAAA",
+            "name": "Laptop with a different name",
+            "type": "desktop",
+            "commands": [{
+                "command": "wipeEngine",
+                "args": ["logins"]
+            }],
+            "fxaDeviceId": "deviceAAAAAA",
+        }, {
+            "id": "deviceBBBBBB",
+            "name": "iPhone",
+            "type": "mobile",
+            "commands": [],
+            "fxaDeviceId": "iPhooooooone",
+        }]);
+        let inbound = if let Value::Array(clients) = clients {
+            let changes = clients
+                .into_iter()
+                .map(|c| (Payload::from_json(c).unwrap(), ServerTimestamp(0)))
+                .collect();
+            IncomingChangeset {
+                changes,
+                timestamp: ServerTimestamp(0),
+                collection: COLLECTION_NAME.into(),
+            }
+        } else {
+            unreachable!("`clients` must be an array of client records")
+        };
+
+        driver.sync_readonly(inbound).expect("Should sync clients");
+        assert!(driver.events.is_empty());
+        assert!(driver.sent_commands.is_empty());
+
+        let mut actual_remote_clients = driver
+            .recent_clients
+            .values()
+            .cloned()
+            .collect::<Vec<RemoteClient>>();
+        actual_remote_clients.sort_by(|a, b| a.device_name.cmp(&b.device_name));
+        assert_eq!(
+            actual_remote_clients,
+            &[
+                RemoteClient {
+                    fxa_device_id: Some("deviceAAAAAA".to_string()),
+                    device_name: "Laptop".into(),
+                    device_type: Some(DeviceType::Desktop),
+                },
+                RemoteClient {
+                    fxa_device_id: Some("iPhooooooone".to_string()),
+                    device_name: "iPhone".into(),
+                    device_type: Some(DeviceType::Mobile),
+                },
+            ]
+        );
+    }
 }
@@ -5,6 +5,7 @@
 use std::collections::HashSet;
 
 use failure;
+use serde_derive::*;
 
 mod engine;
 mod record;
@@ -31,6 +32,28 @@ pub trait CommandProcessor {
     /// commands couldn't be fetched, and halts the sync.
     fn fetch_outgoing_commands(&self) -> Result<HashSet<Command>, failure::Error>;
 
+    /// Fetches commands to send to one specific client, in addition to the
+    /// ones from `fetch_outgoing_commands`. `client_id` is the client's FxA
+    /// device ID, or its record ID if it doesn't have one. `current_commands`
+    /// are the commands already in the client's record: commands that we sent
+    /// on a previous sync, and that aren't in this set anymore, have been
+    /// processed by the other client.
+    fn fetch_outgoing_commands_for_client(
+        &self,
+        _client_id: &str,
+        _current_commands: &HashSet<Command>,
+    ) -> Result<HashSet<Command>, failure::Error> {
+        Ok(HashSet::new())
+    }
+
+    /// Called once the commands from `fetch_outgoing_commands_for_client` have
+    /// been uploaded in the client's record. Commands that didn't fit in the
+    /// record, or whose upload failed, aren't included, and will be fetched
+    /// again on the next sync.
+    fn commands_sent(&self, _client_id: &str, _commands: &[Command]) -> Result<(), failure::Error> {
+        Ok(())
+    }
+
     /// Applies a command sent to this client from another client. This method
     /// should return a `CommandStatus` indicating whether the command was
     /// processed.
@@ -44,9 +67,9 @@ pub trait CommandProcessor {
 
 /// Indicates if a command was applied successfully, ignored, or not supported.
 /// Applied and ignored commands are removed from our client record, and never
-/// retried. Unsupported commands are put back into our record, and retried on
-/// subsequent syncs. This is to handle clients adding support for new data
-/// types.
+/// retried. Unsupported commands are put back into our record, exactly as we
+/// received them, and retried on subsequent syncs until they expire. This is to
+/// handle clients adding support for new data types.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum CommandStatus {
     Applied,
@@ -88,4 +111,100 @@ pub enum Command {
     ResetAll,
     /// Resets local sync state for a specific engine.
     Reset(String),
+    /// Opens a URL, sent from another client.
+    DisplayUri {
+        uri: String,
+        /// The record ID of the client that sent the URL.
+        sender_id: String,
+        title: String,
+    },
+    /// Closes the tabs with these URLs.
+    CloseTabs(Vec<String>),
+    /// Asks another client to upload records that our validator found
+    /// missing or corrupt. Desktop sends these to repair bookmarks.
+    RepairRequest(RepairRequest),
+    /// Responds to a `RepairRequest`, once the records are uploaded.
+    RepairResponse(RepairResponse),
+    /// A command that isn't built in, like Desktop's `logout`, or one that
+    /// the application defines. Only commands whose arguments are all strings
+    /// can be represented this way; others are always unsupported.
+    Custom {
+        name: String,
+        args: Vec<String>,
+        flow_id: Option<String>,
+    },
+}
+
+impl Command {
+    /// The command's name in the client record.
+    pub fn name(&self) -> &str {
+        match self {
+            Command::WipeAll => "wipeAll",
+            Command::Wipe(_) => "wipeEngine",
+            Command::ResetAll => "resetAll",
+            Command::Reset(_) => "resetEngine",
+            Command::DisplayUri { .. } => "displayURI",
+            Command::CloseTabs(_) => "closeTab",
+            Command::RepairRequest(_) => "repairRequest",
+            Command::RepairResponse(_) => "repairResponse",
+            Command::Custom { name, .. } => name,
+        }
+    }
+
+    /// The flow ID that other clients record in their telemetry, if any.
+    pub fn flow_id(&self) -> Option<&str> {
+        match self {
+            Command::RepairRequest(request) => Some(&request.flow_id),
+            Command::RepairResponse(response) => Some(&response.flow_id),
+            Command::Custom { flow_id, .. } => flow_id.as_ref().map(String::as_str),
+            _ => None,
+        }
+    }
+
+    /// How long the command stays in the other client's record, in
+    /// milliseconds, if it's not processed. Wipes and resets never expire,
+    /// since skipping them could merge data that the user wanted gone.
+    pub fn ttl(&self) -> Option<i64> {
+        match self {
+            Command::WipeAll | Command::Wipe(_) | Command::ResetAll | Command::Reset(_) => None,
+            _ => Some(COMMAND_TTL_MS),
+        }
+    }
+}
+
+/// The default lifetime of a command, a week.
+pub const COMMAND_TTL_MS: i64 = 7 * 24 * 60 * 60 * 1000;
+
+/// The argument of a `repairRequest` command. This is a JSON object in the
+/// command record, not a string.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepairRequest {
+    /// The collection to repair, like "bookmarks".
+    pub collection: String,
+    /// What to do; Desktop only sends "upload".
+    pub request: String,
+    /// The record ID of the client that sent the request.
+    pub requestor: String,
+    /// The IDs of the records to upload.
+    #[serde(default)]
+    pub ids: Vec<String>,
+    #[serde(rename = "flowID")]
+    pub flow_id: String,
+}
+
+/// The argument of a `repairResponse` command.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepairResponse {
+    pub collection: String,
+    pub request: String,
+    /// The record ID of the client that uploaded the records.
+    #[serde(rename = "clientID")]
+    pub client_id: String,
+    /// The IDs of the records that were uploaded.
+    #[serde(default)]
+    pub ids: Vec<String>,
+    #[serde(rename = "flowID")]
+    pub flow_id: String,
 }
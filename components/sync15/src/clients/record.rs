@@ -2,12 +2,15 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use serde::de::DeserializeOwned;
 use serde_derive::*;
+use serde_json::Value;
 
 use super::Command;
+use crate::util::ServerTimestamp;
 
 /// The serialized form of a client record.
-#[derive(Clone, Debug, Eq, Deserialize, Hash, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ClientRecord {
     #[serde(rename = "id")]
@@ -58,7 +61,7 @@ pub struct ClientRecord {
 }
 
 /// The serialized form of a client command.
-#[derive(Clone, Debug, Eq, Deserialize, Hash, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CommandRecord {
     /// The command name. This is a string, not an enum, because we want to
@@ -67,54 +70,104 @@ pub struct CommandRecord {
     pub name: String,
 
     /// Extra, command-specific arguments. Note that we must send an empty
-    /// array if the command expects no arguments.
+    /// array if the command expects no arguments. Most arguments are strings,
+    /// but Desktop sends objects for repair commands.
     #[serde(default)]
-    pub args: Vec<String>,
+    pub args: Vec<Value>,
 
-    /// Some commands, like repair, send a "flow ID" that other cliennts can
-    /// record in their telemetry. We don't currently send commands with
-    /// flow IDs, but we round-trip them.
+    /// Some commands, like repair, send a "flow ID" that other clients can
+    /// record in their telemetry.
     #[serde(default, rename = "flowID", skip_serializing_if = "Option::is_none")]
     pub flow_id: Option<String>,
+
+    /// When the command expires, in milliseconds, by the server's clock.
+    /// Expired commands are dropped without being applied. Only we set this,
+    /// and commands from other clients never expire.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
 }
 
 impl CommandRecord {
     /// Converts a serialized command into one that we can apply. Returns `None`
     /// if we don't support the command.
     pub fn as_command(&self) -> Option<Command> {
+        let arg = |index: usize| self.args.get(index).and_then(Value::as_str);
         match self.name.as_str() {
-            "wipeEngine" => self.args.get(0).map(|e| Command::Wipe(e.into())),
+            "wipeEngine" => arg(0).map(|e| Command::Wipe(e.into())),
             "wipeAll" => Some(Command::WipeAll),
-            "resetEngine" => self.args.get(0).map(|e| Command::Reset(e.into())),
+            "resetEngine" => arg(0).map(|e| Command::Reset(e.into())),
             "resetAll" => Some(Command::ResetAll),
-            _ => None,
+            "displayURI" => match (arg(0), arg(1)) {
+                (Some(uri), Some(sender_id)) => Some(Command::DisplayUri {
+                    uri: uri.into(),
+                    sender_id: sender_id.into(),
+                    title: arg(2).unwrap_or_default().into(),
+                }),
+                _ => None,
+            },
+            "closeTab" => self
+                .string_args()
+                .filter(|urls| !urls.is_empty())
+                .map(Command::CloseTabs),
+            "repairRequest" => self.object_arg().map(Command::RepairRequest),
+            "repairResponse" => self.object_arg().map(Command::RepairResponse),
+            _ => self.string_args().map(|args| Command::Custom {
+                name: self.name.clone(),
+                args,
+                flow_id: self.flow_id.clone(),
+            }),
         }
     }
+
+    /// Returns `true` if the command expired before `now`.
+    pub fn is_expired(&self, now: ServerTimestamp) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| expires_at < now.as_millis())
+    }
+
+    fn string_args(&self) -> Option<Vec<String>> {
+        self.args
+            .iter()
+            .map(|arg| arg.as_str().map(str::to_owned))
+            .collect()
+    }
+
+    fn object_arg<T: DeserializeOwned>(&self) -> Option<T> {
+        self.args
+            .get(0)
+            .and_then(|arg| serde_json::from_value(arg.clone()).ok())
+    }
 }
 
 impl From<Command> for CommandRecord {
     fn from(command: Command) -> CommandRecord {
-        match command {
-            Command::Wipe(engine) => CommandRecord {
-                name: "wipeEngine".into(),
-                args: vec![engine],
-                flow_id: None,
-            },
-            Command::WipeAll => CommandRecord {
-                name: "wipeAll".into(),
-                args: Vec::new(),
-                flow_id: None,
-            },
-            Command::Reset(engine) => CommandRecord {
-                name: "resetEngine".into(),
-                args: vec![engine],
-                flow_id: None,
-            },
-            Command::ResetAll => CommandRecord {
-                name: "resetAll".into(),
-                args: Vec::new(),
-                flow_id: None,
-            },
+        let name = command.name().to_owned();
+        let flow_id = command.flow_id().map(str::to_owned);
+        let args = match command {
+            Command::Wipe(engine) | Command::Reset(engine) => vec![engine.into()],
+            Command::WipeAll | Command::ResetAll => Vec::new(),
+            Command::DisplayUri {
+                uri,
+                sender_id,
+                title,
+            } => vec![uri.into(), sender_id.into(), title.into()],
+            Command::CloseTabs(urls) => urls.into_iter().map(Value::from).collect(),
+            // These structs only have string fields, so serializing them
+            // can't fail.
+            Command::RepairRequest(request) => {
+                vec![serde_json::to_value(request).expect("Repair requests should always serialize")]
+            }
+            Command::RepairResponse(response) => {
+                vec![serde_json::to_value(response)
+                    .expect("Repair responses should always serialize")]
+            }
+            Command::Custom { args, .. } => args.into_iter().map(Value::from).collect(),
+        };
+        CommandRecord {
+            name,
+            args,
+            flow_id,
+            expires_at: None,
         }
     }
 }
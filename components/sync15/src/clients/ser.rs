@@ -90,16 +90,19 @@ mod tests {
                 name: "wipeEngine".into(),
                 args: vec!["bookmarks".into()],
                 flow_id: Some("flow".into()),
+                expires_at: None,
             },
             CommandRecord {
                 name: "resetEngine".into(),
                 args: vec!["history".into()],
                 flow_id: Some("flow".into()),
+                expires_at: None,
             },
             CommandRecord {
                 name: "logout".into(),
                 args: Vec::new(),
                 flow_id: None,
+                expires_at: None,
             },
         ];
 
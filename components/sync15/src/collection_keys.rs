@@ -26,6 +26,18 @@ impl CollectionKeys {
         })
     }
 
+    /// Generates a random default key, and a separate random key for each of
+    /// `collections`. Records in those collections are encrypted with their
+    /// own key, so one collection's key doesn't decrypt the others.
+    pub fn new_random_with_collections(collections: &[String]) -> Result<CollectionKeys> {
+        let mut keys = CollectionKeys::new_random()?;
+        for collection in collections {
+            keys.collections
+                .insert(collection.clone(), KeyBundle::new_random()?);
+        }
+        Ok(keys)
+    }
+
     pub fn from_encrypted_bso(
         record: EncryptedBso,
         root_key: &KeyBundle,
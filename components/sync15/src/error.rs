@@ -19,6 +19,8 @@ pub enum ErrorResponse {
     PreconditionFailed { route: String },
     // 5XX
     ServerError { route: String, status: u16 }, // TODO: info for "retry-after" and backoff handling etc here.
+    // 507, or a 403 with the "over quota" error code.
+    QuotaExceeded { route: String, status: u16 },
     // Other HTTP responses.
     RequestFailed { route: String, status: u16 },
 }
@@ -145,4 +147,13 @@ impl Error {
             None
         }
     }
+
+    /// Whether the server rejected a write because the collection changed
+    /// since the timestamp we sent in `X-If-Unmodified-Since`.
+    pub(crate) fn is_precondition_failed(&self) -> bool {
+        match self.kind() {
+            ErrorKind::StorageHttpError(ErrorResponse::PreconditionFailed { .. }) => true,
+            _ => false,
+        }
+    }
 }
@@ -21,12 +21,16 @@ mod status;
 mod sync;
 mod sync_multiple;
 pub mod telemetry;
+#[cfg(test)]
+mod test_utils;
 mod token;
 mod util;
 
 // Re-export some of the types callers are likely to want for convenience.
 pub use crate::bso_record::{BsoRecord, CleartextBso, EncryptedBso, EncryptedPayload, Payload};
-pub use crate::changeset::{IncomingChangeset, OutgoingChangeset, RecordChangeset};
+pub use crate::changeset::{
+    IncomingChangeset, OutgoingChangeset, OutgoingRecords, RecordChangeset,
+};
 pub use crate::client::{
     SetupStorageClient, Sync15ClientResponse, Sync15StorageClient, Sync15StorageClientInit,
 };
@@ -35,8 +39,11 @@ pub use crate::collection_keys::CollectionKeys;
 pub use crate::error::{Error, ErrorKind, Result};
 pub use crate::key_bundle::KeyBundle;
 pub use crate::migrate_state::extract_v1_state;
-pub use crate::request::CollectionRequest;
-pub use crate::state::{GlobalState, SetupStateMachine};
+pub use crate::request::{CollectionRequest, DownloadProgress, StorageQuota};
+pub use crate::state::{
+    get_declined_engines, get_pending_engine_changes, set_pending_engine_changes, GlobalState,
+    SetupStateMachine,
+};
 pub use crate::status::{ServiceStatus, SyncResult};
 pub use crate::sync::{synchronize, Store};
 pub use crate::sync_multiple::{
@@ -6,6 +6,7 @@ use crate::record_types::MetaGlobalRecord;
 use crate::state::PersistedGlobalState;
 use crate::CollSyncIds;
 use serde_json::Value;
+use std::collections::HashMap;
 
 /// Given a string persisted as our old GlobalState V1 struct, extract out
 /// the sync IDs for the collection, plus a string which should be used as the
@@ -46,6 +47,7 @@ pub fn extract_v1_state(
     };
     let pgs = PersistedGlobalState::V2 {
         declined: Some(meta_global.declined),
+        pending_engine_changes: HashMap::new(),
     };
     let new_global_state = serde_json::to_string(&pgs).ok();
 
@@ -136,6 +138,7 @@ mod tests {
         // state reflects that.
         let expected_state = serde_json::to_string(&PersistedGlobalState::V2 {
             declined: Some(Vec::<String>::new()),
+            pending_engine_changes: HashMap::new(),
         })
         .expect("should stringify");
         assert_eq!(new_state, Some(expected_state));
@@ -161,6 +164,7 @@ mod tests {
         let s = get_state_with_engine_changes_and_declined("", "\\\"foo\\\"");
         let expected_state = serde_json::to_string(&PersistedGlobalState::V2 {
             declined: Some(vec!["foo".to_string()]),
+            pending_engine_changes: HashMap::new(),
         })
         .unwrap();
         assert_eq!(
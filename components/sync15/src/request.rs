@@ -10,7 +10,7 @@ use serde_derive::*;
 use std::collections::HashMap;
 use std::default::Default;
 use std::ops::Deref;
-pub use sync15_traits::{CollectionRequest, RequestOrder};
+pub use sync15_traits::{CollectionRequest, DownloadProgress, RequestOrder, StorageQuota};
 use sync_guid::Guid;
 use viaduct::status_codes;
 
@@ -98,6 +98,33 @@ fn default_max_record_payload_bytes() -> usize {
     256 * 1024
 }
 
+impl InfoConfiguration {
+    fn max_record_payload_size(&self) -> usize {
+        let payload_max = self.max_record_payload_bytes;
+        if payload_max <= self.max_post_bytes {
+            self.max_post_bytes.saturating_sub(4096)
+        } else {
+            payload_max
+        }
+    }
+
+    /// Returns the maximum payload size for a record in `collection`.
+    ///
+    /// Collections stored in memcached ("tabs", "clients" or "meta") have a
+    /// different max size than ones stored in the normal storage server db.
+    /// In practice, the real limit here is 1M (bug 1300451 comment 40), but
+    /// there's overhead involved that is hard to calculate on the client, so we
+    /// use 512k to be safe (at the recommendation of the server team). Note
+    /// that if the server reports a lower limit (via info/configuration), we
+    /// respect that limit instead. See also bug 1403052.
+    pub fn max_record_payload_size_for(&self, collection: &str) -> usize {
+        match collection {
+            "tabs" | "clients" | "meta" => self.max_record_payload_size().min(512 * 1024),
+            _ => self.max_record_payload_size(),
+        }
+    }
+}
+
 impl Default for InfoConfiguration {
     #[inline]
     fn default() -> InfoConfiguration {
@@ -542,10 +569,22 @@ mod test {
             .sort_by(RequestOrder::Oldest)
             .older_than(ServerTimestamp(9_876_540))
             .newer_than(ServerTimestamp(1_234_560))
-            .build_url(base)
+            .build_url(base.clone())
             .unwrap();
         assert_eq!(complex.as_str(),
             "https://example.com/sync/storage/specific?full=1&limit=10&older=9876.54&newer=1234.56&sort=oldest");
+
+        let page = CollectionRequest::new("paged")
+            .full()
+            .limit(100)
+            .offset("1234:100")
+            .sort_by(RequestOrder::Oldest)
+            .build_url(base)
+            .unwrap();
+        assert_eq!(
+            page.as_str(),
+            "https://example.com/sync/storage/paged?full=1&limit=100&offset=1234%3A100&sort=oldest"
+        );
     }
 
     #[derive(Debug, Clone)]
@@ -805,6 +844,25 @@ mod test {
             .sum::<usize>()
     }
 
+    #[test]
+    fn test_max_record_payload_size_for() {
+        let config = InfoConfiguration {
+            max_record_payload_bytes: 1024 * 1024,
+            max_post_bytes: 100 * 1024,
+            ..InfoConfiguration::default()
+        };
+        assert_eq!(config.max_record_payload_size_for("tabs"), 512 * 1024);
+        assert_eq!(config.max_record_payload_size_for("history"), 1024 * 1024);
+
+        let config = InfoConfiguration {
+            max_record_payload_bytes: 100 * 1024,
+            max_post_bytes: 200 * 1024,
+            ..InfoConfiguration::default()
+        };
+        assert_eq!(config.max_record_payload_size_for("tabs"), 196 * 1024);
+        assert_eq!(config.max_record_payload_size_for("history"), 196 * 1024);
+    }
+
     #[test]
     fn test_pq_basic() {
         let cfg = InfoConfiguration {
@@ -23,7 +23,8 @@ const STORAGE_VERSION: usize = 5;
 /// Maps names to storage versions for engines to include in a fresh
 /// `meta/global` record. We include engines that we don't implement
 /// because they'll be disabled on other clients if we omit them
-/// (bug 1479929).
+/// (bug 1479929). Engines that only some of our consumers implement are
+/// declared by their stores instead, with `Store::meta_global_version`.
 const DEFAULT_ENGINES: &[(&str, usize)] = &[
     ("passwords", 1),
     ("clients", 1),
@@ -59,13 +60,26 @@ pub enum PersistedGlobalState {
     /// V2 is just tracking the globally declined list.
     /// None means "I've no idea" and theoretically should only happen on the
     /// very first sync for an app.
-    V2 { declined: Option<Vec<String>> },
+    ///
+    /// `pending_engine_changes` holds engines that were enabled (true) or
+    /// declined (false) on this device, but haven't been uploaded to
+    /// `meta/global` yet. The Sync Manager keeps them here, with
+    /// `set_pending_engine_changes`, so they aren't lost if the app exits
+    /// before a sync uploads them.
+    V2 {
+        declined: Option<Vec<String>>,
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        pending_engine_changes: HashMap<String, bool>,
+    },
 }
 
 impl Default for PersistedGlobalState {
     #[inline]
     fn default() -> PersistedGlobalState {
-        PersistedGlobalState::V2 { declined: None }
+        PersistedGlobalState::V2 {
+            declined: None,
+            pending_engine_changes: HashMap::new(),
+        }
     }
 }
 
@@ -73,6 +87,9 @@ impl Default for PersistedGlobalState {
 pub(crate) struct EngineChangesNeeded {
     pub local_resets: HashSet<String>,
     pub remote_wipes: HashSet<String>,
+    // Engines another client enabled (true) or declined (false) since we
+    // last saw `meta/global`.
+    pub remote_changes: HashMap<String, bool>,
 }
 
 #[derive(Debug, Default, Clone, PartialEq)]
@@ -126,6 +143,20 @@ fn compute_engine_states(input: EngineStateInput) -> EngineStateOutput {
         &must_enable,
     );
 
+    // Our own changes win over another client's, so we don't report those.
+    let changed_locally = set_union(&must_enable, &must_disable);
+    let mut remote_changes = HashMap::new();
+    if have_remote {
+        let enabled = set_difference(&input.local_declined, &remote_declined);
+        let declined = set_difference(&remote_declined, &input.local_declined);
+        for engine in set_difference(&enabled, &changed_locally) {
+            remote_changes.insert(engine, true);
+        }
+        for engine in set_difference(&declined, &changed_locally) {
+            remote_changes.insert(engine, false);
+        }
+    }
+
     let output = EngineStateOutput {
         changes_needed: EngineChangesNeeded {
             // Anything now declined which wasn't in our declined list before gets a reset.
@@ -135,6 +166,7 @@ fn compute_engine_states(input: EngineStateInput) -> EngineStateOutput {
             // and info/collections, but we'll let other clients pick up their
             // own mess for now.
             remote_wipes: set_intersection(&info_collections, &must_disable),
+            remote_changes,
         },
         declined: result_declined,
     };
@@ -143,16 +175,67 @@ fn compute_engine_states(input: EngineStateInput) -> EngineStateOutput {
     output
 }
 
+/// Returns the declined engines from the persisted state returned by a sync,
+/// or `None` if we haven't synced yet, or the state can't be parsed.
+pub fn get_declined_engines(persisted_state: &str) -> Option<Vec<String>> {
+    match serde_json::from_str::<PersistedGlobalState>(persisted_state) {
+        Ok(PersistedGlobalState::V2 { declined, .. }) => declined,
+        Err(_) => None,
+    }
+}
+
+/// Returns the engine changes saved in the persisted state returned by a
+/// sync, or an empty map if there aren't any, or the state can't be parsed.
+pub fn get_pending_engine_changes(persisted_state: &str) -> HashMap<String, bool> {
+    match serde_json::from_str::<PersistedGlobalState>(persisted_state) {
+        Ok(PersistedGlobalState::V2 {
+            pending_engine_changes,
+            ..
+        }) => pending_engine_changes,
+        Err(_) => HashMap::new(),
+    }
+}
+
+/// Returns `persisted_state`, with its pending engine changes replaced by
+/// `changes`. If `persisted_state` is missing or can't be parsed, returns a
+/// new state with just the changes.
+pub fn set_pending_engine_changes(
+    persisted_state: Option<&str>,
+    changes: &HashMap<String, bool>,
+) -> String {
+    let mut pgs = persisted_state
+        .and_then(|s| serde_json::from_str::<PersistedGlobalState>(s).ok())
+        .unwrap_or_default();
+    match &mut pgs {
+        PersistedGlobalState::V2 {
+            pending_engine_changes,
+            ..
+        } => *pending_engine_changes = changes.clone(),
+    }
+    // Unwrap here can never fail -- it indicates trying to serialize an
+    // unserializable type.
+    serde_json::to_string(&pgs).unwrap()
+}
+
 impl PersistedGlobalState {
     fn set_declined(&mut self, new_declined: Vec<String>) {
         match self {
-            Self::V2 { ref mut declined } => *declined = Some(new_declined),
+            Self::V2 {
+                ref mut declined, ..
+            } => *declined = Some(new_declined),
+        }
+    }
+    fn knows_declined(&self) -> bool {
+        match self {
+            Self::V2 { declined, .. } => declined.is_some(),
         }
     }
     pub(crate) fn get_declined(&self) -> &[String] {
         match self {
-            Self::V2 { declined: Some(d) } => &d,
-            Self::V2 { declined: None } => &[],
+            Self::V2 {
+                declined: Some(d), ..
+            } => &d,
+            Self::V2 { declined: None, .. } => &[],
         }
     }
 }
@@ -171,26 +254,36 @@ pub struct GlobalState {
     pub keys: EncryptedBso,
 }
 
-/// Creates a fresh `meta/global` record, using the default engine selections,
-/// and declined engines from our PersistedGlobalState.
-fn new_global(pgs: &PersistedGlobalState) -> error::Result<MetaGlobalRecord> {
+/// Returns the default engines, followed by `extra_engines`.
+fn engines_to_declare<'a>(
+    extra_engines: &'a [(String, usize)],
+) -> impl Iterator<Item = (&'a str, usize)> {
+    DEFAULT_ENGINES.iter().cloned().chain(
+        extra_engines
+            .iter()
+            .map(|(name, version)| (name.as_str(), *version)),
+    )
+}
+
+/// Creates a fresh `meta/global` record, using the default engine selections
+/// and `extra_engines`, and declined engines from our PersistedGlobalState.
+fn new_global(
+    pgs: &PersistedGlobalState,
+    extra_engines: &[(String, usize)],
+) -> error::Result<MetaGlobalRecord> {
     let sync_id = Guid::random();
     let mut engines: HashMap<String, _> = HashMap::new();
-    for (name, version) in DEFAULT_ENGINES.iter() {
+    for (name, version) in engines_to_declare(extra_engines) {
         let sync_id = Guid::random();
-        engines.insert(
-            (*name).to_string(),
-            MetaGlobalEngine {
-                version: *version,
-                sync_id,
-            },
-        );
+        engines.insert(name.to_string(), MetaGlobalEngine { version, sync_id });
     }
     // We only need our PersistedGlobalState to fill out a new meta/global - if
     // we previously saw a meta/global then we would have updated it with what
     // it was at the time.
     let declined = match pgs {
-        PersistedGlobalState::V2 { declined: Some(d) } => d.clone(),
+        PersistedGlobalState::V2 {
+            declined: Some(d), ..
+        } => d.clone(),
         _ => DEFAULT_DECLINED.iter().map(ToString::to_string).collect(),
     };
 
@@ -202,9 +295,9 @@ fn new_global(pgs: &PersistedGlobalState) -> error::Result<MetaGlobalRecord> {
     })
 }
 
-fn fixup_meta_global(global: &mut MetaGlobalRecord) -> bool {
+fn fixup_meta_global(global: &mut MetaGlobalRecord, extra_engines: &[(String, usize)]) -> bool {
     let mut changed_any = false;
-    for &(name, version) in DEFAULT_ENGINES.iter() {
+    for (name, version) in engines_to_declare(extra_engines) {
         let had_engine = global.engines.contains_key(name);
         let should_have_engine = !global.declined.iter().any(|c| c == name);
         if had_engine != should_have_engine {
@@ -240,8 +333,17 @@ pub struct SetupStateMachine<'a> {
     // We should almost certainly remove this and instead allow for a "time
     // budget", after which we get interrupted. Later...
     allowed_states: Vec<&'static str>,
+    // A read-only sync never writes `meta/global` or `crypto/keys`, even to
+    // fix up declined engines or missing sync IDs.
+    readonly: bool,
     sequence: Vec<&'static str>,
     engine_updates: Option<&'a HashMap<String, bool>>,
+    // If set, we start over with new `crypto/keys`, with a separate key for
+    // each of these collections. Taken when we upload the new keys, so that
+    // we only rotate them once.
+    key_rotation: Option<Vec<String>>,
+    // Engines to declare in `meta/global`, in addition to `DEFAULT_ENGINES`.
+    extra_engines: Vec<(String, usize)>,
     interruptee: &'a dyn Interruptee,
     pub(crate) changes_needed: Option<EngineChangesNeeded>,
 }
@@ -272,6 +374,7 @@ impl<'a> SetupStateMachine<'a> {
                 "FreshStartRequired",
                 "WithPreviousState",
             ],
+            false,
         )
     }
 
@@ -294,6 +397,7 @@ impl<'a> SetupStateMachine<'a> {
             interruptee,
             engine_updates,
             vec!["Ready", "WithPreviousState"],
+            false,
         )
     }
 
@@ -322,6 +426,7 @@ impl<'a> SetupStateMachine<'a> {
                 "Ready",
                 "WithPreviousState",
             ],
+            true,
         )
     }
 
@@ -332,6 +437,7 @@ impl<'a> SetupStateMachine<'a> {
         interruptee: &'a dyn Interruptee,
         engine_updates: Option<&'a HashMap<String, bool>>,
         allowed_states: Vec<&'static str>,
+        readonly: bool,
     ) -> SetupStateMachine<'a> {
         SetupStateMachine {
             client,
@@ -339,12 +445,39 @@ impl<'a> SetupStateMachine<'a> {
             pgs,
             sequence: Vec::new(),
             allowed_states,
+            readonly,
             engine_updates,
+            key_rotation: None,
+            extra_engines: Vec::new(),
             interruptee,
             changes_needed: None,
         }
     }
 
+    /// Declares `engines`, as pairs of names and storage versions, in
+    /// `meta/global` along with the default engines, unless they've been
+    /// declined.
+    pub fn with_extra_engines(mut self, engines: Vec<(String, usize)>) -> Self {
+        self.extra_engines = engines;
+        self
+    }
+
+    /// Rotates `crypto/keys` before getting to ready. We fetch `meta/global`
+    /// to learn the declined engines, then start over: wipe the server, and
+    /// upload a `meta/global` with new sync IDs and a new random default key,
+    /// along with a separate random key for each of `per_collection_keys`.
+    /// The new sync IDs make every client, including this one, reset its
+    /// engines and upload its records again with the new keys.
+    ///
+    /// This should be used after a suspected compromise of the keys, like
+    /// when a device is removed from the account. Read-only and fast syncs
+    /// can't start over, so rotating keys with them fails with
+    /// `SetupRequired`.
+    pub fn with_key_rotation(mut self, per_collection_keys: Vec<String>) -> Self {
+        self.key_rotation = Some(per_collection_keys);
+        self
+    }
+
     fn advance(&mut self, from: SetupState) -> error::Result<SetupState> {
         match from {
             // Fetch `info/configuration` with current server limits, and
@@ -407,6 +540,7 @@ impl<'a> SetupStateMachine<'a> {
                             log::info!("Have info/collections and meta/global. Computing new engine states");
                             let initial_global_declined: HashSet<String> =
                                 global.declined.iter().cloned().collect();
+                            let knew_declined = self.pgs.knows_declined();
                             let result = compute_engine_states(EngineStateInput {
                                 local_declined: self.pgs.get_declined().iter().cloned().collect(),
                                 user_changes: self.engine_updates.cloned().unwrap_or_default(),
@@ -419,7 +553,9 @@ impl<'a> SetupStateMachine<'a> {
                             self.pgs
                                 .set_declined(result.declined.iter().cloned().collect());
                             // If the declined engines differ from remote, fix that.
-                            let fixed_declined = if result.declined != initial_global_declined {
+                            let fixed_declined = if self.readonly {
+                                false
+                            } else if result.declined != initial_global_declined {
                                 global.declined = result.declined.iter().cloned().collect();
                                 log::info!(
                                     "Uploading new declined {:?} to meta/global with timestamp {:?}",
@@ -431,7 +567,9 @@ impl<'a> SetupStateMachine<'a> {
                                 false
                             };
                             // If there are missing syncIds, we need to fix those as well
-                            let fixed_ids = if fixup_meta_global(&mut global) {
+                            let fixed_ids = if self.readonly {
+                                false
+                            } else if fixup_meta_global(&mut global, &self.extra_engines) {
                                 log::info!(
                                     "Uploading corrected meta/global with timestamp {:?}",
                                     global_timestamp,
@@ -453,7 +591,18 @@ impl<'a> SetupStateMachine<'a> {
                                 // anyway.
                                 log::warn!("Already have a set of changes needed, Overwriting...");
                             }
-                            self.changes_needed = Some(result.changes_needed);
+                            let mut changes_needed = result.changes_needed;
+                            if !knew_declined {
+                                // Every engine looks like it was just
+                                // declined on our first sync.
+                                changes_needed.remote_changes.clear();
+                            }
+                            if self.readonly {
+                                // We can't wipe the server, but resetting
+                                // our local engines is fine.
+                                changes_needed.remote_wipes.clear();
+                            }
+                            self.changes_needed = Some(changes_needed);
                             Ok(InitialWithMetaGlobal {
                                 config,
                                 collections,
@@ -475,6 +624,12 @@ impl<'a> SetupStateMachine<'a> {
                 global,
                 global_timestamp,
             } => {
+                // We've persisted the declined engines from `meta/global`, so
+                // starting over to rotate the keys keeps them.
+                if self.key_rotation.is_some() {
+                    log::info!("Rotating keys: starting over");
+                    return Ok(FreshStartRequired { config });
+                }
                 // Now try and get keys etc - if we fresh-start we'll re-use declined.
                 match self.client.fetch_crypto_keys()? {
                     Sync15ClientResponse::Success {
@@ -551,13 +706,23 @@ impl<'a> SetupStateMachine<'a> {
 
                 self.changes_needed = Some(computed.changes_needed);
 
-                let new_global = new_global(self.pgs)?;
+                let new_global = new_global(self.pgs, &self.extra_engines)?;
 
                 self.client
                     .put_meta_global(ServerTimestamp::default(), &new_global)?;
 
                 // ...And a fresh `crypto/keys`.
-                let new_keys = CollectionKeys::new_random()?.to_encrypted_bso(&self.root_key)?;
+                let new_keys = match self.key_rotation.take() {
+                    Some(collections) => {
+                        log::info!(
+                            "Uploading rotated crypto/keys with {} collection keys",
+                            collections.len()
+                        );
+                        CollectionKeys::new_random_with_collections(&collections)?
+                    }
+                    None => CollectionKeys::new_random()?,
+                }
+                .to_encrypted_bso(&self.root_key)?;
                 self.client
                     .put_crypto_keys(ServerTimestamp::default(), &new_keys)?;
 
@@ -573,8 +738,10 @@ impl<'a> SetupStateMachine<'a> {
     /// Runs through the state machine to the ready state.
     pub fn run_to_ready(&mut self, state: Option<GlobalState>) -> error::Result<GlobalState> {
         let mut s = match state {
-            Some(old_state) => WithPreviousState { old_state },
-            None => Initial,
+            // Our cached state has the old keys, so it's no use if we're
+            // rotating them.
+            Some(old_state) if self.key_rotation.is_none() => WithPreviousState { old_state },
+            _ => Initial,
         };
         loop {
             self.interruptee.err_if_interrupted()?;
@@ -585,6 +752,11 @@ impl<'a> SetupStateMachine<'a> {
                     self.sequence.push(label);
                     return Ok(state);
                 }
+                // A state machine that can't write to the server can't start
+                // over.
+                FreshStartRequired { .. } if !self.allowed_states.contains(&label) => {
+                    return Err(ErrorKind::SetupRequired.into());
+                }
                 // If we already started over once before, we're likely in a
                 // cycle, and should try again later. Intermediate states
                 // aren't a problem, just the initial ones.
@@ -666,6 +838,7 @@ mod tests {
     use crate::bso_record::{BsoRecord, EncryptedBso, EncryptedPayload, Payload};
     use crate::record_types::CryptoKeysRecord;
     use interrupt::NeverInterrupts;
+    use std::cell::{Cell, RefCell};
 
     struct InMemoryClient {
         info_configuration: error::Result<Sync15ClientResponse<InfoConfiguration>>,
@@ -831,7 +1004,7 @@ mod tests {
                 888_000,
             ),
         };
-        let mut pgs = PersistedGlobalState::V2 { declined: None };
+        let mut pgs = PersistedGlobalState::default();
 
         let mut state_machine =
             SetupStateMachine::for_full_sync(&client, &root_key, &mut pgs, None, &NeverInterrupts);
@@ -852,6 +1025,241 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_readonly_state_machine_never_writes() {
+        let root_key = KeyBundle::new_random().unwrap();
+        let keys = CollectionKeys {
+            timestamp: ServerTimestamp(123_400),
+            default: KeyBundle::new_random().unwrap(),
+            collections: HashMap::new(),
+        };
+        // This meta/global is missing most engines, so a full sync would
+        // upload a fixed-up one.
+        let mg = MetaGlobalRecord {
+            sync_id: "syncIDAAAAAA".into(),
+            storage_version: 5usize,
+            engines: vec![(
+                "bookmarks",
+                MetaGlobalEngine {
+                    version: 1usize,
+                    sync_id: "syncIDBBBBBB".into(),
+                },
+            )]
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value))
+            .collect(),
+            declined: vec![],
+        };
+        let client = InMemoryClient {
+            info_configuration: mocked_success(InfoConfiguration::default()),
+            info_collections: mocked_success(InfoCollections::new(
+                vec![("meta", 123_456), ("crypto", 145_000)]
+                    .into_iter()
+                    .map(|(key, value)| (key.to_owned(), ServerTimestamp(value)))
+                    .collect(),
+            )),
+            meta_global: mocked_success_ts(mg, 999_000),
+            crypto_keys: mocked_success_ts(
+                keys.to_encrypted_bso_with_timestamp(&root_key, ServerTimestamp(888_000))
+                    .expect("should always work in this test"),
+                888_000,
+            ),
+        };
+        let mut pgs = PersistedGlobalState::default();
+
+        let mut state_machine =
+            SetupStateMachine::for_readonly_sync(&client, &root_key, &mut pgs, &NeverInterrupts);
+        let state = state_machine
+            .run_to_ready(None)
+            .expect("Should drive state machine to ready");
+        assert_eq!(
+            state.global.engines.keys().collect::<Vec<_>>(),
+            vec!["bookmarks"],
+            "Shouldn't fix up meta/global"
+        );
+
+        // If meta/global is missing, a full sync would start over, but a
+        // read-only sync should fail without wiping the server.
+        let client = InMemoryClient {
+            meta_global: Ok(Sync15ClientResponse::Error(ErrorResponse::NotFound {
+                route: "meta/global".into(),
+            })),
+            ..client
+        };
+        let mut state_machine =
+            SetupStateMachine::for_readonly_sync(&client, &root_key, &mut pgs, &NeverInterrupts);
+        match state_machine.run_to_ready(None) {
+            Err(e) => match e.kind() {
+                ErrorKind::SetupRequired => {}
+                _ => panic!("Wrong error: {}", e),
+            },
+            Ok(_) => panic!("Shouldn't get to ready"),
+        }
+        assert!(!state_machine.sequence.contains(&"FreshStartRequired"));
+    }
+
+    // A client that keeps the `meta/global` and `crypto/keys` we upload, so
+    // that the state machine can start over and fetch them again.
+    struct RotatingClient {
+        global: RefCell<(MetaGlobalRecord, ServerTimestamp)>,
+        keys: RefCell<EncryptedBso>,
+        wipes: Cell<usize>,
+        last_modified: Cell<i64>,
+    }
+
+    impl RotatingClient {
+        fn next_timestamp(&self) -> ServerTimestamp {
+            self.last_modified.set(self.last_modified.get() + 1_000);
+            ServerTimestamp(self.last_modified.get())
+        }
+    }
+
+    impl SetupStorageClient for RotatingClient {
+        fn fetch_info_configuration(
+            &self,
+        ) -> error::Result<Sync15ClientResponse<InfoConfiguration>> {
+            mocked_success(InfoConfiguration::default())
+        }
+
+        fn fetch_info_collections(&self) -> error::Result<Sync15ClientResponse<InfoCollections>> {
+            mocked_success(InfoCollections::new(
+                vec![
+                    ("meta".to_string(), self.global.borrow().1),
+                    ("crypto".to_string(), self.keys.borrow().modified),
+                ]
+                .into_iter()
+                .collect(),
+            ))
+        }
+
+        fn fetch_meta_global(&self) -> error::Result<Sync15ClientResponse<MetaGlobalRecord>> {
+            let (global, timestamp) = self.global.borrow().clone();
+            mocked_success_ts(global, timestamp.0)
+        }
+
+        fn put_meta_global(
+            &self,
+            xius: ServerTimestamp,
+            global: &MetaGlobalRecord,
+        ) -> error::Result<ServerTimestamp> {
+            let current = self.global.borrow().1;
+            assert!(xius == ServerTimestamp::default() || xius == current);
+            let timestamp = self.next_timestamp();
+            *self.global.borrow_mut() = (global.clone(), timestamp);
+            Ok(timestamp)
+        }
+
+        fn fetch_crypto_keys(&self) -> error::Result<Sync15ClientResponse<EncryptedBso>> {
+            let keys = self.keys.borrow().clone();
+            let modified = keys.modified;
+            mocked_success_ts(keys, modified.0)
+        }
+
+        fn put_crypto_keys(&self, xius: ServerTimestamp, keys: &EncryptedBso) -> error::Result<()> {
+            assert_eq!(xius, ServerTimestamp::default());
+            let mut keys = keys.clone();
+            keys.modified = self.next_timestamp();
+            *self.keys.borrow_mut() = keys;
+            Ok(())
+        }
+
+        fn wipe_all_remote(&self) -> error::Result<()> {
+            self.wipes.set(self.wipes.get() + 1);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_state_machine_rotates_keys() {
+        let root_key = KeyBundle::new_random().unwrap();
+        let old_keys = CollectionKeys::new_random().unwrap();
+        let mut pgs = PersistedGlobalState::default();
+        let mut old_global = new_global(&pgs, &[]).unwrap();
+        old_global.declined = vec!["addons".to_string()];
+        old_global.engines.remove("addons");
+        let client = RotatingClient {
+            global: RefCell::new((old_global.clone(), ServerTimestamp(1_000))),
+            keys: RefCell::new(
+                old_keys
+                    .to_encrypted_bso_with_timestamp(&root_key, ServerTimestamp(2_000))
+                    .unwrap(),
+            ),
+            wipes: Cell::new(0),
+            last_modified: Cell::new(2_000),
+        };
+
+        // Without rotation, we should reuse the existing keys.
+        let mut state_machine =
+            SetupStateMachine::for_full_sync(&client, &root_key, &mut pgs, None, &NeverInterrupts);
+        let old_state = state_machine
+            .run_to_ready(None)
+            .expect("Should drive state machine to ready");
+        assert_eq!(client.wipes.get(), 0);
+
+        let mut state_machine =
+            SetupStateMachine::for_full_sync(&client, &root_key, &mut pgs, None, &NeverInterrupts)
+                .with_key_rotation(vec!["passwords".to_string()]);
+        let state = state_machine
+            .run_to_ready(Some(old_state))
+            .expect("Should drive state machine to ready");
+        assert_eq!(
+            state_machine.sequence,
+            vec![
+                "Initial",
+                "InitialWithConfig",
+                "InitialWithInfo",
+                "InitialWithMetaGlobal",
+                "FreshStartRequired",
+                "InitialWithConfig",
+                "InitialWithInfo",
+                "InitialWithMetaGlobal",
+                "Ready",
+            ],
+            "Should start over once, ignoring the previous state"
+        );
+        assert_eq!(client.wipes.get(), 1);
+
+        // Other clients should notice the new sync IDs, and we should keep
+        // the declined engines.
+        assert_ne!(state.global.sync_id, old_global.sync_id);
+        assert_ne!(
+            state.global.engines["bookmarks"].sync_id,
+            old_global.engines["bookmarks"].sync_id
+        );
+        assert_eq!(state.global.declined, vec!["addons".to_string()]);
+
+        let new_keys = CollectionKeys::from_encrypted_bso(state.keys, &root_key).unwrap();
+        assert_ne!(new_keys.default, old_keys.default);
+        assert_eq!(
+            new_keys.collections.keys().collect::<Vec<_>>(),
+            vec!["passwords"]
+        );
+        assert_ne!(new_keys.key_for_collection("passwords"), &new_keys.default);
+        assert_eq!(new_keys.key_for_collection("bookmarks"), &new_keys.default);
+    }
+
+    #[test]
+    fn test_extra_engines() {
+        let pgs = PersistedGlobalState::V2 {
+            declined: Some(vec!["closedtabs".to_string()]),
+            pending_engine_changes: HashMap::new(),
+        };
+        let mut global = new_global(&pgs, &[]).unwrap();
+        assert!(!global.engines.contains_key("closedtabs"));
+
+        let extra = vec![("closedtabs".to_string(), 1)];
+        assert_eq!(
+            new_global(&pgs, &extra).unwrap().engines["closedtabs"].version,
+            1
+        );
+
+        // Declined engines aren't added back when fixing up `meta/global`.
+        assert!(!fixup_meta_global(&mut global, &extra));
+        global.declined.clear();
+        assert!(fixup_meta_global(&mut global, &extra));
+        assert_eq!(global.engines["closedtabs"].version, 1);
+    }
+
     fn string_set(s: &[&str]) -> HashSet<String> {
         s.iter().map(ToString::to_string).collect()
     }
@@ -885,7 +1293,11 @@ mod tests {
                 // Now we have `foo`.
                 declined: string_set(&["foo"]),
                 // No wipes, no resets, should just be a local update.
-                changes_needed: Default::default(),
+                changes_needed: EngineChangesNeeded {
+                    // Another client enabled `bar`.
+                    remote_changes: string_map(&[("bar", true)]),
+                    ..Default::default()
+                },
             }
         );
         assert_eq!(
@@ -905,6 +1317,8 @@ mod tests {
                     local_resets: string_set(&["quux"]),
                     // No wipes, though.
                     remote_wipes: string_set(&[]),
+                    // Another client declined `quux`.
+                    remote_changes: string_map(&[("quux", false)]),
                 }
             }
         );
@@ -942,8 +1356,64 @@ mod tests {
                     local_resets: string_set(&["foo"]),
                     // And wipe the server.
                     remote_wipes: string_set(&["foo"]),
+                    remote_changes: HashMap::new(),
                 }
             }
         );
+        assert_eq!(
+            compute_engine_states(EngineStateInput {
+                local_declined: string_set(&["bar"]),
+                remote: Some(RemoteEngineState {
+                    declined: string_set(&["foo"]),
+                    info_collections: string_set(&["bar", "quux"])
+                }),
+                // Another client declined `foo` and enabled `bar`, but we
+                // enabled `foo` again at the same time.
+                user_changes: string_map(&[("foo", true)]),
+            }),
+            EngineStateOutput {
+                declined: string_set(&[]),
+                changes_needed: EngineChangesNeeded {
+                    remote_changes: string_map(&[("bar", true)]),
+                    ..Default::default()
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn test_get_declined_engines() {
+        assert_eq!(
+            get_declined_engines(r#"{"schema_version":"V2","declined":["tabs"]}"#),
+            Some(vec!["tabs".to_string()])
+        );
+        assert_eq!(
+            get_declined_engines(r#"{"schema_version":"V2","declined":null}"#),
+            None
+        );
+        assert_eq!(get_declined_engines("not json"), None);
+    }
+
+    #[test]
+    fn test_pending_engine_changes() {
+        let state = r#"{"schema_version":"V2","declined":["tabs"]}"#;
+        assert!(get_pending_engine_changes(state).is_empty());
+
+        let mut changes = HashMap::new();
+        changes.insert("tabs".to_string(), true);
+        changes.insert("history".to_string(), false);
+        let state = set_pending_engine_changes(Some(state), &changes);
+        assert_eq!(get_pending_engine_changes(&state), changes);
+        // The declined engines are kept.
+        assert_eq!(get_declined_engines(&state), Some(vec!["tabs".to_string()]));
+
+        // Clearing the changes leaves the state as it was.
+        let state = set_pending_engine_changes(Some(&state), &HashMap::new());
+        assert_eq!(state, r#"{"schema_version":"V2","declined":["tabs"]}"#);
+
+        let state = set_pending_engine_changes(None, &changes);
+        assert_eq!(get_pending_engine_changes(&state), changes);
+        assert_eq!(get_declined_engines(&state), None);
+        assert!(get_pending_engine_changes("not json").is_empty());
     }
 }
@@ -23,6 +23,9 @@ pub enum ServiceStatus {
     BackedOff,
     /// We were interrupted.
     Interrupted,
+    /// The account has used all of its storage on the server, so nothing
+    /// more can be uploaded until some is freed.
+    OverQuota,
     /// Something else - you need to check the logs for more details. May
     /// or may not be transient, we really don't know.
     OtherError,
@@ -46,6 +49,7 @@ impl ServiceStatus {
             ErrorKind::BackoffError(_) => ServiceStatus::ServiceError,
             ErrorKind::StorageHttpError(ref e) => match e {
                 ErrorResponse::Unauthorized { .. } => ServiceStatus::AuthenticationError,
+                ErrorResponse::QuotaExceeded { .. } => ServiceStatus::OverQuota,
                 _ => ServiceStatus::ServiceError,
             },
 
@@ -70,6 +74,10 @@ pub struct SyncResult {
     /// The set of declined engines, if we know them.
     pub declined: Option<Vec<String>>,
 
+    /// Engines that another client enabled (true) or declined (false) since
+    /// our last sync. Declined engines have already been reset.
+    pub remote_engine_changes: HashMap<String, bool>,
+
     /// The result of the sync.
     pub result: Result<(), Error>,
 
@@ -80,6 +88,10 @@ pub struct SyncResult {
     pub telemetry: SyncTelemetryPing,
 
     pub next_sync_after: Option<std::time::SystemTime>,
+
+    /// How many bytes the account can still store on the server, from the
+    /// last `X-Weave-Quota-Remaining` header we saw, if any.
+    pub quota_remaining: Option<u64>,
 }
 
 // If `r` has a BackoffError, then returns the later backoff value.
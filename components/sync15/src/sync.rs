@@ -2,18 +2,24 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use crate::changeset::CollectionUpdate;
+use crate::changeset::IncomingChangeset;
 use crate::client::Sync15StorageClient;
 use crate::clients;
-use crate::coll_state::LocalCollStateMachine;
+use crate::coll_state::{CollState, LocalCollStateMachine};
 use crate::error::Error;
 use crate::key_bundle::KeyBundle;
+use crate::request::CollectionRequest;
 use crate::state::GlobalState;
 use crate::telemetry;
 use interrupt::Interruptee;
+use std::time::Instant;
 
 pub use sync15_traits::Store;
 
+/// How many times we download the changes another client uploaded during our
+/// sync, and try our upload again, before giving up on the sync.
+const MAX_UPLOAD_CONFLICT_RETRIES: usize = 2;
+
 pub fn synchronize(
     client: &Sync15StorageClient,
     global_state: &GlobalState,
@@ -30,11 +36,16 @@ pub fn synchronize(
         None,
         store,
         fully_atomic,
+        false,
         telem_engine,
         interruptee,
     )
 }
 
+/// Syncs a store, like `synchronize`, using the clients engine to prepare
+/// the store for syncing. If `readonly` is set, incoming records are applied,
+/// but nothing is uploaded, and the store's local changes, including
+/// tombstones, are left for a later sync to upload.
 #[allow(clippy::too_many_arguments)]
 pub fn synchronize_with_clients_engine(
     client: &Sync15StorageClient,
@@ -43,6 +54,7 @@ pub fn synchronize_with_clients_engine(
     clients: Option<&clients::Engine<'_>>,
     store: &dyn Store,
     fully_atomic: bool,
+    readonly: bool,
     telem_engine: &mut telemetry::Engine,
     interruptee: &dyn Interruptee,
 ) -> Result<(), Error> {
@@ -66,43 +78,116 @@ pub fn synchronize_with_clients_engine(
     if let Some(clients) = clients {
         store.prepare_for_sync(&|| clients.get_client_data())?;
     }
+    store.set_max_payload_size(coll_state.config.max_record_payload_size_for(&collection));
 
     let collection_requests = store.get_collection_requests()?;
     // Should this be allowed?
     assert_ne!(collection_requests.len(), 0);
     assert_eq!(collection_requests.last().unwrap().collection, collection);
 
-    let count = collection_requests.len();
-    let incoming = collection_requests
-        .into_iter()
-        .enumerate()
-        .map(|(idx, collection_request)| {
-            interruptee.err_if_interrupted()?;
-            let incoming_changes =
-                crate::changeset::fetch_incoming(client, &mut coll_state, &collection_request)?;
+    let download_start = Instant::now();
+    let mut incoming = download_incoming(
+        client,
+        &mut coll_state,
+        store,
+        &collection_requests,
+        true,
+        telem_engine,
+        interruptee,
+    )?;
+    telem_engine.step("download", download_start.elapsed());
+
+    let mut retries = 0;
+    let upload_info = loop {
+        let new_timestamp = incoming.last().expect("already checked len == 0").timestamp;
+        // If another client uploads before we do, we'll only need the
+        // records newer than the ones we're about to apply.
+        let conflict_requests = collection_requests
+            .iter()
+            .zip(incoming.iter())
+            .map(|(request, changeset)| {
+                let mut request = request.clone().newer_than(changeset.timestamp);
+                request.offset = None;
+                request
+            })
+            .collect::<Vec<_>>();
+
+        let apply_start = Instant::now();
+        let mut outgoing = store.apply_incoming(incoming, telem_engine)?;
+        telem_engine.step("apply", apply_start.elapsed());
 
+        interruptee.err_if_interrupted()?;
+        // xxx - duplication below smells wrong
+        outgoing.timestamp = new_timestamp;
+        coll_state.last_modified = new_timestamp;
+
+        if readonly {
             log::info!(
-                "Downloaded {} remote changes (request {} of {})",
-                incoming_changes.changes.len(),
-                idx,
-                count,
+                "Read-only sync, not uploading {} outgoing changes",
+                outgoing.changes.len()
             );
-            Ok(incoming_changes)
-        })
-        .collect::<Result<Vec<_>, Error>>()?;
-
-    let new_timestamp = incoming.last().expect("already checked len == 0").timestamp;
-    let mut outgoing = store.apply_incoming(incoming, telem_engine)?;
+            // Nothing was uploaded, so the store keeps all its changes, but
+            // won't download the records it just applied again.
+            let finish_start = Instant::now();
+            store.sync_finished_readonly(new_timestamp)?;
+            telem_engine.step("finish", finish_start.elapsed());
+            log::info!("Sync finished!");
+            return Ok(());
+        }
 
-    interruptee.err_if_interrupted()?;
-    // xxx - duplication below smells wrong
-    outgoing.timestamp = new_timestamp;
-    coll_state.last_modified = new_timestamp;
+        log::info!(
+            "Uploading {} outgoing changes, and any the store fetches during the upload",
+            outgoing.changes.len()
+        );
+        store.check_quota(&outgoing, &|| {
+            client.fetch_info_quota().map_err(failure::Error::from)
+        })?;
+        let upload_start = Instant::now();
+        let records = store.fetch_outgoing_records()?;
+        let result =
+            crate::changeset::upload_outgoing(client, &coll_state, outgoing, records, fully_atomic);
+        telem_engine.step("upload", upload_start.elapsed());
 
-    log::info!("Uploading {} outgoing changes", outgoing.changes.len());
-    let upload_info =
-        CollectionUpdate::new_from_changeset(client, &coll_state, outgoing, fully_atomic)?
-            .upload()?;
+        match result {
+            // Another client uploaded records since we downloaded, so our
+            // upload was rejected. An atomic upload commits nothing until
+            // the last batch, so the server has none of our records, and
+            // the store still has all its changes; we can apply theirs and
+            // try again. A non-atomic upload may have committed some
+            // batches already, so we fail the sync instead.
+            Err(ref e)
+                if fully_atomic
+                    && e.is_precondition_failed()
+                    && retries < MAX_UPLOAD_CONFLICT_RETRIES =>
+            {
+                retries += 1;
+                log::warn!(
+                    "{} changed during the sync, retrying the upload ({} of {})",
+                    collection,
+                    retries,
+                    MAX_UPLOAD_CONFLICT_RETRIES,
+                );
+                telem_engine.event(
+                    telemetry::Event::new("sync", "uploadconflict")
+                        .extra("engine", collection.to_string())
+                        .extra("retry", retries.to_string()),
+                );
+                interruptee.err_if_interrupted()?;
+                let download_start = Instant::now();
+                incoming = download_incoming(
+                    client,
+                    &mut coll_state,
+                    store,
+                    &conflict_requests,
+                    false,
+                    telem_engine,
+                    interruptee,
+                )?;
+                telem_engine.step("download", download_start.elapsed());
+            }
+            result => break result?,
+        }
+    };
 
     log::info!(
         "Upload success ({} records success, {} records failed)",
@@ -116,8 +201,291 @@ pub fn synchronize_with_clients_engine(
     telem_outgoing.failed(upload_info.failed_ids.len());
     telem_engine.outgoing(telem_outgoing);
 
+    let finish_start = Instant::now();
     store.sync_finished(upload_info.modified_timestamp, upload_info.successful_ids)?;
+    telem_engine.step("finish", finish_start.elapsed());
 
     log::info!("Sync finished!");
     Ok(())
 }
+
+/// Downloads the records for `collection_requests`. Stores that download in
+/// pages apply each page as it arrives, resuming from their download progress
+/// if `resume` is set, and get changesets without records.
+fn download_incoming(
+    client: &Sync15StorageClient,
+    coll_state: &mut CollState,
+    store: &dyn Store,
+    collection_requests: &[CollectionRequest],
+    resume: bool,
+    telem_engine: &mut telemetry::Engine,
+    interruptee: &dyn Interruptee,
+) -> Result<Vec<IncomingChangeset>, Error> {
+    let count = collection_requests.len();
+    if store.downloads_in_pages() {
+        // The store applies each page as it's downloaded, so the download
+        // step includes applying them.
+        let mut incoming = Vec::with_capacity(count);
+        for collection_request in collection_requests {
+            let progress = if resume {
+                store.get_download_progress(collection_request)?
+            } else {
+                None
+            };
+            incoming.push(crate::changeset::fetch_incoming_in_pages(
+                client,
+                coll_state,
+                collection_request,
+                progress,
+                interruptee,
+                &mut |page, progress| {
+                    interruptee.err_if_interrupted()?;
+                    // Only the downloads we'd resume should replace the
+                    // store's progress.
+                    let progress = if resume { Some(progress) } else { None };
+                    store.apply_incoming_page(page, progress, telem_engine)?;
+                    Ok(())
+                },
+            )?);
+        }
+        Ok(incoming)
+    } else {
+        collection_requests
+            .iter()
+            .enumerate()
+            .map(|(idx, collection_request)| {
+                interruptee.err_if_interrupted()?;
+                let incoming_changes =
+                    crate::changeset::fetch_incoming(client, coll_state, collection_request)?;
+
+                log::info!(
+                    "Downloaded {} remote changes (request {} of {})",
+                    incoming_changes.changes.len(),
+                    idx,
+                    count,
+                );
+                Ok(incoming_changes)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bso_record::Payload;
+    use crate::changeset::OutgoingChangeset;
+    use crate::coll_state::{CollSyncIds, StoreSyncAssociation};
+    use crate::collection_keys::CollectionKeys;
+    use crate::record_types::{MetaGlobalEngine, MetaGlobalRecord};
+    use crate::request::{InfoCollections, InfoConfiguration};
+    use crate::test_utils::{client_for, insert_record};
+    use crate::util::ServerTimestamp;
+    use interrupt::NeverInterrupts;
+    use serde_json::json;
+    use std::cell::{Cell, RefCell};
+    use std::collections::{HashMap, HashSet};
+    use sync_guid::Guid;
+    use sync_test_server::TestServer;
+
+    fn get_global_state(root_key: &KeyBundle, keys: &CollectionKeys) -> GlobalState {
+        GlobalState {
+            config: InfoConfiguration::default(),
+            collections: InfoCollections::new(HashMap::new()),
+            global: MetaGlobalRecord {
+                sync_id: "syncIDAAAAAA".into(),
+                storage_version: 5usize,
+                engines: vec![(
+                    "history",
+                    MetaGlobalEngine {
+                        version: 1usize,
+                        sync_id: "syncIDBBBBBB".into(),
+                    },
+                )]
+                .into_iter()
+                .map(|(key, value)| (key.to_owned(), value))
+                .collect(),
+                declined: vec![],
+            },
+            global_timestamp: ServerTimestamp::default(),
+            keys: keys.to_encrypted_bso(root_key).unwrap(),
+        }
+    }
+
+    fn payload(id: &str) -> Payload {
+        Payload::from_json(json!({ "id": id })).unwrap()
+    }
+
+    /// A store that stages its outgoing records like history does, and, the
+    /// first time it applies incoming records, uploads a record as another
+    /// client, so that our upload conflicts with it.
+    struct HistoryLikeStore<'a> {
+        server: &'a TestServer,
+        key: &'a KeyBundle,
+        local_changes: Vec<Guid>,
+        staged: RefCell<HashSet<Guid>>,
+        applied: RefCell<Vec<Guid>>,
+        num_applies: Cell<usize>,
+        synced: RefCell<Option<Vec<Guid>>>,
+    }
+
+    impl<'a> HistoryLikeStore<'a> {
+        fn new(server: &'a TestServer, key: &'a KeyBundle, local_changes: &[&str]) -> Self {
+            Self {
+                server,
+                key,
+                local_changes: local_changes.iter().map(|&id| id.into()).collect(),
+                staged: RefCell::default(),
+                applied: RefCell::default(),
+                num_applies: Cell::new(0),
+                synced: RefCell::default(),
+            }
+        }
+    }
+
+    impl<'a> Store for HistoryLikeStore<'a> {
+        fn collection_name(&self) -> std::borrow::Cow<'static, str> {
+            "history".into()
+        }
+
+        fn apply_incoming(
+            &self,
+            inbound: Vec<IncomingChangeset>,
+            _telem: &mut telemetry::Engine,
+        ) -> Result<OutgoingChangeset, failure::Error> {
+            self.num_applies.set(self.num_applies.get() + 1);
+            if self.num_applies.get() == 1 {
+                insert_record(self.server, self.key, "history", payload("remote000000"));
+            }
+            let inbound = inbound.into_iter().next().unwrap();
+            self.applied
+                .borrow_mut()
+                .extend(inbound.changes.into_iter().map(|(p, _)| p.id));
+
+            // Like history's temp table, the staged records are only cleared
+            // by `sync_finished`, so forget the ones staged for an earlier
+            // upload before staging them again.
+            let mut staged = self.staged.borrow_mut();
+            staged.clear();
+            let mut outgoing = OutgoingChangeset::new("history", inbound.timestamp);
+            for id in &self.local_changes {
+                if !staged.insert(id.clone()) {
+                    failure::bail!("UNIQUE constraint failed: {}", id);
+                }
+                outgoing.changes.push(payload(id.as_str()));
+            }
+            Ok(outgoing)
+        }
+
+        fn sync_finished(
+            &self,
+            _new_timestamp: ServerTimestamp,
+            records_synced: Vec<Guid>,
+        ) -> Result<(), failure::Error> {
+            self.staged.borrow_mut().clear();
+            self.synced.replace(Some(records_synced));
+            Ok(())
+        }
+
+        fn get_collection_requests(&self) -> Result<Vec<CollectionRequest>, failure::Error> {
+            Ok(vec![CollectionRequest::new("history")
+                .full()
+                .newer_than(ServerTimestamp(0))])
+        }
+
+        fn get_sync_assoc(&self) -> Result<StoreSyncAssociation, failure::Error> {
+            Ok(StoreSyncAssociation::Connected(CollSyncIds {
+                global: "syncIDAAAAAA".into(),
+                coll: "syncIDBBBBBB".into(),
+            }))
+        }
+
+        fn reset(&self, _assoc: &StoreSyncAssociation) -> Result<(), failure::Error> {
+            unreachable!("these tests shouldn't call this");
+        }
+
+        fn wipe(&self) -> Result<(), failure::Error> {
+            unreachable!("these tests shouldn't call this");
+        }
+    }
+
+    fn sync(
+        server: &TestServer,
+        store: &HistoryLikeStore<'_>,
+        root_key: &KeyBundle,
+        keys: &CollectionKeys,
+        fully_atomic: bool,
+    ) -> Result<(), Error> {
+        let global_state = get_global_state(root_key, keys);
+        let mut telem_engine = telemetry::Engine::new("history");
+        synchronize(
+            &client_for(server),
+            &global_state,
+            root_key,
+            store,
+            fully_atomic,
+            &mut telem_engine,
+            &NeverInterrupts,
+        )
+    }
+
+    fn server_ids(server: &TestServer) -> Vec<String> {
+        let mut ids = server
+            .records("history")
+            .into_iter()
+            .map(|record| record.id)
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_upload_conflict_retries() {
+        let server = TestServer::start().expect("should start the server");
+        let root_key = KeyBundle::new_random().unwrap();
+        let keys = CollectionKeys::new_random().unwrap();
+        let store = HistoryLikeStore::new(
+            &server,
+            keys.key_for_collection("history"),
+            &["local0000000", "local0000001"],
+        );
+
+        sync(&server, &store, &root_key, &keys, true).expect("should retry the upload");
+
+        // We applied the conflicting record, and staged our changes again,
+        // before uploading them.
+        assert_eq!(store.num_applies.get(), 2);
+        assert_eq!(*store.applied.borrow(), vec![Guid::from("remote000000")]);
+        let mut synced = store.synced.borrow_mut().take().expect("should finish");
+        synced.sort();
+        assert_eq!(
+            synced,
+            vec![Guid::from("local0000000"), Guid::from("local0000001")]
+        );
+        assert_eq!(
+            server_ids(&server),
+            vec!["local0000000", "local0000001", "remote000000"]
+        );
+    }
+
+    #[test]
+    fn test_upload_conflict_not_atomic() {
+        let server = TestServer::start().expect("should start the server");
+        let root_key = KeyBundle::new_random().unwrap();
+        let keys = CollectionKeys::new_random().unwrap();
+        let store = HistoryLikeStore::new(
+            &server,
+            keys.key_for_collection("history"),
+            &["local0000000", "local0000001"],
+        );
+
+        // Some of a non-atomic upload might have been committed, so we don't
+        // retry it.
+        let err = sync(&server, &store, &root_key, &keys, false)
+            .expect_err("shouldn't retry a non-atomic upload");
+        assert!(err.is_precondition_failed());
+        assert_eq!(store.num_applies.get(), 1);
+        assert!(store.synced.borrow().is_none());
+        assert_eq!(server_ids(&server), vec!["remote000000"]);
+    }
+}
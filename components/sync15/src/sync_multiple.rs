@@ -8,7 +8,7 @@
 use crate::client::{BackoffListener, Sync15StorageClient, Sync15StorageClientInit};
 use crate::clients::{self, CommandProcessor};
 use crate::coll_state::StoreSyncAssociation;
-use crate::error::Error;
+use crate::error::{Error, ErrorKind};
 use crate::key_bundle::KeyBundle;
 use crate::state::{EngineChangesNeeded, GlobalState, PersistedGlobalState, SetupStateMachine};
 use crate::status::{ServiceStatus, SyncResult};
@@ -122,9 +122,11 @@ pub fn sync_multiple_with_command_processor(
         service_status: ServiceStatus::OtherError,
         result: Ok(()),
         declined: None,
+        remote_engine_changes: HashMap::new(),
         next_sync_after: None,
         engine_results: HashMap::with_capacity(stores.len()),
         telemetry: telemetry::SyncTelemetryPing::new(),
+        quota_remaining: None,
     };
     let backoff = crate::client::new_backoff_listener();
     let req_info = req_info.unwrap_or_default();
@@ -141,6 +143,8 @@ pub fn sync_multiple_with_command_processor(
         mem_cached_state,
         any_failed_engines: false,
         ignore_soft_backoff: req_info.is_user_action,
+        readonly: req_info.readonly,
+        rotate_keys: req_info.rotate_keys,
     };
     match driver.sync() {
         Ok(()) => {
@@ -174,6 +178,16 @@ pub fn sync_multiple_with_command_processor(
 pub struct SyncRequestInfo<'a> {
     pub engines_to_state_change: Option<&'a HashMap<String, bool>>,
     pub is_user_action: bool,
+    /// If set, we download and apply records, but never write anything to
+    /// the server: not records, our client record, `meta/global` or
+    /// `crypto/keys`. Engine state changes are ignored.
+    pub readonly: bool,
+    /// If set, we wipe the server and upload new `crypto/keys` and a
+    /// `meta/global` with new sync IDs, so that every client resets and
+    /// uploads its records with the new keys. The named collections get
+    /// their own keys, instead of using the default key. This can't be
+    /// combined with `readonly`.
+    pub rotate_keys: Option<&'a [String]>,
 }
 
 // The sync multiple driver
@@ -189,6 +203,8 @@ struct SyncMultipleDriver<'info, 'res, 'pgs, 'mcs> {
     persisted_global_state: &'pgs mut Option<String>,
     mem_cached_state: &'mcs mut MemoryCachedState,
     ignore_soft_backoff: bool,
+    readonly: bool,
+    rotate_keys: Option<&'info [String]>,
     any_failed_engines: bool,
 }
 
@@ -221,7 +237,12 @@ impl<'info, 'res, 'pgs, 'mcs> SyncMultipleDriver<'info, 'res, 'pgs, 'mcs> {
         let clients_engine = if let Some(command_processor) = self.command_processor {
             log::info!("Synchronizing clients engine");
             let mut engine = clients::Engine::new(command_processor, self.interruptee);
-            if let Err(e) = engine.sync(&client_info.client, &global_state, &self.root_sync_key) {
+            let result = if self.readonly {
+                engine.sync_readonly(&client_info.client, &global_state, &self.root_sync_key)
+            } else {
+                engine.sync(&client_info.client, &global_state, &self.root_sync_key)
+            };
+            if let Err(e) = result {
                 // Record telemetry with the error just in case...
                 let mut telem_sync = telemetry::SyncTelemetry::new();
                 let mut telem_engine = telemetry::Engine::new("clients");
@@ -236,6 +257,9 @@ impl<'info, 'res, 'pgs, 'mcs> SyncMultipleDriver<'info, 'res, 'pgs, 'mcs> {
             // syncs, since we only keep client records in memory, we
             // expect the counts to be the same most times, and a
             // failure aborts the entire sync.
+            for event in engine.events.drain(..) {
+                self.result.telemetry.event(event);
+            }
             if self.was_interrupted() {
                 return Ok(());
             }
@@ -248,6 +272,7 @@ impl<'info, 'res, 'pgs, 'mcs> SyncMultipleDriver<'info, 'res, 'pgs, 'mcs> {
 
         let telem_sync = self.sync_stores(&client_info, &mut global_state, clients_engine.as_ref());
         self.result.telemetry.sync(telem_sync);
+        self.result.quota_remaining = client_info.client.take_quota_remaining();
 
         log::info!(
             "Finished syncing stores. All successful: {}",
@@ -306,6 +331,7 @@ impl<'info, 'res, 'pgs, 'mcs> SyncMultipleDriver<'info, 'res, 'pgs, 'mcs> {
                 clients,
                 *store,
                 true,
+                self.readonly,
                 &mut telem_engine,
                 self.interruptee,
             );
@@ -349,15 +375,49 @@ impl<'info, 'res, 'pgs, 'mcs> SyncMultipleDriver<'info, 'res, 'pgs, 'mcs> {
     ) -> result::Result<GlobalState, Error> {
         let last_state = mem::replace(&mut self.mem_cached_state.last_global_state, None);
 
-        let mut state_machine = SetupStateMachine::for_full_sync(
-            &client_info.client,
-            &self.root_sync_key,
-            pgs,
-            self.engines_to_state_change,
-            self.interruptee,
-        );
+        let mut state_machine = if self.readonly {
+            if self.engines_to_state_change.is_some() {
+                log::warn!("Ignoring engine state changes in a read-only sync");
+            }
+            if self.rotate_keys.is_some() {
+                log::error!("Can't rotate keys in a read-only sync");
+                return Err(ErrorKind::SetupRequired.into());
+            }
+            log::info!("Advancing state machine to ready (read-only)");
+            SetupStateMachine::for_readonly_sync(
+                &client_info.client,
+                &self.root_sync_key,
+                pgs,
+                self.interruptee,
+            )
+        } else {
+            log::info!("Advancing state machine to ready (full)");
+            let extra_engines = self
+                .stores
+                .iter()
+                .filter_map(|store| {
+                    store
+                        .meta_global_version()
+                        .map(|version| (store.collection_name().into_owned(), version))
+                })
+                .collect();
+            let state_machine = SetupStateMachine::for_full_sync(
+                &client_info.client,
+                &self.root_sync_key,
+                pgs,
+                self.engines_to_state_change,
+                self.interruptee,
+            )
+            .with_extra_engines(extra_engines);
+            match self.rotate_keys {
+                Some(collections) => {
+                    log::info!("Rotating keys");
+                    state_machine.with_key_rotation(collections.to_vec())
+                }
+                None => state_machine,
+            }
+        };
 
-        log::info!("Advancing state machine to ready (full)");
         let res = state_machine.run_to_ready(last_state);
         // Grab this now even though we don't need it until later to avoid a
         // lifetime issue
@@ -378,6 +438,10 @@ impl<'info, 'res, 'pgs, 'mcs> SyncMultipleDriver<'info, 'res, 'pgs, 'mcs> {
         );
 
         if let Some(c) = changes {
+            if !c.remote_changes.is_empty() {
+                log::info!("Engines changed by other clients: {:?}", c.remote_changes);
+            }
+            self.result.remote_engine_changes = c.remote_changes.clone();
             self.wipe_or_reset_engines(c, &client_info.client)?;
         }
         let state = match res {
@@ -26,6 +26,7 @@ impl<'a> From<&'a Error> for SyncFailure {
                 ErrorResponse::Unauthorized { .. } => SyncFailure::Auth { from: "storage" },
                 ErrorResponse::PreconditionFailed { .. } => SyncFailure::Http { code: 412 },
                 ErrorResponse::ServerError { status, .. } => SyncFailure::Http { code: *status },
+                ErrorResponse::QuotaExceeded { status, .. } => SyncFailure::Http { code: *status },
                 ErrorResponse::RequestFailed { status, .. } => SyncFailure::Http { code: *status },
             },
             ErrorKind::CryptoError(ref e) => SyncFailure::Unexpected {
@@ -0,0 +1,53 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Helpers for tests that talk to a `sync_test_server::TestServer`.
+
+use crate::bso_record::{CleartextBso, Payload};
+use crate::client::{Sync15StorageClient, Sync15StorageClientInit};
+use crate::coll_state::CollState;
+use crate::key_bundle::KeyBundle;
+use crate::request::InfoConfiguration;
+use crate::util::ServerTimestamp;
+use sync_test_server::{ServerRecord, TestServer};
+
+/// Returns a client for the server. The server accepts any credentials.
+pub fn client_for(server: &TestServer) -> Sync15StorageClient {
+    Sync15StorageClient::new(Sync15StorageClientInit {
+        key_id: "test-key-id".into(),
+        access_token: "test-access-token".into(),
+        tokenserver_url: server.tokenserver_url(),
+    })
+    .expect("should create a client")
+}
+
+/// Returns the state for a collection we haven't synced, with a new key.
+pub fn new_coll_state() -> CollState {
+    CollState {
+        config: InfoConfiguration::default(),
+        last_modified: ServerTimestamp(0),
+        key: KeyBundle::new_random().expect("should generate a key"),
+    }
+}
+
+/// Writes a record to the server, encrypted with `key`, as if another
+/// client had uploaded it. Returns the collection's new modified time.
+pub fn insert_record(
+    server: &TestServer,
+    key: &KeyBundle,
+    collection: &str,
+    payload: Payload,
+) -> ServerTimestamp {
+    let bso = CleartextBso::from_payload(payload, collection)
+        .encrypt(key)
+        .expect("should encrypt");
+    let record = ServerRecord {
+        id: bso.id.to_string(),
+        modified: 0,
+        payload: serde_json::to_string(&bso.payload).expect("should serialize"),
+        sortindex: bso.sortindex.map(i64::from),
+        ttl: bso.ttl.map(u64::from),
+    };
+    ServerTimestamp(server.insert_records(collection, vec![record]))
+}
@@ -0,0 +1,51 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+package mozilla.appservices.syncmanager
+
+/**
+ * Whether an engine is enabled, declined, or has a change that hasn't been
+ * synced yet, from [SyncManager.getEngineStates].
+ */
+data class EngineState(
+    /**
+     * The engine's collection name, like "history" or "passwords".
+     */
+    val engine: String,
+
+    /**
+     * Whether the engine will be synced, including changes from
+     * [SyncManager.setEngineEnabled] that haven't been synced yet.
+     */
+    val enabled: Boolean,
+
+    /**
+     * Whether the engine was declined as of our last sync, or null if we
+     * haven't synced yet.
+     */
+    val declined: Boolean?,
+
+    /**
+     * The state passed to [SyncManager.setEngineEnabled], if the next sync
+     * still needs to upload it.
+     */
+    val pendingEnabled: Boolean?,
+
+    /**
+     * Whether an open engine is registered for this collection.
+     */
+    val registered: Boolean
+) {
+    companion object {
+        internal fun fromProtobuf(pb: MsgTypes.EngineState): EngineState {
+            return EngineState(
+                engine = pb.engine,
+                enabled = pb.enabled,
+                declined = if (pb.hasDeclined()) pb.declined else null,
+                pendingEnabled = if (pb.hasPendingEnabled()) pb.pendingEnabled else null,
+                registered = pb.registered
+            )
+        }
+    }
+}
@@ -21,8 +21,22 @@ internal interface LibSyncManagerFFI : Library {
     fun sync_manager_set_tabs(handle: TabsApiHandle, error: RustError.ByReference)
     fun sync_manager_disconnect(error: RustError.ByReference)
 
+    fun sync_manager_set_engine_enabled(
+        engine: String,
+        enabled: Byte,
+        wipeLocal: Byte,
+        error: RustError.ByReference
+    )
+    fun sync_manager_get_engine_states(persistedState: String?, error: RustError.ByReference): RustBuffer.ByValue
+
+    fun sync_manager_open_status_store(path: String, error: RustError.ByReference)
+    fun sync_manager_get_sync_status(error: RustError.ByReference): RustBuffer.ByValue
+
     fun sync_manager_sync(data: Pointer, len: Int, error: RustError.ByReference): RustBuffer.ByValue
 
+    // Returns the JSON for a sync ping, or null if no telemetry was queued.
+    fun sync_manager_flush_telemetry(why: String, error: RustError.ByReference): Pointer?
+
     fun sync_manager_destroy_string(s: Pointer)
     fun sync_manager_destroy_bytebuffer(bb: RustBuffer.ByValue)
 }
@@ -54,6 +54,101 @@ object SyncManager {
             LibSyncManagerFFI.INSTANCE.sync_manager_disconnect(err)
         }
     }
+
+    /**
+     * Enable or decline an engine on every device. The change is uploaded by the next sync
+     * that isn't read-only, and is saved in the [SyncResult.persistedState] of each sync until
+     * then. Apps should sync with [SyncReason.ENABLED_CHANGE] soon after calling this, since a
+     * change that hasn't been through a sync is only kept in memory.
+     *
+     * @param engine The engine's collection name, like "history" or "passwords".
+     * @param enabled Whether the engine should be synced.
+     * @param wipeLocal If true when declining, the engine's local data is wiped now.
+     */
+    fun setEngineEnabled(engine: String, enabled: Boolean, wipeLocal: Boolean = false) {
+        rustCall { err ->
+            LibSyncManagerFFI.INSTANCE.sync_manager_set_engine_enabled(
+                engine,
+                (if (enabled) 1 else 0).toByte(),
+                (if (wipeLocal) 1 else 0).toByte(),
+                err
+            )
+        }
+    }
+
+    /**
+     * Returns the state of every engine that's registered, declined, or has a
+     * change that hasn't been synced yet, sorted by name.
+     *
+     * @param persistedState The [SyncResult.persistedState] from the last sync, if any.
+     * It's used for the declined engines, and changes made before the app restarted,
+     * if we haven't synced since.
+     */
+    fun getEngineStates(persistedState: String? = null): List<EngineState> {
+        val rustBuf = rustCall { err ->
+            LibSyncManagerFFI.INSTANCE.sync_manager_get_engine_states(persistedState, err)
+        }
+        try {
+            val stream = rustBuf.asCodedInputStream()
+            return MsgTypes.EngineStates.parseFrom(stream).statesList.map {
+                EngineState.fromProtobuf(it)
+            }
+        } finally {
+            LibSyncManagerFFI.INSTANCE.sync_manager_destroy_bytebuffer(rustBuf)
+        }
+    }
+
+    /**
+     * Keep the sync history in a database at [path], so that it's kept across restarts.
+     * Apps that show the sync status must call this before syncing; otherwise, the
+     * history, including when each engine last synced successfully, is kept in
+     * memory, and lost when the app exits.
+     *
+     * @param path The path to the database, which is created if it doesn't exist.
+     */
+    fun openStatusStore(path: String) {
+        rustCall { err ->
+            LibSyncManagerFFI.INSTANCE.sync_manager_open_status_store(path, err)
+        }
+    }
+
+    /**
+     * Returns each engine's recent syncs, with when it last synced successfully
+     * and how many times it's failed since, sorted by engine name.
+     */
+    fun getSyncStatus(): List<EngineSyncStatus> {
+        val rustBuf = rustCall { err ->
+            LibSyncManagerFFI.INSTANCE.sync_manager_get_sync_status(err)
+        }
+        try {
+            val stream = rustBuf.asCodedInputStream()
+            return MsgTypes.EngineSyncStatuses.parseFrom(stream).statusesList.map {
+                EngineSyncStatus.fromProtobuf(it)
+            }
+        } finally {
+            LibSyncManagerFFI.INSTANCE.sync_manager_destroy_bytebuffer(rustBuf)
+        }
+    }
+
+    /**
+     * Returns the JSON for a sync ping with the telemetry from every sync that
+     * set [SyncParams.queueTelemetry] since the last call, or null if there
+     * isn't any. Queued telemetry is only kept in memory, and the oldest syncs
+     * are dropped if too many are queued, so this should be called regularly.
+     *
+     * @param why The reason the ping is being submitted, like "schedule" or "shutdown".
+     */
+    fun flushTelemetry(why: String): String? {
+        val ptr = rustCall { err ->
+            LibSyncManagerFFI.INSTANCE.sync_manager_flush_telemetry(why, err)
+        } ?: return null
+        try {
+            return ptr.getString(0, "utf8")
+        } finally {
+            LibSyncManagerFFI.INSTANCE.sync_manager_destroy_string(ptr)
+        }
+    }
+
     /**
      * Perform a sync.
      */
@@ -112,7 +112,22 @@ data class SyncParams(
     /**
      * The information used to populate a client record for this device.
      */
-    val deviceSettings: DeviceSettings
+    val deviceSettings: DeviceSettings,
+
+    /**
+     * If true, this sync's telemetry is added to a ping that accumulates
+     * syncs until [SyncManager.flushTelemetry] is called.
+     */
+    val queueTelemetry: Boolean = false,
+
+    /**
+     * The hashed FxA device ID and details about the OS, which are included
+     * in the accumulated ping if given.
+     */
+    val telemetryDeviceId: String? = null,
+    val osName: String? = null,
+    val osVersion: String? = null,
+    val osLocale: String? = null
 ) {
     @Suppress("ComplexMethod")
     internal fun toProtobuf(): MsgTypes.SyncParams {
@@ -152,6 +167,12 @@ data class SyncParams(
             DeviceType.TV -> MsgTypes.DeviceType.TV
         }
 
+        builder.queueTelemetry = this.queueTelemetry
+        this.telemetryDeviceId?.let { builder.telemetryDeviceId = it }
+        this.osName?.let { builder.osName = it }
+        this.osVersion?.let { builder.osVersion = it }
+        this.osLocale?.let { builder.osLocale = it }
+
         return builder.build()
     }
 }
@@ -37,10 +37,32 @@ enum class SyncServiceStatus {
      */
     BACKED_OFF,
 
+    /**
+     * The account has used all of its storage on the server, so nothing
+     * more can be uploaded until some is freed, for example by removing
+     * bookmarks or history.
+     */
+    OVER_QUOTA,
+
     /**
      * Some other error occurred.
      */
-    OTHER_ERROR,
+    OTHER_ERROR;
+
+    companion object {
+        internal fun fromProtobuf(status: MsgTypes.ServiceStatus): SyncServiceStatus {
+            return when (status) {
+                MsgTypes.ServiceStatus.OK -> OK
+                MsgTypes.ServiceStatus.NETWORK_ERROR -> NETWORK_ERROR
+                MsgTypes.ServiceStatus.SERVICE_ERROR -> SERVICE_ERROR
+                MsgTypes.ServiceStatus.AUTH_ERROR -> AUTH_ERROR
+                MsgTypes.ServiceStatus.BACKED_OFF -> BACKED_OFF
+                MsgTypes.ServiceStatus.OVER_QUOTA -> OVER_QUOTA
+                MsgTypes.ServiceStatus.OTHER_ERROR -> OTHER_ERROR
+                else -> OTHER_ERROR // impossible *sigh*
+            }
+        }
+    }
 }
 
 /**
@@ -80,6 +102,13 @@ data class SyncResult(
      */
     val declined: List<String>?,
 
+    /**
+     * Engines another device enabled (true) or declined (false) since our
+     * last sync. Declined engines have already been reset, so apps should
+     * update their settings to match.
+     */
+    val remoteEngineChanges: Map<String, Boolean>,
+
     /**
      * The next time we're allowed to sync, in milliseconds since
      * the unix epoch, or null if there are no known restrictions on
@@ -95,6 +124,13 @@ data class SyncResult(
      */
     val nextSyncAllowedAt: Long?,
 
+    /**
+     * How many bytes of storage the account has left on the server, or
+     * null if the server didn't say. Servers only report this when the
+     * account is close to its quota.
+     */
+    val quotaRemaining: Long?,
+
     /**
      * A bundle of telemetry information recorded during this sync.
      */
@@ -109,6 +145,12 @@ data class SyncResult(
                 null
             }
 
+            val quotaRemaining = if (pb.hasQuotaRemaining()) {
+                pb.quotaRemaining
+            } else {
+                null
+            }
+
             val declined = if (pb.haveDeclined) {
                 pb.declinedList
             } else {
@@ -128,23 +170,15 @@ data class SyncResult(
                 null
             }
 
-            val status = when (pb.status) {
-                MsgTypes.ServiceStatus.OK -> SyncServiceStatus.OK
-                MsgTypes.ServiceStatus.NETWORK_ERROR -> SyncServiceStatus.NETWORK_ERROR
-                MsgTypes.ServiceStatus.SERVICE_ERROR -> SyncServiceStatus.SERVICE_ERROR
-                MsgTypes.ServiceStatus.AUTH_ERROR -> SyncServiceStatus.AUTH_ERROR
-                MsgTypes.ServiceStatus.BACKED_OFF -> SyncServiceStatus.BACKED_OFF
-                MsgTypes.ServiceStatus.OTHER_ERROR -> SyncServiceStatus.OTHER_ERROR
-                else -> SyncServiceStatus.OTHER_ERROR // impossible *sigh*
-            }
-
             return SyncResult(
-                status = status,
+                status = SyncServiceStatus.fromProtobuf(pb.status),
                 failures = failures,
                 successful = successful,
                 declined = declined,
+                remoteEngineChanges = pb.remoteEngineChangesMap,
                 telemetry = telemetry,
                 nextSyncAllowedAt = nextSyncAllowedAt,
+                quotaRemaining = quotaRemaining,
                 persistedState = pb.persistedState
             )
         }
@@ -0,0 +1,94 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+package mozilla.appservices.syncmanager
+
+/**
+ * An engine's recent syncs, from [SyncManager.getSyncStatus].
+ */
+data class EngineSyncStatus(
+    /**
+     * The engine's collection name, like "history" or "passwords".
+     */
+    val engine: String,
+
+    /**
+     * When the engine's last successful sync started, in milliseconds since
+     * the unix epoch, or null if it hasn't synced successfully.
+     */
+    val lastSuccessAt: Long?,
+
+    /**
+     * How many times the engine failed to sync since it last synced successfully.
+     */
+    val consecutiveFailures: Int,
+
+    /**
+     * The engine's most recent syncs, newest first.
+     */
+    val history: List<EngineSyncRecord>
+) {
+    companion object {
+        internal fun fromProtobuf(pb: MsgTypes.EngineSyncStatus): EngineSyncStatus {
+            return EngineSyncStatus(
+                engine = pb.engine,
+                lastSuccessAt = if (pb.hasLastSuccessAt()) pb.lastSuccessAt else null,
+                consecutiveFailures = pb.consecutiveFailures,
+                history = pb.historyList.map { EngineSyncRecord.fromProtobuf(it) }
+            )
+        }
+    }
+}
+
+/**
+ * An engine's part in a sync.
+ */
+data class EngineSyncRecord(
+    /**
+     * When the sync started, in milliseconds since the unix epoch.
+     */
+    val startedAt: Long,
+
+    /**
+     * How long the engine took to sync, in milliseconds.
+     */
+    val took: Long,
+
+    /**
+     * Whether the engine synced, or why it failed.
+     */
+    val status: SyncServiceStatus,
+
+    /**
+     * The error, if the engine failed.
+     */
+    val error: String?,
+
+    /**
+     * How many incoming records were applied, and how many failed to apply.
+     */
+    val applied: Long,
+    val incomingFailed: Long,
+
+    /**
+     * How many records were uploaded, and how many the server rejected.
+     */
+    val uploaded: Long,
+    val outgoingFailed: Long
+) {
+    companion object {
+        internal fun fromProtobuf(pb: MsgTypes.EngineSyncRecord): EngineSyncRecord {
+            return EngineSyncRecord(
+                startedAt = pb.startedAt,
+                took = pb.took,
+                status = SyncServiceStatus.fromProtobuf(pb.status),
+                error = if (pb.hasError()) pb.error else null,
+                applied = pb.applied,
+                incomingFailed = pb.incomingFailed,
+                uploaded = pb.uploaded,
+                outgoingFailed = pb.outgoingFailed
+            )
+        }
+    }
+}
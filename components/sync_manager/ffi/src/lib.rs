@@ -8,7 +8,8 @@
 // the closure is small.
 #![allow(clippy::redundant_closure)]
 
-use ffi_support::{ExternError, HandleError};
+use ffi_support::{ExternError, FfiStr, HandleError};
+use std::convert::TryInto;
 use sync_manager::Result as MgrResult;
 
 #[no_mangle]
@@ -56,6 +57,56 @@ pub extern "C" fn sync_manager_disconnect(error: &mut ExternError) {
     });
 }
 
+/// Enables or declines an engine on every device, the next time we sync.
+/// If `wipe_local` is set when declining, the engine's local data is wiped now.
+#[no_mangle]
+pub extern "C" fn sync_manager_set_engine_enabled(
+    engine: FfiStr<'_>,
+    enabled: u8,
+    wipe_local: u8,
+    error: &mut ExternError,
+) {
+    ffi_support::call_with_result(error, || {
+        log::debug!("sync_manager_set_engine_enabled");
+        sync_manager::set_engine_enabled(engine.as_str(), enabled != 0, wipe_local != 0)
+    })
+}
+
+/// Returns an `EngineStates` message with whether each engine is enabled,
+/// declined, or has a change that hasn't been synced yet. `persisted_state`
+/// may be null.
+#[no_mangle]
+pub extern "C" fn sync_manager_get_engine_states(
+    persisted_state: FfiStr<'_>,
+    error: &mut ExternError,
+) -> ffi_support::ByteBuffer {
+    ffi_support::call_with_output(error, || {
+        log::debug!("sync_manager_get_engine_states");
+        sync_manager::get_engine_states(persisted_state.as_opt_str())
+    })
+}
+
+/// Keeps the sync history in a database at `path`, instead of in memory.
+/// Apps that show the sync status must call this before syncing, or the
+/// history, including when each engine last synced successfully, is lost
+/// when the app exits.
+#[no_mangle]
+pub extern "C" fn sync_manager_open_status_store(path: FfiStr<'_>, error: &mut ExternError) {
+    ffi_support::call_with_result(error, || {
+        log::debug!("sync_manager_open_status_store");
+        sync_manager::open_status_store(path.as_str())
+    })
+}
+
+/// Returns an `EngineSyncStatuses` message with each engine's recent syncs.
+#[no_mangle]
+pub extern "C" fn sync_manager_get_sync_status(error: &mut ExternError) -> ffi_support::ByteBuffer {
+    ffi_support::call_with_result(error, || {
+        log::debug!("sync_manager_get_sync_status");
+        sync_manager::get_sync_status()
+    })
+}
+
 unsafe fn get_buffer<'a>(data: *const u8, len: i32) -> &'a [u8] {
     assert!(len >= 0, "Bad buffer len: {}", len);
     if len == 0 {
@@ -82,5 +133,55 @@ pub unsafe extern "C" fn sync_manager_sync(
     })
 }
 
+/// # Safety
+/// Reads pointer, thus unsafe.
+#[no_mangle]
+pub unsafe extern "C" fn sync_manager_set_scheduler_policy(
+    policy_data: *const u8,
+    policy_len: i32,
+    error: &mut ExternError,
+) {
+    ffi_support::call_with_result(error, || -> MgrResult<()> {
+        log::debug!("sync_manager_set_scheduler_policy");
+        let buffer = get_buffer(policy_data, policy_len);
+        let policy: sync_manager::msg_types::SchedulerPolicy = prost::Message::decode(buffer)?;
+        sync_manager::set_scheduler_policy(policy.into());
+        Ok(())
+    })
+}
+
+/// Tells the scheduler about an event, and returns a `SchedulerDecision`
+/// saying whether to sync now, or when to ask again.
+///
+/// # Safety
+/// Reads pointer, thus unsafe.
+#[no_mangle]
+pub unsafe extern "C" fn sync_manager_scheduler_event(
+    event_data: *const u8,
+    event_len: i32,
+    error: &mut ExternError,
+) -> ffi_support::ByteBuffer {
+    ffi_support::call_with_result(error, || -> MgrResult<_> {
+        log::debug!("sync_manager_scheduler_event");
+        let buffer = get_buffer(event_data, event_len);
+        let event: sync_manager::msg_types::SchedulerEvent = prost::Message::decode(buffer)?;
+        let decision = sync_manager::scheduler_event(event.try_into()?);
+        Ok(sync_manager::msg_types::SchedulerDecision::from(decision))
+    })
+}
+
+/// Returns the JSON for a sync ping with the telemetry from every sync that
+/// set `queue_telemetry` since the last call, or null if there isn't any.
+#[no_mangle]
+pub extern "C" fn sync_manager_flush_telemetry(
+    why: FfiStr<'_>,
+    error: &mut ExternError,
+) -> *mut std::os::raw::c_char {
+    ffi_support::call_with_output(error, || {
+        log::debug!("sync_manager_flush_telemetry");
+        sync_manager::flush_telemetry(why.into_string())
+    })
+}
+
 ffi_support::define_string_destructor!(sync_manager_destroy_string);
 ffi_support::define_bytebuffer_destructor!(sync_manager_destroy_bytebuffer);
@@ -0,0 +1,392 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! `SyncEngine`s for the components the sync manager has always supported.
+//! Each one holds a weak reference to its component, so closing the
+//! component closes the engine.
+
+use crate::engine::{EngineCommands, EngineSession, SyncEngine};
+use crate::error::*;
+use logins::PasswordEngine;
+use places::{
+    bookmark_sync::{repair, store::BookmarksStore},
+    history_sync::store::HistoryStore,
+    PlacesApi, PlacesDb,
+};
+use sql_support::SqlInterruptScope;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, Weak};
+use sync15::clients::{Command, CommandStatus};
+use sync15::{Store, StoreSyncAssociation};
+use tabs::{ClosedTabsStore, TabsEngine, TabsStorage, TabsStore};
+
+pub(crate) const LOGINS_ENGINE: &str = "passwords";
+pub(crate) const HISTORY_ENGINE: &str = "history";
+pub(crate) const BOOKMARKS_ENGINE: &str = "bookmarks";
+pub(crate) const TABS_ENGINE: &str = "tabs";
+
+pub(crate) struct PlacesEngine(Weak<PlacesApi>);
+
+impl PlacesEngine {
+    pub(crate) fn new(places: &Arc<PlacesApi>) -> Self {
+        Self(Arc::downgrade(places))
+    }
+
+    fn places(&self, collection: &str) -> Result<Arc<PlacesApi>> {
+        self.0
+            .upgrade()
+            .ok_or_else(|| ErrorKind::ConnectionClosed(collection.into()).into())
+    }
+}
+
+impl SyncEngine for PlacesEngine {
+    fn collection_names(&self) -> Vec<String> {
+        vec![HISTORY_ENGINE.into(), BOOKMARKS_ENGINE.into()]
+    }
+
+    fn is_open(&self) -> bool {
+        self.0.upgrade().is_some()
+    }
+
+    fn with_session(
+        &self,
+        collections: &[&str],
+        interruptee: &SqlInterruptScope,
+        f: &mut dyn FnMut(EngineSession<'_>) -> Result<()>,
+    ) -> Result<()> {
+        let places = match self.0.upgrade() {
+            Some(places) if !collections.is_empty() => places,
+            _ => return f(EngineSession::default()),
+        };
+        let conn = match places.open_sync_connection() {
+            Ok(conn) => conn,
+            Err(e) => {
+                log::warn!("Failed to open the places sync connection: {}", e);
+                return f(EngineSession::default());
+            }
+        };
+        let mut stores: Vec<Box<dyn Store + '_>> = vec![];
+        if collections.contains(&HISTORY_ENGINE) {
+            stores.push(Box::new(HistoryStore::new(&conn, interruptee)));
+        }
+        if collections.contains(&BOOKMARKS_ENGINE) {
+            stores.push(Box::new(BookmarksStore::new(&conn, interruptee)));
+        }
+        let commands = PlacesCommands {
+            db: &conn,
+            interruptee,
+            history: collections.contains(&HISTORY_ENGINE),
+            bookmarks: collections.contains(&BOOKMARKS_ENGINE),
+        };
+        f(EngineSession {
+            stores,
+            commands: Some(Box::new(commands)),
+        })
+    }
+
+    fn wipe(&self, collection: &str) -> Result<()> {
+        let places = self.places(collection)?;
+        match collection {
+            HISTORY_ENGINE => places.wipe_history()?,
+            BOOKMARKS_ENGINE => places.wipe_bookmarks()?,
+            _ => return Err(ErrorKind::UnknownEngine(collection.into()).into()),
+        }
+        Ok(())
+    }
+
+    fn reset(&self, collection: &str) -> Result<()> {
+        let places = self.places(collection)?;
+        match collection {
+            HISTORY_ENGINE => places.reset_history()?,
+            BOOKMARKS_ENGINE => places.reset_bookmarks()?,
+            _ => return Err(ErrorKind::UnknownEngine(collection.into()).into()),
+        }
+        Ok(())
+    }
+}
+
+// Wipes and resets the collections we're syncing, sends the
+// `repairRequest`s for items that a bookmark repair found missing, and
+// stops asking for the items that other clients' `repairResponse`s say they
+// uploaded.
+struct PlacesCommands<'a> {
+    db: &'a PlacesDb,
+    interruptee: &'a SqlInterruptScope,
+    // Which collections we're syncing.
+    history: bool,
+    bookmarks: bool,
+}
+
+impl<'a> PlacesCommands<'a> {
+    fn is_syncing(&self, collection: &str) -> bool {
+        match collection {
+            HISTORY_ENGINE => self.history,
+            BOOKMARKS_ENGINE => self.bookmarks,
+            _ => false,
+        }
+    }
+
+    fn wipe(&self, collection: &str) -> Result<()> {
+        if collection == HISTORY_ENGINE {
+            places::storage::history::delete_everything(self.db)?;
+        } else {
+            places::storage::bookmarks::delete_everything(self.db)?;
+        }
+        Ok(())
+    }
+
+    fn reset(&self, collection: &str) -> Result<()> {
+        let assoc = StoreSyncAssociation::Disconnected;
+        if collection == HISTORY_ENGINE {
+            HistoryStore::new(self.db, self.interruptee).reset(&assoc)?;
+        } else {
+            BookmarksStore::new(self.db, self.interruptee).reset(&assoc)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> EngineCommands for PlacesCommands<'a> {
+    fn apply_incoming_command(&self, command: &Command) -> Result<CommandStatus> {
+        Ok(match command {
+            Command::Wipe(collection) if self.is_syncing(collection) => {
+                self.wipe(collection)?;
+                CommandStatus::Applied
+            }
+            Command::Reset(collection) if self.is_syncing(collection) => {
+                self.reset(collection)?;
+                CommandStatus::Applied
+            }
+            Command::RepairResponse(response)
+                if self.bookmarks && response.collection == BOOKMARKS_ENGINE =>
+            {
+                log::info!(
+                    "Client {} uploaded {} bookmarks for repair {}",
+                    response.client_id,
+                    response.ids.len(),
+                    response.flow_id
+                );
+                repair::repair_response_received(self.db, response)?;
+                CommandStatus::Applied
+            }
+            _ => CommandStatus::Unsupported,
+        })
+    }
+
+    fn fetch_outgoing_commands_for_client(
+        &self,
+        local_id: &str,
+        _client_id: &str,
+        current_commands: &HashSet<Command>,
+    ) -> Result<HashSet<Command>> {
+        let mut outgoing = HashSet::new();
+        if !self.bookmarks {
+            return Ok(outgoing);
+        }
+        if let Some(request) = repair::fetch_outgoing_repair_request(self.db, local_id)? {
+            let command = Command::RepairRequest(request);
+            if !current_commands.contains(&command) {
+                outgoing.insert(command);
+            }
+        }
+        Ok(outgoing)
+    }
+
+    fn commands_sent(&self, _local_id: &str, _client_id: &str, sent: &[Command]) -> Result<()> {
+        let sent_request = sent.iter().any(|command| match command {
+            Command::RepairRequest(request) => request.collection == BOOKMARKS_ENGINE,
+            _ => false,
+        });
+        if sent_request {
+            repair::repair_request_sent(self.db)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct LoginsEngine(Weak<Mutex<PasswordEngine>>);
+
+impl LoginsEngine {
+    pub(crate) fn new(logins: &Arc<Mutex<PasswordEngine>>) -> Self {
+        Self(Arc::downgrade(logins))
+    }
+
+    fn logins(&self) -> Result<Arc<Mutex<PasswordEngine>>> {
+        self.0
+            .upgrade()
+            .ok_or_else(|| ErrorKind::ConnectionClosed(LOGINS_ENGINE.into()).into())
+    }
+}
+
+impl SyncEngine for LoginsEngine {
+    fn collection_names(&self) -> Vec<String> {
+        vec![LOGINS_ENGINE.into()]
+    }
+
+    fn is_open(&self) -> bool {
+        self.0.upgrade().is_some()
+    }
+
+    fn with_session(
+        &self,
+        collections: &[&str],
+        _interruptee: &SqlInterruptScope,
+        f: &mut dyn FnMut(EngineSession<'_>) -> Result<()>,
+    ) -> Result<()> {
+        let logins = match self.0.upgrade() {
+            Some(logins) if !collections.is_empty() => logins,
+            _ => return f(EngineSession::default()),
+        };
+        let engine = logins.lock().expect("poisoned logins mutex");
+        f(EngineSession {
+            stores: vec![Box::new(logins::LoginStore::new(&engine.db))],
+            commands: Some(Box::new(LoginsCommands(&engine))),
+        })
+    }
+
+    fn wipe(&self, _collection: &str) -> Result<()> {
+        let logins = self.logins()?;
+        let engine = logins.lock().expect("poisoned logins mutex");
+        engine.wipe()?;
+        Ok(())
+    }
+
+    fn reset(&self, _collection: &str) -> Result<()> {
+        let logins = self.logins()?;
+        let engine = logins.lock().expect("poisoned logins mutex");
+        engine.reset()?;
+        Ok(())
+    }
+}
+
+// Wipes and resets the logins we're syncing.
+struct LoginsCommands<'a>(&'a PasswordEngine);
+
+impl<'a> EngineCommands for LoginsCommands<'a> {
+    fn apply_incoming_command(&self, command: &Command) -> Result<CommandStatus> {
+        Ok(match command {
+            Command::Wipe(collection) if collection == LOGINS_ENGINE => {
+                self.0.wipe()?;
+                CommandStatus::Applied
+            }
+            Command::Reset(collection) if collection == LOGINS_ENGINE => {
+                self.0.reset()?;
+                CommandStatus::Applied
+            }
+            _ => CommandStatus::Unsupported,
+        })
+    }
+}
+
+pub(crate) struct TabsSyncEngine(Weak<Mutex<TabsEngine>>);
+
+impl TabsSyncEngine {
+    pub(crate) fn new(tabs: &Arc<Mutex<TabsEngine>>) -> Self {
+        Self(Arc::downgrade(tabs))
+    }
+
+    fn tabs(&self) -> Result<Arc<Mutex<TabsEngine>>> {
+        self.0
+            .upgrade()
+            .ok_or_else(|| ErrorKind::ConnectionClosed(TABS_ENGINE.into()).into())
+    }
+
+    // Closed tabs are synced along with tabs, if the app has turned them on,
+    // so this returns the stores for both.
+    fn stores(storage: &TabsStorage) -> Result<Vec<Box<dyn Store + '_>>> {
+        let mut stores: Vec<Box<dyn Store + '_>> = vec![Box::new(TabsStore::new(storage))];
+        if storage.closed_tabs_sync_enabled()? {
+            stores.push(Box::new(ClosedTabsStore::new(storage)));
+        }
+        Ok(stores)
+    }
+}
+
+impl SyncEngine for TabsSyncEngine {
+    fn collection_names(&self) -> Vec<String> {
+        vec![TABS_ENGINE.into()]
+    }
+
+    fn is_open(&self) -> bool {
+        self.0.upgrade().is_some()
+    }
+
+    fn with_session(
+        &self,
+        collections: &[&str],
+        _interruptee: &SqlInterruptScope,
+        f: &mut dyn FnMut(EngineSession<'_>) -> Result<()>,
+    ) -> Result<()> {
+        let tabs = match self.0.upgrade() {
+            Some(tabs) if !collections.is_empty() => tabs,
+            _ => return f(EngineSession::default()),
+        };
+        let engine = tabs.lock().expect("poisoned tabs mutex");
+        f(EngineSession {
+            stores: Self::stores(&engine.storage)?,
+            commands: Some(Box::new(TabsCommands(&engine.storage))),
+        })
+    }
+
+    fn wipe(&self, _collection: &str) -> Result<()> {
+        let tabs = self.tabs()?;
+        let engine = tabs.lock().expect("poisoned tabs mutex");
+        for store in Self::stores(&engine.storage)? {
+            store.wipe()?;
+        }
+        Ok(())
+    }
+
+    fn reset(&self, _collection: &str) -> Result<()> {
+        let tabs = self.tabs()?;
+        let engine = tabs.lock().expect("poisoned tabs mutex");
+        for store in Self::stores(&engine.storage)? {
+            store.reset(&StoreSyncAssociation::Disconnected)?;
+        }
+        Ok(())
+    }
+}
+
+struct TabsCommands<'a>(&'a TabsStorage);
+
+impl<'a> EngineCommands for TabsCommands<'a> {
+    fn apply_incoming_command(&self, command: &Command) -> Result<CommandStatus> {
+        match command {
+            Command::Wipe(collection) if collection == TABS_ENGINE => {
+                for store in TabsSyncEngine::stores(self.0)? {
+                    store.wipe()?;
+                }
+                return Ok(CommandStatus::Applied);
+            }
+            Command::Reset(collection) if collection == TABS_ENGINE => {
+                for store in TabsSyncEngine::stores(self.0)? {
+                    store.reset(&StoreSyncAssociation::Disconnected)?;
+                }
+                return Ok(CommandStatus::Applied);
+            }
+            _ => {}
+        }
+        Ok(if self.0.apply_incoming_command(command)? {
+            CommandStatus::Applied
+        } else {
+            CommandStatus::Unsupported
+        })
+    }
+
+    fn fetch_outgoing_commands_for_client(
+        &self,
+        local_id: &str,
+        client_id: &str,
+        current_commands: &HashSet<Command>,
+    ) -> Result<HashSet<Command>> {
+        Ok(self
+            .0
+            .fetch_outgoing_commands_for_client(local_id, client_id, current_commands)?)
+    }
+
+    fn commands_sent(&self, local_id: &str, client_id: &str, sent: &[Command]) -> Result<()> {
+        self.0.commands_sent(local_id, client_id, sent)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,332 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The engines the sync manager knows how to sync. Components register a
+//! `SyncEngine` for their collections, and the manager uses it to sync,
+//! wipe and reset them, and to handle commands sent to them, without
+//! knowing anything else about the component.
+
+use crate::error::*;
+use sql_support::SqlInterruptScope;
+use std::collections::HashSet;
+use sync15::clients::{Command, CommandStatus};
+use sync15::Store;
+
+/// A component that syncs one or more collections.
+///
+/// Implementations usually hold a `Weak` reference to the component, so
+/// that registering it with the sync manager doesn't keep it open.
+pub trait SyncEngine: Send {
+    /// The names of the collections this engine syncs, like `"history"`
+    /// and `"bookmarks"`. These are the names used in `SyncParams`, and
+    /// for wiping and resetting.
+    fn collection_names(&self) -> Vec<String>;
+
+    /// Returns false if the component has been closed, in which case the
+    /// engine won't be synced, wiped or reset.
+    fn is_open(&self) -> bool;
+
+    /// Calls `f` with the stores for `collections`, which are some of the
+    /// engine's `collection_names`, and its command handler. The stores
+    /// only need to live until `f` returns.
+    ///
+    /// This is only called for engines with collections being synced.
+    /// Commands for other engines are left in our client record, and
+    /// handled by the first sync that includes them.
+    fn with_session(
+        &self,
+        collections: &[&str],
+        interruptee: &SqlInterruptScope,
+        f: &mut dyn FnMut(EngineSession<'_>) -> Result<()>,
+    ) -> Result<()>;
+
+    /// Erases the collection's local data.
+    fn wipe(&self, collection: &str) -> Result<()>;
+
+    /// Resets the collection's sync metadata, so that its next sync is a
+    /// first sync.
+    fn reset(&self, collection: &str) -> Result<()>;
+}
+
+/// What an engine provides for the duration of a sync.
+#[derive(Default)]
+pub struct EngineSession<'a> {
+    pub stores: Vec<Box<dyn Store + 'a>>,
+    pub commands: Option<Box<dyn EngineCommands + 'a>>,
+}
+
+/// Handles commands sent to and from an engine through the clients
+/// collection.
+pub trait EngineCommands {
+    /// Applies a command that another client sent us. Engines return
+    /// `CommandStatus::Unsupported` for commands that aren't theirs.
+    ///
+    /// Commands are applied before any collection syncs, so engines should
+    /// apply `Command::Wipe` and `Command::Reset` for the collections in
+    /// their session right away, using the session's connections. The sync
+    /// manager applies the ones engines don't support, and the ones for
+    /// collections that aren't syncing, with `SyncEngine::wipe` and
+    /// `SyncEngine::reset` once the sync finishes.
+    fn apply_incoming_command(&self, command: &Command) -> Result<CommandStatus>;
+
+    /// Returns the commands to send to the client with the given ID, given
+    /// the commands already in its record.
+    fn fetch_outgoing_commands_for_client(
+        &self,
+        _local_id: &str,
+        _client_id: &str,
+        _current_commands: &HashSet<Command>,
+    ) -> Result<HashSet<Command>> {
+        Ok(HashSet::new())
+    }
+
+    /// Called once the commands for a client have been uploaded.
+    fn commands_sent(&self, _local_id: &str, _client_id: &str, _sent: &[Command]) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The registered engines.
+#[derive(Default)]
+pub struct EngineRegistry {
+    engines: Vec<Box<dyn SyncEngine>>,
+}
+
+impl EngineRegistry {
+    /// Registers an engine, replacing any engine already registered for
+    /// the same collections.
+    pub fn register(&mut self, engine: Box<dyn SyncEngine>) {
+        let names = engine.collection_names();
+        self.engines.retain(|existing| {
+            let replaced = existing
+                .collection_names()
+                .iter()
+                .any(|name| names.contains(name));
+            if replaced {
+                log::info!("Replacing the engine for {:?}", existing.collection_names());
+            }
+            !replaced
+        });
+        self.engines.push(engine);
+    }
+
+    /// Returns the engine for a collection, or an error if there isn't one,
+    /// or it's closed.
+    pub fn get(&self, collection: &str) -> Result<&dyn SyncEngine> {
+        let engine = self
+            .engines
+            .iter()
+            .find(|e| e.collection_names().iter().any(|name| name == collection))
+            .ok_or_else(|| ErrorKind::UnknownEngine(collection.into()))?;
+        if !engine.is_open() {
+            return Err(ErrorKind::ConnectionClosed(collection.into()).into());
+        }
+        Ok(&**engine)
+    }
+
+    /// Returns the open engines.
+    pub fn open_engines(&self) -> impl Iterator<Item = &dyn SyncEngine> {
+        self.engines.iter().map(|e| &**e).filter(|e| e.is_open())
+    }
+
+    /// Returns the names of all the open engines' collections.
+    pub fn open_collection_names(&self) -> Vec<String> {
+        self.open_engines()
+            .flat_map(|e| e.collection_names())
+            .collect()
+    }
+
+    /// Opens a session for every open engine with collections in
+    /// `collections`, with stores for those collections, and calls `f` with
+    /// all the stores and command handlers.
+    pub fn with_sessions(
+        &self,
+        collections: &[String],
+        interruptee: &SqlInterruptScope,
+        f: &mut dyn FnMut(&[&dyn Store], &[&dyn EngineCommands]) -> Result<()>,
+    ) -> Result<()> {
+        let engines = self
+            .open_engines()
+            .filter(|e| {
+                e.collection_names()
+                    .iter()
+                    .any(|name| collections.contains(name))
+            })
+            .collect::<Vec<_>>();
+        with_sessions(&engines, collections, interruptee, &[], &[], f)
+    }
+}
+
+// Each engine's session only lives as long as the call to its
+// `with_session`, so we open them recursively, and call `f` from the
+// innermost one.
+fn with_sessions(
+    engines: &[&dyn SyncEngine],
+    collections: &[String],
+    interruptee: &SqlInterruptScope,
+    stores: &[&dyn Store],
+    commands: &[&dyn EngineCommands],
+    f: &mut dyn FnMut(&[&dyn Store], &[&dyn EngineCommands]) -> Result<()>,
+) -> Result<()> {
+    let (engine, rest) = match engines.split_first() {
+        Some(split) => split,
+        None => return f(stores, commands),
+    };
+    let names = engine.collection_names();
+    let engine_collections = collections
+        .iter()
+        .filter(|c| names.contains(c))
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    engine.with_session(&engine_collections, interruptee, &mut |session| {
+        let mut all_stores = stores.to_vec();
+        all_stores.extend(session.stores.iter().map(|s| &**s));
+        let mut all_commands = commands.to_vec();
+        all_commands.extend(session.commands.as_ref().map(|c| &**c));
+        with_sessions(
+            rest,
+            collections,
+            interruptee,
+            &all_stores,
+            &all_commands,
+            f,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct TestEngine {
+        names: Vec<&'static str>,
+        open: bool,
+        // The collections each session was opened for.
+        sessions: Arc<Mutex<Vec<Vec<String>>>>,
+    }
+
+    impl TestEngine {
+        fn new(names: &[&'static str], sessions: &Arc<Mutex<Vec<Vec<String>>>>) -> Self {
+            Self {
+                names: names.to_vec(),
+                open: true,
+                sessions: sessions.clone(),
+            }
+        }
+    }
+
+    struct TestCommands;
+
+    impl EngineCommands for TestCommands {
+        fn apply_incoming_command(&self, _command: &Command) -> Result<CommandStatus> {
+            Ok(CommandStatus::Unsupported)
+        }
+    }
+
+    impl SyncEngine for TestEngine {
+        fn collection_names(&self) -> Vec<String> {
+            self.names.iter().map(|name| name.to_string()).collect()
+        }
+
+        fn is_open(&self) -> bool {
+            self.open
+        }
+
+        fn with_session(
+            &self,
+            collections: &[&str],
+            _interruptee: &SqlInterruptScope,
+            f: &mut dyn FnMut(EngineSession<'_>) -> Result<()>,
+        ) -> Result<()> {
+            self.sessions
+                .lock()
+                .unwrap()
+                .push(collections.iter().map(|c| c.to_string()).collect());
+            f(EngineSession {
+                stores: vec![],
+                commands: Some(Box::new(TestCommands)),
+            })
+        }
+
+        fn wipe(&self, _collection: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn reset(&self, _collection: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_register_replaces() {
+        let sessions = Arc::new(Mutex::new(vec![]));
+        let mut registry = EngineRegistry::default();
+        registry.register(Box::new(TestEngine::new(
+            &["history", "bookmarks"],
+            &sessions,
+        )));
+        registry.register(Box::new(TestEngine::new(&["addresses"], &sessions)));
+        registry.register(Box::new(TestEngine::new(&["bookmarks"], &sessions)));
+        assert_eq!(
+            registry.open_collection_names(),
+            vec!["addresses".to_string(), "bookmarks".to_string()]
+        );
+
+        match registry.get("history") {
+            Err(e) => match e.kind() {
+                ErrorKind::UnknownEngine(name) => assert_eq!(name, "history"),
+                kind => panic!("Wrong error: {:?}", kind),
+            },
+            Ok(_) => panic!("Should have replaced the history engine"),
+        }
+
+        let mut closed = TestEngine::new(&["creditcards"], &sessions);
+        closed.open = false;
+        registry.register(Box::new(closed));
+        match registry.get("creditcards") {
+            Err(e) => match e.kind() {
+                ErrorKind::ConnectionClosed(name) => assert_eq!(name, "creditcards"),
+                kind => panic!("Wrong error: {:?}", kind),
+            },
+            Ok(_) => panic!("Closed engines shouldn't be returned"),
+        }
+        assert!(registry.get("addresses").is_ok());
+    }
+
+    #[test]
+    fn test_with_sessions() {
+        let sessions = Arc::new(Mutex::new(vec![]));
+        let mut registry = EngineRegistry::default();
+        registry.register(Box::new(TestEngine::new(
+            &["history", "bookmarks"],
+            &sessions,
+        )));
+        registry.register(Box::new(TestEngine::new(&["addresses"], &sessions)));
+        let mut closed = TestEngine::new(&["creditcards"], &sessions);
+        closed.open = false;
+        registry.register(Box::new(closed));
+
+        let interruptee = SqlInterruptScope::new(Default::default());
+        let mut calls = 0;
+        registry
+            .with_sessions(
+                &["bookmarks".to_string()],
+                &interruptee,
+                &mut |stores, commands| {
+                    calls += 1;
+                    assert!(stores.is_empty());
+                    assert_eq!(commands.len(), 1);
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(calls, 1);
+        // Only engines that are syncing get a session.
+        assert_eq!(
+            *sessions.lock().unwrap(),
+            vec![vec!["bookmarks".to_string()]]
+        );
+    }
+}
@@ -6,6 +6,7 @@ use interrupt::Interrupted;
 use logins;
 use places;
 use sync15;
+use tabs;
 
 #[derive(Debug, Fail)]
 pub enum ErrorKind {
@@ -15,6 +16,8 @@ pub enum ErrorKind {
     UnsupportedFeature(String),
     #[fail(display = "Database connection for '{}' is not open", _0)]
     ConnectionClosed(String),
+    #[fail(display = "Unknown scheduler event: {}", _0)]
+    UnknownSchedulerEvent(i32),
     #[fail(display = "Handle is invalid: {}", _0)]
     InvalidHandle(#[fail(cause)] ffi_support::HandleError),
     #[fail(display = "Protobuf decode error: {}", _0)]
@@ -33,6 +36,13 @@ pub enum ErrorKind {
     LoginsError(#[fail(cause)] logins::Error),
     #[fail(display = "Places error: {}", _0)]
     PlacesError(#[fail(cause)] places::Error),
+    #[fail(display = "Tabs error: {}", _0)]
+    TabsError(#[fail(cause)] tabs::Error),
+    #[fail(display = "Error executing SQL: {}", _0)]
+    SqlError(#[fail(cause)] rusqlite::Error),
+    // Errors from stores and engines registered by other components.
+    #[fail(display = "Engine error: {}", _0)]
+    EngineError(#[fail(cause)] failure::Error),
 }
 
 error_support::define_error! {
@@ -45,5 +55,8 @@ error_support::define_error! {
         (JsonError, serde_json::Error),
         (LoginsError, logins::Error),
         (PlacesError, places::Error),
+        (TabsError, tabs::Error),
+        (SqlError, rusqlite::Error),
+        (EngineError, failure::Error),
     }
 }
@@ -47,3 +47,6 @@ impl From<Error> for ExternError {
 
 ffi_support::implement_into_ffi_by_protobuf!(crate::msg_types::SyncResult);
 ffi_support::implement_into_ffi_by_protobuf!(crate::msg_types::SyncParams);
+ffi_support::implement_into_ffi_by_protobuf!(crate::msg_types::SchedulerDecision);
+ffi_support::implement_into_ffi_by_protobuf!(crate::msg_types::EngineStates);
+ffi_support::implement_into_ffi_by_protobuf!(crate::msg_types::EngineSyncStatuses);
@@ -5,9 +5,13 @@
 #![allow(unknown_lints)]
 #![warn(rust_2018_idioms)]
 
+mod builtin_engines;
+pub mod engine;
 pub mod error;
 mod ffi;
 mod manager;
+pub mod scheduler;
+mod sync_status;
 
 pub use error::{Error, ErrorKind, Result};
 
@@ -15,9 +19,11 @@ pub mod msg_types {
     include!(concat!(env!("OUT_DIR"), "/msg_types.rs"));
 }
 
+use engine::SyncEngine;
 use logins::PasswordEngine;
 use manager::SyncManager;
 use places::PlacesApi;
+use scheduler::{SchedulerDecision, SchedulerEvent, SchedulerPolicy};
 use std::sync::Arc;
 use std::sync::Mutex;
 use tabs::TabsEngine;
@@ -26,6 +32,13 @@ lazy_static::lazy_static! {
     static ref MANAGER: Mutex<SyncManager> = Mutex::new(SyncManager::new());
 }
 
+/// Registers an engine with the sync manager, replacing any engine that was
+/// registered for the same collections.
+pub fn register_engine(engine: Box<dyn SyncEngine>) {
+    let mut manager = MANAGER.lock().unwrap();
+    manager.register_engine(engine);
+}
+
 pub fn set_places(places: Arc<PlacesApi>) {
     let mut manager = MANAGER.lock().unwrap();
     manager.set_places(places);
@@ -46,6 +59,30 @@ pub fn disconnect() {
     manager.disconnect();
 }
 
+pub fn set_engine_enabled(engine: &str, enabled: bool, wipe_local: bool) -> Result<()> {
+    let mut manager = MANAGER.lock().unwrap();
+    manager.set_engine_enabled(engine, enabled, wipe_local)
+}
+
+pub fn get_engine_states(persisted_state: Option<&str>) -> msg_types::EngineStates {
+    let manager = MANAGER.lock().unwrap();
+    msg_types::EngineStates {
+        states: manager.engine_states(persisted_state),
+    }
+}
+
+pub fn open_status_store(path: &str) -> Result<()> {
+    let mut manager = MANAGER.lock().unwrap();
+    manager.open_status_store(path)
+}
+
+pub fn get_sync_status() -> Result<msg_types::EngineSyncStatuses> {
+    let mut manager = MANAGER.lock().unwrap();
+    Ok(msg_types::EngineSyncStatuses {
+        statuses: manager.sync_statuses()?,
+    })
+}
+
 pub fn wipe(engine: &str) -> Result<()> {
     let mut manager = MANAGER.lock().unwrap();
     manager.wipe(engine)
@@ -70,3 +107,18 @@ pub fn sync(params: msg_types::SyncParams) -> Result<msg_types::SyncResult> {
     let mut manager = MANAGER.lock().unwrap();
     manager.sync(params)
 }
+
+pub fn flush_telemetry(why: String) -> Option<String> {
+    let mut manager = MANAGER.lock().unwrap();
+    manager.flush_telemetry(why)
+}
+
+pub fn set_scheduler_policy(policy: SchedulerPolicy) {
+    let mut manager = MANAGER.lock().unwrap();
+    manager.set_scheduler_policy(policy);
+}
+
+pub fn scheduler_event(event: SchedulerEvent) -> SchedulerDecision {
+    let mut manager = MANAGER.lock().unwrap();
+    manager.scheduler_event(event)
+}
@@ -2,27 +2,29 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use crate::builtin_engines::{LoginsEngine, PlacesEngine, TabsSyncEngine, LOGINS_ENGINE};
+use crate::engine::{EngineCommands, EngineRegistry, SyncEngine};
 use crate::error::*;
-use crate::msg_types::{DeviceType, ServiceStatus, SyncParams, SyncReason, SyncResult};
-use crate::{reset, reset_all, wipe, wipe_all};
+use crate::msg_types::{
+    DeviceType, EngineState, EngineSyncRecord, EngineSyncStatus, ServiceStatus, SyncParams,
+    SyncReason, SyncResult,
+};
+use crate::scheduler::{SchedulerDecision, SchedulerEvent, SchedulerPolicy, SyncScheduler};
+use crate::sync_status::SyncStatusStore;
 use logins::PasswordEngine;
-use places::{bookmark_sync::store::BookmarksStore, history_sync::store::HistoryStore, PlacesApi};
-use std::collections::{HashMap, HashSet};
+use places::PlacesApi;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::result;
-use std::sync::{atomic::AtomicUsize, Arc, Mutex, Weak};
+use std::sync::{atomic::AtomicUsize, Arc, Mutex};
 use std::time::SystemTime;
 use sync15::{
     self,
     clients::{self, Command, CommandProcessor, CommandStatus, Settings},
-    MemoryCachedState,
+    telemetry, MemoryCachedState,
 };
 use tabs::TabsEngine;
 
-const LOGINS_ENGINE: &str = "passwords";
-const HISTORY_ENGINE: &str = "history";
-const BOOKMARKS_ENGINE: &str = "bookmarks";
-const TABS_ENGINE: &str = "tabs";
-
 // Casts aren't allowed in `match` arms, so we can't directly match
 // `SyncParams.device_type`, which is an `i32`, against `DeviceType`
 // variants. Instead, we reflect all variants into constants, cast them
@@ -34,175 +36,375 @@ const DEVICE_TYPE_TABLET: i32 = DeviceType::Tablet as i32;
 const DEVICE_TYPE_VR: i32 = DeviceType::Vr as i32;
 const DEVICE_TYPE_TV: i32 = DeviceType::Tv as i32;
 
+// The most syncs and events we queue for `flush_telemetry`, the same limits
+// Desktop uses for its sync ping. If the app doesn't flush often enough, we
+// drop the oldest.
+const MAX_QUEUED_SYNCS: usize = 500;
+const MAX_QUEUED_EVENTS: usize = 1000;
+
 pub struct SyncManager {
     mem_cached_state: Option<MemoryCachedState>,
-    places: Weak<PlacesApi>,
-    logins: Weak<Mutex<PasswordEngine>>,
-    tabs: Weak<Mutex<TabsEngine>>,
+    engines: EngineRegistry,
+    // Telemetry for syncs with `queue_telemetry` set, until it's flushed.
+    // This is only kept in memory, so anything that isn't flushed before the
+    // app exits is lost.
+    queued_telemetry: telemetry::SyncTelemetryPing,
+    scheduler: SyncScheduler,
+    // Changes from `set_engine_enabled` that haven't been synced yet. These
+    // are also saved in the persisted state, so that they're kept across
+    // restarts.
+    pending_engine_changes: HashMap<String, bool>,
+    // The declined engines as of the last sync, if we've synced.
+    declined: Option<Vec<String>>,
+    // Each engine's recent syncs, and when it last synced successfully. Kept
+    // in memory, and lost when the app exits, unless the app opens a database
+    // with `open_status_store`.
+    status_store: Option<SyncStatusStore>,
 }
 
 impl SyncManager {
     pub fn new() -> Self {
         Self {
             mem_cached_state: None,
-            places: Weak::new(),
-            logins: Weak::new(),
-            tabs: Weak::new(),
+            engines: EngineRegistry::default(),
+            queued_telemetry: telemetry::SyncTelemetryPing::new(),
+            scheduler: SyncScheduler::new(SchedulerPolicy::default()),
+            pending_engine_changes: HashMap::new(),
+            declined: None,
+            status_store: None,
         }
     }
 
+    pub fn register_engine(&mut self, engine: Box<dyn SyncEngine>) {
+        self.engines.register(engine);
+    }
+
     pub fn set_places(&mut self, places: Arc<PlacesApi>) {
-        self.places = Arc::downgrade(&places);
+        self.register_engine(Box::new(PlacesEngine::new(&places)));
     }
 
     pub fn set_logins(&mut self, logins: Arc<Mutex<PasswordEngine>>) {
-        self.logins = Arc::downgrade(&logins);
+        self.register_engine(Box::new(LoginsEngine::new(&logins)));
     }
 
     pub fn set_tabs(&mut self, tabs: Arc<Mutex<TabsEngine>>) {
-        self.tabs = Arc::downgrade(&tabs);
+        self.register_engine(Box::new(TabsSyncEngine::new(&tabs)));
     }
 
     pub fn wipe(&mut self, engine: &str) -> Result<()> {
-        match engine {
-            "logins" => {
-                if let Some(logins) = self
-                    .logins
-                    .upgrade()
-                    .as_ref()
-                    .map(|l| l.lock().expect("poisoned logins mutex"))
-                {
-                    logins.wipe()?;
-                    Ok(())
-                } else {
-                    Err(ErrorKind::ConnectionClosed(engine.into()).into())
-                }
-            }
-            "bookmarks" => {
-                if let Some(places) = self.places.upgrade() {
-                    places.wipe_bookmarks()?;
-                    Ok(())
-                } else {
-                    Err(ErrorKind::ConnectionClosed(engine.into()).into())
-                }
-            }
-            "history" => {
-                if let Some(places) = self.places.upgrade() {
-                    places.wipe_history()?;
-                    Ok(())
-                } else {
-                    Err(ErrorKind::ConnectionClosed(engine.into()).into())
-                }
+        let collection = collection_name(engine);
+        self.engines.get(collection)?.wipe(collection)
+    }
+
+    pub fn wipe_all(&mut self) -> Result<()> {
+        for engine in self.engines.open_engines() {
+            for collection in engine.collection_names() {
+                engine.wipe(&collection)?;
             }
-            _ => Err(ErrorKind::UnknownEngine(engine.into()).into()),
         }
+        Ok(())
     }
 
-    pub fn wipe_all(&mut self) -> Result<()> {
-        if let Some(logins) = self
-            .logins
-            .upgrade()
-            .as_ref()
-            .map(|l| l.lock().expect("poisoned logins mutex"))
-        {
-            logins.wipe()?;
+    pub fn reset(&mut self, engine: &str) -> Result<()> {
+        let collection = collection_name(engine);
+        self.engines.get(collection)?.reset(collection)
+    }
+
+    pub fn reset_all(&mut self) -> Result<()> {
+        for engine in self.engines.open_engines() {
+            for collection in engine.collection_names() {
+                engine.reset(&collection)?;
+            }
         }
-        if let Some(places) = self.places.upgrade() {
-            places.wipe_bookmarks()?;
-            places.wipe_history()?;
+        Ok(())
+    }
+
+    /// Enables or declines an engine on every device. The change is uploaded
+    /// by the next sync that isn't read-only, and until then, it's returned
+    /// as pending from `engine_states`, and saved in the persisted state that
+    /// each sync returns, so that it's kept if the app restarts. Apps should
+    /// sync soon after changing an engine, since a change that hasn't been
+    /// through a sync is only kept in memory. If `wipe_local` is set when
+    /// declining, the engine's local data is wiped now.
+    pub fn set_engine_enabled(
+        &mut self,
+        engine: &str,
+        enabled: bool,
+        wipe_local: bool,
+    ) -> Result<()> {
+        let collection = collection_name(engine);
+        if !enabled && wipe_local {
+            self.engines.get(collection)?.wipe(collection)?;
         }
+        self.pending_engine_changes
+            .insert(collection.to_string(), enabled);
         Ok(())
     }
 
-    pub fn reset(&mut self, engine: &str) -> Result<()> {
-        match engine {
-            "logins" => {
-                if let Some(logins) = self
-                    .logins
-                    .upgrade()
-                    .as_ref()
-                    .map(|l| l.lock().expect("poisoned logins mutex"))
-                {
-                    logins.reset()?;
-                    Ok(())
-                } else {
-                    Err(ErrorKind::ConnectionClosed(engine.into()).into())
+    /// Returns the state of every engine that's registered, declined, or has
+    /// a pending change, sorted by name. If we haven't synced since the
+    /// manager was created, the declined engines are read from
+    /// `persisted_state`, as are pending changes made before the app
+    /// restarted.
+    pub fn engine_states(&self, persisted_state: Option<&str>) -> Vec<EngineState> {
+        let declined = self
+            .declined
+            .clone()
+            .or_else(|| persisted_state.and_then(sync15::get_declined_engines));
+        let pending_engine_changes = self.load_pending_engine_changes(persisted_state);
+        let mut names: BTreeSet<String> =
+            self.engines.open_collection_names().into_iter().collect();
+        names.extend(declined.iter().flatten().cloned());
+        names.extend(pending_engine_changes.keys().cloned());
+        names
+            .into_iter()
+            .map(|engine| {
+                let is_declined = declined.as_ref().map(|d| d.contains(&engine));
+                let pending_enabled = pending_engine_changes.get(&engine).copied();
+                EngineState {
+                    // Engines are enabled unless they're declined.
+                    enabled: pending_enabled
+                        .or_else(|| is_declined.map(|d| !d))
+                        .unwrap_or(true),
+                    declined: is_declined,
+                    pending_enabled,
+                    registered: self.engines.get(&engine).is_ok(),
+                    engine,
                 }
+            })
+            .collect()
+    }
+
+    /// Returns our pending engine changes, and the ones saved in
+    /// `persisted_state` that we haven't changed since.
+    fn load_pending_engine_changes(&self, persisted_state: Option<&str>) -> HashMap<String, bool> {
+        let mut changes = persisted_state
+            .map(sync15::get_pending_engine_changes)
+            .unwrap_or_default();
+        changes.extend(
+            self.pending_engine_changes
+                .iter()
+                .map(|(engine, enabled)| (engine.clone(), *enabled)),
+        );
+        changes
+    }
+
+    /// Keeps the sync history in a database at `path`, so that it's kept
+    /// across restarts. Apps that show the sync status must call this before
+    /// syncing; otherwise, the history, including when each engine last
+    /// synced successfully, is kept in memory, and lost when the app exits.
+    pub fn open_status_store(&mut self, path: &str) -> Result<()> {
+        self.status_store = Some(SyncStatusStore::new(path)?);
+        Ok(())
+    }
+
+    fn status_store(&mut self) -> Result<&SyncStatusStore> {
+        if self.status_store.is_none() {
+            self.status_store = Some(SyncStatusStore::new_in_memory()?);
+        }
+        Ok(self.status_store.as_ref().unwrap())
+    }
+
+    pub fn sync_statuses(&mut self) -> Result<Vec<EngineSyncStatus>> {
+        self.status_store()?.get_statuses()
+    }
+
+    pub fn disconnect(&mut self) {
+        self.pending_engine_changes.clear();
+        self.declined = None;
+        if let Some(store) = &self.status_store {
+            if let Err(e) = store.clear() {
+                log::error!("Failed to clear the sync status: {}", e);
             }
-            "bookmarks" | "history" => {
-                if let Some(places) = self.places.upgrade() {
-                    if engine == "bookmarks" {
-                        places.reset_bookmarks()?;
-                    } else {
-                        places.reset_history()?;
-                    }
-                    Ok(())
-                } else {
-                    Err(ErrorKind::ConnectionClosed(engine.into()).into())
+        }
+        let mut reset_any = false;
+        for engine in self.engines.open_engines() {
+            for collection in engine.collection_names() {
+                if let Err(e) = engine.reset(&collection) {
+                    log::error!("Failed to reset {}: {}", collection, e);
                 }
+                reset_any = true;
             }
-            _ => Err(ErrorKind::UnknownEngine(engine.into()).into()),
+        }
+        if !reset_any {
+            log::warn!("No engines to reset, be sure to register them before disconnect if this is surprising");
         }
     }
 
-    pub fn reset_all(&mut self) -> Result<()> {
-        if let Some(logins) = self
-            .logins
-            .upgrade()
-            .as_ref()
-            .map(|l| l.lock().expect("poisoned logins mutex"))
-        {
-            logins.reset()?;
-        }
-        if let Some(places) = self.places.upgrade() {
-            places.reset_bookmarks()?;
-            places.reset_history()?;
+    /// Returns the JSON for a sync ping holding all the telemetry queued
+    /// since the last flush, or `None` if nothing was queued. `why` is the
+    /// reason the app is submitting the ping, like "schedule" or "shutdown".
+    pub fn flush_telemetry(&mut self, why: String) -> Option<String> {
+        if self.queued_telemetry.is_empty() {
+            return None;
         }
-        Ok(())
+        let mut ping = std::mem::replace(
+            &mut self.queued_telemetry,
+            telemetry::SyncTelemetryPing::new(),
+        );
+        ping.why(why);
+        // Unwrap here can never fail -- it indicates trying to serialize an
+        // unserializable type.
+        Some(serde_json::to_string(&ping).unwrap())
     }
 
-    pub fn disconnect(&mut self) {
-        if let Some(logins) = self
-            .logins
-            .upgrade()
-            .as_ref()
-            .map(|l| l.lock().expect("poisoned logins mutex"))
-        {
-            if let Err(e) = logins.reset() {
-                log::error!("Failed to reset logins: {}", e);
-            }
-        } else {
-            log::warn!("Unable to reset logins, be sure to call set_logins before disconnect if this is surprising");
+    fn queue_telemetry(&mut self, params: &mut SyncParams, ping: telemetry::SyncTelemetryPing) {
+        if let Some(device_id) = params.telemetry_device_id.take() {
+            self.queued_telemetry.device_id(device_id);
         }
+        if let Some(name) = params.os_name.take() {
+            self.queued_telemetry.os(telemetry::Os {
+                name,
+                version: params.os_version.take().unwrap_or_default(),
+                locale: params.os_locale.take().unwrap_or_default(),
+            });
+        }
+        self.queued_telemetry.accumulate(ping);
+        let dropped = self
+            .queued_telemetry
+            .drop_oldest(MAX_QUEUED_SYNCS, MAX_QUEUED_EVENTS);
+        if dropped > 0 {
+            log::warn!("Dropped {} queued syncs that weren't flushed", dropped);
+        }
+    }
 
-        if let Some(places) = self.places.upgrade() {
-            if let Err(e) = places.reset_bookmarks() {
-                log::error!("Failed to reset bookmarks: {}", e);
+    /// Records each engine's part in a sync. Engines that didn't get a
+    /// chance to sync because the whole sync failed are recorded with the
+    /// sync's error.
+    fn record_sync_status(
+        &mut self,
+        started_at: i64,
+        collections: &[String],
+        result: &sync15::SyncResult,
+    ) -> Result<()> {
+        let engine_telemetry = result
+            .telemetry
+            .get_syncs()
+            .last()
+            .map(|sync| sync.get_engines())
+            .unwrap_or_default();
+        let mut records = Vec::with_capacity(collections.len());
+        for (name, engine_result) in &result.engine_results {
+            let mut record = match engine_telemetry.iter().find(|e| e.get_name() == name) {
+                Some(engine) => {
+                    let incoming = engine.get_incoming();
+                    let outgoing = engine.get_outgoing();
+                    EngineSyncRecord {
+                        started_at,
+                        took: engine.get_took() as i64,
+                        status: ServiceStatus::Ok as i32,
+                        error: None,
+                        applied: incoming.map(|i| i64::from(i.get_applied())).unwrap_or(0),
+                        incoming_failed: incoming.map(|i| i64::from(i.get_failed())).unwrap_or(0),
+                        uploaded: outgoing.get_sent() as i64,
+                        outgoing_failed: outgoing.get_failed() as i64,
+                    }
+                }
+                None => empty_sync_record(started_at, ServiceStatus::Ok, None),
+            };
+            if let Err(e) = engine_result {
+                record.status = ServiceStatus::from(sync15::ServiceStatus::from_err(e)) as i32;
+                record.error = Some(e.to_string());
             }
-            if let Err(e) = places.reset_history() {
-                log::error!("Failed to reset history: {}", e);
+            records.push((name.clone(), record));
+        }
+        if let Err(e) = &result.result {
+            for collection in collections {
+                let name = collection_name(collection);
+                if !result.engine_results.contains_key(name) {
+                    records.push((
+                        name.to_string(),
+                        empty_sync_record(
+                            started_at,
+                            ServiceStatus::from(result.service_status.clone()),
+                            Some(e.to_string()),
+                        ),
+                    ));
+                }
             }
-        } else {
-            log::warn!("Unable to reset places, be sure to call set_places before disconnect if this is surprising");
         }
+        let store = self.status_store()?;
+        for (engine, record) in records {
+            store.record(&engine, &record)?;
+        }
+        Ok(())
     }
 
-    pub fn sync(&mut self, params: SyncParams) -> Result<SyncResult> {
-        let mut have_engines = vec![];
-        let places = self.places.upgrade();
-        let tabs = self.tabs.upgrade();
-        let logins = self.logins.upgrade();
-        if places.is_some() {
-            have_engines.push(HISTORY_ENGINE);
-            have_engines.push(BOOKMARKS_ENGINE);
+    /// Records a sync that failed before any engines synced, like one with a
+    /// bad sync key or token server URL, for each engine it was for.
+    fn record_sync_failure(
+        &mut self,
+        started_at: i64,
+        collections: &[String],
+        error: &Error,
+    ) -> Result<()> {
+        let status = service_status_for_error(error);
+        let store = self.status_store()?;
+        for collection in collections {
+            store.record(
+                collection_name(collection),
+                &empty_sync_record(started_at, status, Some(error.to_string())),
+            )?;
         }
-        if logins.is_some() {
-            have_engines.push(LOGINS_ENGINE);
-        }
-        if tabs.is_some() {
-            have_engines.push(TABS_ENGINE);
+        Ok(())
+    }
+
+    pub fn set_scheduler_policy(&mut self, policy: SchedulerPolicy) {
+        self.scheduler.set_policy(policy);
+    }
+
+    pub fn scheduler_event(&mut self, event: SchedulerEvent) -> SchedulerDecision {
+        self.scheduler.handle_event(event, SystemTime::now())
+    }
+
+    pub fn sync(&mut self, params: SyncParams) -> Result<SyncResult> {
+        let started_at = SystemTime::now();
+        let collections = if params.sync_all_engines {
+            self.engines.open_collection_names()
+        } else {
+            params.engines_to_sync.clone()
+        };
+        let result = self.sync_without_scheduling(params);
+        let (status, next_sync_allowed_at) = match &result {
+            Ok(result) => (
+                ServiceStatus::from_i32(result.status).unwrap_or(ServiceStatus::OtherError),
+                result
+                    .next_sync_allowed_at
+                    .map(|ms| std::time::UNIX_EPOCH + std::time::Duration::from_millis(ms as u64)),
+            ),
+            // The scheduler still needs to know the sync finished, so that it
+            // retries network errors, and doesn't wait for the sync forever.
+            Err(e) => {
+                let started_at = system_time_to_millis(Some(started_at)).unwrap_or_default();
+                if let Err(e) = self.record_sync_failure(started_at, &collections, e) {
+                    log::error!("Failed to record the sync status: {}", e);
+                }
+                (service_status_for_error(e), None)
+            }
+        };
+        self.scheduler.handle_event(
+            SchedulerEvent::SyncFinished {
+                status,
+                next_sync_allowed_at,
+                started_at,
+            },
+            SystemTime::now(),
+        );
+        result
+    }
+
+    fn sync_without_scheduling(&mut self, mut params: SyncParams) -> Result<SyncResult> {
+        check_engine_list(&params.engines_to_sync, &self.engines)?;
+        // Pick up changes that a sync before the app restarted didn't upload.
+        self.pending_engine_changes =
+            self.load_pending_engine_changes(params.persisted_state.as_deref());
+        // Changes passed in the params win over ones from `set_engine_enabled`.
+        for (engine, enabled) in &self.pending_engine_changes {
+            params
+                .engines_to_change_state
+                .entry(engine.clone())
+                .or_insert(*enabled);
         }
-        check_engine_list(&params.engines_to_sync, &have_engines)?;
 
         let next_sync_after = self
             .mem_cached_state
@@ -223,44 +425,27 @@ impl SyncManager {
                 have_declined: false,
                 declined: vec![],
                 next_sync_allowed_at: ts,
-                persisted_state: params.persisted_state.unwrap_or_default(),
+                persisted_state: sync15::set_pending_engine_changes(
+                    params.persisted_state.as_deref(),
+                    &self.pending_engine_changes,
+                ),
                 // It would be nice to record telemetry here.
                 telemetry_json: None,
+                quota_remaining: None,
+                remote_engine_changes: HashMap::new(),
             })
         }
     }
 
     fn do_sync(&mut self, mut params: SyncParams) -> Result<SyncResult> {
-        let mut places = self.places.upgrade();
-        let logins = self.logins.upgrade();
-        let tabs = self.tabs.upgrade();
-
+        let started_at = system_time_to_millis(Some(SystemTime::now())).unwrap_or_default();
         let key_bundle = sync15::KeyBundle::from_ksync_base64(&params.acct_sync_key)?;
         let tokenserver_url = url::Url::parse(&params.acct_tokenserver_url)?;
 
-        let bookmarks_sync = should_sync(&params, BOOKMARKS_ENGINE) && places.is_some();
-        let history_sync = should_sync(&params, HISTORY_ENGINE) && places.is_some();
-        let logins_sync = should_sync(&params, LOGINS_ENGINE) && logins.is_some();
-        let tabs_sync = should_sync(&params, TABS_ENGINE) && tabs.is_some();
-
-        let places_conn = if bookmarks_sync || history_sync {
-            places
-                .as_mut()
-                .expect("trying to sync an engine that has not been configured")
-                .open_sync_connection()
-                .ok()
-        } else {
-            None
-        };
-        let l = if logins_sync {
-            logins.as_ref().map(|l| l.lock().expect("poisoned mutex"))
-        } else {
-            None
-        };
-        let t = if tabs_sync {
-            tabs.as_ref().map(|t| t.lock().expect("poisoned mutex"))
+        let collections = if params.sync_all_engines {
+            self.engines.open_collection_names()
         } else {
-            None
+            params.engines_to_sync.clone()
         };
 
         // TODO(issue 1684) this isn't ideal, we should have real support for interruption.
@@ -269,34 +454,6 @@ impl SyncManager {
 
         let mut mem_cached_state = self.mem_cached_state.take().unwrap_or_default();
         let mut disk_cached_state = params.persisted_state.take();
-        // `sync_multiple` takes a &[&dyn Store], but we need something to hold
-        // ownership of our stores.
-        let mut stores: Vec<Box<dyn sync15::Store>> = vec![];
-
-        if let Some(pc) = places_conn.as_ref() {
-            assert!(
-                history_sync || bookmarks_sync,
-                "Should have already checked"
-            );
-            if history_sync {
-                stores.push(Box::new(HistoryStore::new(pc, &interruptee)))
-            }
-            if bookmarks_sync {
-                stores.push(Box::new(BookmarksStore::new(pc, &interruptee)))
-            }
-        }
-
-        if let Some(le) = l.as_ref() {
-            assert!(logins_sync, "Should have already checked");
-            stores.push(Box::new(logins::LoginStore::new(&le.db)));
-        }
-
-        if let Some(tbs) = t.as_ref() {
-            assert!(tabs_sync, "Should have already checked");
-            stores.push(Box::new(tabs::TabsStore::new(&tbs.storage)));
-        }
-
-        let store_refs: Vec<&dyn sync15::Store> = stores.iter().map(|s| &**s).collect();
 
         let client_init = sync15::Sync15StorageClientInit {
             key_id: params.acct_key_id.clone(),
@@ -310,8 +467,8 @@ impl SyncManager {
         };
 
         let settings = Settings {
-            fxa_device_id: params.fxa_device_id,
-            device_name: params.device_name,
+            fxa_device_id: params.fxa_device_id.clone(),
+            device_name: params.device_name.clone(),
             device_type: match params.device_type {
                 DEVICE_TYPE_DESKTOP => clients::DeviceType::Desktop,
                 DEVICE_TYPE_MOBILE => clients::DeviceType::Mobile,
@@ -327,22 +484,58 @@ impl SyncManager {
                 }
             },
         };
-        let c = SyncClient::new(settings);
-        let result = sync15::sync_multiple_with_command_processor(
-            Some(&c),
-            &store_refs,
-            &mut disk_cached_state,
-            &mut mem_cached_state,
-            &client_init,
-            &key_bundle,
-            &interruptee,
-            Some(sync15::SyncRequestInfo {
-                engines_to_state_change: engines_to_change,
-                is_user_action: params.reason == (SyncReason::User as i32),
-            }),
-        );
+        let engines = &self.engines;
+        let mut result = None;
+        let mut deferred = vec![];
+        engines.with_sessions(&collections, &interruptee, &mut |stores, commands| {
+            let c = SyncClient::new(settings.clone(), engines, commands);
+            result = Some(sync15::sync_multiple_with_command_processor(
+                Some(&c),
+                stores,
+                &mut disk_cached_state,
+                &mut mem_cached_state,
+                &client_init,
+                &key_bundle,
+                &interruptee,
+                Some(sync15::SyncRequestInfo {
+                    engines_to_state_change: engines_to_change,
+                    is_user_action: params.reason == (SyncReason::User as i32),
+                    readonly: params.readonly.unwrap_or(false),
+                    rotate_keys: if params.rotate_keys.unwrap_or(false) {
+                        Some(params.per_collection_keys.as_slice())
+                    } else {
+                        None
+                    },
+                }),
+            ));
+            deferred = c.deferred_commands.into_inner();
+            Ok(())
+        })?;
+        // The sessions hold the engines' connections and locks, so we wait
+        // until they're closed to wipe or reset the collections for other
+        // clients that the engines didn't apply during the sync.
+        for command in deferred {
+            if let Err(e) = apply_engine_command(engines, &command) {
+                log::error!("Failed to apply {:?}: {}", command, e);
+            }
+        }
+        let result = result.expect("sessions should have called us");
 
         log::info!("Sync finished with status {:?}", result.service_status);
+        if result.result.is_ok() && !params.readonly.unwrap_or(false) {
+            // The state machine uploaded our engine changes.
+            self.pending_engine_changes.clear();
+        }
+        let disk_cached_state = sync15::set_pending_engine_changes(
+            disk_cached_state.as_deref(),
+            &self.pending_engine_changes,
+        );
+        if result.declined.is_some() {
+            self.declined = result.declined.clone();
+        }
+        if let Err(e) = self.record_sync_status(started_at, &collections, &result) {
+            log::error!("Failed to record the sync status: {}", e);
+        }
         let status = ServiceStatus::from(result.service_status) as i32;
         let results: HashMap<String, String> = result
             .engine_results
@@ -376,6 +569,9 @@ impl SyncManager {
         // Unwrap here can never fail -- it indicates trying to serialize an
         // unserializable type.
         let telemetry_json = serde_json::to_string(&result.telemetry).unwrap();
+        if params.queue_telemetry.unwrap_or(false) {
+            self.queue_telemetry(&mut params, result.telemetry);
+        }
 
         Ok(SyncResult {
             status,
@@ -383,8 +579,10 @@ impl SyncManager {
             have_declined: result.declined.is_some(),
             declined: result.declined.unwrap_or_default(),
             next_sync_allowed_at: system_time_to_millis(result.next_sync_after),
-            persisted_state: disk_cached_state.unwrap_or_default(),
+            persisted_state: disk_cached_state,
             telemetry_json: Some(telemetry_json),
+            quota_remaining: result.quota_remaining.map(|q| q as i64),
+            remote_engine_changes: result.remote_engine_changes,
         })
     }
 }
@@ -428,73 +626,371 @@ impl From<sync15::ServiceStatus> for ServiceStatus {
             AuthenticationError => ServiceStatus::AuthError,
             BackedOff => ServiceStatus::BackedOff,
             Interrupted => ServiceStatus::OtherError, // Eh...
+            OverQuota => ServiceStatus::OverQuota,
             OtherError => ServiceStatus::OtherError,
         }
     }
 }
 
-fn system_time_to_millis(st: Option<SystemTime>) -> Option<i64> {
+fn service_status_for_error(e: &Error) -> ServiceStatus {
+    match e.kind() {
+        ErrorKind::Sync15Error(e) => ServiceStatus::from(sync15::ServiceStatus::from_err(e)),
+        _ => ServiceStatus::OtherError,
+    }
+}
+
+pub(crate) fn system_time_to_millis(st: Option<SystemTime>) -> Option<i64> {
     use std::convert::TryFrom;
     let d = st?.duration_since(std::time::UNIX_EPOCH).ok()?;
     // This should always succeed for remotely sane values.
     i64::try_from(d.as_secs() * 1_000 + u64::from(d.subsec_nanos()) / 1_000_000).ok()
 }
 
-fn should_sync(p: &SyncParams, engine: &str) -> bool {
-    p.sync_all_engines || p.engines_to_sync.iter().any(|e| e == engine)
+fn empty_sync_record(
+    started_at: i64,
+    status: ServiceStatus,
+    error: Option<String>,
+) -> EngineSyncRecord {
+    EngineSyncRecord {
+        started_at,
+        took: 0,
+        status: status as i32,
+        error,
+        applied: 0,
+        incoming_failed: 0,
+        uploaded: 0,
+        outgoing_failed: 0,
+    }
+}
+
+// `logins` is what the manager's callers have always called the `passwords`
+// collection.
+fn collection_name(engine: &str) -> &str {
+    if engine == "logins" {
+        LOGINS_ENGINE
+    } else {
+        engine
+    }
 }
 
-fn check_engine_list(list: &[String], have_engines: &[&str]) -> Result<()> {
+fn check_engine_list(list: &[String], engines: &EngineRegistry) -> Result<()> {
     log::trace!(
         "Checking engines requested ({:?}) vs local engines ({:?})",
         list,
-        have_engines
+        engines.open_collection_names()
     );
     for e in list {
-        if [BOOKMARKS_ENGINE, HISTORY_ENGINE, LOGINS_ENGINE, TABS_ENGINE].contains(&e.as_ref()) {
-            if !have_engines.iter().any(|engine| e == engine) {
-                return Err(ErrorKind::UnsupportedFeature(e.to_string()).into());
+        if let Err(err) = engines.get(e) {
+            if let ErrorKind::ConnectionClosed(_) = err.kind() {
+                return Err(ErrorKind::UnsupportedFeature(e.clone()).into());
             }
-        } else {
-            return Err(ErrorKind::UnknownEngine(e.to_string()).into());
+            return Err(err);
         }
     }
     Ok(())
 }
 
-struct SyncClient(Settings);
+// Wipes or resets a collection for another client. `SyncClient` only
+// defers `Wipe` and `Reset` commands for collections we have.
+fn apply_engine_command(engines: &EngineRegistry, command: &Command) -> Result<()> {
+    match command {
+        Command::Wipe(collection) => engines.get(collection)?.wipe(collection)?,
+        Command::Reset(collection) => engines.get(collection)?.reset(collection)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+struct SyncClient<'a> {
+    settings: Settings,
+    engines: &'a EngineRegistry,
+    commands: &'a [&'a dyn EngineCommands],
+    // Wipe and reset commands that the engines didn't apply during the
+    // sync, which we apply after it, once the engines are free.
+    deferred_commands: RefCell<Vec<Command>>,
+}
+
+impl<'a> SyncClient<'a> {
+    pub fn new(
+        settings: Settings,
+        engines: &'a EngineRegistry,
+        commands: &'a [&'a dyn EngineCommands],
+    ) -> SyncClient<'a> {
+        SyncClient {
+            settings,
+            engines,
+            commands,
+            deferred_commands: RefCell::new(vec![]),
+        }
+    }
 
-impl SyncClient {
-    pub fn new(settings: Settings) -> SyncClient {
-        SyncClient(settings)
+    // Offers a command to the engines, and returns the first status that
+    // isn't `Unsupported`.
+    fn apply_to_engines(&self, command: &Command) -> result::Result<CommandStatus, failure::Error> {
+        for handler in self.commands {
+            match handler.apply_incoming_command(command)? {
+                CommandStatus::Unsupported => continue,
+                status => return Ok(status),
+            }
+        }
+        Ok(CommandStatus::Unsupported)
+    }
+
+    // Engines wipe and reset the collections they're syncing right away, so
+    // that the collections sync afterward. The rest wait until the sync
+    // finishes.
+    fn wipe_or_reset(&self, command: Command) -> result::Result<(), failure::Error> {
+        if let CommandStatus::Unsupported = self.apply_to_engines(&command)? {
+            self.deferred_commands.borrow_mut().push(command);
+        }
+        Ok(())
     }
 }
 
-impl CommandProcessor for SyncClient {
+impl<'a> CommandProcessor for SyncClient<'a> {
     fn settings(&self) -> &Settings {
-        &self.0
+        &self.settings
     }
 
     fn apply_incoming_command(
         &self,
         command: Command,
     ) -> result::Result<CommandStatus, failure::Error> {
-        let result = match command {
-            Command::Wipe(engine) => wipe(&engine),
-            Command::WipeAll => wipe_all(),
-            Command::Reset(engine) => reset(&engine),
-            Command::ResetAll => reset_all(),
-        };
-        match result {
-            Ok(()) => Ok(CommandStatus::Applied),
-            Err(err) => match err.kind() {
-                ErrorKind::UnknownEngine(_) => Ok(CommandStatus::Unsupported),
-                _ => Err(err.into()),
-            },
+        match command {
+            Command::Wipe(ref collection) | Command::Reset(ref collection)
+                if self.engines.get(collection).is_err() =>
+            {
+                // Leave it for a client that has the engine.
+                Ok(CommandStatus::Unsupported)
+            }
+            Command::Wipe(_) | Command::Reset(_) => {
+                self.wipe_or_reset(command)?;
+                Ok(CommandStatus::Applied)
+            }
+            Command::WipeAll | Command::ResetAll => {
+                for collection in self.engines.open_collection_names() {
+                    self.wipe_or_reset(if command == Command::WipeAll {
+                        Command::Wipe(collection)
+                    } else {
+                        Command::Reset(collection)
+                    })?;
+                }
+                Ok(CommandStatus::Applied)
+            }
+            // If no engine knows it, leave it for when an engine that does is
+            // registered, or syncs.
+            _ => self.apply_to_engines(&command),
         }
     }
 
     fn fetch_outgoing_commands(&self) -> result::Result<HashSet<Command>, failure::Error> {
         Ok(HashSet::new())
     }
+
+    fn fetch_outgoing_commands_for_client(
+        &self,
+        client_id: &str,
+        current_commands: &HashSet<Command>,
+    ) -> result::Result<HashSet<Command>, failure::Error> {
+        let mut outgoing = HashSet::new();
+        for handler in self.commands {
+            outgoing.extend(handler.fetch_outgoing_commands_for_client(
+                &self.settings.fxa_device_id,
+                client_id,
+                current_commands,
+            )?);
+        }
+        Ok(outgoing)
+    }
+
+    fn commands_sent(
+        &self,
+        client_id: &str,
+        commands: &[Command],
+    ) -> result::Result<(), failure::Error> {
+        for handler in self.commands {
+            handler.commands_sent(&self.settings.fxa_device_id, client_id, commands)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engine_states() {
+        let mut manager = SyncManager::new();
+        // We haven't synced, and don't know the declined engines.
+        assert!(manager.engine_states(None).is_empty());
+
+        let persisted = r#"{"schema_version":"V2","declined":["tabs","history"]}"#;
+        manager.set_engine_enabled("history", true, false).unwrap();
+        manager.set_engine_enabled("logins", false, false).unwrap();
+        let states = manager.engine_states(Some(persisted));
+        let summary = states
+            .iter()
+            .map(|s| {
+                (
+                    s.engine.as_str(),
+                    s.enabled,
+                    s.declined,
+                    s.pending_enabled,
+                    s.registered,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("history", true, Some(true), Some(true), false),
+                ("passwords", false, Some(false), Some(false), false),
+                ("tabs", false, Some(true), None, false),
+            ]
+        );
+
+        manager.disconnect();
+        assert!(manager.engine_states(None).is_empty());
+    }
+
+    #[test]
+    fn test_persisted_engine_changes() {
+        let mut changes = HashMap::new();
+        changes.insert("bookmarks".to_string(), false);
+        changes.insert("history".to_string(), false);
+        let persisted = sync15::set_pending_engine_changes(None, &changes);
+
+        // A new manager, as if the app restarted before syncing our changes,
+        // picks them up from the persisted state, and ours win.
+        let mut manager = SyncManager::new();
+        manager.set_engine_enabled("history", true, false).unwrap();
+        let pending = manager
+            .engine_states(Some(&persisted))
+            .into_iter()
+            .map(|s| (s.engine, s.pending_enabled))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pending,
+            vec![
+                ("bookmarks".to_string(), Some(false)),
+                ("history".to_string(), Some(true)),
+            ]
+        );
+        assert_eq!(
+            manager.load_pending_engine_changes(Some(&persisted)).len(),
+            2
+        );
+    }
+
+    struct TestEngine(Vec<&'static str>);
+
+    impl SyncEngine for TestEngine {
+        fn collection_names(&self) -> Vec<String> {
+            self.0.iter().map(|name| name.to_string()).collect()
+        }
+
+        fn is_open(&self) -> bool {
+            true
+        }
+
+        fn with_session(
+            &self,
+            _collections: &[&str],
+            _interruptee: &sql_support::SqlInterruptScope,
+            f: &mut dyn FnMut(crate::engine::EngineSession<'_>) -> Result<()>,
+        ) -> Result<()> {
+            f(Default::default())
+        }
+
+        fn wipe(&self, _collection: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn reset(&self, _collection: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // Wipes history, as if we were syncing it.
+    struct SyncingHistory(RefCell<Vec<Command>>);
+
+    impl EngineCommands for SyncingHistory {
+        fn apply_incoming_command(&self, command: &Command) -> Result<CommandStatus> {
+            Ok(match command {
+                Command::Wipe(collection) if collection == "history" => {
+                    self.0.borrow_mut().push(command.clone());
+                    CommandStatus::Applied
+                }
+                _ => CommandStatus::Unsupported,
+            })
+        }
+    }
+
+    #[test]
+    fn test_records_early_failures() {
+        let mut manager = SyncManager::new();
+        manager.register_engine(Box::new(TestEngine(vec!["history"])));
+        let params = SyncParams {
+            engines_to_sync: vec!["history".to_string()],
+            acct_sync_key: "not a key".to_string(),
+            ..SyncParams::default()
+        };
+        manager
+            .sync(params)
+            .expect_err("shouldn't sync with a bad key");
+
+        let statuses = manager.sync_statuses().unwrap();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].engine, "history");
+        assert_eq!(statuses[0].last_success_at, None);
+        assert_eq!(statuses[0].consecutive_failures, 1);
+        assert!(statuses[0].history[0].error.is_some());
+    }
+
+    #[test]
+    fn test_wipe_commands() {
+        let mut engines = EngineRegistry::default();
+        engines.register(Box::new(TestEngine(vec!["history", "bookmarks"])));
+        engines.register(Box::new(TestEngine(vec!["passwords"])));
+        let history = SyncingHistory(RefCell::new(vec![]));
+        let commands: Vec<&dyn EngineCommands> = vec![&history];
+        let client = SyncClient::new(
+            Settings {
+                fxa_device_id: "device".into(),
+                device_name: "Device".into(),
+                device_type: clients::DeviceType::Mobile,
+            },
+            &engines,
+            &commands,
+        );
+
+        assert_eq!(
+            client
+                .apply_incoming_command(Command::Wipe("tabs".into()))
+                .unwrap(),
+            CommandStatus::Unsupported
+        );
+        assert_eq!(
+            client.apply_incoming_command(Command::WipeAll).unwrap(),
+            CommandStatus::Applied
+        );
+        assert_eq!(
+            client
+                .apply_incoming_command(Command::Reset("history".into()))
+                .unwrap(),
+            CommandStatus::Applied
+        );
+        // History is syncing, so it's wiped before it syncs, and the rest
+        // are wiped or reset after the sync.
+        assert_eq!(*history.0.borrow(), vec![Command::Wipe("history".into())]);
+        assert_eq!(
+            client.deferred_commands.into_inner(),
+            vec![
+                Command::Wipe("bookmarks".into()),
+                Command::Wipe("passwords".into()),
+                Command::Reset("history".into()),
+            ]
+        );
+    }
 }
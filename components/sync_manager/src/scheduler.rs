@@ -0,0 +1,561 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Decides when to sync, based on the app's lifecycle, local changes, and
+//! the results of previous syncs. Apps tell the scheduler what happened, and
+//! it tells them whether to sync now, or when to ask again. The scheduler
+//! doesn't have a timer of its own.
+
+use crate::error::*;
+use crate::msg_types::{self, SchedulerEventType, ServiceStatus, SyncReason};
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime};
+
+/// How often, and how eagerly, to sync. The defaults are used for any
+/// fields the app doesn't set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SchedulerPolicy {
+    /// How long to wait between syncs while the app is in the foreground.
+    pub foreground_interval: Duration,
+    /// How long to wait between syncs while the app is in the background,
+    /// or `None` to only sync in the foreground.
+    pub background_interval: Option<Duration>,
+    /// How long to wait for more local changes before syncing them.
+    pub change_delay: Duration,
+    /// The longest we'll put off syncing local changes, even if more keep
+    /// coming in.
+    pub max_change_delay: Duration,
+    /// How long to wait before retrying after a network or server error.
+    /// This doubles for each failure in a row, up to `max_retry_delay`.
+    pub min_retry_delay: Duration,
+    pub max_retry_delay: Duration,
+    /// Whether to sync unsynced local changes when the app goes to the
+    /// background.
+    pub sync_before_sleep: bool,
+}
+
+impl Default for SchedulerPolicy {
+    fn default() -> Self {
+        Self {
+            foreground_interval: Duration::from_secs(60 * 60),
+            background_interval: None,
+            change_delay: Duration::from_secs(10),
+            max_change_delay: Duration::from_secs(60),
+            min_retry_delay: Duration::from_secs(30),
+            max_retry_delay: Duration::from_secs(30 * 60),
+            sync_before_sleep: true,
+        }
+    }
+}
+
+fn millis_to_duration(ms: i64) -> Duration {
+    Duration::from_millis(u64::try_from(ms).unwrap_or_default())
+}
+
+impl From<msg_types::SchedulerPolicy> for SchedulerPolicy {
+    fn from(msg: msg_types::SchedulerPolicy) -> Self {
+        let default = SchedulerPolicy::default();
+        Self {
+            foreground_interval: msg
+                .foreground_interval
+                .map_or(default.foreground_interval, millis_to_duration),
+            background_interval: msg
+                .background_interval
+                .filter(|&ms| ms > 0)
+                .map(millis_to_duration),
+            change_delay: msg
+                .change_delay
+                .map_or(default.change_delay, millis_to_duration),
+            max_change_delay: msg
+                .max_change_delay
+                .map_or(default.max_change_delay, millis_to_duration),
+            min_retry_delay: msg
+                .min_retry_delay
+                .map_or(default.min_retry_delay, millis_to_duration),
+            max_retry_delay: msg
+                .max_retry_delay
+                .map_or(default.max_retry_delay, millis_to_duration),
+            sync_before_sleep: msg.sync_before_sleep.unwrap_or(default.sync_before_sleep),
+        }
+    }
+}
+
+/// Something that happened, which might change when we should sync.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SchedulerEvent {
+    /// The app started, and is in the foreground.
+    Startup,
+    Foreground,
+    Background,
+    /// An engine's local data changed.
+    LocalChange {
+        engine: String,
+    },
+    /// The user asked to sync.
+    UserRequested,
+    /// The user enabled or disabled an engine.
+    EnabledChange,
+    /// A sync finished. The sync manager reports this itself, so apps don't
+    /// need to. Only local changes from before `started_at` were synced.
+    SyncFinished {
+        status: ServiceStatus,
+        next_sync_allowed_at: Option<SystemTime>,
+        started_at: SystemTime,
+    },
+}
+
+impl TryFrom<msg_types::SchedulerEvent> for SchedulerEvent {
+    type Error = Error;
+    fn try_from(msg: msg_types::SchedulerEvent) -> Result<Self> {
+        Ok(match SchedulerEventType::from_i32(msg.event_type) {
+            Some(SchedulerEventType::Startup) => SchedulerEvent::Startup,
+            Some(SchedulerEventType::Foreground) => SchedulerEvent::Foreground,
+            Some(SchedulerEventType::Background) => SchedulerEvent::Background,
+            Some(SchedulerEventType::LocalChange) => SchedulerEvent::LocalChange {
+                engine: msg.engine.unwrap_or_default(),
+            },
+            Some(SchedulerEventType::UserRequested) => SchedulerEvent::UserRequested,
+            Some(SchedulerEventType::EnabledChange) => SchedulerEvent::EnabledChange,
+            None => return Err(ErrorKind::UnknownSchedulerEvent(msg.event_type).into()),
+        })
+    }
+}
+
+/// What the app should do next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SchedulerDecision {
+    /// Sync now, for this reason.
+    SyncNow(SyncReason),
+    /// Ask again at this time, unless something else happens first.
+    SyncAt(SystemTime),
+    /// Don't sync until something happens, like the user asking to sync
+    /// after fixing an authentication error.
+    Wait,
+}
+
+impl From<SchedulerDecision> for msg_types::SchedulerDecision {
+    fn from(decision: SchedulerDecision) -> Self {
+        match decision {
+            SchedulerDecision::SyncNow(reason) => msg_types::SchedulerDecision {
+                sync_now: true,
+                reason: Some(reason as i32),
+                next_sync_at: None,
+            },
+            SchedulerDecision::SyncAt(at) => msg_types::SchedulerDecision {
+                sync_now: false,
+                reason: None,
+                next_sync_at: crate::manager::system_time_to_millis(Some(at)),
+            },
+            SchedulerDecision::Wait => msg_types::SchedulerDecision {
+                sync_now: false,
+                reason: None,
+                next_sync_at: None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncScheduler {
+    policy: SchedulerPolicy,
+    in_foreground: bool,
+    // A sync that should happen as soon as possible, like one the user asked
+    // for. Cleared when any sync finishes.
+    requested: Option<SyncReason>,
+    last_sync_at: Option<SystemTime>,
+    // Network and server errors in a row.
+    failures: u32,
+    // Set after an auth error, until the user asks to sync.
+    needs_auth: bool,
+    // When the first and the latest unsynced local changes happened.
+    first_change_at: Option<SystemTime>,
+    last_change_at: Option<SystemTime>,
+    backoff_until: Option<SystemTime>,
+}
+
+impl SyncScheduler {
+    pub fn new(policy: SchedulerPolicy) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: SchedulerPolicy) {
+        self.policy = policy;
+    }
+
+    /// Records an event, and returns what to do next.
+    pub fn handle_event(&mut self, event: SchedulerEvent, now: SystemTime) -> SchedulerDecision {
+        log::debug!("Scheduler event: {:?}", event);
+        match event {
+            SchedulerEvent::Startup => {
+                self.in_foreground = true;
+                self.requested = Some(SyncReason::Startup);
+            }
+            SchedulerEvent::Foreground => self.in_foreground = true,
+            SchedulerEvent::Background => {
+                self.in_foreground = false;
+                if self.policy.sync_before_sleep && self.first_change_at.is_some() {
+                    self.requested = Some(SyncReason::PreSleep);
+                }
+            }
+            SchedulerEvent::LocalChange { .. } => {
+                self.first_change_at.get_or_insert(now);
+                self.last_change_at = Some(now);
+            }
+            SchedulerEvent::UserRequested => {
+                self.needs_auth = false;
+                self.requested = Some(SyncReason::User);
+            }
+            SchedulerEvent::EnabledChange => self.requested = Some(SyncReason::EnabledChange),
+            SchedulerEvent::SyncFinished {
+                status,
+                next_sync_allowed_at,
+                started_at,
+            } => {
+                self.requested = None;
+                self.last_sync_at = Some(now);
+                self.backoff_until = next_sync_allowed_at;
+                match status {
+                    ServiceStatus::NetworkError | ServiceStatus::ServiceError => {
+                        self.failures += 1;
+                    }
+                    ServiceStatus::AuthError => self.needs_auth = true,
+                    // We didn't sync, but the backoff time tells us when
+                    // to try again.
+                    ServiceStatus::BackedOff => {}
+                    ServiceStatus::Ok | ServiceStatus::OtherError | ServiceStatus::OverQuota => {
+                        // Other errors are unlikely to go away by retrying
+                        // sooner, so we wait for the next regular sync, and
+                        // don't keep syncing the same local changes.
+                        self.failures = 0;
+                        self.forget_changes_before(started_at);
+                    }
+                }
+            }
+        }
+        self.decide(now)
+    }
+
+    /// Returns what to do next, without recording an event.
+    pub fn decide(&self, now: SystemTime) -> SchedulerDecision {
+        // The sync manager ignores backoff for these, so we do too.
+        if let Some(reason @ SyncReason::User) | Some(reason @ SyncReason::EnabledChange) =
+            self.requested
+        {
+            return SchedulerDecision::SyncNow(reason);
+        }
+        if self.needs_auth {
+            return SchedulerDecision::Wait;
+        }
+        let next = if let Some(reason) = self.requested {
+            Some((now, reason))
+        } else if self.failures > 0 {
+            self.last_sync_at
+                .map(|at| (at + self.retry_delay(), SyncReason::Scheduled))
+        } else {
+            self.next_scheduled_sync(now)
+        };
+        let (at, reason) = match next {
+            Some(next) => next,
+            None => return SchedulerDecision::Wait,
+        };
+        let at = match self.backoff_until {
+            Some(backoff_until) if backoff_until > at => backoff_until,
+            _ => at,
+        };
+        if at <= now {
+            SchedulerDecision::SyncNow(reason)
+        } else {
+            SchedulerDecision::SyncAt(at)
+        }
+    }
+
+    // Forgets the local changes a sync that started at `started_at` synced.
+    // Changes made while it was running might have been missed, so we
+    // treat them like new changes, made no earlier than the sync started.
+    fn forget_changes_before(&mut self, started_at: SystemTime) {
+        match self.last_change_at {
+            Some(last) if last >= started_at => {
+                self.first_change_at = self.first_change_at.map(|first| first.max(started_at));
+            }
+            _ => {
+                self.first_change_at = None;
+                self.last_change_at = None;
+            }
+        }
+    }
+
+    fn retry_delay(&self) -> Duration {
+        let multiplier = 1u32
+            .checked_shl(self.failures.saturating_sub(1))
+            .unwrap_or(u32::max_value());
+        self.policy
+            .min_retry_delay
+            .checked_mul(multiplier)
+            .map_or(self.policy.max_retry_delay, |delay| {
+                delay.min(self.policy.max_retry_delay)
+            })
+    }
+
+    // The earlier of the next regular sync, and the sync for local changes.
+    fn next_scheduled_sync(&self, now: SystemTime) -> Option<(SystemTime, SyncReason)> {
+        let interval = if self.in_foreground {
+            Some(self.policy.foreground_interval)
+        } else {
+            self.policy.background_interval
+        };
+        let regular = interval.map(|interval| match self.last_sync_at {
+            Some(at) => at + interval,
+            None => now,
+        });
+        // In the background, local changes wait for the next regular sync,
+        // or were synced before going to sleep.
+        let for_changes = match (self.first_change_at, self.last_change_at) {
+            (Some(first), Some(last)) if self.in_foreground => {
+                Some((last + self.policy.change_delay).min(first + self.policy.max_change_delay))
+            }
+            _ => None,
+        };
+        let at = match (regular, for_changes) {
+            (Some(regular), Some(for_changes)) => regular.min(for_changes),
+            (regular, for_changes) => regular.or(for_changes)?,
+        };
+        Some((at, SyncReason::Scheduled))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    fn started_scheduler(now: SystemTime) -> SyncScheduler {
+        let mut scheduler = SyncScheduler::new(SchedulerPolicy::default());
+        assert_eq!(
+            scheduler.handle_event(SchedulerEvent::Startup, now),
+            SchedulerDecision::SyncNow(SyncReason::Startup)
+        );
+        scheduler.handle_event(
+            SchedulerEvent::SyncFinished {
+                status: ServiceStatus::Ok,
+                next_sync_allowed_at: None,
+                started_at: now,
+            },
+            now,
+        );
+        scheduler
+    }
+
+    #[test]
+    fn test_intervals() {
+        let now = SystemTime::now();
+        let mut scheduler = started_scheduler(now);
+        assert_eq!(
+            scheduler.decide(now),
+            SchedulerDecision::SyncAt(now + secs(60 * 60))
+        );
+        assert_eq!(
+            scheduler.decide(now + secs(60 * 60)),
+            SchedulerDecision::SyncNow(SyncReason::Scheduled)
+        );
+
+        // No background syncs by default.
+        assert_eq!(
+            scheduler.handle_event(SchedulerEvent::Background, now),
+            SchedulerDecision::Wait
+        );
+        scheduler.set_policy(SchedulerPolicy {
+            background_interval: Some(secs(4 * 60 * 60)),
+            ..SchedulerPolicy::default()
+        });
+        assert_eq!(
+            scheduler.decide(now),
+            SchedulerDecision::SyncAt(now + secs(4 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn test_batches_local_changes() {
+        let now = SystemTime::now();
+        let mut scheduler = started_scheduler(now);
+        let change = || SchedulerEvent::LocalChange {
+            engine: "bookmarks".into(),
+        };
+        assert_eq!(
+            scheduler.handle_event(change(), now),
+            SchedulerDecision::SyncAt(now + secs(10))
+        );
+        assert_eq!(
+            scheduler.handle_event(change(), now + secs(5)),
+            SchedulerDecision::SyncAt(now + secs(15))
+        );
+        // Changes that keep coming don't put off the sync forever.
+        for s in (10..60).step_by(5) {
+            scheduler.handle_event(change(), now + secs(s));
+        }
+        assert_eq!(
+            scheduler.decide(now + secs(59)),
+            SchedulerDecision::SyncAt(now + secs(60))
+        );
+        assert_eq!(
+            scheduler.decide(now + secs(60)),
+            SchedulerDecision::SyncNow(SyncReason::Scheduled)
+        );
+
+        // Unsynced changes are synced before going to sleep.
+        assert_eq!(
+            scheduler.handle_event(SchedulerEvent::Background, now + secs(60)),
+            SchedulerDecision::SyncNow(SyncReason::PreSleep)
+        );
+        assert_eq!(
+            scheduler.handle_event(
+                SchedulerEvent::SyncFinished {
+                    status: ServiceStatus::Ok,
+                    next_sync_allowed_at: None,
+                    started_at: now + secs(60),
+                },
+                now + secs(61)
+            ),
+            SchedulerDecision::Wait
+        );
+    }
+
+    #[test]
+    fn test_keeps_changes_made_during_sync() {
+        let now = SystemTime::now();
+        let mut scheduler = started_scheduler(now);
+        let change = || SchedulerEvent::LocalChange {
+            engine: "history".into(),
+        };
+        scheduler.handle_event(change(), now);
+        assert_eq!(
+            scheduler.decide(now + secs(10)),
+            SchedulerDecision::SyncNow(SyncReason::Scheduled)
+        );
+        // The sync starts, and there's another change before it finishes.
+        scheduler.handle_event(change(), now + secs(15));
+        assert_eq!(
+            scheduler.handle_event(
+                SchedulerEvent::SyncFinished {
+                    status: ServiceStatus::Ok,
+                    next_sync_allowed_at: None,
+                    started_at: now + secs(10),
+                },
+                now + secs(20)
+            ),
+            SchedulerDecision::SyncAt(now + secs(25))
+        );
+        // Once that's synced, there's nothing left to sync until the next
+        // regular sync.
+        assert_eq!(
+            scheduler.handle_event(
+                SchedulerEvent::SyncFinished {
+                    status: ServiceStatus::Ok,
+                    next_sync_allowed_at: None,
+                    started_at: now + secs(25),
+                },
+                now + secs(30)
+            ),
+            SchedulerDecision::SyncAt(now + secs(30 + 60 * 60))
+        );
+    }
+
+    #[test]
+    fn test_retry_and_backoff() {
+        let now = SystemTime::now();
+        let mut scheduler = started_scheduler(now);
+        let network_error = SchedulerEvent::SyncFinished {
+            status: ServiceStatus::NetworkError,
+            next_sync_allowed_at: None,
+            started_at: now,
+        };
+        assert_eq!(
+            scheduler.handle_event(network_error.clone(), now),
+            SchedulerDecision::SyncAt(now + secs(30))
+        );
+        assert_eq!(
+            scheduler.handle_event(network_error.clone(), now),
+            SchedulerDecision::SyncAt(now + secs(60))
+        );
+        for _ in 0..20 {
+            scheduler.handle_event(network_error.clone(), now);
+        }
+        assert_eq!(
+            scheduler.decide(now),
+            SchedulerDecision::SyncAt(now + secs(30 * 60))
+        );
+
+        // The server's backoff wins over our own schedule...
+        assert_eq!(
+            scheduler.handle_event(
+                SchedulerEvent::SyncFinished {
+                    status: ServiceStatus::BackedOff,
+                    next_sync_allowed_at: Some(now + secs(2 * 60 * 60)),
+                    started_at: now,
+                },
+                now
+            ),
+            SchedulerDecision::SyncAt(now + secs(2 * 60 * 60))
+        );
+        // ...Unless the user asks to sync.
+        assert_eq!(
+            scheduler.handle_event(SchedulerEvent::UserRequested, now),
+            SchedulerDecision::SyncNow(SyncReason::User)
+        );
+    }
+
+    #[test]
+    fn test_auth_error() {
+        let now = SystemTime::now();
+        let mut scheduler = started_scheduler(now);
+        assert_eq!(
+            scheduler.handle_event(
+                SchedulerEvent::SyncFinished {
+                    status: ServiceStatus::AuthError,
+                    next_sync_allowed_at: None,
+                    started_at: now,
+                },
+                now
+            ),
+            SchedulerDecision::Wait
+        );
+        assert_eq!(
+            scheduler.handle_event(
+                SchedulerEvent::LocalChange {
+                    engine: "history".into()
+                },
+                now
+            ),
+            SchedulerDecision::Wait
+        );
+        assert_eq!(
+            scheduler.handle_event(SchedulerEvent::UserRequested, now),
+            SchedulerDecision::SyncNow(SyncReason::User)
+        );
+    }
+
+    #[test]
+    fn test_policy_from_msg() {
+        let policy = SchedulerPolicy::from(msg_types::SchedulerPolicy {
+            foreground_interval: Some(5 * 60 * 1000),
+            background_interval: Some(0),
+            sync_before_sleep: Some(false),
+            ..Default::default()
+        });
+        assert_eq!(
+            policy,
+            SchedulerPolicy {
+                foreground_interval: secs(5 * 60),
+                background_interval: None,
+                sync_before_sleep: false,
+                ..SchedulerPolicy::default()
+            }
+        );
+    }
+}
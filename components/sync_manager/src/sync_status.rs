@@ -0,0 +1,367 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A history of each engine's recent syncs, so that apps can show when an
+//! engine last synced, and notice engines that keep failing, without keeping
+//! their own records.
+//!
+//! The history is kept in the `engine_syncs` table, with a row for each
+//! engine in each sync. Only the last `MAX_HISTORY` rows for each engine are
+//! kept, so when each engine last synced successfully, and how many times it's
+//! failed since, are kept in the `engine_status` table, which isn't pruned.
+
+use crate::error::*;
+use crate::msg_types::{EngineSyncRecord, EngineSyncStatus, ServiceStatus};
+use rusqlite::{named_params, Connection, Row};
+use sql_support::ConnExt;
+use std::path::Path;
+
+const VERSION: i64 = 2;
+
+// How many syncs we keep for each engine.
+const MAX_HISTORY: i64 = 20;
+
+// `status` is a `msg_types::ServiceStatus`. Times are in milliseconds.
+const CREATE_ENGINE_SYNCS_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS engine_syncs (
+        id              INTEGER PRIMARY KEY,
+        engine          TEXT NOT NULL,
+        started_at      INTEGER NOT NULL,
+        took            INTEGER NOT NULL,
+        status          INTEGER NOT NULL,
+        error           TEXT,
+        applied         INTEGER NOT NULL,
+        incoming_failed INTEGER NOT NULL,
+        uploaded        INTEGER NOT NULL,
+        outgoing_failed INTEGER NOT NULL
+    )
+";
+
+// `last_success_at` is when the last successful sync started, or NULL if the
+// engine hasn't synced successfully.
+const CREATE_ENGINE_STATUS_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS engine_status (
+        engine               TEXT PRIMARY KEY,
+        last_success_at      INTEGER,
+        consecutive_failures INTEGER NOT NULL DEFAULT 0
+    )
+";
+
+fn init(db: &Connection) -> Result<()> {
+    let user_version = db.query_one::<i64>("PRAGMA user_version")?;
+    if user_version == 0 {
+        log::debug!("Creating sync status schema");
+        db.execute_all(&[
+            CREATE_ENGINE_SYNCS_TABLE_SQL,
+            CREATE_ENGINE_STATUS_TABLE_SQL,
+            &format!("PRAGMA user_version = {version}", version = VERSION),
+        ])?;
+    } else if user_version < VERSION {
+        log::debug!("Upgrading sync status schema from version {}", user_version);
+        upgrade_from_v1(db)?;
+    } else if user_version > VERSION {
+        log::warn!(
+            "Loaded future sync status schema version {} (we only understand version {})",
+            user_version,
+            VERSION
+        );
+    }
+    Ok(())
+}
+
+// Version 1 only had the history, so we fill in each engine's status from the
+// syncs it kept.
+fn upgrade_from_v1(db: &Connection) -> Result<()> {
+    let tx = db.unchecked_transaction()?;
+    db.execute_batch(CREATE_ENGINE_STATUS_TABLE_SQL)?;
+    db.execute_named(
+        "INSERT INTO engine_status (engine, last_success_at, consecutive_failures)
+         SELECT engine, MAX(CASE WHEN status = :ok THEN started_at END), 0
+         FROM engine_syncs
+         GROUP BY engine",
+        named_params! { ":ok": ServiceStatus::Ok as i32 },
+    )?;
+    db.execute_named(
+        "UPDATE engine_status SET consecutive_failures = (
+             SELECT COUNT(*) FROM engine_syncs s
+             WHERE s.engine = engine_status.engine AND
+                   s.status <> :ok AND
+                   (engine_status.last_success_at IS NULL OR
+                    s.started_at > engine_status.last_success_at)
+         )",
+        named_params! { ":ok": ServiceStatus::Ok as i32 },
+    )?;
+    db.execute_batch(&format!(
+        "PRAGMA user_version = {version}",
+        version = VERSION
+    ))?;
+    tx.commit()?;
+    Ok(())
+}
+
+impl EngineSyncRecord {
+    fn from_row(row: &Row<'_>) -> Result<Self> {
+        Ok(EngineSyncRecord {
+            started_at: row.get("started_at")?,
+            took: row.get("took")?,
+            status: row.get("status")?,
+            error: row.get("error")?,
+            applied: row.get("applied")?,
+            incoming_failed: row.get("incoming_failed")?,
+            uploaded: row.get("uploaded")?,
+            outgoing_failed: row.get("outgoing_failed")?,
+        })
+    }
+}
+
+pub struct SyncStatusStore {
+    db: Connection,
+}
+
+impl SyncStatusStore {
+    pub fn new(db_path: impl AsRef<Path>) -> Result<Self> {
+        Self::with_connection(Connection::open(db_path)?)
+    }
+
+    pub fn new_in_memory() -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(db: Connection) -> Result<Self> {
+        init(&db)?;
+        Ok(Self { db })
+    }
+
+    /// Records an engine's part in a sync, updates its status, and forgets
+    /// its oldest syncs if it has more than `MAX_HISTORY`.
+    pub fn record(&self, engine: &str, record: &EngineSyncRecord) -> Result<()> {
+        let tx = self.db.unchecked_transaction()?;
+        self.db.execute_named_cached(
+            "INSERT OR IGNORE INTO engine_status (engine) VALUES (:engine)",
+            named_params! { ":engine": engine },
+        )?;
+        if record.status == ServiceStatus::Ok as i32 {
+            self.db.execute_named_cached(
+                "UPDATE engine_status
+                 SET last_success_at = :started_at,
+                     consecutive_failures = 0
+                 WHERE engine = :engine",
+                named_params! {
+                    ":engine": engine,
+                    ":started_at": record.started_at,
+                },
+            )?;
+        } else {
+            self.db.execute_named_cached(
+                "UPDATE engine_status
+                 SET consecutive_failures = consecutive_failures + 1
+                 WHERE engine = :engine",
+                named_params! { ":engine": engine },
+            )?;
+        }
+        self.db.execute_named_cached(
+            "INSERT INTO engine_syncs (engine, started_at, took, status, error, applied,
+                                       incoming_failed, uploaded, outgoing_failed)
+             VALUES (:engine, :started_at, :took, :status, :error, :applied,
+                     :incoming_failed, :uploaded, :outgoing_failed)",
+            named_params! {
+                ":engine": engine,
+                ":started_at": record.started_at,
+                ":took": record.took,
+                ":status": record.status,
+                ":error": record.error,
+                ":applied": record.applied,
+                ":incoming_failed": record.incoming_failed,
+                ":uploaded": record.uploaded,
+                ":outgoing_failed": record.outgoing_failed,
+            },
+        )?;
+        self.db.execute_named_cached(
+            "DELETE FROM engine_syncs
+             WHERE engine = :engine AND id NOT IN (
+                 SELECT id FROM engine_syncs
+                 WHERE engine = :engine
+                 ORDER BY started_at DESC, id DESC
+                 LIMIT :max_history
+             )",
+            named_params! {
+                ":engine": engine,
+                ":max_history": MAX_HISTORY,
+            },
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns the status of every engine that's synced, sorted by name,
+    /// with its history newest first.
+    pub fn get_statuses(&self) -> Result<Vec<EngineSyncStatus>> {
+        let mut statuses = self.db.query_rows_and_then_named_cached(
+            "SELECT engine, last_success_at, consecutive_failures
+             FROM engine_status
+             ORDER BY engine",
+            &[],
+            |row| -> Result<_> {
+                Ok(EngineSyncStatus {
+                    engine: row.get("engine")?,
+                    last_success_at: row.get("last_success_at")?,
+                    consecutive_failures: row.get("consecutive_failures")?,
+                    history: vec![],
+                })
+            },
+        )?;
+        let mut stmt = self.db.prepare_cached(
+            "SELECT started_at, took, status, error, applied, incoming_failed,
+                    uploaded, outgoing_failed
+             FROM engine_syncs
+             WHERE engine = :engine
+             ORDER BY started_at DESC, id DESC",
+        )?;
+        for status in &mut statuses {
+            let mut rows = stmt.query_named(named_params! { ":engine": status.engine })?;
+            while let Some(row) = rows.next()? {
+                status.history.push(EngineSyncRecord::from_row(row)?);
+            }
+        }
+        Ok(statuses)
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        self.db
+            .execute_all(&["DELETE FROM engine_syncs", "DELETE FROM engine_status"])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(started_at: i64, status: ServiceStatus) -> EngineSyncRecord {
+        EngineSyncRecord {
+            started_at,
+            took: 100,
+            status: status as i32,
+            error: if status == ServiceStatus::Ok {
+                None
+            } else {
+                Some("oops".into())
+            },
+            applied: 1,
+            incoming_failed: 0,
+            uploaded: 2,
+            outgoing_failed: 0,
+        }
+    }
+
+    #[test]
+    fn test_statuses() {
+        let store = SyncStatusStore::new_in_memory().unwrap();
+        assert!(store.get_statuses().unwrap().is_empty());
+
+        store
+            .record("history", &record(1000, ServiceStatus::Ok))
+            .unwrap();
+        store
+            .record("history", &record(2000, ServiceStatus::NetworkError))
+            .unwrap();
+        store
+            .record("history", &record(3000, ServiceStatus::ServiceError))
+            .unwrap();
+        store
+            .record("bookmarks", &record(3000, ServiceStatus::Ok))
+            .unwrap();
+
+        let statuses = store.get_statuses().unwrap();
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(statuses[0].engine, "bookmarks");
+        assert_eq!(statuses[0].last_success_at, Some(3000));
+        assert_eq!(statuses[0].consecutive_failures, 0);
+        assert_eq!(statuses[1].engine, "history");
+        assert_eq!(statuses[1].last_success_at, Some(1000));
+        assert_eq!(statuses[1].consecutive_failures, 2);
+        assert_eq!(
+            statuses[1]
+                .history
+                .iter()
+                .map(|r| r.started_at)
+                .collect::<Vec<_>>(),
+            vec![3000, 2000, 1000]
+        );
+        assert_eq!(statuses[1].history[0].error.as_deref(), Some("oops"));
+
+        store.clear().unwrap();
+        assert!(store.get_statuses().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_history_limit() {
+        let store = SyncStatusStore::new_in_memory().unwrap();
+        store.record("tabs", &record(0, ServiceStatus::Ok)).unwrap();
+        for i in 1..=MAX_HISTORY + 5 {
+            store
+                .record("tabs", &record(i, ServiceStatus::NetworkError))
+                .unwrap();
+        }
+        let statuses = store.get_statuses().unwrap();
+        assert_eq!(statuses[0].history.len(), MAX_HISTORY as usize);
+        assert_eq!(statuses[0].history[0].started_at, MAX_HISTORY + 5);
+        // The successful sync was pruned from the history, but its status is
+        // kept.
+        assert_eq!(statuses[0].last_success_at, Some(0));
+        assert_eq!(statuses[0].consecutive_failures, MAX_HISTORY as i32 + 5);
+
+        store
+            .record("tabs", &record(100, ServiceStatus::Ok))
+            .unwrap();
+        let statuses = store.get_statuses().unwrap();
+        assert_eq!(statuses[0].last_success_at, Some(100));
+        assert_eq!(statuses[0].consecutive_failures, 0);
+    }
+
+    #[test]
+    fn test_upgrade_from_v1() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_all(&[CREATE_ENGINE_SYNCS_TABLE_SQL, "PRAGMA user_version = 1"])
+            .unwrap();
+        let syncs = [
+            ("history", 1000, ServiceStatus::Ok),
+            ("history", 2000, ServiceStatus::NetworkError),
+            ("history", 3000, ServiceStatus::ServiceError),
+            ("tabs", 1000, ServiceStatus::AuthError),
+        ];
+        for (engine, started_at, status) in &syncs {
+            db.execute_named(
+                "INSERT INTO engine_syncs (engine, started_at, took, status, applied,
+                                           incoming_failed, uploaded, outgoing_failed)
+                 VALUES (:engine, :started_at, 0, :status, 0, 0, 0, 0)",
+                named_params! {
+                    ":engine": engine,
+                    ":started_at": started_at,
+                    ":status": *status as i32,
+                },
+            )
+            .unwrap();
+        }
+
+        let store = SyncStatusStore::with_connection(db).unwrap();
+        let statuses = store.get_statuses().unwrap();
+        assert_eq!(
+            statuses
+                .iter()
+                .map(|s| (
+                    s.engine.as_str(),
+                    s.last_success_at,
+                    s.consecutive_failures,
+                    s.history.len()
+                ))
+                .collect::<Vec<_>>(),
+            vec![("history", Some(1000), 2, 3), ("tabs", None, 1, 1)]
+        );
+        assert_eq!(
+            store.db.query_one::<i64>("PRAGMA user_version").unwrap(),
+            VERSION
+        );
+    }
+}
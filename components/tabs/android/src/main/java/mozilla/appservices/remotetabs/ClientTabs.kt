@@ -25,7 +25,16 @@ data class RemoteTab(
     val title: String,
     val urlHistory: List<String>,
     val icon: String?,
-    val lastUsed: Long?
+    val lastUsed: Long?,
+    val windowId: String? = null,
+    // The tab's position in its window.
+    val index: Int? = null,
+    val pinned: Boolean = false,
+    val groupId: String? = null,
+    val groupName: String? = null,
+    // The name of the container (or "contextual identity") the tab is in.
+    val container: String? = null,
+    val inactive: Boolean = false
 ) {
     internal fun toProtobuf(): MsgTypes.RemoteTab {
         val builder = MsgTypes.RemoteTab.newBuilder()
@@ -37,6 +46,23 @@ data class RemoteTab(
             builder.setLastUsed(it)
         }
         builder.addAllUrlHistory(urlHistory)
+        windowId?.let {
+            builder.setWindowId(it)
+        }
+        index?.let {
+            builder.setIndex(it)
+        }
+        builder.setPinned(pinned)
+        groupId?.let {
+            builder.setGroupId(it)
+        }
+        groupName?.let {
+            builder.setGroupName(it)
+        }
+        container?.let {
+            builder.setContainer(it)
+        }
+        builder.setInactive(inactive)
         return builder.build()
     }
 
@@ -46,7 +72,14 @@ data class RemoteTab(
                     title = msg.title,
                     urlHistory = msg.urlHistoryList,
                     icon = msg.icon,
-                    lastUsed = msg.lastUsed
+                    lastUsed = msg.lastUsed,
+                    windowId = if (msg.hasWindowId()) msg.windowId else null,
+                    index = if (msg.hasIndex()) msg.index else null,
+                    pinned = msg.pinned,
+                    groupId = if (msg.hasGroupId()) msg.groupId else null,
+                    groupName = if (msg.hasGroupName()) msg.groupName else null,
+                    container = if (msg.hasContainer()) msg.container else null,
+                    inactive = msg.inactive
             )
         }
     }
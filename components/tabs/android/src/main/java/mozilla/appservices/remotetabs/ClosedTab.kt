@@ -0,0 +1,32 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+package mozilla.appservices.remotetabs
+
+/**
+ * A recently closed tab, which can be reopened with
+ * [RemoteTabsProvider.restoreClosedTab].
+ */
+data class ClosedTab(
+    val guid: String,
+    // Null for tabs closed on this device.
+    val clientId: String?,
+    val tab: RemoteTab,
+    val timeClosed: Long
+) {
+    companion object {
+        internal fun fromCollectionMessage(msg: MsgTypes.ClosedTabs): List<ClosedTab> {
+            return msg.closedTabsList.map { fromMessage(it) }
+        }
+
+        private fun fromMessage(msg: MsgTypes.ClosedTab): ClosedTab {
+            return ClosedTab(
+                    guid = msg.guid,
+                    clientId = if (msg.hasClientId()) msg.clientId else null,
+                    tab = RemoteTab.fromMessage(msg.tab),
+                    timeClosed = msg.timeClosed
+            )
+        }
+    }
+}
@@ -12,12 +12,22 @@ import mozilla.appservices.remotetabs.rust.RustError
 import mozilla.appservices.sync15.SyncTelemetryPing
 import java.util.concurrent.atomic.AtomicLong
 
-class RemoteTabsProvider : AutoCloseable {
+/**
+ * Provides access to the tabs of other devices.
+ *
+ * If [dbPath] is null, the remote tabs are only kept in memory, and are lost
+ * when the provider is closed.
+ */
+class RemoteTabsProvider(dbPath: String? = null) : AutoCloseable {
     private var handle: AtomicLong = AtomicLong(0)
 
     init {
         handle.set(rustCall { error ->
-            LibRemoteTabsFFI.INSTANCE.remote_tabs_new(error)
+            if (dbPath != null) {
+                LibRemoteTabsFFI.INSTANCE.remote_tabs_new_with_path(dbPath, error)
+            } else {
+                LibRemoteTabsFFI.INSTANCE.remote_tabs_new(error)
+            }
         })
     }
 
@@ -53,6 +63,114 @@ class RemoteTabsProvider : AutoCloseable {
         }
     }
 
+    /**
+     * Queue a command for the device with [clientId]. Commands are sent when
+     * syncing through the sync manager.
+     */
+    fun queueCommand(clientId: String, command: TabCommand) {
+        val (nioBuf, len) = command.toProtobuf().toNioDirectBuffer()
+        rustCallWithLock { err ->
+            val ptr = Native.getDirectBufferPointer(nioBuf)
+            LibRemoteTabsFFI.INSTANCE.remote_tabs_queue_command(this.handle.get(), clientId, ptr, len, err)
+        }
+    }
+
+    /**
+     * Get the commands we've queued for other devices, and their status.
+     */
+    fun getOutgoingCommands(): List<OutgoingTabCommand> {
+        val rustBuf = rustCallWithLock { error ->
+            LibRemoteTabsFFI.INSTANCE.remote_tabs_get_outgoing_commands(
+                this.handle.get(), error)
+        }
+
+        try {
+            return rustBuf.asCodedInputStream()?.let { stream ->
+                OutgoingTabCommand.fromCollectionMessage(MsgTypes.OutgoingTabCommands.parseFrom(stream))
+            } ?: listOf()
+        } finally {
+            LibRemoteTabsFFI.INSTANCE.remote_tabs_destroy_bytebuffer(rustBuf)
+        }
+    }
+
+    /**
+     * Get the commands other devices have sent us. Each command is only
+     * returned once.
+     */
+    fun takeIncomingCommands(): List<IncomingTabCommand> {
+        val rustBuf = rustCallWithLock { error ->
+            LibRemoteTabsFFI.INSTANCE.remote_tabs_take_incoming_commands(
+                this.handle.get(), error)
+        }
+
+        try {
+            return rustBuf.asCodedInputStream()?.let { stream ->
+                IncomingTabCommand.fromCollectionMessage(MsgTypes.IncomingTabCommands.parseFrom(stream))
+            } ?: listOf()
+        } finally {
+            LibRemoteTabsFFI.INSTANCE.remote_tabs_destroy_bytebuffer(rustBuf)
+        }
+    }
+
+    /**
+     * Remember a tab the user just closed. Returns the ID to restore it with.
+     */
+    fun addClosedTab(tab: RemoteTab): String {
+        val (nioBuf, len) = tab.toProtobuf().toNioDirectBuffer()
+        return rustCallWithLock { err ->
+            val ptr = Native.getDirectBufferPointer(nioBuf)
+            LibRemoteTabsFFI.INSTANCE.remote_tabs_add_closed_tab(this.handle.get(), ptr, len, err)
+        }.getAndConsumeRustString()
+    }
+
+    /**
+     * Get the recently closed tabs, from this and (if syncing them is
+     * enabled) other devices.
+     */
+    fun getClosedTabs(): List<ClosedTab> {
+        val rustBuf = rustCallWithLock { error ->
+            LibRemoteTabsFFI.INSTANCE.remote_tabs_get_closed_tabs(
+                this.handle.get(), error)
+        }
+
+        try {
+            return rustBuf.asCodedInputStream()?.let { stream ->
+                ClosedTab.fromCollectionMessage(MsgTypes.ClosedTabs.parseFrom(stream))
+            } ?: listOf()
+        } finally {
+            LibRemoteTabsFFI.INSTANCE.remote_tabs_destroy_bytebuffer(rustBuf)
+        }
+    }
+
+    /**
+     * Remove a closed tab from the list, and return it. Returns null if it's
+     * already been restored.
+     */
+    fun restoreClosedTab(guid: String): RemoteTab? {
+        val rustBuf = rustCallWithLock { error ->
+            LibRemoteTabsFFI.INSTANCE.remote_tabs_restore_closed_tab(
+                this.handle.get(), guid, error)
+        }
+
+        try {
+            return rustBuf.asCodedInputStream()?.let { stream ->
+                RemoteTab.fromMessage(MsgTypes.RemoteTab.parseFrom(stream))
+            }
+        } finally {
+            LibRemoteTabsFFI.INSTANCE.remote_tabs_destroy_bytebuffer(rustBuf)
+        }
+    }
+
+    /**
+     * Set whether closed tabs are synced with other devices.
+     */
+    fun setClosedTabsSyncEnabled(enabled: Boolean) {
+        rustCallWithLock { error ->
+            LibRemoteTabsFFI.INSTANCE.remote_tabs_set_closed_tabs_sync_enabled(
+                this.handle.get(), (if (enabled) 1 else 0).toByte(), error)
+        }
+    }
+
     /**
      * Convenience Sync function.
      */
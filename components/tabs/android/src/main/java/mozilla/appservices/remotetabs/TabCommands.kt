@@ -0,0 +1,112 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+package mozilla.appservices.remotetabs
+
+/**
+ * A command to send to, or received from, another device.
+ */
+sealed class TabCommand {
+    /**
+     * Opens [url] in a new tab.
+     */
+    data class OpenUrl(val url: String, val title: String? = null) : TabCommand()
+
+    /**
+     * Closes the tabs showing any of [urls].
+     */
+    data class CloseTabs(val urls: List<String>) : TabCommand()
+
+    internal fun toProtobuf(): MsgTypes.TabCommand {
+        val builder = MsgTypes.TabCommand.newBuilder()
+        when (this) {
+            is OpenUrl -> {
+                builder.setKind(MsgTypes.TabCommand.Kind.OPEN_URL)
+                builder.addUrls(url)
+                title?.let {
+                    builder.setTitle(it)
+                }
+            }
+            is CloseTabs -> {
+                builder.setKind(MsgTypes.TabCommand.Kind.CLOSE_TABS)
+                builder.addAllUrls(urls)
+            }
+        }
+        return builder.build()
+    }
+
+    companion object {
+        internal fun fromMessage(msg: MsgTypes.TabCommand): TabCommand {
+            return when (msg.kind) {
+                MsgTypes.TabCommand.Kind.CLOSE_TABS -> CloseTabs(msg.urlsList)
+                else -> OpenUrl(
+                        url = msg.urlsList.first(),
+                        title = if (msg.hasTitle()) msg.title else null
+                )
+            }
+        }
+    }
+}
+
+/**
+ * A command we've queued for another device.
+ */
+data class OutgoingTabCommand(
+    val id: Long,
+    val clientId: String,
+    val command: TabCommand,
+    val status: Status,
+    val timeRequested: Long
+) {
+    enum class Status {
+        QUEUED,
+        SENT,
+        ACKNOWLEDGED
+    }
+
+    companion object {
+        internal fun fromCollectionMessage(msg: MsgTypes.OutgoingTabCommands): List<OutgoingTabCommand> {
+            return msg.commandsList.map { fromMessage(it) }
+        }
+
+        private fun fromMessage(msg: MsgTypes.OutgoingTabCommand): OutgoingTabCommand {
+            return OutgoingTabCommand(
+                    id = msg.id,
+                    clientId = msg.clientId,
+                    command = TabCommand.fromMessage(msg.command),
+                    status = when (msg.status) {
+                        MsgTypes.OutgoingTabCommand.Status.SENT -> Status.SENT
+                        MsgTypes.OutgoingTabCommand.Status.ACKNOWLEDGED -> Status.ACKNOWLEDGED
+                        else -> Status.QUEUED
+                    },
+                    timeRequested = msg.timeRequested
+            )
+        }
+    }
+}
+
+/**
+ * A command another device sent us.
+ */
+data class IncomingTabCommand(
+    // Other clients don't say who sent a `CloseTabs` command, so this is only
+    // set for `OpenUrl`.
+    val senderId: String?,
+    val command: TabCommand,
+    val timeReceived: Long
+) {
+    companion object {
+        internal fun fromCollectionMessage(msg: MsgTypes.IncomingTabCommands): List<IncomingTabCommand> {
+            return msg.commandsList.map { fromMessage(it) }
+        }
+
+        private fun fromMessage(msg: MsgTypes.IncomingTabCommand): IncomingTabCommand {
+            return IncomingTabCommand(
+                    senderId = if (msg.hasSenderId()) msg.senderId else null,
+                    command = TabCommand.fromMessage(msg.command),
+                    timeReceived = msg.timeReceived
+            )
+        }
+    }
+}
@@ -22,6 +22,11 @@ internal interface LibRemoteTabsFFI : Library {
         error: RustError.ByReference
     ): TabsApiHandle
 
+    fun remote_tabs_new_with_path(
+        db_path: String,
+        error: RustError.ByReference
+    ): TabsApiHandle
+
     fun remote_tabs_destroy(handle: TabsApiHandle, error: RustError.ByReference)
 
     fun remote_tabs_update_local(
@@ -47,6 +52,51 @@ internal interface LibRemoteTabsFFI : Library {
         error: RustError.ByReference
     ): Pointer?
 
+    fun remote_tabs_queue_command(
+        handle: TabsApiHandle,
+        client_id: String,
+        command_data: Pointer,
+        command_len: Int,
+        error: RustError.ByReference
+    )
+
+    fun remote_tabs_get_outgoing_commands(
+        handle: TabsApiHandle,
+        error: RustError.ByReference
+    ): RustBuffer.ByValue
+
+    fun remote_tabs_take_incoming_commands(
+        handle: TabsApiHandle,
+        error: RustError.ByReference
+    ): RustBuffer.ByValue
+
+    // Returns the ID of the closed tab.
+    fun remote_tabs_add_closed_tab(
+        handle: TabsApiHandle,
+        tab_data: Pointer,
+        tab_len: Int,
+        error: RustError.ByReference
+    ): Pointer?
+
+    fun remote_tabs_get_closed_tabs(
+        handle: TabsApiHandle,
+        error: RustError.ByReference
+    ): RustBuffer.ByValue
+
+    // Returns an empty buffer if the tab was already restored.
+    fun remote_tabs_restore_closed_tab(
+        handle: TabsApiHandle,
+        guid: String,
+        error: RustError.ByReference
+    ): RustBuffer.ByValue
+
+    // `enabled` is 1 for true and 0 for false.
+    fun remote_tabs_set_closed_tabs_sync_enabled(
+        handle: TabsApiHandle,
+        enabled: Byte,
+        error: RustError.ByReference
+    )
+
     fun remote_tabs_destroy_bytebuffer(buffer: RustBuffer.ByValue)
     fun remote_tabs_destroy_string(p: Pointer)
 }
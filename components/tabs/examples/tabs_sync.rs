@@ -25,6 +25,14 @@ fn main() -> Result<()> {
                     "Path to store our cached fxa credentials (defaults to \"./credentials.json\"",
                 ),
         )
+        .arg(
+            clap::Arg::with_name("database_path")
+                .short("d")
+                .long("database")
+                .value_name("TABS_DATABASE")
+                .takes_value(true)
+                .help("Path to the tabs database (default: \"./tabs.db\")"),
+        )
         .get_matches();
     let cred_file = matches
         .value_of("credential_file")
@@ -33,7 +41,7 @@ fn main() -> Result<()> {
     let mut cli_fxa = get_cli_fxa(get_default_fxa_config(), &cred_file)?;
     let device_id = cli_fxa.account.get_current_device_id()?;
 
-    let mut engine = TabsEngine::new();
+    let mut engine = TabsEngine::new(matches.value_of("database_path").unwrap_or("./tabs.db"))?;
 
     loop {
         match prompt_char("[U]pdate local state, [L]ist remote tabs, [S]ync or [Q]uit")
@@ -47,7 +55,7 @@ fn main() -> Result<()> {
             }
             'L' | 'l' => {
                 log::info!("Listing remote tabs.");
-                let tabs_and_clients = match engine.remote_tabs() {
+                let tabs_and_clients = match engine.remote_tabs()? {
                     Some(tc) => tc,
                     None => {
                         println!("No remote tabs! Did you try syncing first?");
@@ -134,6 +142,7 @@ fn read_local_state() -> Vec<RemoteTab> {
             url_history,
             icon,
             last_used,
+            ..RemoteTab::default()
         });
     }
     local_state
@@ -10,6 +10,7 @@ use ffi_support::{
     ConcurrentHandleMap, ExternError, FfiStr,
 };
 use std::{
+    convert::TryInto,
     os::raw::c_char,
     sync::{Arc, Mutex},
 };
@@ -26,11 +27,23 @@ fn parse_url(url: &str) -> Result<url::Url> {
     Ok(url::Url::parse(url)?)
 }
 
+/// Creates an engine whose remote tabs are only kept in memory, so they're
+/// lost when the engine is destroyed. Prefer `remote_tabs_new_with_path`.
 #[no_mangle]
 pub extern "C" fn remote_tabs_new(error: &mut ExternError) -> u64 {
     log::debug!("remote_tabs_new");
     ENGINES.insert_with_result(error, || -> Result<_> {
-        Ok(Arc::new(Mutex::new(TabsEngine::new())))
+        Ok(Arc::new(Mutex::new(TabsEngine::new_in_memory()?)))
+    })
+}
+
+/// Creates an engine that stores the remote tabs in the database at
+/// `db_path`, which is created if it doesn't exist.
+#[no_mangle]
+pub extern "C" fn remote_tabs_new_with_path(db_path: FfiStr<'_>, error: &mut ExternError) -> u64 {
+    log::debug!("remote_tabs_new_with_path");
+    ENGINES.insert_with_result(error, || -> Result<_> {
+        Ok(Arc::new(Mutex::new(TabsEngine::new(db_path.as_str())?)))
     })
 }
 
@@ -89,11 +102,133 @@ pub extern "C" fn remote_tabs_get_all(handle: u64, error: &mut ExternError) -> B
         Ok(engine
             .lock()
             .unwrap()
-            .remote_tabs()
+            .remote_tabs()?
             .map(|tabs| -> ClientsTabs { tabs.into() }))
     })
 }
 
+/// Queues a command, encoded as a `TabCommand` protobuf message, for the
+/// client with the given ID. Commands are sent when syncing through the sync
+/// manager.
+///
+/// # Safety
+/// Deref pointer, thus unsafe
+#[no_mangle]
+pub unsafe extern "C" fn remote_tabs_queue_command(
+    handle: u64,
+    client_id: FfiStr<'_>,
+    command_data: *const u8,
+    command_len: i32,
+    error: &mut ExternError,
+) {
+    log::debug!("remote_tabs_queue_command");
+    use tabs::msg_types::TabCommand;
+    ENGINES.call_with_result(error, handle, |engine| -> Result<_> {
+        let buffer = get_buffer(command_data, command_len);
+        let command: TabCommand = prost::Message::decode(buffer)?;
+        engine
+            .lock()
+            .unwrap()
+            .queue_command(client_id.as_str(), command.try_into()?)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn remote_tabs_get_outgoing_commands(
+    handle: u64,
+    error: &mut ExternError,
+) -> ByteBuffer {
+    log::debug!("remote_tabs_get_outgoing_commands");
+    use tabs::msg_types::OutgoingTabCommands;
+    ENGINES.call_with_result(error, handle, |engine| -> Result<_> {
+        Ok(OutgoingTabCommands::from(
+            engine.lock().unwrap().outgoing_commands()?,
+        ))
+    })
+}
+
+/// Returns the commands other clients have sent us, as an
+/// `IncomingTabCommands` protobuf message. Each command is only returned once.
+#[no_mangle]
+pub extern "C" fn remote_tabs_take_incoming_commands(
+    handle: u64,
+    error: &mut ExternError,
+) -> ByteBuffer {
+    log::debug!("remote_tabs_take_incoming_commands");
+    use tabs::msg_types::IncomingTabCommands;
+    ENGINES.call_with_result(error, handle, |engine| -> Result<_> {
+        Ok(IncomingTabCommands::from(
+            engine.lock().unwrap().take_incoming_commands()?,
+        ))
+    })
+}
+
+/// Remembers a tab, encoded as a `RemoteTab` protobuf message, that the user
+/// just closed. Returns the ID to restore it with.
+///
+/// # Safety
+/// Deref pointer, thus unsafe
+#[no_mangle]
+pub unsafe extern "C" fn remote_tabs_add_closed_tab(
+    handle: u64,
+    tab_data: *const u8,
+    tab_len: i32,
+    error: &mut ExternError,
+) -> *mut c_char {
+    log::debug!("remote_tabs_add_closed_tab");
+    use tabs::msg_types::RemoteTab;
+    ENGINES.call_with_result(error, handle, |engine| -> Result<_> {
+        let buffer = get_buffer(tab_data, tab_len);
+        let tab: RemoteTab = prost::Message::decode(buffer)?;
+        engine.lock().unwrap().add_closed_tab(&tab.into())
+    })
+}
+
+/// Returns the recently closed tabs, from this and (if syncing them is
+/// enabled) other devices, as a `ClosedTabs` protobuf message.
+#[no_mangle]
+pub extern "C" fn remote_tabs_get_closed_tabs(handle: u64, error: &mut ExternError) -> ByteBuffer {
+    log::debug!("remote_tabs_get_closed_tabs");
+    use tabs::msg_types::ClosedTabs;
+    ENGINES.call_with_result(error, handle, |engine| -> Result<_> {
+        Ok(ClosedTabs::from(engine.lock().unwrap().closed_tabs()?))
+    })
+}
+
+/// Removes a closed tab from the list, and returns it as a `RemoteTab`
+/// protobuf message, or an empty buffer if it's already been restored.
+#[no_mangle]
+pub extern "C" fn remote_tabs_restore_closed_tab(
+    handle: u64,
+    guid: FfiStr<'_>,
+    error: &mut ExternError,
+) -> ByteBuffer {
+    log::debug!("remote_tabs_restore_closed_tab");
+    use tabs::msg_types::RemoteTab;
+    ENGINES.call_with_result(error, handle, |engine| -> Result<_> {
+        Ok(engine
+            .lock()
+            .unwrap()
+            .restore_closed_tab(guid.as_str())?
+            .map(RemoteTab::from))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn remote_tabs_set_closed_tabs_sync_enabled(
+    handle: u64,
+    enabled: u8,
+    error: &mut ExternError,
+) {
+    log::debug!("remote_tabs_set_closed_tabs_sync_enabled");
+    ENGINES.call_with_result(error, handle, |engine| -> Result<_> {
+        engine
+            .lock()
+            .unwrap()
+            .set_closed_tabs_sync_enabled(enabled != 0)
+    })
+}
+
 unsafe fn get_buffer<'a>(data: *const u8, len: i32) -> &'a [u8] {
     assert!(len >= 0, "Bad buffer len: {}", len);
     if len == 0 {
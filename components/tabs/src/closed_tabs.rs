@@ -0,0 +1,455 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Recently closed tabs.
+//!
+//! The application tells us when the user closes a tab with `add_closed_tab`,
+//! and can reopen it later with `restore_closed_tab`. We keep the
+//! `MAX_CLOSED_TABS_PER_CLIENT` most recently closed tabs for each device.
+//!
+//! If the application enables it with `set_closed_tabs_sync_enabled`, closed
+//! tabs are also synced, one record per tab, in the `closedtabs` collection,
+//! so that a tab closed on one device can be restored on another. Restoring a
+//! tab removes it from every device's list.
+
+use crate::error::*;
+use crate::schema;
+use crate::storage::{now_ms, RemoteTab, TabsStorage};
+use rusqlite::{named_params, Row, NO_PARAMS};
+use sql_support::ConnExt;
+use sync15::{CollSyncIds, ServerTimestamp, StoreSyncAssociation};
+use sync_guid::Guid;
+
+/// Desktop's default for `browser.sessionstore.max_tabs_undo`.
+pub(crate) const MAX_CLOSED_TABS_PER_CLIENT: usize = 25;
+
+/// The `sync_status` of a row in `closed_tabs`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub(crate) enum SyncStatus {
+    /// Closed on this device, and not uploaded yet.
+    New = 0,
+    /// Uploaded, or downloaded from another device.
+    Synced = 1,
+}
+
+impl SyncStatus {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            0 => SyncStatus::New,
+            _ => SyncStatus::Synced,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClosedTab {
+    pub guid: String,
+    /// The `client_id` of the device the tab was closed on, or `None` if it
+    /// was closed on this device.
+    pub client_id: Option<String>,
+    pub tab: RemoteTab,
+    pub time_closed: i64, // In ms.
+}
+
+impl ClosedTab {
+    fn from_row(row: &Row<'_>) -> Result<Self> {
+        let tab: String = row.get("tab")?;
+        Ok(Self {
+            guid: row.get("guid")?,
+            client_id: row.get("client_id")?,
+            tab: serde_json::from_str(&tab)?,
+            time_closed: row.get("time_closed")?,
+        })
+    }
+}
+
+impl TabsStorage {
+    /// Remembers a tab that the user just closed, and returns its ID.
+    pub fn add_closed_tab(&self, tab: &RemoteTab) -> Result<String> {
+        let guid = Guid::random().into_string();
+        let tx = self.db.unchecked_transaction()?;
+        self.db.execute_named_cached(
+            "INSERT INTO closed_tabs (guid, client_id, tab, time_closed, sync_status)
+             VALUES (:guid, NULL, :tab, :time_closed, :sync_status)",
+            named_params! {
+                ":guid": guid,
+                ":tab": serde_json::to_string(tab)?,
+                ":time_closed": now_ms(),
+                ":sync_status": SyncStatus::New as u8,
+            },
+        )?;
+        self.prune_closed_tabs()?;
+        tx.commit()?;
+        Ok(guid)
+    }
+
+    /// Returns the recently closed tabs, most recently closed first.
+    pub fn get_closed_tabs(&self) -> Result<Vec<ClosedTab>> {
+        let mut stmt = self.db.prepare_cached(
+            "SELECT guid, client_id, tab, time_closed
+             FROM closed_tabs
+             ORDER BY time_closed DESC, guid",
+        )?;
+        let rows = stmt.query_and_then(NO_PARAMS, ClosedTab::from_row)?;
+        rows.collect()
+    }
+
+    /// Removes a closed tab from the list, and returns it so that the
+    /// application can reopen it. Returns `None` if there's no such tab,
+    /// for example if it was already restored on another device.
+    pub fn restore_closed_tab(&self, guid: &str) -> Result<Option<RemoteTab>> {
+        let tx = self.db.unchecked_transaction()?;
+        let row = self.db.try_query_row(
+            "SELECT tab, sync_status FROM closed_tabs WHERE guid = :guid",
+            named_params! { ":guid": guid },
+            |row| -> Result<_> {
+                let tab: String = row.get("tab")?;
+                Ok((serde_json::from_str(&tab)?, row.get("sync_status")?))
+            },
+            true,
+        )?;
+        let tab = match row {
+            Some((tab, sync_status)) => {
+                self.delete_closed_tab(guid, SyncStatus::from_u8(sync_status))?;
+                Some(tab)
+            }
+            None => None,
+        };
+        tx.commit()?;
+        Ok(tab)
+    }
+
+    pub fn closed_tabs_sync_enabled(&self) -> Result<bool> {
+        Ok(self
+            .get_meta(schema::CLOSED_TABS_SYNC_ENABLED_META_KEY)?
+            .unwrap_or(false))
+    }
+
+    /// Enables or disables syncing closed tabs, the next time tabs are synced.
+    pub fn set_closed_tabs_sync_enabled(&self, enabled: bool) -> Result<()> {
+        self.put_meta(schema::CLOSED_TABS_SYNC_ENABLED_META_KEY, &enabled)
+    }
+
+    // Deletes a closed tab, and records a tombstone if it's on the server.
+    fn delete_closed_tab(&self, guid: &str, sync_status: SyncStatus) -> Result<()> {
+        self.db.execute_named_cached(
+            "DELETE FROM closed_tabs WHERE guid = :guid",
+            named_params! { ":guid": guid },
+        )?;
+        if sync_status == SyncStatus::Synced {
+            self.db.execute_named_cached(
+                "INSERT OR IGNORE INTO closed_tabs_tombstones (guid) VALUES (:guid)",
+                named_params! { ":guid": guid },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Drops all but the most recently closed tabs for each client. Only
+    /// the tabs closed on this device are deleted from the server; other
+    /// devices take care of their own.
+    pub(crate) fn prune_closed_tabs(&self) -> Result<()> {
+        let mut stmt = self.db.prepare_cached(
+            "SELECT guid, client_id, sync_status FROM (
+                 SELECT guid, client_id, sync_status,
+                        ROW_NUMBER() OVER (
+                            PARTITION BY client_id
+                            ORDER BY time_closed DESC, guid
+                        ) AS position
+                 FROM closed_tabs
+             )
+             WHERE position > :max",
+        )?;
+        let rows = stmt.query_and_then_named(
+            named_params! { ":max": MAX_CLOSED_TABS_PER_CLIENT as i64 },
+            |row| -> Result<(String, Option<String>, u8)> {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            },
+        )?;
+        // Collect the rows first, so that we aren't deleting from the table
+        // while we're reading it.
+        let rows = rows.collect::<Result<Vec<_>>>()?;
+        for (guid, client_id, sync_status) in rows {
+            if client_id.is_none() {
+                self.delete_closed_tab(&guid, SyncStatus::from_u8(sync_status))?;
+            } else {
+                self.delete_closed_tab(&guid, SyncStatus::New)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores a closed tab from the server. Tabs that we've restored, but
+    /// haven't uploaded the tombstone for yet, are ignored.
+    pub(crate) fn put_synced_closed_tab(
+        &self,
+        guid: &str,
+        client_id: Option<&str>,
+        tab: &RemoteTab,
+        time_closed: i64,
+    ) -> Result<()> {
+        self.db.execute_named_cached(
+            "REPLACE INTO closed_tabs (guid, client_id, tab, time_closed, sync_status)
+             SELECT :guid, :client_id, :tab, :time_closed, :sync_status
+             WHERE NOT EXISTS (
+                 SELECT 1 FROM closed_tabs_tombstones WHERE guid = :guid
+             )",
+            named_params! {
+                ":guid": guid,
+                ":client_id": client_id,
+                ":tab": serde_json::to_string(tab)?,
+                ":time_closed": time_closed,
+                ":sync_status": SyncStatus::Synced as u8,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Deletes a closed tab that was restored, or dropped, on another device.
+    pub(crate) fn delete_synced_closed_tab(&self, guid: &str) -> Result<()> {
+        self.db.execute_named_cached(
+            "DELETE FROM closed_tabs WHERE guid = :guid",
+            named_params! { ":guid": guid },
+        )?;
+        self.db.execute_named_cached(
+            "DELETE FROM closed_tabs_tombstones WHERE guid = :guid",
+            named_params! { ":guid": guid },
+        )?;
+        Ok(())
+    }
+
+    /// Returns the tabs closed on this device that we haven't uploaded yet,
+    /// and the IDs of the tabs to delete from the server.
+    pub(crate) fn get_closed_tabs_to_upload(&self) -> Result<(Vec<ClosedTab>, Vec<String>)> {
+        let tabs = {
+            let mut stmt = self.db.prepare_cached(
+                "SELECT guid, client_id, tab, time_closed
+                 FROM closed_tabs
+                 WHERE sync_status = :new",
+            )?;
+            let rows = stmt.query_and_then_named(
+                named_params! { ":new": SyncStatus::New as u8 },
+                ClosedTab::from_row,
+            )?;
+            rows.collect::<Result<Vec<_>>>()?
+        };
+        let tombstones = self.db.query_rows_and_then_named(
+            "SELECT guid FROM closed_tabs_tombstones",
+            &[],
+            |row| -> Result<String> { Ok(row.get(0)?) },
+        )?;
+        Ok((tabs, tombstones))
+    }
+
+    pub(crate) fn mark_closed_tabs_synced(&self, guids: &[Guid]) -> Result<()> {
+        let tx = self.db.unchecked_transaction()?;
+        for guid in guids {
+            self.db.execute_named_cached(
+                "UPDATE closed_tabs SET sync_status = :synced WHERE guid = :guid",
+                named_params! { ":synced": SyncStatus::Synced as u8, ":guid": guid },
+            )?;
+            self.db.execute_named_cached(
+                "DELETE FROM closed_tabs_tombstones WHERE guid = :guid",
+                named_params! { ":guid": guid },
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub(crate) fn get_closed_tabs_last_sync(&self) -> Result<Option<ServerTimestamp>> {
+        Ok(self
+            .get_meta::<i64>(schema::CLOSED_TABS_LAST_SYNC_META_KEY)?
+            .map(ServerTimestamp))
+    }
+
+    pub(crate) fn set_closed_tabs_last_sync(&self, last_sync: ServerTimestamp) -> Result<()> {
+        self.put_meta(
+            schema::CLOSED_TABS_LAST_SYNC_META_KEY,
+            &last_sync.as_millis(),
+        )
+    }
+
+    pub(crate) fn get_closed_tabs_sync_assoc(&self) -> Result<StoreSyncAssociation> {
+        let global = self.get_meta(schema::CLOSED_TABS_GLOBAL_SYNCID_META_KEY)?;
+        let coll = self.get_meta(schema::CLOSED_TABS_COLLECTION_SYNCID_META_KEY)?;
+        Ok(if let (Some(global), Some(coll)) = (global, coll) {
+            StoreSyncAssociation::Connected(CollSyncIds { global, coll })
+        } else {
+            StoreSyncAssociation::Disconnected
+        })
+    }
+
+    /// Forgets the closed tabs from other devices, and marks ours as needing
+    /// to be uploaded again.
+    pub(crate) fn reset_closed_tabs(&self, assoc: &StoreSyncAssociation) -> Result<()> {
+        let tx = self.db.unchecked_transaction()?;
+        self.db.execute_all(&[
+            "DELETE FROM closed_tabs WHERE client_id NOT NULL",
+            "DELETE FROM closed_tabs_tombstones",
+        ])?;
+        self.db.execute_named(
+            "UPDATE closed_tabs SET sync_status = :new",
+            named_params! { ":new": SyncStatus::New as u8 },
+        )?;
+        self.delete_meta(schema::CLOSED_TABS_LAST_SYNC_META_KEY)?;
+        match assoc {
+            StoreSyncAssociation::Disconnected => {
+                self.delete_meta(schema::CLOSED_TABS_GLOBAL_SYNCID_META_KEY)?;
+                self.delete_meta(schema::CLOSED_TABS_COLLECTION_SYNCID_META_KEY)?;
+            }
+            StoreSyncAssociation::Connected(ids) => {
+                self.put_meta(schema::CLOSED_TABS_GLOBAL_SYNCID_META_KEY, &ids.global)?;
+                self.put_meta(schema::CLOSED_TABS_COLLECTION_SYNCID_META_KEY, &ids.coll)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub(crate) fn delete_all_closed_tabs(&self) -> Result<()> {
+        self.db.execute_all(&[
+            "DELETE FROM closed_tabs",
+            "DELETE FROM closed_tabs_tombstones",
+        ])?;
+        self.delete_meta(schema::CLOSED_TABS_LAST_SYNC_META_KEY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tab(url: &str) -> RemoteTab {
+        RemoteTab {
+            title: "A tab".to_owned(),
+            url_history: vec![url.to_owned()],
+            last_used: 1234,
+            ..RemoteTab::default()
+        }
+    }
+
+    fn closed_urls(storage: &TabsStorage) -> Vec<String> {
+        storage
+            .get_closed_tabs()
+            .unwrap()
+            .into_iter()
+            .map(|c| c.tab.url_history[0].clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_closed_tabs() {
+        let storage = TabsStorage::new_in_memory().unwrap();
+        let guid = storage.add_closed_tab(&tab("https://a.com")).unwrap();
+        storage.add_closed_tab(&tab("https://b.com")).unwrap();
+        storage
+            .put_synced_closed_tab("remote000001", Some("laptop"), &tab("https://c.com"), 1)
+            .unwrap();
+        let closed = storage.get_closed_tabs().unwrap();
+        assert_eq!(closed.len(), 3);
+        assert_eq!(closed[2].client_id, Some("laptop".to_owned()));
+
+        // Restoring a tab that was never uploaded doesn't need a tombstone.
+        assert_eq!(
+            storage.restore_closed_tab(&guid).unwrap(),
+            Some(tab("https://a.com"))
+        );
+        assert_eq!(storage.restore_closed_tab(&guid).unwrap(), None);
+        assert_eq!(
+            storage.restore_closed_tab("remote000001").unwrap(),
+            Some(tab("https://c.com"))
+        );
+        assert_eq!(closed_urls(&storage), vec!["https://b.com"]);
+
+        let (to_upload, tombstones) = storage.get_closed_tabs_to_upload().unwrap();
+        assert_eq!(to_upload.len(), 1);
+        assert_eq!(tombstones, vec!["remote000001"]);
+
+        // We won't take the tab back from the server until the tombstone's
+        // uploaded.
+        storage
+            .put_synced_closed_tab("remote000001", Some("laptop"), &tab("https://c.com"), 1)
+            .unwrap();
+        assert_eq!(closed_urls(&storage), vec!["https://b.com"]);
+
+        storage
+            .mark_closed_tabs_synced(&[to_upload[0].guid.as_str().into(), "remote000001".into()])
+            .unwrap();
+        let (to_upload, tombstones) = storage.get_closed_tabs_to_upload().unwrap();
+        assert!(to_upload.is_empty());
+        assert!(tombstones.is_empty());
+    }
+
+    #[test]
+    fn test_prune_closed_tabs() {
+        let storage = TabsStorage::new_in_memory().unwrap();
+        for i in 0..MAX_CLOSED_TABS_PER_CLIENT {
+            storage
+                .put_synced_closed_tab(
+                    &format!("remote{:06}", i),
+                    Some("laptop"),
+                    &tab("https://remote.com"),
+                    i as i64,
+                )
+                .unwrap();
+        }
+        let first = storage.add_closed_tab(&tab("https://first.com")).unwrap();
+        storage.mark_closed_tabs_synced(&[first.into()]).unwrap();
+        for _ in 1..MAX_CLOSED_TABS_PER_CLIENT {
+            storage.add_closed_tab(&tab("https://local.com")).unwrap();
+        }
+        // Make sure the first tab is the oldest, even if they were all
+        // closed in the same millisecond.
+        storage
+            .db
+            .execute_all(&["UPDATE closed_tabs SET time_closed = -1 WHERE tab LIKE '%first.com%'"])
+            .unwrap();
+        // Storing a synced tab doesn't prune anything (the sync does that
+        // once it's applied every record), but adding a local tab prunes
+        // both lists.
+        storage
+            .put_synced_closed_tab("remote999999", Some("laptop"), &tab("https://new.com"), 99)
+            .unwrap();
+        storage.add_closed_tab(&tab("https://last.com")).unwrap();
+
+        let closed = storage.get_closed_tabs().unwrap();
+        assert_eq!(closed.len(), MAX_CLOSED_TABS_PER_CLIENT * 2);
+        assert!(!closed_urls(&storage).contains(&"https://first.com".to_owned()));
+        assert!(!closed.iter().any(|c| c.guid == "remote000000"));
+        // The local tab we dropped was uploaded, so we need to delete it from
+        // the server. The remote one is up to the other device.
+        let (_, tombstones) = storage.get_closed_tabs_to_upload().unwrap();
+        assert_eq!(tombstones.len(), 1);
+    }
+
+    #[test]
+    fn test_reset_closed_tabs() {
+        let storage = TabsStorage::new_in_memory().unwrap();
+        assert!(!storage.closed_tabs_sync_enabled().unwrap());
+        storage.set_closed_tabs_sync_enabled(true).unwrap();
+        assert!(storage.closed_tabs_sync_enabled().unwrap());
+
+        let guid = storage.add_closed_tab(&tab("https://a.com")).unwrap();
+        storage.mark_closed_tabs_synced(&[guid.into()]).unwrap();
+        storage
+            .put_synced_closed_tab("remote000001", Some("laptop"), &tab("https://c.com"), 1)
+            .unwrap();
+        storage
+            .set_closed_tabs_last_sync(ServerTimestamp(1000))
+            .unwrap();
+
+        let assoc = StoreSyncAssociation::Connected(CollSyncIds {
+            global: "global".into(),
+            coll: "coll".into(),
+        });
+        storage.reset_closed_tabs(&assoc).unwrap();
+        assert_eq!(storage.get_closed_tabs_sync_assoc().unwrap(), assoc);
+        assert_eq!(storage.get_closed_tabs_last_sync().unwrap(), None);
+        assert_eq!(closed_urls(&storage), vec!["https://a.com"]);
+        let (to_upload, _) = storage.get_closed_tabs_to_upload().unwrap();
+        assert_eq!(to_upload.len(), 1);
+    }
+}
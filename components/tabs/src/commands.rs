@@ -0,0 +1,464 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Commands that act on another device's tabs, like "open this URL" or "close
+//! these tabs".
+//!
+//! Commands are sent through the `clients` collection: when the application
+//! asks us to send one, we add it to the `outgoing_commands` queue, and the
+//! sync manager's command processor writes it into the other client's record
+//! on the next sync (see `sync15::clients::CommandProcessor`). That client
+//! removes the command from its record once it's processed it, which is how
+//! we know it's been acknowledged. Commands sent to us go into the
+//! `incoming_commands` table, until the application takes them.
+//!
+//! Note that the standalone `TabsEngine::sync` doesn't sync the `clients`
+//! collection, so commands are only sent and received when syncing through the
+//! sync manager.
+
+use crate::error::*;
+use crate::storage::{now_ms, TabsStorage};
+use rusqlite::{named_params, Row, NO_PARAMS};
+use serde_derive::{Deserialize, Serialize};
+use sql_support::ConnExt;
+use std::collections::HashSet;
+use sync15::clients::Command;
+
+/// Outgoing commands are forgotten this long after they were requested,
+/// whether or not the other client has acknowledged them, so that the queue
+/// doesn't grow forever if that client never syncs again.
+const COMMAND_EXPIRY_MS: i64 = 7 * 24 * 60 * 60 * 1000;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum TabCommand {
+    /// Opens a URL in a new tab. This is the same as "Send Tab to Device"
+    /// using the `clients` collection, so Desktop understands it too.
+    OpenUrl { url: String, title: String },
+    /// Closes the tabs with these URLs.
+    CloseTabs { urls: Vec<String> },
+}
+
+impl TabCommand {
+    fn to_clients_command(&self, local_id: &str) -> Command {
+        match self {
+            TabCommand::OpenUrl { url, title } => Command::DisplayUri {
+                uri: url.clone(),
+                sender_id: local_id.to_owned(),
+                title: title.clone(),
+            },
+            TabCommand::CloseTabs { urls } => Command::CloseTabs(urls.clone()),
+        }
+    }
+
+    /// Returns the command, and the ID of the client that sent it, if it's
+    /// one we handle.
+    fn from_clients_command(command: &Command) -> Option<(Self, Option<String>)> {
+        match command {
+            Command::DisplayUri {
+                uri,
+                sender_id,
+                title,
+            } => Some((
+                TabCommand::OpenUrl {
+                    url: uri.clone(),
+                    title: title.clone(),
+                },
+                Some(sender_id.clone()),
+            )),
+            Command::CloseTabs(urls) => Some((TabCommand::CloseTabs { urls: urls.clone() }, None)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutgoingCommandStatus {
+    /// Waiting for the next sync.
+    Queued,
+    /// Written into the other client's record, but not processed yet.
+    Sent,
+    /// Processed by the other client.
+    Acknowledged,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutgoingTabCommand {
+    pub id: i64,
+    /// The `client_id` of the `ClientRemoteTabs` this was sent to.
+    pub client_id: String,
+    pub command: TabCommand,
+    pub status: OutgoingCommandStatus,
+    pub time_requested: i64, // In ms.
+}
+
+impl OutgoingTabCommand {
+    fn from_row(row: &Row<'_>) -> Result<Self> {
+        let command: String = row.get("command")?;
+        let time_sent: Option<i64> = row.get("time_sent")?;
+        let time_acked: Option<i64> = row.get("time_acked")?;
+        Ok(Self {
+            id: row.get("id")?,
+            client_id: row.get("client_id")?,
+            command: serde_json::from_str(&command)?,
+            status: match (time_sent, time_acked) {
+                (_, Some(_)) => OutgoingCommandStatus::Acknowledged,
+                (Some(_), None) => OutgoingCommandStatus::Sent,
+                (None, None) => OutgoingCommandStatus::Queued,
+            },
+            time_requested: row.get("time_requested")?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IncomingTabCommand {
+    /// The ID of the client that sent the command. Other clients don't say
+    /// who sent a `CloseTabs` command, so this is only set for `OpenUrl`.
+    pub sender_id: Option<String>,
+    pub command: TabCommand,
+    pub time_received: i64, // In ms.
+}
+
+impl IncomingTabCommand {
+    fn from_row(row: &Row<'_>) -> Result<Self> {
+        let command: String = row.get("command")?;
+        Ok(Self {
+            sender_id: row.get("sender_id")?,
+            command: serde_json::from_str(&command)?,
+            time_received: row.get("time_received")?,
+        })
+    }
+}
+
+impl TabsStorage {
+    /// Queues a command to send to the client with the given `client_id`,
+    /// as reported in `ClientRemoteTabs`, on the next sync.
+    pub fn queue_command(&self, client_id: &str, command: TabCommand) -> Result<()> {
+        if let TabCommand::CloseTabs { urls } = &command {
+            if urls.is_empty() {
+                log::warn!("Not queuing a command to close no tabs");
+                return Ok(());
+            }
+        }
+        let command = serde_json::to_string(&command)?;
+        // Queuing the same command twice before it's sent is a no-op.
+        self.db.execute_named_cached(
+            "INSERT INTO outgoing_commands (client_id, command, time_requested)
+             SELECT :client_id, :command, :now
+             WHERE NOT EXISTS (
+                 SELECT 1 FROM outgoing_commands
+                 WHERE client_id = :client_id AND
+                       command = :command AND
+                       time_sent IS NULL
+             )",
+            named_params! {
+                ":client_id": client_id,
+                ":command": command,
+                ":now": now_ms(),
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Returns the commands we've been asked to send, oldest first.
+    pub fn get_outgoing_commands(&self) -> Result<Vec<OutgoingTabCommand>> {
+        let mut stmt = self.db.prepare_cached(
+            "SELECT id, client_id, command, time_requested, time_sent, time_acked
+             FROM outgoing_commands
+             ORDER BY time_requested, id",
+        )?;
+        let rows = stmt.query_and_then(NO_PARAMS, OutgoingTabCommand::from_row)?;
+        rows.collect()
+    }
+
+    /// Returns the commands other clients have sent us since the last call,
+    /// oldest first, and removes them.
+    pub fn take_incoming_commands(&self) -> Result<Vec<IncomingTabCommand>> {
+        let tx = self.db.unchecked_transaction()?;
+        let commands = {
+            let mut stmt = self.db.prepare_cached(
+                "SELECT sender_id, command, time_received
+                 FROM incoming_commands
+                 ORDER BY time_received, id",
+            )?;
+            let rows = stmt.query_and_then(NO_PARAMS, IncomingTabCommand::from_row)?;
+            rows.collect::<Result<Vec<_>>>()?
+        };
+        self.db.execute_all(&["DELETE FROM incoming_commands"])?;
+        tx.commit()?;
+        Ok(commands)
+    }
+
+    /// Returns the queued commands to write into another client's record.
+    /// This also acknowledges the commands that we sent on a previous sync,
+    /// and that aren't in `current_commands` (the commands in that client's
+    /// record) anymore. For the sync manager's command processor.
+    pub fn fetch_outgoing_commands_for_client(
+        &self,
+        local_id: &str,
+        client_id: &str,
+        current_commands: &HashSet<Command>,
+    ) -> Result<HashSet<Command>> {
+        let now = now_ms();
+        let tx = self.db.unchecked_transaction()?;
+        self.expire_outgoing_commands(now)?;
+        let mut to_send = HashSet::new();
+        for pending in self.get_unacked_commands(client_id)? {
+            let command = pending.command.to_clients_command(local_id);
+            let in_record = current_commands.contains(&command);
+            match pending.status {
+                OutgoingCommandStatus::Sent if !in_record => {
+                    log::debug!("Command {} acknowledged by client", pending.id);
+                    self.set_command_time("time_acked", pending.id, now)?;
+                }
+                // Another of the user's devices might have already written
+                // the same command into the record.
+                OutgoingCommandStatus::Queued if in_record => {
+                    self.set_command_time("time_sent", pending.id, now)?;
+                }
+                OutgoingCommandStatus::Queued => {
+                    to_send.insert(command);
+                }
+                _ => {}
+            }
+        }
+        tx.commit()?;
+        Ok(to_send)
+    }
+
+    /// Marks the queued commands as sent, once the other client's record has
+    /// been uploaded. For the sync manager's command processor.
+    pub fn commands_sent(&self, local_id: &str, client_id: &str, sent: &[Command]) -> Result<()> {
+        let now = now_ms();
+        let tx = self.db.unchecked_transaction()?;
+        for pending in self.get_unacked_commands(client_id)? {
+            if pending.status == OutgoingCommandStatus::Queued
+                && sent.contains(&pending.command.to_clients_command(local_id))
+            {
+                self.set_command_time("time_sent", pending.id, now)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Stores a command sent to us by another client, for the application to
+    /// take. Returns `false` if it's not a tabs command. For the sync
+    /// manager's command processor.
+    pub fn apply_incoming_command(&self, command: &Command) -> Result<bool> {
+        let (command, sender_id) = match TabCommand::from_clients_command(command) {
+            Some(c) => c,
+            None => return Ok(false),
+        };
+        self.db.execute_named_cached(
+            "INSERT INTO incoming_commands (sender_id, command, time_received)
+             VALUES (:sender_id, :command, :now)",
+            named_params! {
+                ":sender_id": sender_id,
+                ":command": serde_json::to_string(&command)?,
+                ":now": now_ms(),
+            },
+        )?;
+        Ok(true)
+    }
+
+    /// Forgets all outgoing and incoming commands.
+    pub(crate) fn delete_all_commands(&self) -> Result<()> {
+        self.db.execute_all(&[
+            "DELETE FROM outgoing_commands",
+            "DELETE FROM incoming_commands",
+        ])?;
+        Ok(())
+    }
+
+    fn get_unacked_commands(&self, client_id: &str) -> Result<Vec<OutgoingTabCommand>> {
+        let mut stmt = self.db.prepare_cached(
+            "SELECT id, client_id, command, time_requested, time_sent, time_acked
+             FROM outgoing_commands
+             WHERE client_id = :client_id AND
+                   time_acked IS NULL",
+        )?;
+        let rows = stmt.query_and_then_named(
+            named_params! { ":client_id": client_id },
+            OutgoingTabCommand::from_row,
+        )?;
+        rows.collect()
+    }
+
+    fn set_command_time(&self, column: &str, id: i64, time: i64) -> Result<()> {
+        self.db.execute_named(
+            &format!(
+                "UPDATE outgoing_commands SET {column} = :time WHERE id = :id",
+                column = column
+            ),
+            named_params! { ":time": time, ":id": id },
+        )?;
+        Ok(())
+    }
+
+    fn expire_outgoing_commands(&self, now: i64) -> Result<()> {
+        self.db.execute_named_cached(
+            "DELETE FROM outgoing_commands WHERE time_requested < :cutoff",
+            named_params! { ":cutoff": now - COMMAND_EXPIRY_MS },
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(commands: &[Command]) -> HashSet<Command> {
+        commands.iter().cloned().collect()
+    }
+
+    fn statuses(storage: &TabsStorage) -> Vec<OutgoingCommandStatus> {
+        storage
+            .get_outgoing_commands()
+            .unwrap()
+            .into_iter()
+            .map(|c| c.status)
+            .collect()
+    }
+
+    #[test]
+    fn test_outgoing_commands() {
+        let storage = TabsStorage::new_in_memory().unwrap();
+        let close = TabCommand::CloseTabs {
+            urls: vec!["https://example.com".into()],
+        };
+        let open = TabCommand::OpenUrl {
+            url: "https://example.org".into(),
+            title: "Example".into(),
+        };
+        storage.queue_command("laptop", close.clone()).unwrap();
+        storage.queue_command("laptop", close.clone()).unwrap();
+        storage.queue_command("laptop", open.clone()).unwrap();
+        storage
+            .queue_command("laptop", TabCommand::CloseTabs { urls: vec![] })
+            .unwrap();
+        assert_eq!(
+            statuses(&storage),
+            vec![OutgoingCommandStatus::Queued, OutgoingCommandStatus::Queued]
+        );
+
+        let close_cmd = close.to_clients_command("phone");
+        let open_cmd = open.to_clients_command("phone");
+        assert_eq!(
+            open_cmd,
+            Command::DisplayUri {
+                uri: "https://example.org".into(),
+                sender_id: "phone".into(),
+                title: "Example".into(),
+            }
+        );
+
+        // Nothing for other clients.
+        assert!(storage
+            .fetch_outgoing_commands_for_client("phone", "tablet", &HashSet::new())
+            .unwrap()
+            .is_empty());
+
+        let to_send = storage
+            .fetch_outgoing_commands_for_client("phone", "laptop", &HashSet::new())
+            .unwrap();
+        assert_eq!(to_send, set(&[close_cmd.clone(), open_cmd.clone()]));
+
+        // Say only the first one fit in the record.
+        storage
+            .commands_sent("phone", "laptop", &[close_cmd.clone()])
+            .unwrap();
+        assert_eq!(
+            statuses(&storage),
+            vec![OutgoingCommandStatus::Sent, OutgoingCommandStatus::Queued]
+        );
+
+        // The laptop hasn't processed it yet, so we try the other one again.
+        let to_send = storage
+            .fetch_outgoing_commands_for_client("phone", "laptop", &set(&[close_cmd]))
+            .unwrap();
+        assert_eq!(to_send, set(&[open_cmd.clone()]));
+        storage
+            .commands_sent("phone", "laptop", &[open_cmd.clone()])
+            .unwrap();
+
+        // Now it's processed the first.
+        let to_send = storage
+            .fetch_outgoing_commands_for_client("phone", "laptop", &set(&[open_cmd]))
+            .unwrap();
+        assert!(to_send.is_empty());
+        assert_eq!(
+            statuses(&storage),
+            vec![
+                OutgoingCommandStatus::Acknowledged,
+                OutgoingCommandStatus::Sent
+            ]
+        );
+
+        // And we can queue the same command again, now that it's been sent.
+        storage.queue_command("laptop", close).unwrap();
+        assert_eq!(storage.get_outgoing_commands().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let storage = TabsStorage::new_in_memory().unwrap();
+        storage
+            .queue_command(
+                "laptop",
+                TabCommand::CloseTabs {
+                    urls: vec!["https://example.com".into()],
+                },
+            )
+            .unwrap();
+        storage
+            .db
+            .execute_all(&["UPDATE outgoing_commands SET time_requested = 0"])
+            .unwrap();
+        assert!(storage
+            .fetch_outgoing_commands_for_client("phone", "laptop", &HashSet::new())
+            .unwrap()
+            .is_empty());
+        assert!(storage.get_outgoing_commands().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_incoming_commands() {
+        let storage = TabsStorage::new_in_memory().unwrap();
+        assert!(!storage
+            .apply_incoming_command(&Command::Wipe("tabs".into()))
+            .unwrap());
+        assert!(storage
+            .apply_incoming_command(&Command::DisplayUri {
+                uri: "https://example.org".into(),
+                sender_id: "laptop".into(),
+                title: "Example".into(),
+            })
+            .unwrap());
+        assert!(storage
+            .apply_incoming_command(&Command::CloseTabs(vec!["https://example.com".into()]))
+            .unwrap());
+
+        let commands = storage.take_incoming_commands().unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].sender_id, Some("laptop".to_string()));
+        assert_eq!(
+            commands[0].command,
+            TabCommand::OpenUrl {
+                url: "https://example.org".into(),
+                title: "Example".into(),
+            }
+        );
+        assert_eq!(commands[1].sender_id, None);
+        assert_eq!(
+            commands[1].command,
+            TabCommand::CloseTabs {
+                urls: vec!["https://example.com".into()],
+            }
+        );
+        assert!(storage.take_incoming_commands().unwrap().is_empty());
+    }
+}
@@ -17,6 +17,12 @@ pub enum ErrorKind {
 
     #[fail(display = "Protobuf decode error: {}", _0)]
     ProtobufDecodeError(#[fail(cause)] prost::DecodeError),
+
+    #[fail(display = "Error executing SQL: {}", _0)]
+    SqlError(#[fail(cause)] rusqlite::Error),
+
+    #[fail(display = "Invalid tab command: {}", _0)]
+    InvalidTabCommand(String),
 }
 
 error_support::define_error! {
@@ -25,5 +31,6 @@ error_support::define_error! {
         (JsonError, serde_json::Error),
         (UrlParseError, url::ParseError),
         (ProtobufDecodeError, prost::DecodeError),
+        (SqlError, rusqlite::Error),
     }
 }
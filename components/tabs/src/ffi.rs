@@ -4,9 +4,13 @@
 
 // This module implement the traits that make the FFI code easier to manage.
 
-use crate::{msg_types, ClientRemoteTabs, Error, ErrorKind, RemoteTab};
+use crate::{
+    msg_types, ClientRemoteTabs, ClosedTab, Error, ErrorKind, IncomingTabCommand,
+    OutgoingCommandStatus, OutgoingTabCommand, RemoteTab, Result, TabCommand,
+};
 use ffi_support::{implement_into_ffi_by_protobuf, ErrorCode, ExternError};
-use std::convert::TryInto;
+use msg_types::{outgoing_tab_command::Status, tab_command::Kind};
+use std::convert::{TryFrom, TryInto};
 use sync15::ErrorKind as Sync15ErrorKind;
 
 pub mod error_codes {
@@ -67,6 +71,13 @@ impl From<RemoteTab> for msg_types::RemoteTab {
             url_history: tab.url_history,
             icon: tab.icon,
             last_used: tab.last_used.try_into().unwrap_or(0),
+            window_id: tab.window_id,
+            index: tab.index,
+            pinned: Some(tab.pinned),
+            group_id: tab.group_id,
+            group_name: tab.group_name,
+            container: tab.container,
+            inactive: Some(tab.inactive),
         }
     }
 }
@@ -78,6 +89,13 @@ impl From<msg_types::RemoteTab> for RemoteTab {
             url_history: msg.url_history,
             icon: msg.icon,
             last_used: msg.last_used.try_into().unwrap_or(0),
+            window_id: msg.window_id,
+            index: msg.index,
+            pinned: msg.pinned.unwrap_or_default(),
+            group_id: msg.group_id,
+            group_name: msg.group_name,
+            container: msg.container,
+            inactive: msg.inactive.unwrap_or_default(),
         }
     }
 }
@@ -88,6 +106,98 @@ impl From<msg_types::RemoteTabs> for Vec<RemoteTab> {
     }
 }
 
+impl From<TabCommand> for msg_types::TabCommand {
+    fn from(command: TabCommand) -> Self {
+        match command {
+            TabCommand::OpenUrl { url, title } => Self {
+                kind: Kind::OpenUrl as i32,
+                urls: vec![url],
+                title: Some(title),
+            },
+            TabCommand::CloseTabs { urls } => Self {
+                kind: Kind::CloseTabs as i32,
+                urls,
+                title: None,
+            },
+        }
+    }
+}
+
+impl TryFrom<msg_types::TabCommand> for TabCommand {
+    type Error = Error;
+    fn try_from(msg: msg_types::TabCommand) -> Result<Self> {
+        Ok(match Kind::from_i32(msg.kind) {
+            Some(Kind::OpenUrl) => match msg.urls.into_iter().next() {
+                Some(url) => TabCommand::OpenUrl {
+                    url,
+                    title: msg.title.unwrap_or_default(),
+                },
+                None => {
+                    return Err(ErrorKind::InvalidTabCommand("Missing URL to open".into()).into())
+                }
+            },
+            Some(Kind::CloseTabs) => TabCommand::CloseTabs { urls: msg.urls },
+            None => {
+                return Err(
+                    ErrorKind::InvalidTabCommand(format!("Unknown kind {}", msg.kind)).into(),
+                )
+            }
+        })
+    }
+}
+
+impl From<Vec<OutgoingTabCommand>> for msg_types::OutgoingTabCommands {
+    fn from(commands: Vec<OutgoingTabCommand>) -> Self {
+        Self {
+            commands: commands
+                .into_iter()
+                .map(|c| msg_types::OutgoingTabCommand {
+                    id: c.id,
+                    client_id: c.client_id,
+                    command: c.command.into(),
+                    status: match c.status {
+                        OutgoingCommandStatus::Queued => Status::Queued,
+                        OutgoingCommandStatus::Sent => Status::Sent,
+                        OutgoingCommandStatus::Acknowledged => Status::Acknowledged,
+                    } as i32,
+                    time_requested: c.time_requested,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<Vec<IncomingTabCommand>> for msg_types::IncomingTabCommands {
+    fn from(commands: Vec<IncomingTabCommand>) -> Self {
+        Self {
+            commands: commands
+                .into_iter()
+                .map(|c| msg_types::IncomingTabCommand {
+                    sender_id: c.sender_id,
+                    command: c.command.into(),
+                    time_received: c.time_received,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<Vec<ClosedTab>> for msg_types::ClosedTabs {
+    fn from(closed_tabs: Vec<ClosedTab>) -> Self {
+        Self {
+            closed_tabs: closed_tabs
+                .into_iter()
+                .map(|c| msg_types::ClosedTab {
+                    guid: c.guid,
+                    client_id: c.client_id,
+                    tab: c.tab.into(),
+                    time_closed: c.time_closed,
+                })
+                .collect(),
+        }
+    }
+}
+
 impl From<Error> for ExternError {
     fn from(e: Error) -> ExternError {
         ExternError::new_error(get_code(&e), e.to_string())
@@ -95,3 +205,7 @@ impl From<Error> for ExternError {
 }
 
 implement_into_ffi_by_protobuf!(msg_types::ClientsTabs);
+implement_into_ffi_by_protobuf!(msg_types::RemoteTab);
+implement_into_ffi_by_protobuf!(msg_types::ClosedTabs);
+implement_into_ffi_by_protobuf!(msg_types::OutgoingTabCommands);
+implement_into_ffi_by_protobuf!(msg_types::IncomingTabCommands);
@@ -7,7 +7,10 @@
 
 #[macro_use]
 pub mod error;
+mod closed_tabs;
+mod commands;
 mod ffi;
+mod schema;
 mod storage;
 mod sync;
 
@@ -15,7 +18,12 @@ pub mod msg_types {
     include!(concat!(env!("OUT_DIR"), "/msg_types.rs"));
 }
 
-pub use crate::storage::{ClientRemoteTabs, RemoteTab};
+pub use crate::closed_tabs::ClosedTab;
+pub use crate::commands::{
+    IncomingTabCommand, OutgoingCommandStatus, OutgoingTabCommand, TabCommand,
+};
+pub use crate::storage::{ClientRemoteTabs, RemoteTab, TabsStorage};
+pub use crate::sync::closed_tabs::ClosedTabsStore;
 pub use crate::sync::engine::TabsEngine;
 pub use crate::sync::store::TabsStore;
 pub use error::{Error, ErrorKind, Result};
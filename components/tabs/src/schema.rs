@@ -0,0 +1,196 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Tabs Schema v3
+//! ==============
+//!
+//! There are six tables:
+//!
+//! - `tabs`: The remote tabs we've downloaded, one row per client record in
+//!   the `tabs` collection. Our own tabs aren't stored, since the application
+//!   gives us the full list of them whenever they change.
+//!
+//! - `moz_meta`: A key-value table for the sync metadata: the last sync
+//!   timestamp, and the sync IDs for the collection. Like the `moz_meta`
+//!   table in places.
+//!
+//! - `outgoing_commands`: Commands, like "close these tabs", that we've been
+//!   asked to send to other clients, and whether they've been sent and
+//!   acknowledged. See the `commands` module.
+//!
+//! - `incoming_commands`: Commands that other clients sent us, which the
+//!   application hasn't taken yet.
+//!
+//! - `closed_tabs`: Recently closed tabs, from this device and, if syncing
+//!   them is enabled, other devices. See the `closed_tabs` module.
+//!
+//! - `closed_tabs_tombstones`: The IDs of closed tabs that were restored or
+//!   dropped locally, and that we need to delete from the server.
+//!
+//! ## `tabs` Columns
+//!
+//! - `guid`: The ID of the record in the `tabs` collection.
+//!
+//! - `client_id`: The ID we report for the client, which is the Firefox
+//!   Accounts device ID when we know it, otherwise the same as `guid`.
+//!
+//! - `client_name` and `device_type`: From the `clients` collection if the
+//!   client was in it when we downloaded the record, otherwise from the
+//!   record itself.
+//!
+//! - `tabs`: The client's tabs, as a JSON array of `RemoteTab`s.
+//!
+//! - `server_modified`: The record's last modified time on the server, in
+//!   milliseconds.
+
+use crate::error::*;
+use rusqlite::Connection;
+use sql_support::ConnExt;
+
+const VERSION: i64 = 3;
+
+const CREATE_TABS_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS tabs (
+        guid            TEXT PRIMARY KEY,
+        client_id       TEXT NOT NULL,
+        client_name     TEXT NOT NULL,
+        device_type     TEXT NOT NULL,
+        tabs            TEXT NOT NULL,
+        server_modified INTEGER NOT NULL
+    )
+";
+
+const CREATE_META_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS moz_meta (
+        key TEXT PRIMARY KEY,
+        value NOT NULL
+    )
+";
+
+const CREATE_OUTGOING_COMMANDS_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS outgoing_commands (
+        id             INTEGER PRIMARY KEY,
+        client_id      TEXT NOT NULL,
+        command        TEXT NOT NULL,
+        time_requested INTEGER NOT NULL,
+        time_sent      INTEGER,
+        time_acked     INTEGER
+    )
+";
+
+const CREATE_INCOMING_COMMANDS_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS incoming_commands (
+        id            INTEGER PRIMARY KEY,
+        sender_id     TEXT,
+        command       TEXT NOT NULL,
+        time_received INTEGER NOT NULL
+    )
+";
+
+// `client_id` is NULL for tabs closed on this device. `sync_status` is one of
+// the `closed_tabs::SyncStatus` values.
+const CREATE_CLOSED_TABS_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS closed_tabs (
+        guid        TEXT PRIMARY KEY,
+        client_id   TEXT,
+        tab         TEXT NOT NULL,
+        time_closed INTEGER NOT NULL,
+        sync_status INTEGER NOT NULL
+    )
+";
+
+const CREATE_CLOSED_TABS_TOMBSTONES_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS closed_tabs_tombstones (
+        guid TEXT PRIMARY KEY
+    )
+";
+
+pub(crate) static LAST_SYNC_META_KEY: &str = "last_sync_time";
+pub(crate) static GLOBAL_SYNCID_META_KEY: &str = "global_sync_id";
+pub(crate) static COLLECTION_SYNCID_META_KEY: &str = "tabs_sync_id";
+pub(crate) static CLOSED_TABS_SYNC_ENABLED_META_KEY: &str = "closed_tabs_sync_enabled";
+pub(crate) static CLOSED_TABS_LAST_SYNC_META_KEY: &str = "closed_tabs_last_sync_time";
+pub(crate) static CLOSED_TABS_GLOBAL_SYNCID_META_KEY: &str = "closed_tabs_global_sync_id";
+pub(crate) static CLOSED_TABS_COLLECTION_SYNCID_META_KEY: &str = "closed_tabs_sync_id";
+
+pub(crate) fn init(db: &Connection) -> Result<()> {
+    let user_version = db.query_one::<i64>("PRAGMA user_version")?;
+    if user_version == 0 {
+        create(db)?;
+    } else if user_version != VERSION {
+        if user_version < VERSION {
+            upgrade(db, user_version)?;
+        } else {
+            log::warn!(
+                "Loaded future schema version {} (we only understand version {}). \
+                 Optimistically ",
+                user_version,
+                VERSION
+            )
+        }
+    }
+    Ok(())
+}
+
+fn upgrade(db: &Connection, from: i64) -> Result<()> {
+    log::debug!("Upgrading schema from {} to {}", from, VERSION);
+    if from < 2 {
+        db.execute_all(&[
+            CREATE_OUTGOING_COMMANDS_TABLE_SQL,
+            CREATE_INCOMING_COMMANDS_TABLE_SQL,
+        ])?;
+    }
+    if from < 3 {
+        db.execute_all(&[
+            CREATE_CLOSED_TABS_TABLE_SQL,
+            CREATE_CLOSED_TABS_TOMBSTONES_TABLE_SQL,
+        ])?;
+    }
+    db.execute_batch(&format!(
+        "PRAGMA user_version = {version}",
+        version = VERSION
+    ))?;
+    Ok(())
+}
+
+fn create(db: &Connection) -> Result<()> {
+    log::debug!("Creating schema");
+    db.execute_all(&[
+        CREATE_TABS_TABLE_SQL,
+        CREATE_META_TABLE_SQL,
+        CREATE_OUTGOING_COMMANDS_TABLE_SQL,
+        CREATE_INCOMING_COMMANDS_TABLE_SQL,
+        CREATE_CLOSED_TABS_TABLE_SQL,
+        CREATE_CLOSED_TABS_TOMBSTONES_TABLE_SQL,
+        &format!("PRAGMA user_version = {version}", version = VERSION),
+    ])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upgrade_from_v1() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_all(&[
+            CREATE_TABS_TABLE_SQL,
+            CREATE_META_TABLE_SQL,
+            "PRAGMA user_version = 1",
+        ])
+        .unwrap();
+        init(&db).unwrap();
+        assert_eq!(db.query_one::<i64>("PRAGMA user_version").unwrap(), VERSION);
+        db.execute_all(&[
+            "INSERT INTO outgoing_commands (client_id, command, time_requested)
+             VALUES ('client', '{}', 0)",
+            "INSERT INTO incoming_commands (command, time_received) VALUES ('{}', 0)",
+            "INSERT INTO closed_tabs (guid, tab, time_closed, sync_status)
+             VALUES ('guid', '{}', 0, 0)",
+            "INSERT INTO closed_tabs_tombstones (guid) VALUES ('guid')",
+        ])
+        .unwrap();
+    }
+}
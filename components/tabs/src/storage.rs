@@ -7,15 +7,53 @@ const URI_LENGTH_MAX: usize = 65536;
 // https://searchfox.org/mozilla-central/rev/ea63a0888d406fae720cf24f4727d87569a8cab5/services/sync/modules/engines/tabs.js#8
 const TAB_ENTRIES_LIMIT: usize = 5;
 
+use crate::error::*;
+use crate::schema;
+use rusqlite::{
+    named_params,
+    types::{FromSql, ToSql},
+    Connection, Row, NO_PARAMS,
+};
+use serde_derive::{Deserialize, Serialize};
+use sql_support::{ConnExt, UncheckedTransaction};
 use std::cell::RefCell;
-use sync15::clients::DeviceType;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use sync15::{clients::DeviceType, CollSyncIds, ServerTimestamp, StoreSyncAssociation};
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct RemoteTab {
     pub title: String,
     pub url_history: Vec<String>,
     pub icon: Option<String>,
     pub last_used: u64, // In ms.
+    /// The ID of the window the tab is in. Only unique per client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_id: Option<String>,
+    /// The position of the tab in its window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub pinned: bool,
+    /// The ID and name of the tab group the tab is in, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_name: Option<String>,
+    /// The name of the container (or "contextual identity") the tab is
+    /// in, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// Inactive tabs haven't been used in a while, and are usually shown
+    /// separately.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub inactive: bool,
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)] // For `skip_serializing_if`.
+pub(crate) fn is_false(b: &bool) -> bool {
+    !*b
 }
 
 #[derive(Clone, Debug)]
@@ -26,23 +64,43 @@ pub struct ClientRemoteTabs {
     pub remote_tabs: Vec<RemoteTab>,
 }
 
+impl ClientRemoteTabs {
+    fn from_row(row: &Row<'_>) -> Result<Self> {
+        let device_type: String = row.get("device_type")?;
+        let tabs: String = row.get("tabs")?;
+        Ok(Self {
+            client_id: row.get("client_id")?,
+            client_name: row.get("client_name")?,
+            device_type: DeviceType::try_from_str(device_type).unwrap_or(DeviceType::Mobile),
+            remote_tabs: serde_json::from_str(&tabs)?,
+        })
+    }
+}
+
+/// Our local tabs are kept in memory, since the application gives us the
+/// full list whenever it changes, but the remote tabs (and everything we need
+/// to sync incrementally) are stored in a SQLite database, so that they're
+/// available before the first sync after a restart.
 pub struct TabsStorage {
     local_tabs: RefCell<Option<Vec<RemoteTab>>>,
-    remote_tabs: RefCell<Option<Vec<ClientRemoteTabs>>>,
+    pub(crate) db: Connection,
 }
 
-impl Default for TabsStorage {
-    fn default() -> Self {
-        Self::new()
+impl TabsStorage {
+    pub fn new(db_path: impl AsRef<Path>) -> Result<Self> {
+        Self::with_connection(Connection::open(db_path)?)
     }
-}
 
-impl TabsStorage {
-    pub fn new() -> Self {
-        Self {
+    pub fn new_in_memory() -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(db: Connection) -> Result<Self> {
+        schema::init(&db)?;
+        Ok(Self {
             local_tabs: RefCell::default(),
-            remote_tabs: RefCell::default(),
-        }
+            db,
+        })
     }
 
     pub fn update_local_state(&mut self, local_state: Vec<RemoteTab>) {
@@ -77,23 +135,154 @@ impl TabsStorage {
         None
     }
 
-    pub fn get_remote_tabs(&self) -> Option<Vec<ClientRemoteTabs>> {
-        self.remote_tabs.borrow().clone()
+    /// Returns the remote tabs from the last sync, or `None` if we haven't
+    /// synced yet.
+    pub fn get_remote_tabs(&self) -> Result<Option<Vec<ClientRemoteTabs>>> {
+        if self.get_last_sync()?.is_none() {
+            return Ok(None);
+        }
+        let mut stmt = self.db.prepare_cached(
+            "SELECT client_id, client_name, device_type, tabs
+             FROM tabs
+             ORDER BY server_modified DESC, guid",
+        )?;
+        let rows = stmt.query_and_then(NO_PARAMS, ClientRemoteTabs::from_row)?;
+        Ok(Some(rows.collect::<Result<_>>()?))
+    }
+
+    pub(crate) fn put_remote_tabs(
+        &self,
+        guid: &str,
+        client: &ClientRemoteTabs,
+        server_modified: ServerTimestamp,
+    ) -> Result<()> {
+        self.db.execute_named_cached(
+            "REPLACE INTO tabs (guid, client_id, client_name, device_type, tabs, server_modified)
+             VALUES (:guid, :client_id, :client_name, :device_type, :tabs, :server_modified)",
+            named_params! {
+                ":guid": guid,
+                ":client_id": client.client_id,
+                ":client_name": client.client_name,
+                ":device_type": client.device_type.as_str(),
+                ":tabs": serde_json::to_string(&client.remote_tabs)?,
+                ":server_modified": server_modified.as_millis(),
+            },
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn delete_remote_tabs(&self, guid: &str) -> Result<()> {
+        self.db.execute_named_cached(
+            "DELETE FROM tabs WHERE guid = :guid",
+            named_params! { ":guid": guid },
+        )?;
+        Ok(())
     }
 
-    pub(crate) fn replace_remote_tabs(&self, new_remote_tabs: Vec<ClientRemoteTabs>) {
-        let mut remote_tabs = self.remote_tabs.borrow_mut();
-        remote_tabs.replace(new_remote_tabs);
+    /// Removes the tabs of clients whose records haven't changed since
+    /// `cutoff`. The server expires tabs records, but we won't hear about that
+    /// when syncing incrementally.
+    pub(crate) fn delete_remote_tabs_older_than(&self, cutoff: ServerTimestamp) -> Result<()> {
+        self.db.execute_named_cached(
+            "DELETE FROM tabs WHERE server_modified < :cutoff",
+            named_params! { ":cutoff": cutoff.as_millis() },
+        )?;
+        Ok(())
     }
 
-    pub fn wipe(&self, delete_local_tabs: bool) {
-        self.remote_tabs.replace(None);
+    pub(crate) fn get_last_sync(&self) -> Result<Option<ServerTimestamp>> {
+        Ok(self
+            .get_meta::<i64>(schema::LAST_SYNC_META_KEY)?
+            .map(ServerTimestamp))
+    }
+
+    pub(crate) fn set_last_sync(&self, last_sync: ServerTimestamp) -> Result<()> {
+        self.put_meta(schema::LAST_SYNC_META_KEY, &last_sync.as_millis())
+    }
+
+    pub(crate) fn get_sync_assoc(&self) -> Result<StoreSyncAssociation> {
+        let global = self.get_meta(schema::GLOBAL_SYNCID_META_KEY)?;
+        let coll = self.get_meta(schema::COLLECTION_SYNCID_META_KEY)?;
+        Ok(if let (Some(global), Some(coll)) = (global, coll) {
+            StoreSyncAssociation::Connected(CollSyncIds { global, coll })
+        } else {
+            StoreSyncAssociation::Disconnected
+        })
+    }
+
+    /// Forgets the remote tabs and the last sync time, and sets the new
+    /// sync IDs.
+    pub(crate) fn reset(&self, assoc: &StoreSyncAssociation) -> Result<()> {
+        let tx = self.db.unchecked_transaction()?;
+        self.delete_all_remote_tabs()?;
+        match assoc {
+            StoreSyncAssociation::Disconnected => {
+                // The commands were for, or from, the devices on the
+                // account we're disconnecting from.
+                self.delete_all_commands()?;
+                self.delete_meta(schema::GLOBAL_SYNCID_META_KEY)?;
+                self.delete_meta(schema::COLLECTION_SYNCID_META_KEY)?;
+            }
+            StoreSyncAssociation::Connected(ids) => {
+                self.put_meta(schema::GLOBAL_SYNCID_META_KEY, &ids.global)?;
+                self.put_meta(schema::COLLECTION_SYNCID_META_KEY, &ids.coll)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn wipe(&self, delete_local_tabs: bool) -> Result<()> {
+        self.delete_all_remote_tabs()?;
+        self.delete_all_commands()?;
         if delete_local_tabs {
             self.local_tabs.replace(None);
         }
+        Ok(())
+    }
+
+    fn delete_all_remote_tabs(&self) -> Result<()> {
+        self.db.execute_all(&["DELETE FROM tabs"])?;
+        self.delete_meta(schema::LAST_SYNC_META_KEY)
+    }
+
+    pub(crate) fn put_meta(&self, key: &str, value: &dyn ToSql) -> Result<()> {
+        self.db.execute_named_cached(
+            "REPLACE INTO moz_meta (key, value) VALUES (:key, :value)",
+            named_params! { ":key": key, ":value": value },
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn get_meta<T: FromSql>(&self, key: &str) -> Result<Option<T>> {
+        Ok(self.db.try_query_row(
+            "SELECT value FROM moz_meta WHERE key = :key",
+            named_params! { ":key": key },
+            |row| Ok::<_, Error>(row.get(0)?),
+            true,
+        )?)
+    }
+
+    pub(crate) fn delete_meta(&self, key: &str) -> Result<()> {
+        self.db.execute_named_cached(
+            "DELETE FROM moz_meta WHERE key = :key",
+            named_params! { ":key": key },
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn unchecked_transaction(&self) -> Result<UncheckedTransaction<'_>> {
+        Ok(self.db.unchecked_transaction()?)
     }
 }
 
+pub(crate) fn now_ms() -> i64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    now.as_secs() as i64 * 1000 + i64::from(now.subsec_millis())
+}
+
 fn is_url_syncable(url: &str) -> bool {
     url.len() <= URI_LENGTH_MAX
         && !(url.starts_with("about:")
@@ -118,9 +307,146 @@ mod tests {
         assert!(!is_url_syncable("file:///Users/eoger/bobo"));
     }
 
+    fn client(id: &str, url: &str) -> ClientRemoteTabs {
+        ClientRemoteTabs {
+            client_id: id.to_owned(),
+            client_name: format!("{}'s device", id),
+            device_type: DeviceType::Desktop,
+            remote_tabs: vec![RemoteTab {
+                title: "A tab".to_owned(),
+                url_history: vec![url.to_owned()],
+                icon: None,
+                last_used: 1234,
+                pinned: true,
+                group_name: Some("Work".to_owned()),
+                ..RemoteTab::default()
+            }],
+        }
+    }
+
+    fn client_ids(storage: &TabsStorage) -> Vec<String> {
+        storage
+            .get_remote_tabs()
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|c| c.client_id)
+            .collect()
+    }
+
+    #[test]
+    fn test_remote_tabs() {
+        let storage = TabsStorage::new_in_memory().unwrap();
+        // We haven't synced yet.
+        assert!(storage.get_remote_tabs().unwrap().is_none());
+
+        storage
+            .put_remote_tabs(
+                "guid1",
+                &client("one", "https://a.com"),
+                ServerTimestamp(1000),
+            )
+            .unwrap();
+        storage
+            .put_remote_tabs(
+                "guid2",
+                &client("two", "https://b.com"),
+                ServerTimestamp(2000),
+            )
+            .unwrap();
+        storage.set_last_sync(ServerTimestamp(2000)).unwrap();
+        // Most recently modified first.
+        assert_eq!(client_ids(&storage), vec!["two", "one"]);
+
+        // Replacing a record keeps one row per client.
+        storage
+            .put_remote_tabs(
+                "guid1",
+                &client("one", "https://c.com"),
+                ServerTimestamp(3000),
+            )
+            .unwrap();
+        let tabs = storage.get_remote_tabs().unwrap().unwrap();
+        assert_eq!(tabs.len(), 2);
+        assert_eq!(tabs[0].client_id, "one");
+        assert_eq!(tabs[0].device_type, DeviceType::Desktop);
+        assert_eq!(tabs[0].remote_tabs[0].url_history, vec!["https://c.com"]);
+        assert!(tabs[0].remote_tabs[0].pinned);
+        assert_eq!(tabs[0].remote_tabs[0].group_name, Some("Work".to_owned()));
+
+        storage.delete_remote_tabs("guid1").unwrap();
+        assert_eq!(client_ids(&storage), vec!["two"]);
+
+        storage
+            .delete_remote_tabs_older_than(ServerTimestamp(2001))
+            .unwrap();
+        assert!(client_ids(&storage).is_empty());
+
+        storage.wipe(false).unwrap();
+        assert!(storage.get_remote_tabs().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_sync_assoc() {
+        let storage = TabsStorage::new_in_memory().unwrap();
+        assert_eq!(
+            storage.get_sync_assoc().unwrap(),
+            StoreSyncAssociation::Disconnected
+        );
+        let assoc = StoreSyncAssociation::Connected(CollSyncIds {
+            global: "global".into(),
+            coll: "coll".into(),
+        });
+        storage
+            .put_remote_tabs(
+                "guid1",
+                &client("one", "https://a.com"),
+                ServerTimestamp(1000),
+            )
+            .unwrap();
+        storage.set_last_sync(ServerTimestamp(1000)).unwrap();
+        storage.reset(&assoc).unwrap();
+        assert_eq!(storage.get_sync_assoc().unwrap(), assoc);
+        assert_eq!(storage.get_last_sync().unwrap(), None);
+        assert!(storage.get_remote_tabs().unwrap().is_none());
+
+        storage.reset(&StoreSyncAssociation::Disconnected).unwrap();
+        assert_eq!(
+            storage.get_sync_assoc().unwrap(),
+            StoreSyncAssociation::Disconnected
+        );
+    }
+
+    #[test]
+    fn test_persistence() {
+        let dir = tempdir::TempDir::new("tabs_persistence").unwrap();
+        let path = dir.path().join("tabs.db");
+        {
+            let mut storage = TabsStorage::new(&path).unwrap();
+            storage
+                .update_local_state(vec![client("local", "https://l.com").remote_tabs.remove(0)]);
+            storage
+                .put_remote_tabs(
+                    "guid1",
+                    &client("one", "https://a.com"),
+                    ServerTimestamp(1000),
+                )
+                .unwrap();
+            storage.set_last_sync(ServerTimestamp(1000)).unwrap();
+        }
+        let storage = TabsStorage::new(&path).unwrap();
+        assert_eq!(
+            storage.get_last_sync().unwrap(),
+            Some(ServerTimestamp(1000))
+        );
+        assert_eq!(client_ids(&storage), vec!["one"]);
+        // Local tabs are only kept in memory.
+        assert_eq!(storage.prepare_local_tabs_for_upload(), None);
+    }
+
     #[test]
     fn test_prepare_local_tabs_for_upload() {
-        let mut storage = TabsStorage::new();
+        let mut storage = TabsStorage::new_in_memory().unwrap();
         assert_eq!(storage.prepare_local_tabs_for_upload(), None);
         storage.update_local_state(vec![
             RemoteTab {
@@ -128,6 +454,7 @@ mod tests {
                 url_history: vec!["about:blank".to_owned(), "https://foo.bar".to_owned()],
                 icon: None,
                 last_used: 0,
+                ..RemoteTab::default()
             },
             RemoteTab {
                 title: "".to_owned(),
@@ -143,6 +470,7 @@ mod tests {
                 ],
                 icon: None,
                 last_used: 0,
+                ..RemoteTab::default()
             },
             RemoteTab {
                 title: "".to_owned(),
@@ -157,12 +485,14 @@ mod tests {
                 ],
                 icon: None,
                 last_used: 0,
+                ..RemoteTab::default()
             },
             RemoteTab {
                 title: "".to_owned(),
                 url_history: vec![],
                 icon: None,
                 last_used: 0,
+                ..RemoteTab::default()
             },
         ]);
         assert_eq!(
@@ -173,6 +503,7 @@ mod tests {
                     url_history: vec!["https://foo.bar".to_owned()],
                     icon: None,
                     last_used: 0,
+                    ..RemoteTab::default()
                 },
                 RemoteTab {
                     title: "".to_owned(),
@@ -185,6 +516,7 @@ mod tests {
                     ],
                     icon: None,
                     last_used: 0,
+                    ..RemoteTab::default()
                 },
             ])
         );
@@ -0,0 +1,256 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::storage::{RemoteTab, TabsStorage};
+use crate::sync::record::ClosedTabRecord;
+use std::cell::RefCell;
+use std::result;
+use sync15::{
+    clients, telemetry, CollectionRequest, IncomingChangeset, OutgoingChangeset, Payload,
+    ServerTimestamp, Store, StoreSyncAssociation,
+};
+use sync_guid::Guid;
+
+pub(crate) const COLLECTION_NAME: &str = "closedtabs";
+
+/// Syncs recently closed tabs. Only used if the application enabled it with
+/// `TabsStorage::set_closed_tabs_sync_enabled`.
+pub struct ClosedTabsStore<'a> {
+    storage: &'a TabsStorage,
+    pub(crate) local_id: RefCell<String>,
+}
+
+impl<'a> ClosedTabsStore<'a> {
+    pub fn new(storage: &'a TabsStorage) -> Self {
+        Self {
+            storage,
+            local_id: RefCell::default(), // Will get replaced in `prepare_for_sync`.
+        }
+    }
+}
+
+impl<'a> Store for ClosedTabsStore<'a> {
+    fn collection_name(&self) -> std::borrow::Cow<'static, str> {
+        COLLECTION_NAME.into()
+    }
+
+    fn meta_global_version(&self) -> Option<usize> {
+        // Other clients don't know about this collection, so it's only in
+        // `meta/global` once one of ours has synced it.
+        Some(1)
+    }
+
+    fn prepare_for_sync(
+        &self,
+        get_client_data: &dyn Fn() -> clients::ClientData,
+    ) -> Result<(), failure::Error> {
+        self.local_id.replace(get_client_data().local_client_id);
+        Ok(())
+    }
+
+    fn apply_incoming(
+        &self,
+        inbound: Vec<IncomingChangeset>,
+        telem: &mut telemetry::Engine,
+    ) -> result::Result<OutgoingChangeset, failure::Error> {
+        assert_eq!(inbound.len(), 1, "only requested one item");
+        let inbound = inbound.into_iter().next().unwrap();
+        let mut incoming_telemetry = telemetry::EngineIncoming::new();
+        let local_id = self.local_id.borrow().clone();
+
+        let tx = self.storage.unchecked_transaction()?;
+        for (payload, _) in inbound.changes {
+            if payload.is_tombstone() {
+                self.storage.delete_synced_closed_tab(payload.id())?;
+                incoming_telemetry.applied(1);
+                continue;
+            }
+            let record = match ClosedTabRecord::from_payload(payload) {
+                Ok(record) => record,
+                Err(e) => {
+                    log::warn!("Error deserializing incoming record: {}", e);
+                    incoming_telemetry.failed_with_reason(1, "deserialize");
+                    continue;
+                }
+            };
+            let client_id = if record.client_id == local_id {
+                None
+            } else {
+                Some(record.client_id.as_str())
+            };
+            self.storage.put_synced_closed_tab(
+                &record.id,
+                client_id,
+                &RemoteTab::from_record_tab(&record.tab),
+                record.closed_at,
+            )?;
+            incoming_telemetry.applied(1);
+        }
+        self.storage.prune_closed_tabs()?;
+        tx.commit()?;
+
+        let mut outgoing = OutgoingChangeset::new(COLLECTION_NAME, inbound.timestamp);
+        let (tabs, tombstones) = self.storage.get_closed_tabs_to_upload()?;
+        for closed in tabs {
+            outgoing.changes.push(Payload::from_record(ClosedTabRecord {
+                id: closed.guid,
+                client_id: local_id.clone(),
+                closed_at: closed.time_closed,
+                tab: closed.tab.to_record_tab(),
+            })?);
+        }
+        for guid in tombstones {
+            outgoing.changes.push(Payload::new_tombstone(guid));
+        }
+        telem.incoming(incoming_telemetry);
+        Ok(outgoing)
+    }
+
+    fn sync_finished(
+        &self,
+        new_timestamp: ServerTimestamp,
+        records_synced: Vec<Guid>,
+    ) -> result::Result<(), failure::Error> {
+        log::info!(
+            "closed tabs sync completed after uploading {} records",
+            records_synced.len()
+        );
+        self.storage.mark_closed_tabs_synced(&records_synced)?;
+        self.storage.set_closed_tabs_last_sync(new_timestamp)?;
+        Ok(())
+    }
+
+    fn sync_finished_readonly(
+        &self,
+        new_timestamp: ServerTimestamp,
+    ) -> result::Result<(), failure::Error> {
+        self.storage.set_closed_tabs_last_sync(new_timestamp)?;
+        Ok(())
+    }
+
+    fn get_collection_requests(&self) -> result::Result<Vec<CollectionRequest>, failure::Error> {
+        let since = self
+            .storage
+            .get_closed_tabs_last_sync()?
+            .unwrap_or_default();
+        Ok(vec![CollectionRequest::new(COLLECTION_NAME)
+            .full()
+            .newer_than(since)])
+    }
+
+    fn get_sync_assoc(&self) -> result::Result<StoreSyncAssociation, failure::Error> {
+        Ok(self.storage.get_closed_tabs_sync_assoc()?)
+    }
+
+    fn reset(&self, assoc: &StoreSyncAssociation) -> result::Result<(), failure::Error> {
+        self.storage.reset_closed_tabs(assoc)?;
+        Ok(())
+    }
+
+    fn wipe(&self) -> result::Result<(), failure::Error> {
+        self.storage.delete_all_closed_tabs()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sync15::telemetry;
+
+    fn incoming(records: Vec<Payload>) -> IncomingChangeset {
+        let mut changeset = IncomingChangeset::new(COLLECTION_NAME, ServerTimestamp(1000));
+        changeset.changes = records
+            .into_iter()
+            .map(|p| (p, ServerTimestamp(1000)))
+            .collect();
+        changeset
+    }
+
+    fn record(id: &str, client_id: &str, url: &str) -> Payload {
+        Payload::from_record(ClosedTabRecord {
+            id: id.to_owned(),
+            client_id: client_id.to_owned(),
+            closed_at: 500,
+            tab: RemoteTab {
+                title: "A tab".to_owned(),
+                url_history: vec![url.to_owned()],
+                ..RemoteTab::default()
+            }
+            .to_record_tab(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sync_closed_tabs() {
+        let storage = TabsStorage::new_in_memory().unwrap();
+        let store = ClosedTabsStore::new(&storage);
+        store.local_id.replace("phone".to_owned());
+        let local_guid = storage
+            .add_closed_tab(&RemoteTab {
+                url_history: vec!["https://local.com".to_owned()],
+                ..RemoteTab::default()
+            })
+            .unwrap();
+        storage
+            .put_synced_closed_tab("restored0001", Some("laptop"), &RemoteTab::default(), 1)
+            .unwrap();
+        storage.restore_closed_tab("restored0001").unwrap();
+
+        let mut telem = telemetry::Engine::new(COLLECTION_NAME);
+        let outgoing = store
+            .apply_incoming(
+                vec![incoming(vec![
+                    record("laptop000001", "laptop", "https://laptop.com"),
+                    // Our own tab, which we forgot about after a reset.
+                    record("phone0000001", "phone", "https://phone.com"),
+                    // Restored here, so it stays deleted.
+                    record("restored0001", "laptop", "https://restored.com"),
+                ])],
+                &mut telem,
+            )
+            .unwrap();
+        let closed = storage.get_closed_tabs().unwrap();
+        assert_eq!(closed.len(), 3);
+        let laptop = closed.iter().find(|c| c.guid == "laptop000001").unwrap();
+        assert_eq!(laptop.client_id, Some("laptop".to_owned()));
+        assert_eq!(laptop.tab.url_history, vec!["https://laptop.com"]);
+        let phone = closed.iter().find(|c| c.guid == "phone0000001").unwrap();
+        assert_eq!(phone.client_id, None);
+
+        let ids: Vec<_> = outgoing
+            .changes
+            .iter()
+            .map(|p| (p.id().to_owned(), p.is_tombstone()))
+            .collect();
+        assert_eq!(
+            ids,
+            vec![(local_guid, false), ("restored0001".to_owned(), true)]
+        );
+        let record = ClosedTabRecord::from_payload(
+            outgoing
+                .changes
+                .into_iter()
+                .find(|p| !p.is_tombstone())
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(record.client_id, "phone");
+
+        // An incoming tombstone means the tab was restored somewhere else.
+        let mut telem = telemetry::Engine::new(COLLECTION_NAME);
+        store
+            .apply_incoming(
+                vec![incoming(vec![Payload::new_tombstone("laptop000001")])],
+                &mut telem,
+            )
+            .unwrap();
+        assert!(!storage
+            .get_closed_tabs()
+            .unwrap()
+            .iter()
+            .any(|c| c.guid == "laptop000001"));
+    }
+}
@@ -2,28 +2,36 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use crate::closed_tabs::ClosedTab;
+use crate::commands::{IncomingTabCommand, OutgoingTabCommand, TabCommand};
 use crate::error::*;
 use crate::storage::{ClientRemoteTabs, RemoteTab, TabsStorage};
+use crate::sync::closed_tabs::{self, ClosedTabsStore};
 use crate::sync::store::TabsStore;
 use interrupt::NeverInterrupts;
 use std::cell::{Cell, RefCell};
-use sync15::{sync_multiple, telemetry, KeyBundle, MemoryCachedState, Sync15StorageClientInit};
+use std::path::Path;
+use sync15::{
+    sync_multiple, telemetry, KeyBundle, MemoryCachedState, Store, Sync15StorageClientInit,
+};
 
 pub struct TabsEngine {
     pub storage: TabsStorage,
     mem_cached_state: Cell<MemoryCachedState>,
 }
 
-impl Default for TabsEngine {
-    fn default() -> Self {
-        Self::new()
+impl TabsEngine {
+    pub fn new(db_path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::with_storage(TabsStorage::new(db_path)?))
     }
-}
 
-impl TabsEngine {
-    pub fn new() -> Self {
+    pub fn new_in_memory() -> Result<Self> {
+        Ok(Self::with_storage(TabsStorage::new_in_memory()?))
+    }
+
+    fn with_storage(storage: TabsStorage) -> Self {
         Self {
-            storage: TabsStorage::new(),
+            storage,
             mem_cached_state: Cell::default(),
         }
     }
@@ -32,10 +40,41 @@ impl TabsEngine {
         self.storage.update_local_state(local_state);
     }
 
-    pub fn remote_tabs(&self) -> Option<Vec<ClientRemoteTabs>> {
+    pub fn remote_tabs(&self) -> Result<Option<Vec<ClientRemoteTabs>>> {
         self.storage.get_remote_tabs()
     }
 
+    /// Queues a command for another client's tabs, to send on the next
+    /// sync through the sync manager.
+    pub fn queue_command(&self, client_id: &str, command: TabCommand) -> Result<()> {
+        self.storage.queue_command(client_id, command)
+    }
+
+    pub fn outgoing_commands(&self) -> Result<Vec<OutgoingTabCommand>> {
+        self.storage.get_outgoing_commands()
+    }
+
+    pub fn take_incoming_commands(&self) -> Result<Vec<IncomingTabCommand>> {
+        self.storage.take_incoming_commands()
+    }
+
+    /// Remembers a tab that the user just closed, and returns its ID.
+    pub fn add_closed_tab(&self, tab: &RemoteTab) -> Result<String> {
+        self.storage.add_closed_tab(tab)
+    }
+
+    pub fn closed_tabs(&self) -> Result<Vec<ClosedTab>> {
+        self.storage.get_closed_tabs()
+    }
+
+    pub fn restore_closed_tab(&self, guid: &str) -> Result<Option<RemoteTab>> {
+        self.storage.restore_closed_tab(guid)
+    }
+
+    pub fn set_closed_tabs_sync_enabled(&self, enabled: bool) -> Result<()> {
+        self.storage.set_closed_tabs_sync_enabled(enabled)
+    }
+
     /// A convenience wrapper around sync_multiple.
     pub fn sync(
         &self,
@@ -51,9 +90,15 @@ impl TabsEngine {
         // which means our `local_id` will never be set.
         // Do it here.
         store.local_id = RefCell::new(local_id.to_owned());
+        let closed_tabs_store = ClosedTabsStore::new(&self.storage);
+        closed_tabs_store.local_id.replace(local_id.to_owned());
+        let mut stores: Vec<&dyn Store> = vec![&store];
+        if self.storage.closed_tabs_sync_enabled()? {
+            stores.push(&closed_tabs_store);
+        }
 
         let mut result = sync_multiple(
-            &[&store],
+            &stores,
             &mut None,
             &mut mem_cached_state,
             storage_init,
@@ -69,9 +114,11 @@ impl TabsEngine {
         if let Err(e) = result.result {
             return Err(e.into());
         }
-        match result.engine_results.remove("tabs") {
-            None | Some(Ok(())) => Ok(result.telemetry),
-            Some(Err(e)) => Err(e.into()),
+        for name in &["tabs", closed_tabs::COLLECTION_NAME] {
+            if let Some(Err(e)) = result.engine_results.remove(*name) {
+                return Err(e.into());
+            }
         }
+        Ok(result.telemetry)
     }
 }
@@ -3,7 +3,9 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use crate::error::*;
+use crate::storage::is_false;
 use serde_derive::{Deserialize, Serialize};
+use std::io::{self, Write};
 
 #[derive(Debug, Clone, Hash, PartialEq, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
@@ -12,6 +14,28 @@ pub struct TabsRecordTab {
     pub url_history: Vec<String>,
     pub icon: Option<String>,
     pub last_used: u64, // Seconds since epoch!
+    // The fields below are optional, and older clients ignore them.
+    /// The ID of the window the tab is in. Only unique per client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_id: Option<String>,
+    /// The position of the tab in its window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub pinned: bool,
+    /// The ID and name of the tab group the tab is in, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_name: Option<String>,
+    /// The name of the container (or "contextual identity") the tab is
+    /// in, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// Inactive tabs haven't been used in a while, and are usually shown
+    /// separately.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub inactive: bool,
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Serialize, Deserialize)]
@@ -28,4 +52,162 @@ impl TabsRecord {
         let record: TabsRecord = payload.into_record()?;
         Ok(record)
     }
+
+    /// Drops tabs, from the end of the list, until the record fits in
+    /// `max_payload_size` bytes once it's encrypted. Callers should sort the
+    /// tabs so that the least recently used ones are last.
+    pub fn shrink_to_fit(&mut self, max_payload_size: usize) -> Result<()> {
+        // The encrypted payload is base64-encoded, and has an IV and HMAC,
+        // so leave some room for that. See bug 535326 comment 8 for the
+        // estimate, which is the same as Desktop's.
+        let max_serialized_size = match ((max_payload_size / 4) * 3).checked_sub(1500) {
+            Some(size) => size,
+            None => {
+                self.tabs.clear();
+                return Ok(());
+            }
+        };
+        let size = serialized_size(self)?;
+        if size <= max_serialized_size || self.tabs.is_empty() {
+            // There's nothing more we can drop if it's still too big without
+            // any tabs.
+            return Ok(());
+        }
+        // Estimate how many tabs will fit, then drop more until they do.
+        let cutoff = (self.tabs.len() * max_serialized_size).saturating_sub(1) / size + 1;
+        self.tabs.truncate(cutoff + 1);
+        while serialized_size(self)? > max_serialized_size {
+            if self.tabs.pop().is_none() {
+                break;
+            }
+        }
+        log::info!(
+            "Trimmed local tabs to {} to fit the record",
+            self.tabs.len()
+        );
+        Ok(())
+    }
+}
+
+/// A recently closed tab, in the `closedtabs` collection.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClosedTabRecord {
+    pub id: String,
+    /// The ID of the client that closed the tab; the same as its `tabs`
+    /// record ID.
+    pub client_id: String,
+    pub closed_at: i64, // In ms.
+    pub tab: TabsRecordTab,
+}
+
+impl ClosedTabRecord {
+    #[inline]
+    pub fn from_payload(payload: sync15::Payload) -> Result<Self> {
+        let record: ClosedTabRecord = payload.into_record()?;
+        Ok(record)
+    }
+}
+
+/// A writer that only counts the bytes written to it.
+#[derive(Default)]
+struct WriteCount(usize);
+
+impl Write for WriteCount {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn serialized_size(record: &TabsRecord) -> Result<usize> {
+    let mut w = WriteCount::default();
+    serde_json::to_writer(&mut w, record)?;
+    Ok(w.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(num_tabs: u64) -> TabsRecord {
+        TabsRecord {
+            id: "client".into(),
+            client_name: "Laptop".into(),
+            tabs: (0..num_tabs)
+                .map(|i| TabsRecordTab {
+                    title: format!("Tab {}", i),
+                    url_history: vec![format!("https://example.com/{}", "x".repeat(100))],
+                    last_used: num_tabs - i,
+                    ..TabsRecordTab::default()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let mut small = record(10);
+        small.shrink_to_fit(256 * 1024).unwrap();
+        assert_eq!(small.tabs.len(), 10);
+
+        let mut large = record(1000);
+        large.shrink_to_fit(32 * 1024).unwrap();
+        assert!(!large.tabs.is_empty() && large.tabs.len() < 1000);
+        assert!(serialized_size(&large).unwrap() <= (32 * 1024 / 4) * 3 - 1500);
+        // The most recently used tabs are kept.
+        assert_eq!(large.tabs[0].title, "Tab 0");
+
+        let mut tiny = record(10);
+        tiny.shrink_to_fit(1000).unwrap();
+        assert!(tiny.tabs.is_empty());
+
+        // Just enough room for the IV and HMAC, but not the record.
+        let mut no_room = record(10);
+        no_room.shrink_to_fit(2000).unwrap();
+        assert!(no_room.tabs.is_empty());
+
+        let mut empty = TabsRecord {
+            client_name: "x".repeat(10 * 1024),
+            ..record(0)
+        };
+        empty.shrink_to_fit(4 * 1024).unwrap();
+        assert!(empty.tabs.is_empty());
+    }
+
+    #[test]
+    fn test_optional_fields() {
+        // Older clients don't send the new fields...
+        let tab: TabsRecordTab = serde_json::from_value(serde_json::json!({
+            "title": "Example",
+            "urlHistory": ["https://example.com"],
+            "icon": null,
+            "lastUsed": 1234,
+        }))
+        .unwrap();
+        assert_eq!(tab.window_id, None);
+        assert!(!tab.pinned);
+        // ...and we don't send the ones that aren't set.
+        let json = serde_json::to_value(&TabsRecordTab {
+            pinned: true,
+            group_name: Some("Work".into()),
+            ..tab
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "title": "Example",
+                "urlHistory": ["https://example.com"],
+                "icon": null,
+                "lastUsed": 1234,
+                "pinned": true,
+                "groupName": "Work",
+            })
+        );
+    }
 }
@@ -14,21 +14,43 @@ use sync15::{
 };
 use sync_guid::Guid;
 
+// https://searchfox.org/mozilla-central/rev/ea63a0888d406fae720cf24f4727d87569a8cab5/services/sync/modules/engines/tabs.js#9
+// The server expires tabs records after this long, so we forget about them
+// too. (Otherwise we'd keep them forever, since we only see changed records.)
+const TABS_TTL_MS: i64 = 21 * 24 * 60 * 60 * 1000;
+
+// The server's default, used until `set_max_payload_size` tells us otherwise.
+const DEFAULT_MAX_PAYLOAD_SIZE: usize = 256 * 1024;
+
 impl RemoteTab {
-    fn from_record_tab(tab: &TabsRecordTab) -> Self {
+    pub(crate) fn from_record_tab(tab: &TabsRecordTab) -> Self {
         Self {
             title: tab.title.clone(),
             url_history: tab.url_history.clone(),
             icon: tab.icon.clone(),
             last_used: tab.last_used.checked_mul(1000).unwrap_or_default(),
+            window_id: tab.window_id.clone(),
+            index: tab.index,
+            pinned: tab.pinned,
+            group_id: tab.group_id.clone(),
+            group_name: tab.group_name.clone(),
+            container: tab.container.clone(),
+            inactive: tab.inactive,
         }
     }
-    fn to_record_tab(&self) -> TabsRecordTab {
+    pub(crate) fn to_record_tab(&self) -> TabsRecordTab {
         TabsRecordTab {
             title: self.title.clone(),
             url_history: self.url_history.clone(),
             icon: self.icon.clone(),
             last_used: self.last_used.checked_div(1000).unwrap_or_default(),
+            window_id: self.window_id.clone(),
+            index: self.index,
+            pinned: self.pinned,
+            group_id: self.group_id.clone(),
+            group_name: self.group_name.clone(),
+            container: self.container.clone(),
+            inactive: self.inactive,
         }
     }
 }
@@ -71,9 +93,8 @@ impl ClientRemoteTabs {
 pub struct TabsStore<'a> {
     storage: &'a TabsStorage,
     remote_clients: RefCell<HashMap<String, RemoteClient>>,
-    last_sync: Cell<Option<ServerTimestamp>>, // We use a cell because `sync_finished` doesn't take a mutable reference to &self.
-    sync_store_assoc: RefCell<StoreSyncAssociation>,
     pub(crate) local_id: RefCell<String>,
+    max_payload_size: Cell<usize>,
 }
 
 impl<'a> TabsStore<'a> {
@@ -81,16 +102,10 @@ impl<'a> TabsStore<'a> {
         Self {
             storage,
             remote_clients: RefCell::default(),
-            last_sync: Cell::default(),
-            sync_store_assoc: RefCell::new(StoreSyncAssociation::Disconnected),
             local_id: RefCell::default(), // Will get replaced in `prepare_for_sync`.
+            max_payload_size: Cell::new(DEFAULT_MAX_PAYLOAD_SIZE),
         }
     }
-    fn wipe_reset_helper(&self, is_wipe: bool) -> result::Result<(), failure::Error> {
-        self.remote_clients.borrow_mut().clear();
-        self.storage.wipe(is_wipe);
-        Ok(())
-    }
 }
 
 impl<'a> Store for TabsStore<'a> {
@@ -108,6 +123,10 @@ impl<'a> Store for TabsStore<'a> {
         Ok(())
     }
 
+    fn set_max_payload_size(&self, max_payload_size: usize) {
+        self.max_payload_size.set(max_payload_size);
+    }
+
     fn apply_incoming(
         &self,
         inbound: Vec<IncomingChangeset>,
@@ -117,18 +136,24 @@ impl<'a> Store for TabsStore<'a> {
         let inbound = inbound.into_iter().next().unwrap();
         let mut incoming_telemetry = telemetry::EngineIncoming::new();
         let local_id = self.local_id.borrow().clone();
-        let mut remote_tabs = Vec::with_capacity(inbound.changes.len());
 
-        for incoming in inbound.changes {
-            if incoming.0.id() == local_id {
+        let tx = self.storage.unchecked_transaction()?;
+        for (payload, server_modified) in inbound.changes {
+            if payload.id() == local_id {
                 // That's our own record, ignore it.
                 continue;
             }
-            let record = match TabsRecord::from_payload(incoming.0) {
+            let guid = payload.id().to_owned();
+            if payload.is_tombstone() {
+                self.storage.delete_remote_tabs(&guid)?;
+                incoming_telemetry.applied(1);
+                continue;
+            }
+            let record = match TabsRecord::from_payload(payload) {
                 Ok(record) => record,
                 Err(e) => {
                     log::warn!("Error deserializing incoming record: {}", e);
-                    incoming_telemetry.failed(1);
+                    incoming_telemetry.failed_with_reason(1, "deserialize");
                     continue;
                 }
             };
@@ -146,11 +171,18 @@ impl<'a> Store for TabsStore<'a> {
             } else {
                 ClientRemoteTabs::from_record(id, record)
             };
-            remote_tabs.push(tab);
+            self.storage.put_remote_tabs(&guid, &tab, server_modified)?;
+            incoming_telemetry.applied(1);
         }
-        self.storage.replace_remote_tabs(remote_tabs);
+        self.storage.delete_remote_tabs_older_than(ServerTimestamp(
+            inbound.timestamp.as_millis() - TABS_TTL_MS,
+        ))?;
+        tx.commit()?;
         let mut outgoing = OutgoingChangeset::new("tabs", inbound.timestamp);
-        if let Some(local_tabs) = self.storage.prepare_local_tabs_for_upload() {
+        if let Some(mut local_tabs) = self.storage.prepare_local_tabs_for_upload() {
+            // If there are too many tabs to fit in the record, we drop the
+            // least recently used ones.
+            local_tabs.sort_by(|a, b| b.last_used.cmp(&a.last_used));
             let (client_name, device_type) = self
                 .remote_clients
                 .borrow()
@@ -166,9 +198,11 @@ impl<'a> Store for TabsStore<'a> {
                 client_id: local_id,
                 client_name,
                 device_type,
-                remote_tabs: local_tabs.to_vec(),
+                remote_tabs: local_tabs,
             };
-            let payload = Payload::from_record(local_record.to_record())?;
+            let mut record = local_record.to_record();
+            record.shrink_to_fit(self.max_payload_size.get())?;
+            let payload = Payload::from_record(record)?;
             log::trace!("outgoing {:?}", payload);
             outgoing.changes.push(payload);
         }
@@ -185,27 +219,38 @@ impl<'a> Store for TabsStore<'a> {
             "sync completed after uploading {} records",
             records_synced.len()
         );
-        self.last_sync.set(Some(new_timestamp));
+        self.storage.set_last_sync(new_timestamp)?;
+        Ok(())
+    }
+
+    fn sync_finished_readonly(
+        &self,
+        new_timestamp: ServerTimestamp,
+    ) -> result::Result<(), failure::Error> {
+        self.storage.set_last_sync(new_timestamp)?;
         Ok(())
     }
 
     fn get_collection_requests(&self) -> result::Result<Vec<CollectionRequest>, failure::Error> {
-        let since = self.last_sync.get().unwrap_or_default();
+        let since = self.storage.get_last_sync()?.unwrap_or_default();
         Ok(vec![CollectionRequest::new("tabs")
             .full()
             .newer_than(since)])
     }
 
     fn get_sync_assoc(&self) -> result::Result<StoreSyncAssociation, failure::Error> {
-        Ok(self.sync_store_assoc.borrow().clone())
+        Ok(self.storage.get_sync_assoc()?)
     }
 
     fn reset(&self, assoc: &StoreSyncAssociation) -> result::Result<(), failure::Error> {
-        self.sync_store_assoc.replace(assoc.clone());
-        self.wipe_reset_helper(false)
+        self.remote_clients.borrow_mut().clear();
+        self.storage.reset(assoc)?;
+        Ok(())
     }
 
     fn wipe(&self) -> result::Result<(), failure::Error> {
-        self.wipe_reset_helper(true)
+        self.remote_clients.borrow_mut().clear();
+        self.storage.wipe(true)?;
+        Ok(())
     }
 }
@@ -38,6 +38,13 @@ pub(crate) fn note_backend(which: &str) {
 
 pub fn send(request: crate::Request) -> Result<crate::Response, crate::Error> {
     validate_request(&request)?;
+    let response = send_with_backend(request)?;
+    #[cfg(feature = "gzip")]
+    let response = crate::gzip::decode_response(response)?;
+    Ok(response)
+}
+
+fn send_with_backend(request: crate::Request) -> Result<crate::Response, crate::Error> {
     if ffi_is_forced() {
         return self::ffi::send(request);
     }
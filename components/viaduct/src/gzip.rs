@@ -0,0 +1,127 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::{header_names, Error, Request, Response};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::io::{Read, Write};
+
+// Every gzip stream starts with these bytes.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+impl Request {
+    /// Compresses the body with gzip, and sets the Content-Encoding header.
+    /// Only use this for servers that accept compressed request bodies.
+    ///
+    /// ## Example
+    /// ```
+    /// # use viaduct::{Request, header_names};
+    /// # use url::Url;
+    /// # fn main() -> Result<(), viaduct::Error> {
+    /// # let some_url = url::Url::parse("https://www.example.com").unwrap();
+    /// let req = Request::post(some_url).body("[1, 2, 3]").gzip_body()?;
+    /// assert_eq!(req.headers.get(header_names::CONTENT_ENCODING), Some("gzip"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn gzip_body(mut self) -> Result<Self, Error> {
+        if let Some(body) = self.body.take() {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder
+                .write_all(&body)
+                .and_then(|_| encoder.finish())
+                .map(|compressed| self.body = Some(compressed))
+                .map_err(|e| Error::BackendError(format!("Failed to gzip request body: {}", e)))?;
+            self.headers
+                .insert(header_names::CONTENT_ENCODING, "gzip")?;
+        }
+        Ok(self)
+    }
+
+    /// Asks the server to gzip the response. Gzipped responses are
+    /// decompressed before they're returned from `send`.
+    pub fn accept_gzip(self) -> Result<Self, Error> {
+        self.header(header_names::ACCEPT_ENCODING, "gzip")
+    }
+}
+
+/// Decompresses the body of a gzipped response. Some backends decompress
+/// responses themselves, but leave the Content-Encoding header, so we check
+/// that the body is actually gzipped first.
+pub(crate) fn decode_response(mut response: Response) -> Result<Response, Error> {
+    let is_gzipped = match response.headers.get(header_names::CONTENT_ENCODING) {
+        Some(encoding) => encoding.eq_ignore_ascii_case("gzip"),
+        None => false,
+    };
+    if is_gzipped && response.body.starts_with(&GZIP_MAGIC) {
+        let mut body = Vec::with_capacity(response.body.len() * 4);
+        GzDecoder::new(&response.body[..])
+            .read_to_end(&mut body)
+            .map_err(|e| Error::NetworkError(format!("Invalid gzipped response: {}", e)))?;
+        response.body = body;
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Headers, Method};
+    use url::Url;
+
+    fn response(body: Vec<u8>, encoding: Option<&str>) -> Response {
+        let mut headers = Headers::new();
+        if let Some(encoding) = encoding {
+            headers
+                .insert(header_names::CONTENT_ENCODING, encoding)
+                .unwrap();
+        }
+        Response {
+            request_method: Method::Get,
+            url: Url::parse("https://www.example.com").unwrap(),
+            status: 200,
+            headers,
+            body,
+        }
+    }
+
+    #[test]
+    fn test_gzip_round_trip() {
+        let body = br#"{"id":"aaaaaaaaaaaa","payload":"[1,2,3]"}"#.repeat(100);
+        let req = Request::post(Url::parse("https://www.example.com").unwrap())
+            .body(body.clone())
+            .gzip_body()
+            .unwrap();
+        let compressed = req.body.unwrap();
+        assert!(compressed.starts_with(&GZIP_MAGIC));
+        assert!(compressed.len() < body.len());
+
+        let resp = decode_response(response(compressed, Some("gzip"))).unwrap();
+        assert_eq!(resp.body, body);
+    }
+
+    #[test]
+    fn test_gzip_empty_body() {
+        let req = Request::get(Url::parse("https://www.example.com").unwrap())
+            .gzip_body()
+            .unwrap();
+        assert_eq!(req.body, None);
+        assert_eq!(req.headers.get(header_names::CONTENT_ENCODING), None);
+    }
+
+    #[test]
+    fn test_already_decoded_response() {
+        // The backend decompressed the body, but left the header.
+        let resp = decode_response(response(b"[]".to_vec(), Some("gzip"))).unwrap();
+        assert_eq!(resp.body, b"[]");
+        let resp = decode_response(response(b"[]".to_vec(), None)).unwrap();
+        assert_eq!(resp.body, b"[]");
+    }
+
+    #[test]
+    fn test_invalid_gzip_response() {
+        let mut body = GZIP_MAGIC.to_vec();
+        body.extend_from_slice(b"not really gzip");
+        assert!(decode_response(response(body, Some("gzip"))).is_err());
+    }
+}
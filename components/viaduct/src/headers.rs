@@ -369,6 +369,7 @@ pub mod consts {
         (ACCEPT_ENCODING, "accept-encoding"),
         (ACCEPT, "accept"),
         (AUTHORIZATION, "authorization"),
+        (CONTENT_ENCODING, "content-encoding"),
         (CONTENT_TYPE, "content-type"),
         (ETAG, "etag"),
         (IF_NONE_MATCH, "if-none-match"),
@@ -380,6 +381,7 @@ pub mod consts {
         (X_LAST_MODIFIED, "x-last-modified"),
         (X_TIMESTAMP, "x-timestamp"),
         (X_WEAVE_NEXT_OFFSET, "x-weave-next-offset"),
+        (X_WEAVE_QUOTA_REMAINING, "x-weave-quota-remaining"),
         (X_WEAVE_RECORDS, "x-weave-records"),
         (X_WEAVE_TIMESTAMP, "x-weave-timestamp"),
         (X_WEAVE_BACKOFF, "x-weave-backoff"),
@@ -11,6 +11,8 @@ mod headers;
 
 mod backend;
 pub mod error;
+#[cfg(feature = "gzip")]
+mod gzip;
 mod settings;
 pub use error::*;
 
@@ -0,0 +1,174 @@
+/* Any copyright is dedicated to the Public Domain.
+http://creativecommons.org/publicdomain/zero/1.0/ */
+
+use crate::http::{Request, Response};
+
+/// A failure to inject into the server's responses, like an error status or
+/// a backoff header. Faults are matched against requests in the order they
+/// were added, and the first match is used.
+///
+/// ```
+/// # use sync_test_server::Fault;
+/// // Fail the next two uploads to the history collection.
+/// let fault = Fault::status(503)
+///     .method("POST")
+///     .path("/storage/history")
+///     .times(2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Fault {
+    method: Option<String>,
+    path: Option<String>,
+    remaining: Option<usize>,
+    action: FaultAction,
+    headers: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+enum FaultAction {
+    // Replace the response with one with this status and body.
+    Respond { status: u16, body: String },
+    // Let the server respond, but add our headers to its response.
+    AddHeaders,
+}
+
+impl Fault {
+    /// Responds with `status`, instead of handling the request.
+    pub fn status(status: u16) -> Self {
+        Self::new(FaultAction::Respond {
+            status,
+            body: "0".into(),
+        })
+    }
+
+    /// Handles the request as usual, but adds a header to the response.
+    pub fn header(name: &str, value: impl ToString) -> Self {
+        Self::new(FaultAction::AddHeaders).with_header(name, value)
+    }
+
+    /// Adds an `X-Weave-Backoff` header to the response.
+    pub fn backoff(seconds: u32) -> Self {
+        Self::header("X-Weave-Backoff", seconds)
+    }
+
+    /// Responds with a 503 and a `Retry-After` header.
+    pub fn retry_after(seconds: u32) -> Self {
+        Self::status(503).with_header("Retry-After", seconds)
+    }
+
+    fn new(action: FaultAction) -> Self {
+        Fault {
+            method: None,
+            path: None,
+            remaining: Some(1),
+            action,
+            headers: vec![],
+        }
+    }
+
+    /// Only matches requests with this method.
+    pub fn method(mut self, method: &str) -> Self {
+        self.method = Some(method.to_ascii_uppercase());
+        self
+    }
+
+    /// Only matches requests whose path contains `path`, like
+    /// `"/info/collections"`, `"/storage/meta/global"` or `"/1.0/sync/1.5"`
+    /// for the tokenserver.
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    /// Matches the first `count` requests. Faults match one request by
+    /// default.
+    pub fn times(mut self, count: usize) -> Self {
+        self.remaining = Some(count);
+        self
+    }
+
+    /// Matches every request until the faults are cleared.
+    pub fn always(mut self) -> Self {
+        self.remaining = None;
+        self
+    }
+
+    /// Adds a header to the response.
+    pub fn with_header(mut self, name: &str, value: impl ToString) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Uses `body` as the body of the response, for faults made with
+    /// `Fault::status`.
+    pub fn with_body(mut self, body: &str) -> Self {
+        if let FaultAction::Respond { body: b, .. } = &mut self.action {
+            *b = body.to_string();
+        }
+        self
+    }
+
+    fn matches(&self, request: &Request) -> bool {
+        self.method.iter().all(|m| *m == request.method)
+            && self.path.iter().all(|p| request.path.contains(p.as_str()))
+    }
+
+    // Returns true if the fault is used up.
+    fn use_once(&mut self) -> bool {
+        match &mut self.remaining {
+            Some(remaining) => {
+                *remaining -= 1;
+                *remaining == 0
+            }
+            None => false,
+        }
+    }
+}
+
+/// The faults waiting to be injected.
+#[derive(Debug, Default)]
+pub(crate) struct Faults(Vec<Fault>);
+
+impl Faults {
+    pub fn add(&mut self, fault: Fault) {
+        if fault.remaining != Some(0) {
+            self.0.push(fault);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// Handles `request` with the first matching fault, calling `handle` to
+    /// make the response if the fault doesn't replace it.
+    pub fn respond(
+        &mut self,
+        request: &Request,
+        handle: impl FnOnce(&Request) -> Response,
+    ) -> Response {
+        let index = match self.0.iter().position(|f| f.matches(request)) {
+            Some(index) => index,
+            None => return handle(request),
+        };
+        let fault = if self.0[index].use_once() {
+            self.0.remove(index)
+        } else {
+            self.0[index].clone()
+        };
+        log::info!(
+            "Injecting {:?} into {} {}",
+            fault,
+            request.method,
+            request.path
+        );
+        let mut response = match fault.action {
+            FaultAction::Respond { status, body } => Response::new(status, body),
+            FaultAction::AddHeaders => handle(request),
+        };
+        for (name, value) in &fault.headers {
+            response.set_header(name, value);
+        }
+        response
+    }
+}
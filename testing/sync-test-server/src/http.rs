@@ -0,0 +1,182 @@
+/* Any copyright is dedicated to the Public Domain.
+http://creativecommons.org/publicdomain/zero/1.0/ */
+
+//! Just enough HTTP/1.1 to talk to our HTTP clients. Every response closes
+//! the connection, so we don't need to worry about keep-alive.
+
+use std::io::{self, BufRead, Write};
+use url::Url;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    // Header names are lowercased.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn read(reader: &mut impl BufRead) -> io::Result<Request> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (method, target) = match (parts.next(), parts.next()) {
+            (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+            _ => return Err(invalid(format!("Bad request line {:?}", line))),
+        };
+
+        let mut headers = vec![];
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let colon = header
+                .find(':')
+                .ok_or_else(|| invalid(format!("Bad header {:?}", header)))?;
+            headers.push((
+                header[..colon].trim().to_ascii_lowercase(),
+                header[colon + 1..].trim().to_string(),
+            ));
+        }
+
+        let url = Url::parse("http://localhost")
+            .and_then(|base| base.join(&target))
+            .map_err(|e| invalid(e.to_string()))?;
+        let mut request = Request {
+            method,
+            path: url.path().to_string(),
+            query: url.query_pairs().into_owned().collect(),
+            headers,
+            body: vec![],
+        };
+
+        let chunked = request.header("transfer-encoding");
+        if matches!(chunked, Some(te) if te.eq_ignore_ascii_case("chunked")) {
+            request.body = read_chunked(reader)?;
+        } else if let Some(len) = request.header("content-length") {
+            let len = len.parse::<usize>().map_err(|e| invalid(e.to_string()))?;
+            request.body = vec![0; len];
+            reader.read_exact(&mut request.body)?;
+        }
+        Ok(request)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn read_chunked(reader: &mut impl BufRead) -> io::Result<Vec<u8>> {
+    let mut body = vec![];
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let size = line.trim().split(';').next().unwrap_or_default();
+        let size = usize::from_str_radix(size, 16).map_err(|e| invalid(e.to_string()))?;
+        if size == 0 {
+            // Skip any trailers.
+            loop {
+                line.clear();
+                reader.read_line(&mut line)?;
+                if line.trim_end().is_empty() {
+                    return Ok(body);
+                }
+            }
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        // The chunk's trailing CRLF.
+        line.clear();
+        reader.read_line(&mut line)?;
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Response {
+            status,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    pub fn json(status: u16, body: &serde_json::Value) -> Self {
+        Response::new(status, body.to_string()).header("Content-Type", "application/json")
+    }
+
+    pub fn header(mut self, name: &str, value: impl ToString) -> Self {
+        self.set_header(name, value);
+        self
+    }
+
+    pub fn set_header(&mut self, name: &str, value: impl ToString) {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\n",
+            self.status,
+            reason_phrase(self.status)
+        )?;
+        for (name, value) in &self.headers {
+            write!(writer, "{}: {}\r\n", name, value)?;
+        }
+        write!(
+            writer,
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.body.len()
+        )?;
+        writer.write_all(&self.body)?;
+        writer.flush()
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        412 => "Precondition Failed",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        507 => "Insufficient Storage",
+        _ => "Unknown",
+    }
+}
@@ -0,0 +1,571 @@
+/* Any copyright is dedicated to the Public Domain.
+http://creativecommons.org/publicdomain/zero/1.0/ */
+
+//! A Sync 1.5 storage server and tokenserver that run in the test process,
+//! so that sync tests can use a real `Sync15StorageClient` without a
+//! network connection or a Firefox Account.
+//!
+//! ```no_run
+//! # use sync_test_server::{Fault, TestServer};
+//! let server = TestServer::start().unwrap();
+//! // Use `server.tokenserver_url()` as the `tokenserver_url` in the
+//! // `Sync15StorageClientInit`. Any access token and key ID are accepted.
+//! let tokenserver_url = server.tokenserver_url();
+//! // Make the next upload fail.
+//! server.add_fault(Fault::status(503).method("POST"));
+//! ```
+//!
+//! The server stores everything in memory, for a single user. It implements
+//! the parts of the storage API our clients use: `info/collections`,
+//! `info/configuration`, fetching collections (including paging with
+//! `X-Weave-Next-Offset`), reading and writing single records, batch
+//! uploads, deleting collections and storage, `X-If-Unmodified-Since`
+//! checks, and `info/quota`. It doesn't check Hawk signatures, only that requests are signed.
+
+#![warn(rust_2018_idioms)]
+
+mod faults;
+mod http;
+mod storage;
+
+pub use faults::Fault;
+pub use storage::{ServerConfig, ServerRecord};
+
+use crate::http::{Request, Response};
+use serde_json::json;
+use std::io::{self, BufReader};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use url::Url;
+
+// The tokenserver path our clients add to the tokenserver URL.
+const TOKENSERVER_PATH: &str = "/token/1.0/sync/1.5";
+// The path of our only user's storage.
+const STORAGE_PATH: &str = "/1.5/1";
+// How long our tokens last, in seconds.
+const TOKEN_DURATION: u64 = 3600;
+
+#[derive(Debug, Default)]
+struct ServerState {
+    storage: storage::Storage,
+    faults: faults::Faults,
+    requests: Vec<String>,
+}
+
+/// A running server. The server stops when this is dropped.
+#[derive(Debug)]
+pub struct TestServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<ServerState>>,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// Starts a server on a free port on localhost.
+    pub fn start() -> io::Result<TestServer> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(ServerState::default()));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let state = state.clone();
+            let stopped = stopped.clone();
+            thread::Builder::new()
+                .name("sync-test-server".into())
+                .spawn(move || serve(listener, &state, &stopped))?
+        };
+        log::info!("Sync test server listening on {}", addr);
+        Ok(TestServer {
+            addr,
+            state,
+            stopped,
+            thread: Some(thread),
+        })
+    }
+
+    /// The URL to use as the `tokenserver_url`.
+    pub fn tokenserver_url(&self) -> Url {
+        self.url("/token/")
+    }
+
+    /// The storage URL the tokenserver hands out.
+    pub fn storage_url(&self) -> Url {
+        self.url(STORAGE_PATH)
+    }
+
+    fn url(&self, path: &str) -> Url {
+        Url::parse(&format!("http://{}{}", self.addr, path)).expect("valid server URL")
+    }
+
+    fn state(&self) -> MutexGuard<'_, ServerState> {
+        self.state.lock().expect("poisoned server state")
+    }
+
+    /// Injects a fault into the responses to matching requests.
+    pub fn add_fault(&self, fault: Fault) {
+        self.state().faults.add(fault);
+    }
+
+    /// Removes the faults that haven't been injected yet.
+    pub fn clear_faults(&self) {
+        self.state().faults.clear();
+    }
+
+    /// Changes the limits the server reports and enforces.
+    pub fn set_config(&self, config: ServerConfig) {
+        self.state().storage.config = config;
+    }
+
+    /// Returns the records in a collection.
+    pub fn records(&self, collection: &str) -> Vec<ServerRecord> {
+        self.state().storage.records(collection)
+    }
+
+    /// Returns a record, or `None` if it doesn't exist.
+    pub fn record(&self, collection: &str, id: &str) -> Option<ServerRecord> {
+        self.state().storage.record(collection, id)
+    }
+
+    /// Returns when a collection was last modified, in milliseconds, or 0
+    /// if it doesn't exist.
+    pub fn collection_modified(&self, collection: &str) -> i64 {
+        self.state().storage.collection_modified(collection)
+    }
+
+    /// Writes records to a collection, as if another client had uploaded
+    /// them. Their `modified` times are ignored. Returns the collection's
+    /// new modified time.
+    pub fn insert_records(&self, collection: &str, records: Vec<ServerRecord>) -> i64 {
+        self.state().storage.insert(collection, records)
+    }
+
+    /// Deletes a collection.
+    pub fn delete_collection(&self, collection: &str) {
+        self.state().storage.delete_collection(collection);
+    }
+
+    /// Deletes everything on the server.
+    pub fn wipe(&self) {
+        self.state().storage.delete_all();
+    }
+
+    /// Returns the requests the server has handled, like
+    /// `"GET /1.5/1/info/collections"`, without their query strings.
+    pub fn requests(&self) -> Vec<String> {
+        self.state().requests.clone()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wake the server thread up, so it notices.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Connections are handled one at a time, which keeps the order of requests
+// predictable.
+fn serve(listener: TcpListener, state: &Mutex<ServerState>, stopped: &AtomicBool) {
+    for stream in listener.incoming() {
+        if stopped.load(Ordering::SeqCst) {
+            break;
+        }
+        let result = stream.and_then(|stream| handle_connection(stream, state));
+        if let Err(e) = result {
+            log::warn!("Sync test server connection failed: {}", e);
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, state: &Mutex<ServerState>) -> io::Result<()> {
+    let request = Request::read(&mut BufReader::new(&stream))?;
+    log::trace!(
+        "Sync test server handling {} {}",
+        request.method,
+        request.path
+    );
+    let response = {
+        let mut state = state.lock().expect("poisoned server state");
+        let ServerState {
+            storage,
+            faults,
+            requests,
+        } = &mut *state;
+        requests.push(format!("{} {}", request.method, request.path));
+        faults.respond(&request, |request| route(storage, request))
+    };
+    let mut stream = stream;
+    response.write_to(&mut stream)
+}
+
+fn route(storage: &mut storage::Storage, request: &Request) -> Response {
+    if request.path == TOKENSERVER_PATH {
+        return token(request);
+    }
+    if request.path == STORAGE_PATH || request.path.starts_with(&format!("{}/", STORAGE_PATH)) {
+        if !matches!(request.header("Authorization"), Some(auth) if auth.starts_with("Hawk ")) {
+            return Response::json(401, &json!(0));
+        }
+        return storage.handle(request, &request.path[STORAGE_PATH.len()..]);
+    }
+    Response::json(404, &json!(0))
+}
+
+fn token(request: &Request) -> Response {
+    let authorized = matches!(request.header("Authorization"), Some(auth) if auth.starts_with("Bearer "))
+        && request.header("X-KeyID").is_some();
+    if !authorized {
+        return Response::json(401, &json!({ "status": "invalid-credentials" }));
+    }
+    let host = request.header("Host").unwrap_or("127.0.0.1");
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("system time before the epoch");
+    Response::json(
+        200,
+        &json!({
+            "id": "sync-test-server-token",
+            "key": "sync-test-server-key",
+            "api_endpoint": format!("http://{}{}", host, STORAGE_PATH),
+            "uid": 1,
+            "duration": TOKEN_DURATION,
+            "hashed_fxa_uid": "sync-test-server-user",
+        }),
+    )
+    .header("X-Timestamp", format!("{:.2}", now.as_secs_f64()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::io::{Read, Write};
+
+    struct TestResponse {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: Value,
+    }
+
+    impl TestResponse {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.as_str())
+        }
+    }
+
+    fn send(
+        server: &TestServer,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: Option<Value>,
+    ) -> TestResponse {
+        let body = body.map(|b| b.to_string()).unwrap_or_default();
+        let mut stream = TcpStream::connect(server.addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n",
+            method,
+            path,
+            server.addr,
+            body.len()
+        )
+        .unwrap();
+        for (name, value) in headers {
+            write!(stream, "{}: {}\r\n", name, value).unwrap();
+        }
+        write!(stream, "\r\n{}", body).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_at(response.find("\r\n\r\n").unwrap());
+        let mut lines = head.split("\r\n");
+        let status = lines.next().unwrap().split(' ').nth(1).unwrap();
+        let headers = lines
+            .map(|line| {
+                let colon = line.find(':').unwrap();
+                (
+                    line[..colon].to_string(),
+                    line[colon + 1..].trim().to_string(),
+                )
+            })
+            .collect();
+        TestResponse {
+            status: status.parse().unwrap(),
+            headers,
+            body: serde_json::from_str(&body[4..]).unwrap_or(Value::Null),
+        }
+    }
+
+    const HAWK: (&str, &str) = ("Authorization", "Hawk id=\"x\"");
+
+    fn storage(
+        server: &TestServer,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: Option<Value>,
+    ) -> TestResponse {
+        let mut all_headers = vec![HAWK];
+        all_headers.extend_from_slice(headers);
+        let path = format!("{}/{}", STORAGE_PATH, path);
+        send(server, method, &path, &all_headers, body)
+    }
+
+    fn bso(id: &str) -> Value {
+        json!({ "id": id, "payload": format!("payload {}", id) })
+    }
+
+    #[test]
+    fn test_token() {
+        let server = TestServer::start().unwrap();
+        let response = send(&server, "GET", TOKENSERVER_PATH, &[], None);
+        assert_eq!(response.status, 401);
+
+        let response = send(
+            &server,
+            "GET",
+            TOKENSERVER_PATH,
+            &[("Authorization", "Bearer abc"), ("X-KeyID", "1234-abcd")],
+            None,
+        );
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.body["api_endpoint"].as_str().unwrap(),
+            server.storage_url().as_str()
+        );
+        assert!(response.header("X-Timestamp").is_some());
+
+        // Storage requests have to be signed.
+        let response = send(&server, "GET", "/1.5/1/info/collections", &[], None);
+        assert_eq!(response.status, 401);
+    }
+
+    #[test]
+    fn test_batch_upload() {
+        let server = TestServer::start().unwrap();
+        let response = storage(
+            &server,
+            "POST",
+            "storage/bookmarks?batch=true",
+            &[("X-If-Unmodified-Since", "0")],
+            Some(json!([bso("a"), bso("b")])),
+        );
+        assert_eq!(response.status, 202);
+        assert_eq!(response.body["success"], json!(["a", "b"]));
+        let batch = response.body["batch"].as_str().unwrap().to_string();
+        // Nothing is written until the batch is committed.
+        assert!(server.records("bookmarks").is_empty());
+
+        let response = storage(
+            &server,
+            "POST",
+            &format!("storage/bookmarks?batch={}&commit=true", batch),
+            &[("X-If-Unmodified-Since", "0")],
+            Some(json!([bso("c")])),
+        );
+        assert_eq!(response.status, 200);
+        let ids = server
+            .records("bookmarks")
+            .into_iter()
+            .map(|r| r.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["a", "b", "c"]);
+
+        let response = storage(&server, "GET", "info/collections", &[], None);
+        assert_eq!(
+            response.header("X-Last-Modified").unwrap(),
+            format!(
+                "{:.2}",
+                server.collection_modified("bookmarks") as f64 / 1000.0
+            )
+        );
+        assert!(response.body["bookmarks"].is_number());
+    }
+
+    #[test]
+    fn test_unmodified_since() {
+        let server = TestServer::start().unwrap();
+        let response = storage(
+            &server,
+            "PUT",
+            "storage/meta/global",
+            &[("X-If-Unmodified-Since", "0")],
+            Some(json!({ "payload": "{}" })),
+        );
+        assert_eq!(response.status, 200);
+        let modified = response.header("X-Last-Modified").unwrap().to_string();
+
+        // Another client writes meta/global...
+        let response = storage(
+            &server,
+            "PUT",
+            "storage/meta/global",
+            &[("X-If-Unmodified-Since", &modified)],
+            Some(json!({ "payload": "{\"syncID\":\"new\"}" })),
+        );
+        assert_eq!(response.status, 200);
+
+        // ...so writing based on the old version fails.
+        let response = storage(
+            &server,
+            "PUT",
+            "storage/meta/global",
+            &[("X-If-Unmodified-Since", &modified)],
+            Some(json!({ "payload": "{}" })),
+        );
+        assert_eq!(response.status, 412);
+        assert_eq!(
+            server.record("meta", "global").unwrap().payload,
+            "{\"syncID\":\"new\"}"
+        );
+
+        server.insert_records("history", vec![]);
+        let response = storage(
+            &server,
+            "POST",
+            "storage/history",
+            &[("X-If-Unmodified-Since", &modified)],
+            Some(json!([bso("a")])),
+        );
+        assert_eq!(response.status, 412);
+    }
+
+    #[test]
+    fn test_paging() {
+        let server = TestServer::start().unwrap();
+        for id in &["a", "b", "c", "d", "e"] {
+            storage(
+                &server,
+                "POST",
+                "storage/history",
+                &[],
+                Some(json!([bso(id)])),
+            );
+        }
+        let mut offset = None;
+        let mut ids = vec![];
+        loop {
+            let path = match &offset {
+                Some(offset) => format!(
+                    "storage/history?full=1&sort=oldest&limit=2&offset={}",
+                    offset
+                ),
+                None => "storage/history?full=1&sort=oldest&limit=2".to_string(),
+            };
+            let response = storage(&server, "GET", &path, &[], None);
+            assert_eq!(response.status, 200);
+            for record in response.body.as_array().unwrap() {
+                ids.push(record["id"].as_str().unwrap().to_string());
+            }
+            offset = response.header("X-Weave-Next-Offset").map(String::from);
+            if offset.is_none() {
+                break;
+            }
+        }
+        assert_eq!(ids, vec!["a", "b", "c", "d", "e"]);
+
+        let response = storage(
+            &server,
+            "GET",
+            "storage/history?sort=newest&limit=1",
+            &[],
+            None,
+        );
+        assert_eq!(response.body, json!(["e"]));
+    }
+
+    #[test]
+    fn test_faults() {
+        let server = TestServer::start().unwrap();
+        server.add_fault(Fault::retry_after(10).path("/info/collections").times(2));
+        server.add_fault(Fault::backoff(60).method("GET").always());
+        for _ in 0..2 {
+            let response = storage(&server, "GET", "info/collections", &[], None);
+            assert_eq!(response.status, 503);
+            assert_eq!(response.header("Retry-After"), Some("10"));
+        }
+        let response = storage(&server, "GET", "info/collections", &[], None);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("X-Weave-Backoff"), Some("60"));
+
+        server.clear_faults();
+        let response = storage(&server, "GET", "info/collections", &[], None);
+        assert_eq!(response.header("X-Weave-Backoff"), None);
+        assert_eq!(server.requests().len(), 4);
+        assert_eq!(server.requests()[0], "GET /1.5/1/info/collections");
+    }
+
+    #[test]
+    fn test_quota() {
+        let server = TestServer::start().unwrap();
+        server.set_config(ServerConfig {
+            quota_bytes: Some(20),
+            ..ServerConfig::default()
+        });
+        let response = storage(&server, "GET", "info/quota", &[], None);
+        assert_eq!(response.body, json!([0.0, 20.0 / 1024.0]));
+
+        // Each payload is 9 bytes.
+        let response = storage(
+            &server,
+            "POST",
+            "storage/bookmarks",
+            &[],
+            Some(json!([bso("a"), bso("b")])),
+        );
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.header("X-Weave-Quota-Remaining"),
+            Some(format!("{:.2}", 2.0 / 1024.0).as_str())
+        );
+
+        let response = storage(
+            &server,
+            "POST",
+            "storage/bookmarks",
+            &[],
+            Some(json!([bso("c")])),
+        );
+        assert_eq!(response.status, 507);
+        assert_eq!(server.records("bookmarks").len(), 2);
+
+        let response = storage(&server, "GET", "info/quota", &[], None);
+        assert_eq!(response.body, json!([18.0 / 1024.0, 20.0 / 1024.0]));
+    }
+
+    #[test]
+    fn test_delete() {
+        let server = TestServer::start().unwrap();
+        server.insert_records(
+            "tabs",
+            vec![ServerRecord {
+                id: "a".into(),
+                modified: 0,
+                payload: "{}".into(),
+                sortindex: None,
+                ttl: None,
+            }],
+        );
+        server.insert_records("clients", server.records("tabs"));
+        let response = storage(&server, "DELETE", "storage/tabs", &[], None);
+        assert_eq!(response.status, 200);
+        assert!(server.records("tabs").is_empty());
+        assert_eq!(server.records("clients").len(), 1);
+
+        // Deleting the storage URL deletes everything.
+        let response = send(&server, "DELETE", STORAGE_PATH, &[HAWK], None);
+        assert_eq!(response.status, 200);
+        assert!(server.records("clients").is_empty());
+    }
+}
@@ -0,0 +1,486 @@
+/* Any copyright is dedicated to the Public Domain.
+http://creativecommons.org/publicdomain/zero/1.0/ */
+
+//! The Sync 1.5 storage API, for a single user. See
+//! https://mozilla-services.readthedocs.io/en/latest/storage/apis-1.5.html
+
+use crate::http::{Request, Response};
+use serde_json::{json, Value};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A record stored on the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerRecord {
+    pub id: String,
+    /// When the record was last written, in milliseconds since the epoch.
+    pub modified: i64,
+    pub payload: String,
+    pub sortindex: Option<i64>,
+    pub ttl: Option<u64>,
+}
+
+impl ServerRecord {
+    fn to_json(&self) -> Value {
+        let mut record = json!({
+            "id": self.id,
+            "modified": self.modified as f64 / 1000.0,
+            "payload": self.payload,
+        });
+        if let Some(sortindex) = self.sortindex {
+            record["sortindex"] = sortindex.into();
+        }
+        record
+    }
+}
+
+/// The limits the server reports in `info/configuration`, and enforces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    pub max_request_bytes: usize,
+    pub max_post_records: usize,
+    pub max_post_bytes: usize,
+    pub max_total_records: usize,
+    pub max_total_bytes: usize,
+    pub max_record_payload_bytes: usize,
+    /// The most payload bytes the account can store, or `None` for no quota.
+    /// Writes that would go over it fail with a 507.
+    pub quota_bytes: Option<u64>,
+}
+
+impl Default for ServerConfig {
+    // The production server's limits.
+    fn default() -> Self {
+        ServerConfig {
+            max_request_bytes: 2_101_248,
+            max_post_records: 100,
+            max_post_bytes: 2_097_152,
+            max_total_records: 10_000,
+            max_total_bytes: 209_715_200,
+            max_record_payload_bytes: 2_097_152,
+            quota_bytes: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    fn to_json(&self) -> Value {
+        json!({
+            "max_request_bytes": self.max_request_bytes,
+            "max_post_records": self.max_post_records,
+            "max_post_bytes": self.max_post_bytes,
+            "max_total_records": self.max_total_records,
+            "max_total_bytes": self.max_total_bytes,
+            "max_record_payload_bytes": self.max_record_payload_bytes,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Collection {
+    modified: i64,
+    records: BTreeMap<String, ServerRecord>,
+}
+
+#[derive(Debug)]
+struct Batch {
+    collection: String,
+    records: Vec<ServerRecord>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Storage {
+    pub config: ServerConfig,
+    collections: BTreeMap<String, Collection>,
+    batches: HashMap<String, Batch>,
+    next_batch_id: u64,
+    last_modified: i64,
+}
+
+// Timestamps are in seconds, with two decimal places.
+fn seconds(ms: i64) -> String {
+    format!("{:.2}", ms as f64 / 1000.0)
+}
+
+// Quotas and usage are reported in kilobytes.
+fn kilobytes(bytes: u64) -> String {
+    format!("{:.2}", bytes as f64 / 1024.0)
+}
+
+fn parse_seconds(s: &str) -> Option<i64> {
+    s.parse::<f64>().ok().map(|s| (s * 1000.0).round() as i64)
+}
+
+fn error(status: u16) -> Response {
+    Response::json(status, &json!(0))
+}
+
+impl Storage {
+    pub fn collection_modified(&self, collection: &str) -> i64 {
+        self.collections.get(collection).map_or(0, |c| c.modified)
+    }
+
+    pub fn records(&self, collection: &str) -> Vec<ServerRecord> {
+        self.collections
+            .get(collection)
+            .map_or_else(Vec::new, |c| c.records.values().cloned().collect())
+    }
+
+    pub fn record(&self, collection: &str, id: &str) -> Option<ServerRecord> {
+        self.collections
+            .get(collection)
+            .and_then(|c| c.records.get(id))
+            .cloned()
+    }
+
+    pub fn insert(&mut self, collection: &str, records: Vec<ServerRecord>) -> i64 {
+        let modified = self.next_timestamp();
+        let coll = self.collections.entry(collection.to_string()).or_default();
+        coll.modified = modified;
+        for mut record in records {
+            record.modified = modified;
+            coll.records.insert(record.id.clone(), record);
+        }
+        modified
+    }
+
+    pub fn delete_collection(&mut self, collection: &str) {
+        self.collections.remove(collection);
+        self.batches.retain(|_, b| b.collection != collection);
+    }
+
+    pub fn delete_all(&mut self) {
+        self.collections.clear();
+        self.batches.clear();
+    }
+
+    // We only count payloads towards the quota.
+    fn usage(&self) -> u64 {
+        self.collections
+            .values()
+            .flat_map(|c| c.records.values())
+            .map(|r| r.payload.len() as u64)
+            .sum()
+    }
+
+    // Returns a 507 if storing `bytes` more would go over the quota.
+    fn check_quota(&self, bytes: u64) -> Option<Response> {
+        let quota = self.config.quota_bytes?;
+        if self.usage() + bytes > quota {
+            log::info!("Over quota: {} + {} > {}", self.usage(), bytes, quota);
+            Some(error(507))
+        } else {
+            None
+        }
+    }
+
+    // Timestamps have a resolution of 10ms, and every write gets a later
+    // timestamp than the last one, even if they happen in the same 10ms.
+    fn next_timestamp(&mut self) -> i64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time before the epoch")
+            .as_millis() as i64;
+        self.last_modified = std::cmp::max(now / 10 * 10, self.last_modified + 10);
+        self.last_modified
+    }
+
+    fn last_modified(&self) -> i64 {
+        self.collections
+            .values()
+            .map(|c| c.modified)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Handles a request to the storage API. `path` is the part of the
+    /// request's path after `/1.5/{uid}`.
+    pub fn handle(&mut self, request: &Request, path: &str) -> Response {
+        let segments = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>();
+        let method = request.method.as_str();
+        let mut response = match (method, segments.as_slice()) {
+            ("GET", ["info", "collections"]) => self.info_collections(),
+            ("GET", ["info", "configuration"]) => Response::json(200, &self.config.to_json()),
+            ("GET", ["info", "quota"]) => self.info_quota(),
+            ("GET", ["storage", collection]) => self.get_collection(request, collection),
+            ("GET", ["storage", collection, id]) => self.get_record(request, collection, id),
+            ("PUT", ["storage", collection, id]) => self.put_record(request, collection, id),
+            ("POST", ["storage", collection]) => self.post(request, collection),
+            ("DELETE", ["storage", collection]) => self.delete(request, Some(collection)),
+            ("DELETE", []) | ("DELETE", ["storage"]) => self.delete(request, None),
+            ("GET", _) | ("PUT", _) | ("POST", _) | ("DELETE", _) => error(404),
+            _ => error(405),
+        };
+        response.set_header("X-Weave-Timestamp", seconds(self.next_read_timestamp()));
+        // The production server only sends this when the account is close to
+        // its quota, but we always do, so tests don't need to fill it up.
+        if let (Some(quota), "PUT") | (Some(quota), "POST") = (self.config.quota_bytes, method) {
+            let remaining = quota.saturating_sub(self.usage());
+            response.set_header("X-Weave-Quota-Remaining", kilobytes(remaining));
+        }
+        response
+    }
+
+    fn next_read_timestamp(&self) -> i64 {
+        std::cmp::max(self.last_modified, self.last_modified())
+    }
+
+    // Returns an error response if the resource has changed since the
+    // request's `X-If-Unmodified-Since`.
+    fn check_unmodified(&self, request: &Request, modified: i64) -> Option<Response> {
+        let since = request.header("X-If-Unmodified-Since")?;
+        match parse_seconds(since) {
+            Some(since) if modified > since => {
+                log::info!("Precondition failed: {} > {}", modified, since);
+                Some(error(412).header("X-Last-Modified", seconds(modified)))
+            }
+            Some(_) => None,
+            None => Some(error(400)),
+        }
+    }
+
+    fn info_collections(&self) -> Response {
+        let collections = self
+            .collections
+            .iter()
+            .filter(|(_, c)| !c.records.is_empty())
+            .map(|(name, c)| (name.clone(), json!(c.modified as f64 / 1000.0)))
+            .collect::<serde_json::Map<_, _>>();
+        Response::json(200, &Value::Object(collections))
+            .header("X-Last-Modified", seconds(self.last_modified()))
+    }
+
+    fn info_quota(&self) -> Response {
+        let usage = self.usage() as f64 / 1024.0;
+        let quota = self.config.quota_bytes.map(|quota| quota as f64 / 1024.0);
+        Response::json(200, &json!([usage, quota]))
+            .header("X-Last-Modified", seconds(self.last_modified()))
+    }
+
+    fn get_collection(&self, request: &Request, collection: &str) -> Response {
+        let modified = self.collection_modified(collection);
+        if let Some(response) = self.check_unmodified(request, modified) {
+            return response;
+        }
+        let ids = request
+            .query("ids")
+            .map(|ids| ids.split(',').collect::<Vec<_>>());
+        let newer = request.query("newer").and_then(parse_seconds);
+        let older = request.query("older").and_then(parse_seconds);
+        let mut records = self
+            .collections
+            .get(collection)
+            .map_or_else(Vec::new, |c| c.records.values().collect());
+        records.retain(|r| {
+            ids.iter().all(|ids| ids.contains(&r.id.as_str()))
+                && newer.iter().all(|newer| r.modified > *newer)
+                && older.iter().all(|older| r.modified < *older)
+        });
+        match request.query("sort") {
+            Some("newest") => records.sort_by_key(|r| Reverse(r.modified)),
+            Some("oldest") => records.sort_by_key(|r| r.modified),
+            Some("index") => records.sort_by_key(|r| Reverse(r.sortindex)),
+            _ => {}
+        }
+
+        // Our offsets are just the index of the next record.
+        let start = match request.query("offset").map(str::parse::<usize>) {
+            None => 0,
+            Some(Ok(offset)) if offset <= records.len() => offset,
+            Some(_) => return error(400),
+        };
+        let end = match request.query("limit").and_then(|l| l.parse::<usize>().ok()) {
+            Some(limit) => std::cmp::min(start + limit, records.len()),
+            None => records.len(),
+        };
+        let page = &records[start..end];
+        let body = if request.query("full").is_some() {
+            page.iter().map(|r| r.to_json()).collect::<Vec<_>>()
+        } else {
+            page.iter().map(|r| json!(r.id)).collect::<Vec<_>>()
+        };
+        let mut response = Response::json(200, &Value::Array(body))
+            .header("X-Last-Modified", seconds(modified))
+            .header("X-Weave-Records", page.len());
+        if end < records.len() {
+            response.set_header("X-Weave-Next-Offset", end);
+        }
+        response
+    }
+
+    fn get_record(&self, request: &Request, collection: &str, id: &str) -> Response {
+        let record = match self.record(collection, id) {
+            Some(record) => record,
+            None => return error(404),
+        };
+        if let Some(response) = self.check_unmodified(request, record.modified) {
+            return response;
+        }
+        Response::json(200, &record.to_json()).header("X-Last-Modified", seconds(record.modified))
+    }
+
+    fn put_record(&mut self, request: &Request, collection: &str, id: &str) -> Response {
+        let existing = self.record(collection, id);
+        let modified = existing.as_ref().map_or(0, |r| r.modified);
+        if let Some(response) = self.check_unmodified(request, modified) {
+            return response;
+        }
+        let mut record = match serde_json::from_slice::<Value>(&request.body)
+            .ok()
+            .and_then(|bso| parse_record(&bso, Some(id)))
+        {
+            Some(record) => record,
+            None => return error(400),
+        };
+        if record.payload.len() > self.config.max_record_payload_bytes {
+            return error(413);
+        }
+        if let Some(response) = self.check_quota(record.payload.len() as u64) {
+            return response;
+        }
+        // The sort index is kept if the request doesn't change it.
+        if let Some(existing) = existing {
+            record.sortindex = record.sortindex.or(existing.sortindex);
+        }
+        let modified = self.insert(collection, vec![record]);
+        Response::json(200, &json!(modified as f64 / 1000.0))
+            .header("X-Last-Modified", seconds(modified))
+    }
+
+    fn post(&mut self, request: &Request, collection: &str) -> Response {
+        if let Some(response) = self.check_unmodified(request, self.collection_modified(collection))
+        {
+            return response;
+        }
+        if request.body.len() > self.config.max_request_bytes {
+            return error(413);
+        }
+        let bsos = match serde_json::from_slice::<Vec<Value>>(&request.body) {
+            Ok(bsos) => bsos,
+            Err(_) => return error(400),
+        };
+        if bsos.len() > self.config.max_post_records {
+            return error(413);
+        }
+
+        let mut success = vec![];
+        let mut failed = serde_json::Map::new();
+        let mut records = vec![];
+        for bso in &bsos {
+            match parse_record(bso, None) {
+                Some(record) if record.payload.len() > self.config.max_record_payload_bytes => {
+                    failed.insert(record.id, json!("retry bytes"));
+                }
+                Some(record) => {
+                    success.push(json!(record.id));
+                    records.push(record);
+                }
+                None => {
+                    if let Some(id) = bso.get("id").and_then(Value::as_str) {
+                        failed.insert(id.to_string(), json!("invalid record"));
+                    } else {
+                        return error(400);
+                    }
+                }
+            }
+        }
+
+        let bytes = records.iter().map(|r| r.payload.len() as u64).sum();
+        if let Some(response) = self.check_quota(bytes) {
+            return response;
+        }
+
+        let commit = request.query("commit") == Some("true");
+        let batch_id = match request.query("batch") {
+            None => {
+                let modified = self.insert(collection, records);
+                return upload_response(200, None, modified, success, failed);
+            }
+            Some("true") => {
+                self.next_batch_id += 1;
+                let id = self.next_batch_id.to_string();
+                self.batches.insert(
+                    id.clone(),
+                    Batch {
+                        collection: collection.to_string(),
+                        records: vec![],
+                    },
+                );
+                id
+            }
+            Some(id) => match self.batches.get(id) {
+                Some(batch) if batch.collection == collection => id.to_string(),
+                _ => return error(400),
+            },
+        };
+
+        let batch = self.batches.get_mut(&batch_id).expect("batch exists");
+        batch.records.extend(records);
+        if batch.records.len() > self.config.max_total_records {
+            self.batches.remove(&batch_id);
+            return error(413);
+        }
+        if !commit {
+            let modified = self.collection_modified(collection);
+            return upload_response(202, Some(batch_id), modified, success, failed);
+        }
+        let batch = self.batches.remove(&batch_id).expect("batch exists");
+        let modified = self.insert(collection, batch.records);
+        upload_response(200, None, modified, success, failed)
+    }
+
+    fn delete(&mut self, request: &Request, collection: Option<&str>) -> Response {
+        let modified = match collection {
+            Some(collection) => self.collection_modified(collection),
+            None => self.last_modified(),
+        };
+        if let Some(response) = self.check_unmodified(request, modified) {
+            return response;
+        }
+        match collection {
+            Some(collection) => self.delete_collection(collection),
+            None => self.delete_all(),
+        }
+        let modified = self.next_timestamp();
+        Response::json(200, &json!({ "modified": modified as f64 / 1000.0 }))
+            .header("X-Last-Modified", seconds(modified))
+    }
+}
+
+fn upload_response(
+    status: u16,
+    batch: Option<String>,
+    modified: i64,
+    success: Vec<Value>,
+    failed: serde_json::Map<String, Value>,
+) -> Response {
+    let mut body = json!({
+        "modified": modified as f64 / 1000.0,
+        "success": success,
+        "failed": failed,
+    });
+    if let Some(batch) = batch {
+        body["batch"] = batch.into();
+    }
+    Response::json(status, &body).header("X-Last-Modified", seconds(modified))
+}
+
+fn parse_record(bso: &Value, id: Option<&str>) -> Option<ServerRecord> {
+    let id = match (bso.get("id").and_then(Value::as_str), id) {
+        (Some(bso_id), Some(id)) if bso_id != id => return None,
+        (Some(bso_id), _) => bso_id,
+        (None, Some(id)) => id,
+        (None, None) => return None,
+    };
+    Some(ServerRecord {
+        id: id.to_string(),
+        modified: 0,
+        payload: bso.get("payload")?.as_str()?.to_string(),
+        sortindex: bso.get("sortindex").and_then(Value::as_i64),
+        ttl: bso.get("ttl").and_then(Value::as_u64),
+    })
+}
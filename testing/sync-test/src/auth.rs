@@ -7,6 +7,7 @@ use logins::PasswordEngine;
 use std::collections::HashMap;
 use std::sync::{Arc, Once};
 use sync15::{KeyBundle, Sync15StorageClientInit};
+use sync_test_server::TestServer;
 use tabs::TabsEngine;
 use url::Url;
 
@@ -101,6 +102,23 @@ pub struct TestAccount {
     pub pass: String,
     pub cfg: FxaConfig,
     pub no_delete: bool,
+    // Set if the account is using a local server instead of FxA.
+    pub local_server: Option<LocalServer>,
+}
+
+/// A sync server running in this process, and the sync key for it.
+#[derive(Debug)]
+pub struct LocalServer {
+    pub server: TestServer,
+    pub root_sync_key: KeyBundle,
+}
+
+fn random_name(len: usize) -> String {
+    use rand::prelude::*;
+    thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(len)
+        .collect()
 }
 
 impl TestAccount {
@@ -119,20 +137,32 @@ impl TestAccount {
             pass,
             cfg,
             no_delete,
+            local_server: None,
+        }))
+    }
+
+    /// Creates an account for a sync server running in this process, which
+    /// doesn't need FxA or the network.
+    pub fn new_local(opts: &Opts) -> Result<Arc<TestAccount>, failure::Error> {
+        log::info!("Starting local sync server");
+        let server = TestServer::start()?;
+        Ok(Arc::new(TestAccount {
+            email: "local@sync-test-server".into(),
+            pass: String::new(),
+            cfg: opts.fxa_stack.to_config(CLIENT_ID, REDIRECT_URI),
+            no_delete: true,
+            local_server: Some(LocalServer {
+                server,
+                root_sync_key: KeyBundle::new_random()?,
+            }),
         }))
     }
 
     pub fn new_random(opts: &Opts) -> Result<Arc<TestAccount>, failure::Error> {
-        use rand::prelude::*;
-        let rng = thread_rng();
-        let name = opts.force_username.clone().unwrap_or_else(|| {
-            format!(
-                "rust-login-sql-test--{}",
-                rng.sample_iter(&rand::distributions::Alphanumeric)
-                    .take(5)
-                    .collect::<String>()
-            )
-        });
+        let name = opts
+            .force_username
+            .clone()
+            .unwrap_or_else(|| format!("rust-login-sql-test--{}", random_name(5)));
         // We should probably check this some other time, but whatever.
         assert!(
             !name.contains('@'),
@@ -153,6 +183,9 @@ impl TestAccount {
 
 impl Drop for TestAccount {
     fn drop(&mut self) {
+        if self.local_server.is_some() {
+            return;
+        }
         if self.no_delete {
             log::info!("Cleanup was explicitly disabled, not deleting account");
             return;
@@ -172,7 +205,10 @@ impl Drop for TestAccount {
 }
 
 pub struct TestClient {
-    pub fxa: fxa_client::FirefoxAccount,
+    // `None` if the account is using a local server.
+    pub fxa: Option<fxa_client::FirefoxAccount>,
+    // Our device ID, if we aren't using FxA.
+    local_device_id: String,
     pub test_acct: Arc<TestAccount>,
     // XXX do this more generically...
     pub logins_engine: PasswordEngine,
@@ -181,6 +217,15 @@ pub struct TestClient {
 
 impl TestClient {
     pub fn new(acct: Arc<TestAccount>) -> Result<Self, failure::Error> {
+        if acct.local_server.is_some() {
+            return Ok(Self {
+                fxa: None,
+                local_device_id: format!("local-device-{}", random_name(8)),
+                test_acct: acct,
+                logins_engine: PasswordEngine::new_in_memory(None)?,
+                tabs_engine: TabsEngine::new_in_memory()?,
+            });
+        }
         log::info!("Doing oauth flow!");
 
         let mut fxa = FirefoxAccount::with_config(acct.cfg.clone());
@@ -206,16 +251,49 @@ impl TestClient {
         fxa.initialize_device("Testing Device", fxa_client::device::Type::Desktop, &[])?;
 
         Ok(Self {
-            fxa,
+            fxa: Some(fxa),
+            local_device_id: String::new(),
             test_acct: acct,
             logins_engine: PasswordEngine::new_in_memory(None)?,
-            tabs_engine: TabsEngine::new(),
+            tabs_engine: TabsEngine::new_in_memory()?,
+        })
+    }
+
+    pub fn device_id(&mut self) -> Result<String, failure::Error> {
+        Ok(match &mut self.fxa {
+            Some(fxa) => fxa.get_current_device_id()?,
+            None => self.local_device_id.clone(),
         })
     }
 
+    /// The sync server running in this process, if the account is using one.
+    pub fn local_server(&self) -> Option<&TestServer> {
+        self.test_acct
+            .local_server
+            .as_ref()
+            .map(|local| &local.server)
+    }
+
     pub fn data_for_sync(
         &mut self,
     ) -> Result<(Sync15StorageClientInit, KeyBundle, String), failure::Error> {
+        let fxa = match (&mut self.fxa, &self.test_acct.local_server) {
+            (Some(fxa), _) => fxa,
+            (None, Some(local)) => {
+                // The local server accepts any credentials.
+                let client_init = Sync15StorageClientInit {
+                    key_id: "local".into(),
+                    access_token: "local".into(),
+                    tokenserver_url: local.server.tokenserver_url(),
+                };
+                return Ok((
+                    client_init,
+                    local.root_sync_key.clone(),
+                    self.local_device_id.clone(),
+                ));
+            }
+            (None, None) => failure::bail!("Client has no account"),
+        };
         // Allow overriding it via environment
         let tokenserver_url = option_env!("TOKENSERVER_URL")
             .map(|env_var| {
@@ -225,7 +303,7 @@ impl TestClient {
                     .expect("Failed to parse TOKENSERVER_URL environment variable!"))
             })
             .unwrap_or_else(|| self.test_acct.cfg.token_server_endpoint_url())?;
-        let token = self.fxa.get_access_token(SYNC_SCOPE)?;
+        let token = fxa.get_access_token(SYNC_SCOPE)?;
 
         let key = token.key.as_ref().unwrap();
 
@@ -237,7 +315,7 @@ impl TestClient {
 
         let root_sync_key = KeyBundle::from_ksync_base64(&key.k)?;
 
-        let device_id = self.fxa.get_current_device_id()?;
+        let device_id = fxa.get_current_device_id()?;
 
         Ok((client_init, root_sync_key, device_id))
     }
@@ -252,7 +330,7 @@ impl TestClient {
     pub fn fully_reset_local_db(&mut self) -> Result<(), failure::Error> {
         // Not great...
         self.logins_engine = PasswordEngine::new_in_memory(None)?;
-        self.tabs_engine = TabsEngine::new();
+        self.tabs_engine = TabsEngine::new_in_memory()?;
         Ok(())
     }
 }
@@ -285,7 +363,11 @@ impl TestUser {
     fn new_random(opts: &Opts, client_count: usize) -> Result<Self, failure::Error> {
         log::info!("Creating test account with {} clients", client_count);
 
-        let account = TestAccount::new_random(&opts)?;
+        let account = if opts.local_server {
+            TestAccount::new_local(&opts)?
+        } else {
+            TestAccount::new_random(&opts)?
+        };
         let mut clients = Vec::with_capacity(client_count);
 
         for c in 0..client_count {
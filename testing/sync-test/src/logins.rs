@@ -4,6 +4,7 @@ http://creativecommons.org/publicdomain/zero/1.0/ */
 use crate::auth::TestClient;
 use crate::testing::TestGroup;
 use logins::{Login, PasswordEngine, Result as LoginResult};
+use sync_test_server::Fault;
 
 // helpers...
 
@@ -80,6 +81,18 @@ pub fn sync_logins(client: &mut TestClient) -> Result<(), failure::Error> {
     Ok(())
 }
 
+// Counts the uploads to the passwords collection, if the client is using a
+// local server.
+fn count_login_uploads(client: &TestClient) -> usize {
+    client.local_server().map_or(0, |server| {
+        server
+            .requests()
+            .iter()
+            .filter(|r| r.starts_with("POST ") && r.contains("/storage/passwords"))
+            .count()
+    })
+}
+
 // Actual tests.
 
 fn test_login_general(c0: &mut TestClient, c1: &mut TestClient) {
@@ -341,12 +354,68 @@ fn test_login_deletes(c0: &mut TestClient, c1: &mut TestClient) {
     verify_missing_login(&c0.logins_engine, l2id);
 }
 
+fn test_login_upload_conflict(c0: &mut TestClient, c1: &mut TestClient) {
+    log::info!("Add a login to client0 and sync");
+    let login0 = add_login(
+        &c0.logins_engine,
+        Login {
+            guid: "aaaaaaaaaaaa".into(),
+            hostname: "https://www.example.com".into(),
+            http_realm: Some("Login".into()),
+            username: "cool_username".into(),
+            password: "hunter2".into(),
+            ..Login::default()
+        },
+    )
+    .expect("add l0");
+    sync_logins(c0).expect("c0 sync to work");
+
+    log::info!("Add a login to client1");
+    let login1 = add_login(
+        &c1.logins_engine,
+        Login {
+            guid: "bbbbbbbbbbbb".into(),
+            hostname: "https://www.example.org".into(),
+            http_realm: Some("Login".into()),
+            username: "cool_username".into(),
+            password: "sekret".into(),
+            ..Login::default()
+        },
+    )
+    .expect("add l1");
+
+    // Pretend another client uploaded while client1 was syncing. Against a
+    // real server, this is just a normal sync.
+    let posts_before = count_login_uploads(c1);
+    if let Some(server) = c1.local_server() {
+        server.add_fault(Fault::status(412).method("POST").path("/storage/passwords"));
+    }
+
+    log::info!("Syncing client1, which should retry its upload");
+    sync_logins(c1).expect("c1 sync to recover from the conflict");
+    verify_login(&c1.logins_engine, &login0);
+    verify_login(&c1.logins_engine, &login1);
+    if c1.local_server().is_some() {
+        assert_eq!(
+            count_login_uploads(c1) - posts_before,
+            2,
+            "c1 should upload, and retry once"
+        );
+    }
+
+    log::info!("Syncing client0");
+    sync_logins(c0).expect("c0 sync to work");
+    verify_login(&c0.logins_engine, &login0);
+    verify_login(&c0.logins_engine, &login1);
+}
+
 pub fn get_test_group() -> TestGroup {
     TestGroup::new(
         "logins",
         vec![
             ("test_login_general", test_login_general),
             ("test_login_deletes", test_login_deletes),
+            ("test_login_upload_conflict", test_login_upload_conflict),
         ],
     )
 }
@@ -87,6 +87,12 @@ pub struct Opts {
     /// Disable deleting the fx account after use. Incompatible with oauth-retries.
     pub no_delete_account: bool,
 
+    #[structopt(name = "local-server", long)]
+    /// Run the tests against a sync server and tokenserver in this process,
+    /// instead of a live FxA account and sync server. This doesn't need
+    /// node, or a network connection.
+    pub local_server: bool,
+
     #[structopt(name = "helper-debug", long)]
     /// Run the helper browser as non-headless, and enable extra logging
     pub helper_debug: bool,
@@ -10,6 +10,7 @@ use tabs::{ClientRemoteTabs, DeviceType, RemoteTab, TabsEngine};
 pub fn verify_tabs(tabs_engine: &TabsEngine, expected: &ClientRemoteTabs) {
     let remote_tabs = tabs_engine
         .remote_tabs()
+        .expect("should be able to read the remote tabs")
         .expect("should have synced already");
     let equivalent = remote_tabs
         .iter()
@@ -49,6 +50,8 @@ fn test_tabs(c0: &mut TestClient, c1: &mut TestClient) {
         last_used: 1_572_265_044_661,
         title: "Welcome to Bobo".to_owned(),
         url_history: vec!["https://bobo.moz".to_owned()],
+
+        ..RemoteTab::default()
     };
     c0.tabs_engine.update_local_state(vec![t0.clone()]);
 
@@ -58,7 +61,7 @@ fn test_tabs(c0: &mut TestClient, c1: &mut TestClient) {
     verify_tabs(
         &c1.tabs_engine,
         &ClientRemoteTabs {
-            client_id: c0.fxa.get_current_device_id().unwrap(),
+            client_id: c0.device_id().unwrap(),
             client_name: String::new(),
             device_type: DeviceType::Mobile,
             remote_tabs: vec![t0],
@@ -70,12 +73,16 @@ fn test_tabs(c0: &mut TestClient, c1: &mut TestClient) {
         last_used: 1_572_267_197_207,
         title: "Foo".to_owned(),
         url_history: vec!["https://foo.org".to_owned()],
+
+        ..RemoteTab::default()
     };
     let t2 = RemoteTab {
         icon: None,
         last_used: 1_572_267_191_104,
         title: "Bar".to_owned(),
         url_history: vec!["https://bar.org".to_owned()],
+
+        ..RemoteTab::default()
     };
 
     c1.tabs_engine
@@ -87,7 +94,7 @@ fn test_tabs(c0: &mut TestClient, c1: &mut TestClient) {
     verify_tabs(
         &c0.tabs_engine,
         &ClientRemoteTabs {
-            client_id: c1.fxa.get_current_device_id().unwrap(),
+            client_id: c1.device_id().unwrap(),
             client_name: String::new(),
             device_type: DeviceType::Mobile,
             remote_tabs: vec![t1, t2],